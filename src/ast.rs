@@ -18,12 +18,47 @@ pub enum Operator {
     Pred,
 }
 
+/// Types of terms, records and variants can be left open with a row variable standing for the
+/// fields or tags that are not listed. Type and row variables only appear in inferred types.
 #[derive(Debug, PartialEq, Clone)]
 pub enum TypeAssignment {
     Single(Type),
     Arrow(Box<TypeAssignment>, Box<TypeAssignment>),
-    Record(HashMap<String, TypeAssignment>),
-    Variant(HashMap<String, TypeAssignment>),
+    Record(HashMap<String, TypeAssignment>, Option<String>),
+    Variant(HashMap<String, TypeAssignment>, Option<String>),
+    Var(String),
+}
+
+impl Display for TypeAssignment {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            TypeAssignment::Single(Type::Bool) => write!(f, "Bool"),
+            TypeAssignment::Single(Type::Nat) => write!(f, "Nat"),
+            TypeAssignment::Arrow(from, to) => write!(f, "({} -> {})", from, to),
+            TypeAssignment::Record(fields, row) => write!(f, "{{{}}}", RowDisplay(fields, row)),
+            TypeAssignment::Variant(tags, row) => write!(f, "<{}>", RowDisplay(tags, row)),
+            TypeAssignment::Var(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Helper to print the elements of a record or variant type, followed by its row variable
+struct RowDisplay<'t>(&'t HashMap<String, TypeAssignment>, &'t Option<String>);
+
+impl<'t> Display for RowDisplay<'t> {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        let mut names: Vec<&String> = self.0.keys().collect();
+        names.sort();
+        let list: Vec<String> = names
+            .iter()
+            .map(|name| format!("{}:{}", name, self.0[*name]))
+            .collect();
+        match (list.is_empty(), self.1) {
+            (_, None) => write!(f, "{}", list.join(", ")),
+            (true, Some(row)) => write!(f, "| {}", row),
+            (false, Some(row)) => write!(f, "{} | {}", list.join(", "), row),
+        }
+    }
 }
 
 impl TypeAssignment {
@@ -35,7 +70,7 @@ impl TypeAssignment {
     /// * `data_type` - type associated with the identifier
     pub fn has_variant(&self, ident: &str, data_type: TypeAssignment) -> bool {
         match self {
-            TypeAssignment::Variant(variants) => {
+            TypeAssignment::Variant(variants, _) => {
                 if let Some(value) = variants.get(ident) {
                     value == &data_type
                 } else {
@@ -47,6 +82,45 @@ impl TypeAssignment {
     }
 }
 
+/// Patterns that can appear in the arms of a case expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    Wildcard,
+    Variable(String),
+    Value(Value),
+    Succ(Box<Pattern>),
+    Tag(String, Box<Pattern>),
+    Record(HashMap<String, Pattern>),
+}
+
+impl Display for Pattern {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            Pattern::Wildcard => write!(f, "_"),
+            Pattern::Variable(name) => write!(f, "{}", name),
+            Pattern::Value(Value::True) => write!(f, "true"),
+            Pattern::Value(Value::False) => write!(f, "false"),
+            Pattern::Value(Value::Zero) => write!(f, "0"),
+            Pattern::Succ(inner) => match **inner {
+                Pattern::Succ(_) | Pattern::Tag(_, _) | Pattern::Record(_) => {
+                    write!(f, "succ ({})", inner)
+                }
+                _ => write!(f, "succ {}", inner),
+            },
+            Pattern::Tag(ident, inner) => write!(f, "<{}={}>", ident, inner),
+            Pattern::Record(fields) => {
+                let mut names: Vec<&String> = fields.keys().collect();
+                names.sort();
+                let list: Vec<String> = names
+                    .iter()
+                    .map(|name| format!("{}={}", name, fields[*name]))
+                    .collect();
+                write!(f, "{{{}}}", list.join(", "))
+            }
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Type {
     Bool,
@@ -59,7 +133,7 @@ pub enum ASTNode<'a> {
     AbstractionNode {
         meta: Span<'a>,
         ident: String,
-        data_type: TypeAssignment,
+        data_type: Option<TypeAssignment>,
         body: Box<ASTNode<'a>>,
     },
     ApplicationNode {
@@ -99,10 +173,25 @@ pub enum ASTNode<'a> {
         meta: Span<'a>,
         records: HashMap<String, ASTNode<'a>>,
     },
+    UpdateNode {
+        meta: Span<'a>,
+        target: Box<ASTNode<'a>>,
+        records: HashMap<String, ASTNode<'a>>,
+    },
+    ExtensionNode {
+        meta: Span<'a>,
+        target: Box<ASTNode<'a>>,
+        records: HashMap<String, ASTNode<'a>>,
+    },
+    RestrictionNode {
+        meta: Span<'a>,
+        target: Box<ASTNode<'a>>,
+        attrib: String,
+    },
     MatchingNode {
         meta: Span<'a>,
         to_match: Box<ASTNode<'a>>,
-        cases: HashMap<String, (String, Box<ASTNode<'a>>)>,
+        cases: Vec<(Pattern, Box<ASTNode<'a>>)>,
     },
     TaggingNode {
         meta: Span<'a>,
@@ -114,6 +203,17 @@ pub enum ASTNode<'a> {
         meta: Span<'a>,
         point: Box<ASTNode<'a>>,
     },
+    LetNode {
+        meta: Span<'a>,
+        pattern: Pattern,
+        value: Box<ASTNode<'a>>,
+        body: Box<ASTNode<'a>>,
+    },
+    LetRecNode {
+        meta: Span<'a>,
+        bindings: Vec<(String, Option<TypeAssignment>, ASTNode<'a>)>,
+        body: Box<ASTNode<'a>>,
+    },
 }
 
 impl<'a> Display for ASTNode<'a> {
@@ -131,13 +231,22 @@ impl<'a> ASTNode<'a> {
                 data_type,
                 body,
             } => {
-                writeln!(
-                    f,
-                    "{}Abstraction with variable {} of type {:?}",
-                    "\t".repeat(level),
-                    ident,
-                    data_type
-                )?;
+                if let Some(data_type) = data_type {
+                    writeln!(
+                        f,
+                        "{}Abstraction with variable {} of type {}",
+                        "\t".repeat(level),
+                        ident,
+                        data_type
+                    )?;
+                } else {
+                    writeln!(
+                        f,
+                        "{}Abstraction with variable {}",
+                        "\t".repeat(level),
+                        ident
+                    )?;
+                }
                 body.print_node(f, level + 1)
             }
             ASTNode::ApplicationNode {
@@ -190,6 +299,40 @@ impl<'a> ASTNode<'a> {
                 }
                 write!(f, "")
             }
+            ASTNode::UpdateNode {
+                meta: _,
+                target,
+                records,
+            } => {
+                writeln!(f, "{}Update with elements:", "\t".repeat(level))?;
+                for (name, assign) in records {
+                    writeln!(f, "{}  {} =", "\t".repeat(level), name)?;
+                    assign.print_node(f, level + 1)?;
+                }
+                writeln!(f, "{}of", "\t".repeat(level))?;
+                target.print_node(f, level + 1)
+            }
+            ASTNode::ExtensionNode {
+                meta: _,
+                target,
+                records,
+            } => {
+                writeln!(f, "{}Extension with elements:", "\t".repeat(level))?;
+                for (name, assign) in records {
+                    writeln!(f, "{}  {} =", "\t".repeat(level), name)?;
+                    assign.print_node(f, level + 1)?;
+                }
+                writeln!(f, "{}of", "\t".repeat(level))?;
+                target.print_node(f, level + 1)
+            }
+            ASTNode::RestrictionNode {
+                meta: _,
+                target,
+                attrib,
+            } => {
+                writeln!(f, "{}Restriction of {} on", "\t".repeat(level), attrib)?;
+                target.print_node(f, level + 1)
+            }
             ASTNode::MatchingNode {
                 meta: _,
                 to_match,
@@ -201,8 +344,8 @@ impl<'a> ASTNode<'a> {
                     "\t".repeat(level),
                     to_match
                 )?;
-                for (variant, (ident, arm)) in cases {
-                    writeln!(f, "{}  {}={} => ", "\t".repeat(level), variant, ident)?;
+                for (pattern, arm) in cases {
+                    writeln!(f, "{}  {} => ", "\t".repeat(level), pattern)?;
                     arm.print_node(f, level + 1)?;
                 }
                 write!(f, "")
@@ -226,10 +369,405 @@ impl<'a> ASTNode<'a> {
                 writeln!(f, "{}Fixpoint", "\t".repeat(level))?;
                 point.print_node(f, level + 1)
             }
+            ASTNode::LetNode {
+                meta: _,
+                pattern,
+                value,
+                body,
+            } => {
+                writeln!(f, "{}Let binding of {} to", "\t".repeat(level), pattern)?;
+                value.print_node(f, level + 1)?;
+                writeln!(f, "{}in", "\t".repeat(level))?;
+                body.print_node(f, level + 1)
+            }
+            ASTNode::LetRecNode {
+                meta: _,
+                bindings,
+                body,
+            } => {
+                writeln!(f, "{}Recursive let binding of", "\t".repeat(level))?;
+                for (name, data_type, value) in bindings {
+                    if let Some(data_type) = data_type {
+                        writeln!(
+                            f,
+                            "{}  {} of type {} =",
+                            "\t".repeat(level),
+                            name,
+                            data_type
+                        )?;
+                    } else {
+                        writeln!(f, "{}  {} =", "\t".repeat(level), name)?;
+                    }
+                    value.print_node(f, level + 1)?;
+                }
+                writeln!(f, "{}in", "\t".repeat(level))?;
+                body.print_node(f, level + 1)
+            }
+        }
+    }
+
+    /// Returns the part of the source text the node was built from
+    pub fn span(&self) -> Span<'a> {
+        let meta = match self {
+            ASTNode::AbstractionNode { meta, .. }
+            | ASTNode::ApplicationNode { meta, .. }
+            | ASTNode::IdentifierNode { meta, .. }
+            | ASTNode::ConditionNode { meta, .. }
+            | ASTNode::ArithmeticNode { meta, .. }
+            | ASTNode::IsZeroNode { meta, .. }
+            | ASTNode::ValueNode { meta, .. }
+            | ASTNode::ProjectionNode { meta, .. }
+            | ASTNode::RecordNode { meta, .. }
+            | ASTNode::UpdateNode { meta, .. }
+            | ASTNode::ExtensionNode { meta, .. }
+            | ASTNode::RestrictionNode { meta, .. }
+            | ASTNode::MatchingNode { meta, .. }
+            | ASTNode::TaggingNode { meta, .. }
+            | ASTNode::FixNode { meta, .. }
+            | ASTNode::LetNode { meta, .. }
+            | ASTNode::LetRecNode { meta, .. } => meta,
+        };
+        meta.clone()
+    }
+
+    /// Returns whether an identifier with the given name occurs anywhere in the tree
+    ///
+    /// # Arguments
+    /// * `name` - the name of the identifier
+    pub fn mentions(&self, name: &str) -> bool {
+        match self {
+            ASTNode::IdentifierNode {
+                meta: _,
+                name: other,
+            } => other == name,
+            ASTNode::ValueNode { .. } => false,
+            ASTNode::AbstractionNode { body, .. } => body.mentions(name),
+            ASTNode::ApplicationNode { left, right, .. } => {
+                left.mentions(name) || right.mentions(name)
+            }
+            ASTNode::ConditionNode {
+                clause,
+                then_arm,
+                else_arm,
+                ..
+            } => clause.mentions(name) || then_arm.mentions(name) || else_arm.mentions(name),
+            ASTNode::ArithmeticNode { expr, .. } => expr.mentions(name),
+            ASTNode::IsZeroNode { expr, .. } => expr.mentions(name),
+            ASTNode::ProjectionNode { target, .. } => target.mentions(name),
+            ASTNode::RecordNode { records, .. } => records.values().any(|n| n.mentions(name)),
+            ASTNode::UpdateNode {
+                target, records, ..
+            }
+            | ASTNode::ExtensionNode {
+                target, records, ..
+            } => target.mentions(name) || records.values().any(|n| n.mentions(name)),
+            ASTNode::RestrictionNode { target, .. } => target.mentions(name),
+            ASTNode::MatchingNode {
+                to_match, cases, ..
+            } => to_match.mentions(name) || cases.iter().any(|(_, arm)| arm.mentions(name)),
+            ASTNode::TaggingNode { value, .. } => value.mentions(name),
+            ASTNode::FixNode { point, .. } => point.mentions(name),
+            ASTNode::LetNode { value, body, .. } => value.mentions(name) || body.mentions(name),
+            ASTNode::LetRecNode { bindings, body, .. } => {
+                bindings.iter().any(|(_, _, value)| value.mentions(name)) || body.mentions(name)
+            }
+        }
+    }
+}
+
+/// Helper to print a tree as source code that parses back to the same tree.
+///
+/// The grammar reads an opening parenthesis followed by an abstraction as part of the abstraction,
+/// so an application in parentheses that starts with an abstraction does not parse back. Such
+/// redexes never occur in normalized terms, see `nbe::normalize`.
+pub struct Source<'n, 'a: 'n>(pub &'n ASTNode<'a>);
+
+impl<'n, 'a> Display for Source<'n, 'a> {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        self.0.write_source(f)
+    }
+}
+
+impl<'a> ASTNode<'a> {
+    /// Returns a value that displays the tree as source code
+    pub fn source(&self) -> Source<'_, 'a> {
+        Source(self)
+    }
+
+    fn write_source(&self, f: &mut Formatter) -> Result {
+        match self {
+            ASTNode::AbstractionNode {
+                meta: _,
+                ident,
+                data_type,
+                body,
+            } => {
+                write!(f, "(@ {}", ident)?;
+                if let Some(data_type) = data_type {
+                    write!(f, ": {}", data_type)?;
+                }
+                write!(f, ". ")?;
+                body.write_source(f)?;
+                write!(f, ")")
+            }
+            ASTNode::ApplicationNode {
+                meta: _,
+                left,
+                right,
+            } => {
+                left.write_operand(f)?;
+                write!(f, " ")?;
+                right.write_operand(f)
+            }
+            ASTNode::IdentifierNode { meta: _, name } => write!(f, "{}", name),
+            ASTNode::ConditionNode {
+                meta: _,
+                clause,
+                then_arm,
+                else_arm,
+            } => {
+                write!(f, "if ")?;
+                clause.write_source(f)?;
+                write!(f, " then ")?;
+                then_arm.write_source(f)?;
+                write!(f, " else ")?;
+                else_arm.write_source(f)
+            }
+            ASTNode::ArithmeticNode { meta: _, op, expr } => {
+                match op {
+                    Operator::Succ => write!(f, "succ ")?,
+                    Operator::Pred => write!(f, "pred ")?,
+                }
+                expr.write_argument(f)
+            }
+            ASTNode::IsZeroNode { meta: _, expr } => {
+                write!(f, "iszero ")?;
+                expr.write_argument(f)
+            }
+            ASTNode::ValueNode { meta: _, value } => match value {
+                Value::True => write!(f, "true"),
+                Value::False => write!(f, "false"),
+                Value::Zero => write!(f, "0"),
+            },
+            ASTNode::ProjectionNode {
+                meta: _,
+                target,
+                attrib,
+            } => {
+                target.write_target(f)?;
+                write!(f, ".{}", attrib)
+            }
+            ASTNode::RestrictionNode {
+                meta: _,
+                target,
+                attrib,
+            } => {
+                target.write_target(f)?;
+                write!(f, "\\{}", attrib)
+            }
+            ASTNode::RecordNode { meta: _, records } => {
+                write!(f, "{{")?;
+                write_fields(f, records, false)?;
+                write!(f, "}}")
+            }
+            ASTNode::UpdateNode {
+                meta: _,
+                target,
+                records,
+            } => {
+                write!(f, "{{")?;
+                target.write_source(f)?;
+                write!(f, " with ")?;
+                write_fields(f, records, false)?;
+                write!(f, "}}")
+            }
+            ASTNode::ExtensionNode {
+                meta: _,
+                target,
+                records,
+            } => {
+                write!(f, "{{")?;
+                write_fields(f, records, true)?;
+                write!(f, " | ")?;
+                target.write_source(f)?;
+                write!(f, "}}")
+            }
+            ASTNode::MatchingNode {
+                meta: _,
+                to_match,
+                cases,
+            } => {
+                write!(f, "case ")?;
+                to_match.write_source(f)?;
+                write!(f, " of ")?;
+                for (index, (pattern, arm)) in cases.iter().enumerate() {
+                    write!(f, "{} => ", pattern)?;
+                    if index + 1 < cases.len() {
+                        arm.write_before_bar(f)?;
+                        write!(f, " | ")?;
+                    } else {
+                        arm.write_source(f)?;
+                    }
+                }
+                Ok(())
+            }
+            ASTNode::TaggingNode {
+                meta: _,
+                ident,
+                value,
+                data_type,
+            } => {
+                write!(f, "<{}=", ident)?;
+                value.write_source(f)?;
+                write!(f, "> as {}", data_type)
+            }
+            ASTNode::FixNode { meta: _, point } => {
+                write!(f, "fix |")?;
+                point.write_before_bar(f)?;
+                write!(f, "|")
+            }
+            ASTNode::LetNode {
+                meta: _,
+                pattern,
+                value,
+                body,
+            } => {
+                write!(f, "let {} = ", pattern)?;
+                value.write_source(f)?;
+                write!(f, " in ")?;
+                body.write_source(f)
+            }
+            ASTNode::LetRecNode {
+                meta: _,
+                bindings,
+                body,
+            } => {
+                write!(f, "letrec ")?;
+                for (index, (name, data_type, value)) in bindings.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, " and ")?;
+                    }
+                    write!(f, "{}", name)?;
+                    if let Some(data_type) = data_type {
+                        write!(f, ": {}", data_type)?;
+                    }
+                    write!(f, " = ")?;
+                    value.write_source(f)?;
+                }
+                write!(f, " in ")?;
+                body.write_source(f)
+            }
+        }
+    }
+
+    /// Writes the node as one side of an application, in parentheses unless it is a single term
+    fn write_operand(&self, f: &mut Formatter) -> Result {
+        match self {
+            ASTNode::ApplicationNode { .. }
+            | ASTNode::ArithmeticNode { .. }
+            | ASTNode::IsZeroNode { .. }
+            | ASTNode::ConditionNode { .. }
+            | ASTNode::MatchingNode { .. }
+            | ASTNode::LetNode { .. }
+            | ASTNode::LetRecNode { .. } => {
+                write!(f, "(")?;
+                self.write_source(f)?;
+                write!(f, ")")
+            }
+            _ => self.write_source(f),
+        }
+    }
+
+    /// Writes the argument of an arithmetic operation or zero check, which only needs parentheses
+    /// for readability when it is an application
+    fn write_argument(&self, f: &mut Formatter) -> Result {
+        match self {
+            ASTNode::ArithmeticNode { .. } | ASTNode::IsZeroNode { .. } => self.write_source(f),
+            _ => self.write_operand(f),
+        }
+    }
+
+    /// Writes the target of a projection or restriction, which has to be a variable or a term in
+    /// parentheses. A record literal is put in parentheses too, because without them it is read
+    /// as a term of its own that the projection can not follow.
+    fn write_target(&self, f: &mut Formatter) -> Result {
+        match self {
+            ASTNode::IdentifierNode { .. } => self.write_source(f),
+            _ => {
+                write!(f, "(")?;
+                self.write_source(f)?;
+                write!(f, ")")
+            }
+        }
+    }
+
+    /// Writes a node that is followed by a bar, in parentheses when it ends in a case expression
+    /// that would otherwise take the bar as the start of another arm
+    fn write_before_bar(&self, f: &mut Formatter) -> Result {
+        if self.ends_in_case() {
+            write!(f, "(")?;
+            self.write_source(f)?;
+            write!(f, ")")
+        } else {
+            self.write_source(f)
+        }
+    }
+
+    fn ends_in_case(&self) -> bool {
+        match self {
+            ASTNode::MatchingNode { .. } => true,
+            ASTNode::ConditionNode { else_arm: last, .. }
+            | ASTNode::ArithmeticNode { expr: last, .. }
+            | ASTNode::IsZeroNode { expr: last, .. }
+            | ASTNode::LetNode { body: last, .. }
+            | ASTNode::LetRecNode { body: last, .. } => last.ends_in_case(),
+            _ => false,
         }
     }
 }
 
+/// Writes the elements of a record in alphabetical order
+///
+/// # Arguments
+/// * `f` - the formatter to write to
+/// * `records` - the elements of the record
+/// * `before_bar` - whether the last element is followed by a bar
+fn write_fields(
+    f: &mut Formatter,
+    records: &HashMap<String, ASTNode<'_>>,
+    before_bar: bool,
+) -> Result {
+    let mut names: Vec<&String> = records.keys().collect();
+    names.sort();
+    for (index, name) in names.iter().enumerate() {
+        if index > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}=", name)?;
+        if before_bar {
+            records[*name].write_before_bar(f)?;
+        } else {
+            records[*name].write_source(f)?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the span of the node built from a rule, without the whitespace that follows it. Errors
+/// of pest can not point at spans ending at the start of a line.
+///
+/// # Arguments
+/// * `pair` - the rule the node is built from
+fn node_span(pair: Pair<'_, Rule>) -> Span<'_> {
+    let span = pair.into_span();
+    let start = span.start_pos();
+    let end = start
+        .clone()
+        .skip(span.as_str().trim_end().chars().count())
+        .expect("Bug in parser: span is longer than the source text");
+    start.span(&end)
+}
+
 /// Builds an abstract syntax tree from the raw parser output
 ///
 /// # Arguments
@@ -238,7 +776,7 @@ impl<'a> ASTNode<'a> {
 /// # Panics
 /// Throws a panic when encountering an incorrect parsing structure, this indicates a problem
 /// in the parser or syntax definition.
-pub fn build_ast(mut parsed: Pairs<Rule>) -> ASTNode {
+pub fn build_ast(mut parsed: Pairs<'_, Rule>) -> ASTNode<'_> {
     let first = parsed.next().expect("Empty program");
     build_node(first)
 }
@@ -247,7 +785,7 @@ pub fn build_ast(mut parsed: Pairs<Rule>) -> ASTNode {
 ///
 /// # Arguments
 /// * `pair` - the part of parsed output being processed
-fn build_node(pair: Pair<'_, Rule>) -> ASTNode {
+fn build_node(pair: Pair<'_, Rule>) -> ASTNode<'_> {
     let rule = pair.as_rule();
     match rule {
         Rule::program => build_program(pair),
@@ -259,23 +797,29 @@ fn build_node(pair: Pair<'_, Rule>) -> ASTNode {
         Rule::zero_check => build_zero_check(pair),
         Rule::if_then => build_if_then(pair),
         Rule::projection => build_projection(pair),
+        Rule::projection_target => build_projection_target(pair),
         Rule::record => build_record(pair),
+        Rule::update => build_update(pair),
+        Rule::extension => build_extension(pair),
+        Rule::restriction => build_restriction(pair),
         Rule::matching => build_matching(pair),
         Rule::tagging => build_tagging(pair),
         Rule::fixpoint => build_fixpoint(pair),
+        Rule::let_in => build_let(pair),
+        Rule::letrec => build_letrec(pair),
         Rule::val_zero => ASTNode::ValueNode {
-            meta: pair.into_span(),
+            meta: node_span(pair),
             value: Value::Zero,
         },
         Rule::val_true => ASTNode::ValueNode {
-            meta: pair.into_span(),
+            meta: node_span(pair),
             value: Value::True,
         },
         Rule::val_false => ASTNode::ValueNode {
-            meta: pair.into_span(),
+            meta: node_span(pair),
             value: Value::False,
         },
-        _ => panic!(format!("Building of {:?} not implemented", rule)),
+        _ => panic!("Building of {:?} not implemented", rule),
     }
 }
 
@@ -283,7 +827,7 @@ fn build_node(pair: Pair<'_, Rule>) -> ASTNode {
 ///
 /// # Arguments
 /// * `pair` - the current rule that is being built
-fn build_program(pair: Pair<'_, Rule>) -> ASTNode {
+fn build_program(pair: Pair<'_, Rule>) -> ASTNode<'_> {
     let mut inner: Pairs<'_, Rule> = pair.into_inner();
     build_node(inner.next().expect("Bug in parser: got empty program"))
 }
@@ -292,7 +836,7 @@ fn build_program(pair: Pair<'_, Rule>) -> ASTNode {
 ///
 /// # Arguments
 /// * `pair` - the current rule that is being built
-fn build_application(pair: Pair<'_, Rule>) -> ASTNode {
+fn build_application(pair: Pair<'_, Rule>) -> ASTNode<'_> {
     let mut inner: Pairs<'_, Rule> = pair.clone().into_inner();
 
     let first = build_node(
@@ -303,7 +847,7 @@ fn build_application(pair: Pair<'_, Rule>) -> ASTNode {
 
     if let Some(second) = inner.next() {
         ASTNode::ApplicationNode {
-            meta: pair.into_span(),
+            meta: node_span(pair),
             left: Box::new(first),
             right: Box::new(build_node(second)),
         }
@@ -316,26 +860,71 @@ fn build_application(pair: Pair<'_, Rule>) -> ASTNode {
 ///
 /// # Arguments
 /// * `pair` - the current rule that is being built
-fn build_abstraction(pair: Pair<'_, Rule>) -> ASTNode {
+fn build_abstraction(pair: Pair<'_, Rule>) -> ASTNode<'_> {
     let mut inner: Pairs<'_, Rule> = pair.clone().into_inner();
 
-    let (ident, data_type) = build_type_term(
-        inner
-            .next()
-            .expect("Bug in parser: found an abstraction with incorrect number of arguments"),
-    );
+    let binder = inner
+        .next()
+        .expect("Bug in parser: found an abstraction with incorrect number of arguments");
     let body = Box::new(build_node(inner.next().expect(
         "Bug in parser: found an abstraction with incorrect number of arguments",
     )));
 
+    let (ident, data_type) = match binder.as_rule() {
+        Rule::pattern_term => return build_pattern_abstraction(pair, binder, body),
+        Rule::p_ident => (binder.into_span().as_str().to_string(), None),
+        _ => {
+            let (ident, data_type) = build_type_term(binder);
+            (ident, Some(data_type))
+        }
+    };
+
     ASTNode::AbstractionNode {
-        meta: pair.into_span(),
+        meta: node_span(pair),
         ident,
         data_type,
         body,
     }
 }
 
+/// Logic to handle an abstraction that destructures its argument with a pattern. The abstraction
+/// is rewritten to an abstraction over a fresh variable whose body binds the pattern with a let.
+///
+/// # Arguments
+/// * `pair` - the abstraction that is being built
+/// * `binder` - the pattern_term of the abstraction
+/// * `body` - the already built body of the abstraction
+fn build_pattern_abstraction<'a>(
+    pair: Pair<'a, Rule>,
+    binder: Pair<'a, Rule>,
+    body: Box<ASTNode<'a>>,
+) -> ASTNode<'a> {
+    let mut inner: Pairs<'_, Rule> = binder.into_inner();
+    let pattern = build_pattern(
+        inner
+            .next()
+            .expect("Bug in parser: found a pattern term with incorrect number of arguments"),
+    );
+    let data_type = inner.next().map(build_type);
+
+    let mut ident = "arg".to_string();
+    while body.mentions(&ident) {
+        ident.push('_');
+    }
+    let meta = node_span(pair);
+    ASTNode::AbstractionNode {
+        meta: meta.clone(),
+        ident: ident.clone(),
+        data_type,
+        body: Box::new(ASTNode::LetNode {
+            meta: meta.clone(),
+            pattern,
+            value: Box::new(ASTNode::IdentifierNode { meta, name: ident }),
+            body,
+        }),
+    }
+}
+
 /// Logic to parse a type_term from the parser output
 ///
 /// # Arguments
@@ -382,7 +971,7 @@ fn build_type(pair: Pair<'_, Rule>) -> TypeAssignment {
                 let (ident, data_type) = build_type_term(el);
                 map.insert(ident, data_type);
             }
-            TypeAssignment::Record(map)
+            TypeAssignment::Record(map, None)
         }
         Rule::type_variant => {
             let mut map = HashMap::new();
@@ -390,9 +979,9 @@ fn build_type(pair: Pair<'_, Rule>) -> TypeAssignment {
                 let (ident, data_type) = build_type_term(el);
                 map.insert(ident, data_type);
             }
-            TypeAssignment::Variant(map)
+            TypeAssignment::Variant(map, None)
         }
-        _ => panic!(format!("Incorrect type {:?}", pair)),
+        _ => panic!("Incorrect type {:?}", pair),
     }
 }
 
@@ -400,13 +989,13 @@ fn build_type(pair: Pair<'_, Rule>) -> TypeAssignment {
 ///
 /// # Arguments
 /// * `pair` - the current rule that is being built
-fn build_ident(pair: Pair<'_, Rule>) -> ASTNode {
+fn build_ident(pair: Pair<'_, Rule>) -> ASTNode<'_> {
     let span = pair.clone().into_span();
     let mut string = span.as_str().to_string();
     // hack because of bug in parser
     string = string.chars().filter(|chr| chr != &' ').collect();
     ASTNode::IdentifierNode {
-        meta: pair.into_span(),
+        meta: node_span(pair),
         name: string,
     }
 }
@@ -415,7 +1004,7 @@ fn build_ident(pair: Pair<'_, Rule>) -> ASTNode {
 ///
 /// # Arguments
 /// * `pair` - the current rule that is being built
-fn build_arithmetic(pair: Pair<'_, Rule>) -> ASTNode {
+fn build_arithmetic(pair: Pair<'_, Rule>) -> ASTNode<'_> {
     let mut inner: Pairs<'_, Rule> = pair.clone().into_inner();
 
     let op = match inner
@@ -425,10 +1014,10 @@ fn build_arithmetic(pair: Pair<'_, Rule>) -> ASTNode {
     {
         Rule::op_succ => Operator::Succ,
         Rule::op_pred => Operator::Pred,
-        _ => panic!(format!("Incorrect operator")),
+        _ => panic!("Incorrect operator"),
     };
     ASTNode::ArithmeticNode {
-        meta: pair.into_span(),
+        meta: node_span(pair),
         op,
         expr: Box::new(build_node(inner.next().expect(
             "Bug in parser: found an arithmetic expression with incorrect number of arguments",
@@ -440,10 +1029,10 @@ fn build_arithmetic(pair: Pair<'_, Rule>) -> ASTNode {
 ///
 /// # Arguments
 /// * `pair` - the current rule that is being built
-fn build_zero_check(pair: Pair<'_, Rule>) -> ASTNode {
+fn build_zero_check(pair: Pair<'_, Rule>) -> ASTNode<'_> {
     let mut inner: Pairs<'_, Rule> = pair.clone().into_inner();
     ASTNode::IsZeroNode {
-        meta: pair.into_span(),
+        meta: node_span(pair),
         expr: Box::new(build_node(inner.next().expect(
             "Bug in parser: found an iszero check with incorrect number of arguments",
         ))),
@@ -454,10 +1043,10 @@ fn build_zero_check(pair: Pair<'_, Rule>) -> ASTNode {
 ///
 /// # Arguments
 /// * `pair` - the current rule that is being built
-fn build_if_then(pair: Pair<'_, Rule>) -> ASTNode {
+fn build_if_then(pair: Pair<'_, Rule>) -> ASTNode<'_> {
     let mut inner = pair.clone().into_inner().map(|el| Box::new(build_node(el)));
     ASTNode::ConditionNode {
-        meta: pair.into_span(),
+        meta: node_span(pair),
         clause: inner
             .next()
             .expect("Bug in parser: found an ifthenelse with incorrect number of arguments"),
@@ -474,7 +1063,7 @@ fn build_if_then(pair: Pair<'_, Rule>) -> ASTNode {
 ///
 /// # Arguments
 /// * `pair` - the current rule that is being built
-fn build_projection(pair: Pair<'_, Rule>) -> ASTNode {
+fn build_projection(pair: Pair<'_, Rule>) -> ASTNode<'_> {
     let mut parts: Pairs<'_, Rule> = pair.clone().into_inner();
 
     let target = build_node(
@@ -489,43 +1078,137 @@ fn build_projection(pair: Pair<'_, Rule>) -> ASTNode {
         .as_str()
         .to_string();
     ASTNode::ProjectionNode {
-        meta: pair.into_span(),
+        meta: node_span(pair),
         target: Box::new(target),
         attrib,
     }
 }
 
+/// Logic to handle the target of a projection or restriction, which is parsed with
+/// whitespace allowed inside it while the `.` or `\` must follow it directly
+///
+/// # Arguments
+/// * `pair` - the current rule that is being built
+fn build_projection_target(pair: Pair<'_, Rule>) -> ASTNode<'_> {
+    let mut inner: Pairs<'_, Rule> = pair.into_inner();
+    build_node(
+        inner
+            .next()
+            .expect("Bug in parser: found a projection without a target"),
+    )
+}
+
 /// Logic to handle the record rule of the parser
 ///
 /// # Arguments
 /// * `pair` - the current rule that is being built
-fn build_record(pair: Pair<'_, Rule>) -> ASTNode {
+fn build_record(pair: Pair<'_, Rule>) -> ASTNode<'_> {
     let mut records = HashMap::new();
     for el in pair.clone().into_inner() {
-        let mut parts: Pairs<'_, Rule> = el.into_inner();
-        let name = parts
-            .next()
-            .expect("Bug in parser: found a record element with incorrect number of arguments")
-            .into_span()
-            .as_str()
-            .to_string();
-        let result =
-            build_node(parts.next().expect(
-                "Bug in parser: found a record element with incorrect number of arguments",
-            ));
+        let (name, result) = build_record_el(el);
         records.insert(name, result);
     }
     ASTNode::RecordNode {
-        meta: pair.into_span(),
+        meta: node_span(pair),
         records,
     }
 }
 
+/// Logic to parse a record_el from the parser output
+///
+/// # Arguments
+/// * `pair` - the current rule that is being built
+fn build_record_el(pair: Pair<'_, Rule>) -> (String, ASTNode<'_>) {
+    let mut parts: Pairs<'_, Rule> = pair.into_inner();
+    let name = parts
+        .next()
+        .expect("Bug in parser: found a record element with incorrect number of arguments")
+        .into_span()
+        .as_str()
+        .to_string();
+    let result = build_node(
+        parts
+            .next()
+            .expect("Bug in parser: found a record element with incorrect number of arguments"),
+    );
+    (name, result)
+}
+
+/// Logic to handle the update rule of the parser
+///
+/// # Arguments
+/// * `pair` - the current rule that is being built
+fn build_update(pair: Pair<'_, Rule>) -> ASTNode<'_> {
+    let mut inner: Pairs<'_, Rule> = pair.clone().into_inner();
+    let target = build_node(
+        inner
+            .next()
+            .expect("Bug in parser: found an update without target"),
+    );
+    let mut records = HashMap::new();
+    for el in inner {
+        let (name, result) = build_record_el(el);
+        records.insert(name, result);
+    }
+    ASTNode::UpdateNode {
+        meta: node_span(pair),
+        target: Box::new(target),
+        records,
+    }
+}
+
+/// Logic to handle the extension rule of the parser
+///
+/// # Arguments
+/// * `pair` - the current rule that is being built
+fn build_extension(pair: Pair<'_, Rule>) -> ASTNode<'_> {
+    let mut records = HashMap::new();
+    let mut target = None;
+    for el in pair.clone().into_inner() {
+        if el.as_rule() == Rule::record_el {
+            let (name, result) = build_record_el(el);
+            records.insert(name, result);
+        } else {
+            target = Some(build_node(el));
+        }
+    }
+    ASTNode::ExtensionNode {
+        meta: node_span(pair),
+        target: Box::new(target.expect("Bug in parser: found an extension without target")),
+        records,
+    }
+}
+
+/// Logic to handle the restriction rule of the parser
+///
+/// # Arguments
+/// * `pair` - the current rule that is being built
+fn build_restriction(pair: Pair<'_, Rule>) -> ASTNode<'_> {
+    let mut parts: Pairs<'_, Rule> = pair.clone().into_inner();
+
+    let target = build_node(
+        parts
+            .next()
+            .expect("Bug in parser: found a restriction with incorrect number of arguments"),
+    );
+    let attrib = parts
+        .next()
+        .expect("Bug in parser: found a restriction with incorrect number of arguments")
+        .into_span()
+        .as_str()
+        .to_string();
+    ASTNode::RestrictionNode {
+        meta: node_span(pair),
+        target: Box::new(target),
+        attrib,
+    }
+}
+
 /// Logic to handle the matching rule of the parser
 ///
 /// # Arguments
 /// * `pair` - the current rule that is being built
-fn build_matching(pair: Pair<'_, Rule>) -> ASTNode {
+fn build_matching(pair: Pair<'_, Rule>) -> ASTNode<'_> {
     let mut inner: Pairs<'_, Rule> = pair.clone().into_inner();
 
     let to_match = build_node(
@@ -533,39 +1216,90 @@ fn build_matching(pair: Pair<'_, Rule>) -> ASTNode {
             .next()
             .expect("Bug in parser: got a matching expression without arguments"),
     );
-    let mut cases = HashMap::new();
+    let mut cases = Vec::new();
     for case_el in inner {
         let mut inner: Pairs<'_, Rule> = case_el.into_inner();
 
-        let variant = inner
-            .next()
-            .expect("Bug in parser: got a case expression with incorrect number of arguments")
-            .into_span()
-            .as_str()
-            .to_string();
-        let ident = inner
-            .next()
-            .expect("Bug in parser: got a case expression with incorrect number of arguments")
-            .into_span()
-            .as_str()
-            .to_string();
+        let pattern = build_pattern(
+            inner
+                .next()
+                .expect("Bug in parser: got a case expression with incorrect number of arguments"),
+        );
         let arm = Box::new(build_node(inner.next().expect(
             "Bug in parser: got a case expression with incorrect number of arguments",
         )));
-        cases.insert(variant, (ident, arm));
+        cases.push((pattern, arm));
     }
     ASTNode::MatchingNode {
-        meta: pair.into_span(),
+        meta: node_span(pair),
         to_match: Box::new(to_match),
         cases,
     }
 }
 
+/// Logic to parse a pattern from the parser output
+///
+/// # Arguments
+/// * `pair` - the current rule that is being built
+fn build_pattern(pair: Pair<'_, Rule>) -> Pattern {
+    match pair.as_rule() {
+        Rule::pat_wildcard => Pattern::Wildcard,
+        Rule::ident => Pattern::Variable(pair.into_span().as_str().to_string()),
+        Rule::val_zero => Pattern::Value(Value::Zero),
+        Rule::val_true => Pattern::Value(Value::True),
+        Rule::val_false => Pattern::Value(Value::False),
+        Rule::pat_succ => {
+            let mut inner: Pairs<'_, Rule> = pair.into_inner();
+            inner
+                .next()
+                .expect("Bug in parser: got a succ pattern without operator");
+            Pattern::Succ(Box::new(build_pattern(
+                inner
+                    .next()
+                    .expect("Bug in parser: got a succ pattern without argument"),
+            )))
+        }
+        Rule::pat_tag => {
+            let mut inner: Pairs<'_, Rule> = pair.into_inner();
+            let ident = inner
+                .next()
+                .expect("Bug in parser: got a tag pattern with incorrect number of arguments")
+                .into_span()
+                .as_str()
+                .to_string();
+            let pattern = build_pattern(
+                inner
+                    .next()
+                    .expect("Bug in parser: got a tag pattern with incorrect number of arguments"),
+            );
+            Pattern::Tag(ident, Box::new(pattern))
+        }
+        Rule::pat_record => {
+            let mut fields = HashMap::new();
+            for el in pair.into_inner() {
+                let mut parts: Pairs<'_, Rule> = el.into_inner();
+                let name = parts
+                    .next()
+                    .expect("Bug in parser: got a record pattern element with incorrect number of arguments")
+                    .into_span()
+                    .as_str()
+                    .to_string();
+                let pattern = build_pattern(parts.next().expect(
+                    "Bug in parser: got a record pattern element with incorrect number of arguments",
+                ));
+                fields.insert(name, pattern);
+            }
+            Pattern::Record(fields)
+        }
+        _ => panic!("Incorrect pattern {:?}", pair),
+    }
+}
+
 /// Logic to handle the tagging rule of the parser
 ///
 /// # Arguments
 /// * `pair` - the current rule that is being built
-fn build_tagging(pair: Pair<'_, Rule>) -> ASTNode {
+fn build_tagging(pair: Pair<'_, Rule>) -> ASTNode<'_> {
     let mut inner: Pairs<'_, Rule> = pair.clone().into_inner();
     let ident = inner
         .next()
@@ -585,7 +1319,7 @@ fn build_tagging(pair: Pair<'_, Rule>) -> ASTNode {
     );
 
     ASTNode::TaggingNode {
-        meta: pair.into_span(),
+        meta: node_span(pair),
         ident,
         value: Box::new(value),
         data_type,
@@ -596,7 +1330,7 @@ fn build_tagging(pair: Pair<'_, Rule>) -> ASTNode {
 ///
 /// # Arguments
 /// * `pair` - the current rule that is being built
-fn build_fixpoint(pair: Pair<'_, Rule>) -> ASTNode {
+fn build_fixpoint(pair: Pair<'_, Rule>) -> ASTNode<'_> {
     let mut inner: Pairs<'_, Rule> = pair.clone().into_inner();
     let point = build_node(
         inner
@@ -604,7 +1338,74 @@ fn build_fixpoint(pair: Pair<'_, Rule>) -> ASTNode {
             .expect("Bug in parser: got a fix without argument"),
     );
     ASTNode::FixNode {
-        meta: pair.into_span(),
+        meta: node_span(pair),
         point: Box::new(point),
     }
 }
+
+/// Logic to handle the let_in rule of the parser
+///
+/// # Arguments
+/// * `pair` - the current rule that is being built
+fn build_let(pair: Pair<'_, Rule>) -> ASTNode<'_> {
+    let mut inner: Pairs<'_, Rule> = pair.clone().into_inner();
+    let pattern = build_pattern(
+        inner
+            .next()
+            .expect("Bug in parser: got a let expression with incorrect number of arguments"),
+    );
+    let value = build_node(
+        inner
+            .next()
+            .expect("Bug in parser: got a let expression with incorrect number of arguments"),
+    );
+    let body = build_node(
+        inner
+            .next()
+            .expect("Bug in parser: got a let expression with incorrect number of arguments"),
+    );
+    ASTNode::LetNode {
+        meta: node_span(pair),
+        pattern,
+        value: Box::new(value),
+        body: Box::new(body),
+    }
+}
+
+/// Logic to handle the letrec rule of the parser
+///
+/// # Arguments
+/// * `pair` - the current rule that is being built
+fn build_letrec(pair: Pair<'_, Rule>) -> ASTNode<'_> {
+    let mut bindings = Vec::new();
+    let mut body = None;
+    for el in pair.clone().into_inner() {
+        if el.as_rule() != Rule::rec_binding {
+            body = Some(build_node(el));
+            continue;
+        }
+        let mut parts: Vec<Pair<'_, Rule>> = el.into_inner().collect();
+        let value = build_node(
+            parts
+                .pop()
+                .expect("Bug in parser: got a recursive binding without value"),
+        );
+        let data_type = if parts.len() > 1 {
+            parts.pop().map(build_type)
+        } else {
+            None
+        };
+        let name = parts
+            .pop()
+            .expect("Bug in parser: got a recursive binding without name")
+            .into_span()
+            .as_str()
+            .to_string();
+        bindings.push((name, data_type, value));
+    }
+    ASTNode::LetRecNode {
+        meta: node_span(pair),
+        bindings,
+        body: Box::new(body.expect("Bug in parser: got a letrec expression without body")),
+    }
+}
@@ -0,0 +1,632 @@
+use ast::*;
+use infer::Typing;
+use matching::{self, Access, Constructor, Decision, Path};
+use nameless;
+use std::collections::HashSet;
+
+/// Instructions of the virtual machine. They work on a stack of operands shared by all calls and
+/// on the slots of the frame of the running function, which hold its parameters and the variables
+/// bound inside its body.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Instr {
+    Nat(usize),
+    Bool(bool),
+    /// Pushes the value in a slot of the frame, unfolding it when it is a recursive binding
+    Local(usize),
+    /// Pushes a variable captured by the running function, unfolding it when it is a recursive
+    /// binding
+    Captured(usize),
+    /// Pops a value into a slot of the frame
+    Store(usize),
+    /// Pushes a closure of the code with the given index, capturing the given variables
+    Closure(usize, Vec<Capture>),
+    /// Pushes a value for every member of a recursive group, the members share the captured
+    /// variables and are given the whole group as parameters when they are unfolded
+    Group(Vec<usize>, Vec<Capture>),
+    /// Pops a function and unfolds its fixpoint, calling the function with the fixpoint as argument
+    Fix,
+    /// Pops an argument and a function and calls the function
+    Call,
+    /// Calls like `Call`, but replaces the frame of the running function
+    TailCall,
+    /// Ends the running function, leaving its result on the stack
+    Return,
+    Succ,
+    Pred,
+    IsZero,
+    Jump(usize),
+    /// Pops a boolean and jumps when it is false
+    JumpIfFalse(usize),
+    /// Pops a value and jumps when it is not built by the tested constructor
+    JumpUnless(Test, usize),
+    /// Pops values for the given fields, in the order listed, and pushes a record of them
+    Record(Vec<usize>),
+    /// Pops values for the given fields and a record, and pushes the record with the fields
+    /// replaced or added
+    Update(Vec<usize>),
+    /// Pops values and a record, and pushes the record with the values placed at the given
+    /// offsets among its fields
+    Replace(Vec<usize>),
+    /// Replaces a record by the value of one of its fields, which is looked up by its index
+    Project(usize),
+    /// Replaces a record by the value at the given offset among its fields
+    Field(usize),
+    /// Removes a field from a record
+    Restrict(usize),
+    /// Pops a value and pushes the variant with the tag carrying that value
+    Tag(usize),
+    /// Replaces a variant by the value it carries
+    Untag,
+    /// Stops the machine, no arm of a case matched the value
+    Fail,
+}
+
+/// Location of a variable captured by a closure, in the function that creates the closure
+#[derive(Clone, Debug, PartialEq)]
+pub enum Capture {
+    Local(usize),
+    Captured(usize),
+}
+
+/// Head constructors that values are tested against when matching patterns
+#[derive(Clone, Debug, PartialEq)]
+pub enum Test {
+    True,
+    False,
+    Zero,
+    Succ,
+    Tag(usize),
+}
+
+/// Compiled body of a function
+#[derive(Clone, Debug, PartialEq)]
+pub struct Code {
+    /// Name of the parameter, or of the bindings for members of a recursive group
+    pub name: String,
+    /// Number of parameters, they are placed in the first slots of the frame
+    pub arity: usize,
+    /// Number of slots the frame needs
+    pub slots: usize,
+    pub instrs: Vec<Instr>,
+}
+
+/// A compiled program. Field names and variant tags are referred to by their index in the tables
+/// of the program. A record keeps its fields ordered by their index, so when the type of a record
+/// lists all of its fields, a field is found at a fixed offset. Records of an open type, which
+/// may have more fields than their type lists, are searched for the index of the field instead.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Program {
+    pub codes: Vec<Code>,
+    /// Index of the code that evaluates the whole program
+    pub entry: usize,
+    pub fields: Vec<String>,
+    pub tags: Vec<String>,
+}
+
+/// Where a variable can be found while a function runs
+#[derive(Clone, Copy)]
+enum Var {
+    Local(usize),
+    Captured(usize),
+}
+
+/// State of the compilation of a single function
+struct Function {
+    scope: Vec<(String, Var)>,
+    instrs: Vec<Instr>,
+    next_slot: usize,
+    slots: usize,
+}
+
+impl Function {
+    /// Creates a function whose parameters are placed in the first slots and whose captured
+    /// variables are numbered in the given order
+    fn new(params: &[String], captures: &[String]) -> Function {
+        let mut scope: Vec<(String, Var)> = captures
+            .iter()
+            .enumerate()
+            .map(|(index, name)| (name.to_string(), Var::Captured(index)))
+            .collect();
+        scope.extend(
+            params
+                .iter()
+                .enumerate()
+                .map(|(slot, name)| (name.to_string(), Var::Local(slot))),
+        );
+        Function {
+            scope,
+            instrs: Vec::new(),
+            next_slot: params.len(),
+            slots: params.len(),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Var {
+        self.scope
+            .iter()
+            .rev()
+            .find(|(bound, _)| bound == name)
+            .map(|(_, var)| *var)
+            .expect("Bug in typechecker: came across unknown variable")
+    }
+
+    fn capture(&self, name: &str) -> Capture {
+        match self.lookup(name) {
+            Var::Local(slot) => Capture::Local(slot),
+            Var::Captured(index) => Capture::Captured(index),
+        }
+    }
+
+    /// Binds the name to a fresh slot and returns the slot
+    fn bind(&mut self, name: &str) -> usize {
+        let slot = self.allocate();
+        self.scope.push((name.to_string(), Var::Local(slot)));
+        slot
+    }
+
+    fn allocate(&mut self) -> usize {
+        let slot = self.next_slot;
+        self.next_slot += 1;
+        self.slots = self.slots.max(self.next_slot);
+        slot
+    }
+
+    /// Returns a marker to drop the bindings made after it with `leave`
+    fn enter(&self) -> (usize, usize) {
+        (self.scope.len(), self.next_slot)
+    }
+
+    fn leave(&mut self, (scope, next_slot): (usize, usize)) {
+        self.scope.truncate(scope);
+        self.next_slot = next_slot;
+    }
+
+    fn emit(&mut self, instr: Instr) -> usize {
+        self.instrs.push(instr);
+        self.instrs.len() - 1
+    }
+
+    fn here(&self) -> usize {
+        self.instrs.len()
+    }
+
+    /// Sets the target of the jump at the given position to the next instruction
+    fn patch(&mut self, jump: usize) {
+        let target = self.here();
+        match &mut self.instrs[jump] {
+            Instr::Jump(to) | Instr::JumpIfFalse(to) | Instr::JumpUnless(_, to) => *to = target,
+            _ => panic!("Patched an instruction that is not a jump"),
+        }
+    }
+}
+
+struct Compiler<'t> {
+    typing: Typing<'t>,
+    codes: Vec<Code>,
+    fields: Vec<String>,
+    tags: Vec<String>,
+}
+
+/// Compiles a typechecked abstract syntax tree into bytecode for the virtual machine in `vm`.
+///
+/// # Panics
+/// Throws a panic when the tree is not well typed
+pub fn compile(node: &ASTNode<'_>) -> Program {
+    let typing = node
+        .infer_types::<i32>()
+        .expect("Only typechecked programs can be compiled to bytecode");
+    let mut compiler = Compiler {
+        typing,
+        codes: Vec::new(),
+        fields: Vec::new(),
+        tags: Vec::new(),
+    };
+    let mut function = Function::new(&[], &[]);
+    compiler.tail(&mut function, node);
+    let entry = compiler.finish(function, "main", 0);
+    Program {
+        codes: compiler.codes,
+        entry,
+        fields: compiler.fields,
+        tags: compiler.tags,
+    }
+}
+
+/// Returns the names of the free variables of the tree, sorted so captures get a stable order
+fn free_vars(node: &ASTNode<'_>) -> Vec<String> {
+    let mut names: Vec<String> = nameless::to_nameless(node)
+        .free_vars()
+        .into_iter()
+        .collect();
+    names.sort();
+    names
+}
+
+impl<'t> Compiler<'t> {
+    fn finish(&mut self, function: Function, name: &str, arity: usize) -> usize {
+        self.codes.push(Code {
+            name: name.to_string(),
+            arity,
+            slots: function.slots,
+            instrs: function.instrs,
+        });
+        self.codes.len() - 1
+    }
+
+    fn field(&mut self, name: &str) -> usize {
+        intern(&mut self.fields, name)
+    }
+
+    fn tag(&mut self, name: &str) -> usize {
+        intern(&mut self.tags, name)
+    }
+
+    /// Returns the offset of the field among the fields of a record of the type, or `None` when
+    /// the type does not list all fields of the record
+    fn offset(&mut self, data_type: &TypeAssignment, name: &str) -> Option<usize> {
+        match data_type {
+            TypeAssignment::Record(types, None) => {
+                let field = self.field(name);
+                let indices: Vec<usize> = types.keys().map(|known| self.field(known)).collect();
+                Some(indices.into_iter().filter(|index| *index < field).count())
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the instruction reading the field from a record of the type
+    fn project(&mut self, data_type: &TypeAssignment, name: &str) -> Instr {
+        match self.offset(data_type, name) {
+            Some(offset) => Instr::Field(offset),
+            None => Instr::Project(self.field(name)),
+        }
+    }
+
+    /// Compiles a node whose value is the result of the function, so calls become tail calls
+    fn tail(&mut self, function: &mut Function, node: &ASTNode<'_>) {
+        match node {
+            ASTNode::ApplicationNode { left, right, .. } => {
+                self.expr(function, left);
+                self.expr(function, right);
+                function.emit(Instr::TailCall);
+            }
+            ASTNode::ConditionNode {
+                clause,
+                then_arm,
+                else_arm,
+                ..
+            } => {
+                self.expr(function, clause);
+                let to_else = function.emit(Instr::JumpIfFalse(0));
+                self.tail(function, then_arm);
+                function.patch(to_else);
+                self.tail(function, else_arm);
+            }
+            ASTNode::MatchingNode {
+                to_match, cases, ..
+            } => {
+                let arms: Vec<&ASTNode<'_>> = cases.iter().map(|(_, arm)| &**arm).collect();
+                let patterns = cases.iter().map(|(pattern, _)| pattern);
+                self.matching(function, to_match, patterns, &arms, true);
+            }
+            ASTNode::LetNode {
+                pattern,
+                value,
+                body,
+                ..
+            } => self.matching(function, value, vec![pattern], &[body], true),
+            ASTNode::LetRecNode { bindings, body, .. } => {
+                let marker = self.group(function, bindings);
+                self.tail(function, body);
+                function.leave(marker);
+            }
+            _ => {
+                self.expr(function, node);
+                function.emit(Instr::Return);
+            }
+        }
+    }
+
+    /// Compiles a node that leaves its value on the stack
+    fn expr(&mut self, function: &mut Function, node: &ASTNode<'_>) {
+        match node {
+            ASTNode::AbstractionNode { ident, body, .. } => {
+                let captures = free_vars(node);
+                let mut inner = Function::new(std::slice::from_ref(ident), &captures);
+                self.tail(&mut inner, body);
+                let code = self.finish(inner, ident, 1);
+                let captures = captures.iter().map(|name| function.capture(name)).collect();
+                function.emit(Instr::Closure(code, captures));
+            }
+            ASTNode::ApplicationNode { left, right, .. } => {
+                self.expr(function, left);
+                self.expr(function, right);
+                function.emit(Instr::Call);
+            }
+            ASTNode::IdentifierNode { name, .. } => {
+                let instr = match function.lookup(name) {
+                    Var::Local(slot) => Instr::Local(slot),
+                    Var::Captured(index) => Instr::Captured(index),
+                };
+                function.emit(instr);
+            }
+            ASTNode::ConditionNode {
+                clause,
+                then_arm,
+                else_arm,
+                ..
+            } => {
+                self.expr(function, clause);
+                let to_else = function.emit(Instr::JumpIfFalse(0));
+                self.expr(function, then_arm);
+                let to_end = function.emit(Instr::Jump(0));
+                function.patch(to_else);
+                self.expr(function, else_arm);
+                function.patch(to_end);
+            }
+            ASTNode::ArithmeticNode { op, expr, .. } => {
+                self.expr(function, expr);
+                function.emit(match op {
+                    Operator::Succ => Instr::Succ,
+                    Operator::Pred => Instr::Pred,
+                });
+            }
+            ASTNode::IsZeroNode { expr, .. } => {
+                self.expr(function, expr);
+                function.emit(Instr::IsZero);
+            }
+            ASTNode::ValueNode { value, .. } => {
+                function.emit(match value {
+                    Value::True => Instr::Bool(true),
+                    Value::False => Instr::Bool(false),
+                    Value::Zero => Instr::Nat(0),
+                });
+            }
+            ASTNode::ProjectionNode { target, attrib, .. } => {
+                self.expr(function, target);
+                let data_type = self.typing.type_of(target).clone();
+                let instr = self.project(&data_type, attrib);
+                function.emit(instr);
+            }
+            ASTNode::RecordNode { records, .. } => {
+                let fields = self.fields(function, records);
+                function.emit(Instr::Record(fields));
+            }
+            ASTNode::UpdateNode {
+                target, records, ..
+            } => {
+                self.expr(function, target);
+                let data_type = self.typing.type_of(target).clone();
+                let fields = self.fields(function, records);
+                let names: Vec<String> = fields.iter().map(|f| self.fields[*f].clone()).collect();
+                let offsets: Option<Vec<usize>> = names
+                    .iter()
+                    .map(|name| self.offset(&data_type, name))
+                    .collect();
+                function.emit(match offsets {
+                    Some(offsets) => Instr::Replace(offsets),
+                    None => Instr::Update(fields),
+                });
+            }
+            ASTNode::ExtensionNode {
+                target, records, ..
+            } => {
+                self.expr(function, target);
+                let fields = self.fields(function, records);
+                function.emit(Instr::Update(fields));
+            }
+            ASTNode::RestrictionNode { target, attrib, .. } => {
+                self.expr(function, target);
+                let field = self.field(attrib);
+                function.emit(Instr::Restrict(field));
+            }
+            ASTNode::MatchingNode {
+                to_match, cases, ..
+            } => {
+                let arms: Vec<&ASTNode<'_>> = cases.iter().map(|(_, arm)| &**arm).collect();
+                let patterns = cases.iter().map(|(pattern, _)| pattern);
+                self.matching(function, to_match, patterns, &arms, false);
+            }
+            ASTNode::TaggingNode { ident, value, .. } => {
+                self.expr(function, value);
+                let tag = self.tag(ident);
+                function.emit(Instr::Tag(tag));
+            }
+            ASTNode::FixNode { point, .. } => {
+                self.expr(function, point);
+                function.emit(Instr::Fix);
+            }
+            ASTNode::LetNode {
+                pattern,
+                value,
+                body,
+                ..
+            } => self.matching(function, value, vec![pattern], &[body], false),
+            ASTNode::LetRecNode { bindings, body, .. } => {
+                let marker = self.group(function, bindings);
+                self.expr(function, body);
+                function.leave(marker);
+            }
+        }
+    }
+
+    /// Pushes the values of the fields in alphabetical order and returns their indices
+    fn fields<'n, 'a: 'n, I>(&mut self, function: &mut Function, records: I) -> Vec<usize>
+    where
+        I: IntoIterator<Item = (&'n String, &'n ASTNode<'a>)>,
+    {
+        let mut records: Vec<(&String, &ASTNode<'_>)> = records.into_iter().collect();
+        records.sort_by_key(|(name, _)| *name);
+        records
+            .into_iter()
+            .map(|(name, node)| {
+                self.expr(function, node);
+                self.field(name)
+            })
+            .collect()
+    }
+
+    /// Compiles the members of a recursive group and binds their names in the function, returns
+    /// the marker to drop the bindings again
+    fn group(
+        &mut self,
+        function: &mut Function,
+        bindings: &[(String, Option<TypeAssignment>, ASTNode<'_>)],
+    ) -> (usize, usize) {
+        let names: Vec<String> = bindings
+            .iter()
+            .map(|(name, _, _)| name.to_string())
+            .collect();
+        let mut captures = HashSet::new();
+        for (_, _, value) in bindings {
+            captures.extend(free_vars(value));
+        }
+        let mut captures: Vec<String> = captures
+            .into_iter()
+            .filter(|name| !names.contains(name))
+            .collect();
+        captures.sort();
+
+        let codes = bindings
+            .iter()
+            .map(|(name, _, value)| {
+                let mut member = Function::new(&names, &captures);
+                self.tail(&mut member, value);
+                self.finish(member, name, names.len())
+            })
+            .collect();
+        let captures = captures.iter().map(|name| function.capture(name)).collect();
+        function.emit(Instr::Group(codes, captures));
+
+        let marker = function.enter();
+        let slots: Vec<usize> = names.iter().map(|name| function.bind(name)).collect();
+        for slot in slots.into_iter().rev() {
+            function.emit(Instr::Store(slot));
+        }
+        marker
+    }
+
+    /// Compiles a case expression, or a let which is a case with a single arm
+    fn matching<'p, I>(
+        &mut self,
+        function: &mut Function,
+        to_match: &ASTNode<'_>,
+        patterns: I,
+        arms: &[&ASTNode<'_>],
+        tail: bool,
+    ) where
+        I: IntoIterator<Item = &'p Pattern>,
+    {
+        let marker = function.enter();
+        self.expr(function, to_match);
+        let scrutinee = Scrutinee {
+            slot: function.allocate(),
+            data_type: self.typing.type_of(to_match).clone(),
+        };
+        function.emit(Instr::Store(scrutinee.slot));
+        let tree = matching::compile(patterns, None);
+        let mut ends = Vec::new();
+        self.decision(function, &tree, &scrutinee, arms, tail, &mut ends);
+        for end in ends {
+            function.patch(end);
+        }
+        function.leave(marker);
+    }
+
+    /// Compiles a decision tree, the jumps to the end of the case are collected in `ends`
+    fn decision(
+        &mut self,
+        function: &mut Function,
+        tree: &Decision,
+        scrutinee: &Scrutinee,
+        arms: &[&ASTNode<'_>],
+        tail: bool,
+        ends: &mut Vec<usize>,
+    ) {
+        match tree {
+            Decision::Fail => {
+                function.emit(Instr::Fail);
+            }
+            Decision::Leaf { arm, bindings } => {
+                let marker = function.enter();
+                for (name, path) in bindings {
+                    self.path(function, scrutinee, path);
+                    let slot = function.bind(name);
+                    function.emit(Instr::Store(slot));
+                }
+                if tail {
+                    self.tail(function, arms[*arm]);
+                } else {
+                    self.expr(function, arms[*arm]);
+                    ends.push(function.emit(Instr::Jump(0)));
+                }
+                function.leave(marker);
+            }
+            Decision::Switch {
+                path,
+                cases,
+                default,
+            } => {
+                for (constructor, case) in cases {
+                    self.path(function, scrutinee, path);
+                    let test = match constructor {
+                        Constructor::True => Test::True,
+                        Constructor::False => Test::False,
+                        Constructor::Zero => Test::Zero,
+                        Constructor::Succ => Test::Succ,
+                        Constructor::Tag(tag) => Test::Tag(self.tag(tag)),
+                    };
+                    let next = function.emit(Instr::JumpUnless(test, 0));
+                    self.decision(function, case, scrutinee, arms, tail, ends);
+                    function.patch(next);
+                }
+                match default {
+                    Some(default) => self.decision(function, default, scrutinee, arms, tail, ends),
+                    None => {
+                        function.emit(Instr::Fail);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Pushes the component of the matched value at the path
+    fn path(&mut self, function: &mut Function, scrutinee: &Scrutinee, path: &Path) {
+        function.emit(Instr::Local(scrutinee.slot));
+        let mut data_type = Some(&scrutinee.data_type);
+        for access in path {
+            let instr = match access {
+                Access::Tag(_) => Instr::Untag,
+                Access::Pred => Instr::Pred,
+                Access::Field(name) => match data_type {
+                    Some(data_type) => self.project(data_type, name),
+                    None => Instr::Project(self.field(name)),
+                },
+            };
+            function.emit(instr);
+            data_type = match (access, data_type) {
+                (Access::Tag(tag), Some(TypeAssignment::Variant(types, _))) => types.get(tag),
+                (Access::Field(name), Some(TypeAssignment::Record(types, _))) => types.get(name),
+                (Access::Pred, data_type) => data_type,
+                _ => None,
+            };
+        }
+    }
+}
+
+/// Slot holding the value a case expression matches, together with the type of the value
+struct Scrutinee {
+    slot: usize,
+    data_type: TypeAssignment,
+}
+
+/// Returns the index of the name in the table, adding it when it is not there yet
+fn intern(table: &mut Vec<String>, name: &str) -> usize {
+    match table.iter().position(|known| known == name) {
+        Some(index) => index,
+        None => {
+            table.push(name.to_string());
+            table.len() - 1
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use ast::*;
+use matching;
 use pest::Error;
 use std::collections::HashMap;
 use sym_tab::*;
@@ -6,14 +7,14 @@ use sym_tab::*;
 impl<'a> ASTNode<'a> {
     /// Performs typechecking on the abstract syntax tree and returns the resulting type or an error
     /// specifying the problem encountered when type checking
-    pub fn check<R: Copy>(&self) -> Result<TypeAssignment, Error<R>> {
+    pub fn check<R: Copy>(&self) -> Result<TypeAssignment, Error<'_, R>> {
         self.check_node(&mut SymbolTable::new())
     }
 
     fn check_node<R: Copy>(
         &self,
         table: &mut SymbolTable<TypeAssignment>,
-    ) -> Result<TypeAssignment, Error<R>> {
+    ) -> Result<TypeAssignment, Error<'_, R>> {
         match self {
             ASTNode::ValueNode { meta: _, value } => match value {
                 Value::True => Ok(TypeAssignment::Single(Type::Bool)),
@@ -91,13 +92,20 @@ impl<'a> ASTNode<'a> {
                 }
             }
             ASTNode::AbstractionNode {
-                meta: _,
+                meta,
                 ident,
                 data_type,
                 body,
             } => {
-                table.push(Scope::new(ident.to_string(), data_type.clone()));
-                let body_type = body.check_node(table)?;
+                let data_type = data_type.as_ref().ok_or_else(|| Error::CustomErrorSpan {
+                    message: "Argument of an abstraction needs a type annotation, its type can only be inferred"
+                        .to_string(),
+                    span: meta.clone(),
+                })?;
+                let body_type = table
+                    .with_scope(Scope::new(ident.to_string(), data_type.clone()), |table| {
+                        body.check_node(table)
+                    })?;
                 Ok(TypeAssignment::Arrow(
                     Box::new(data_type.clone()),
                     Box::new(body_type),
@@ -108,7 +116,7 @@ impl<'a> ASTNode<'a> {
                 target,
                 attrib,
             } => {
-                if let TypeAssignment::Record(types) = target.check_node(table)? {
+                if let TypeAssignment::Record(types, None) = target.check_node(table)? {
                     if let Some(attrib_type) = types.get(attrib) {
                         Ok(attrib_type.clone())
                     } else {
@@ -130,48 +138,143 @@ impl<'a> ASTNode<'a> {
                 for (name, node) in records {
                     types.insert(name.to_string(), node.check_node(table)?);
                 }
-                Ok(TypeAssignment::Record(types))
+                Ok(TypeAssignment::Record(types, None))
             }
-            ASTNode::MatchingNode {
+            ASTNode::UpdateNode {
                 meta,
-                to_match,
-                cases,
+                target,
+                records,
             } => {
-                if let TypeAssignment::Variant(variant) = to_match.check_node(table)? {
-                    let mut arm_type = None;
-                    for (variant_name, variant_type) in variant {
-                        if let Some((ident, arm)) = cases.get(&variant_name) {
-                            table.push(Scope::new(ident.to_string(), variant_type.clone()));
-
-                            if arm_type == None {
-                                arm_type = Some(arm.check_node(table)?);
-                            } else {
-                                if arm_type != Some(arm.check_node(table)?) {
-                                    return Err(Error::CustomErrorSpan {
-                                        message: "All outcomes of a case expression should result in the same type".to_string(),
-                                        span: meta.clone(),
-                                    });
-                                }
+                if let TypeAssignment::Record(types, None) = target.check_node(table)? {
+                    for (name, node) in records {
+                        let node_type = node.check_node(table)?;
+                        match types.get(name) {
+                            Some(field_type) if *field_type == node_type => {}
+                            Some(_) => return Err(Error::CustomErrorSpan {
+                                message:
+                                    "Updated attribute should keep the type it has in the record"
+                                        .to_string(),
+                                span: meta.clone(),
+                            }),
+                            None => {
+                                return Err(Error::CustomErrorSpan {
+                                    message: "Updated attribute is not part of the record type"
+                                        .to_string(),
+                                    span: meta.clone(),
+                                })
                             }
-                        } else {
+                        }
+                    }
+                    Ok(TypeAssignment::Record(types, None))
+                } else {
+                    Err(Error::CustomErrorSpan {
+                        message: "Target of an update should be of type record".to_string(),
+                        span: meta.clone(),
+                    })
+                }
+            }
+            ASTNode::ExtensionNode {
+                meta,
+                target,
+                records,
+            } => {
+                if let TypeAssignment::Record(mut types, None) = target.check_node(table)? {
+                    for (name, node) in records {
+                        if types.contains_key(name) {
                             return Err(Error::CustomErrorSpan {
-                                message: "Not all possible types in the variant are being handled"
-                                    .to_string(),
+                                message:
+                                    "Attribute of extension is already part of the record type"
+                                        .to_string(),
                                 span: meta.clone(),
                             });
                         }
+                        types.insert(name.to_string(), node.check_node(table)?);
                     }
-                    if let Some(arm_type) = arm_type {
-                        Ok(arm_type)
+                    Ok(TypeAssignment::Record(types, None))
+                } else {
+                    Err(Error::CustomErrorSpan {
+                        message: "Target of an extension should be of type record".to_string(),
+                        span: meta.clone(),
+                    })
+                }
+            }
+            ASTNode::RestrictionNode {
+                meta,
+                target,
+                attrib,
+            } => {
+                if let TypeAssignment::Record(mut types, None) = target.check_node(table)? {
+                    if types.remove(attrib).is_some() {
+                        Ok(TypeAssignment::Record(types, None))
                     } else {
                         Err(Error::CustomErrorSpan {
-                            message: "Variant can't be empty".to_string(),
+                            message: "Attribute of restriction is not part of the record type"
+                                .to_string(),
                             span: meta.clone(),
                         })
                     }
                 } else {
                     Err(Error::CustomErrorSpan {
-                        message: "Argument of case expression should be a variant".to_string(),
+                        message: "Target of a restriction should be of type record".to_string(),
+                        span: meta.clone(),
+                    })
+                }
+            }
+            ASTNode::MatchingNode {
+                meta,
+                to_match,
+                cases,
+            } => {
+                let match_type = to_match.check_node(table)?;
+                let mut arm_type = None;
+                for (pattern, arm) in cases {
+                    let mut bindings = HashMap::new();
+                    check_pattern(pattern, &match_type, &mut bindings).map_err(|message| {
+                        Error::CustomErrorSpan {
+                            message,
+                            span: arm.span(),
+                        }
+                    })?;
+                    let case_type = table
+                        .with_scope(Scope::from_map(bindings), |table| arm.check_node(table))?;
+                    if arm_type.is_none() {
+                        arm_type = Some(case_type);
+                    } else if arm_type != Some(case_type) {
+                        return Err(Error::CustomErrorSpan {
+                            message:
+                                "All outcomes of a case expression should result in the same type"
+                                    .to_string(),
+                            span: arm.span(),
+                        });
+                    }
+                }
+
+                let tree =
+                    matching::compile(cases.iter().map(|(pattern, _)| pattern), Some(&match_type));
+                if let Some(missing) = tree.missing(&match_type) {
+                    return Err(Error::CustomErrorSpan {
+                        message: format!(
+                            "Case expression is not exhaustive, values matching {} are not handled",
+                            missing
+                        ),
+                        span: to_match.span(),
+                    });
+                }
+                if let Some(arm) = tree.reachable_arms(cases.len()).iter().position(|r| !r) {
+                    return Err(Error::CustomErrorSpan {
+                        message: format!(
+                            "Arm {} of the case expression can never be reached",
+                            arm + 1
+                        ),
+                        span: cases[arm].1.span(),
+                    });
+                }
+
+                if let Some(arm_type) = arm_type {
+                    Ok(arm_type)
+                } else {
+                    Err(Error::CustomErrorSpan {
+                        message: "Case expression should have at least one arm".to_string(),
                         span: meta.clone(),
                     })
                 }
@@ -192,6 +295,65 @@ impl<'a> ASTNode<'a> {
                     })
                 }
             }
+            ASTNode::LetNode {
+                meta,
+                pattern,
+                value,
+                body,
+            } => {
+                let value_type = value.check_node(table)?;
+                let mut bindings = HashMap::new();
+                check_pattern(pattern, &value_type, &mut bindings).map_err(|message| {
+                    Error::CustomErrorSpan {
+                        message,
+                        span: meta.clone(),
+                    }
+                })?;
+                if let Some(missing) =
+                    matching::compile(vec![pattern], Some(&value_type)).missing(&value_type)
+                {
+                    return Err(Error::CustomErrorSpan {
+                        message: format!(
+                            "Pattern of a let expression should match every value, values matching {} are not handled",
+                            missing
+                        ),
+                        span: meta.clone(),
+                    });
+                }
+                table.with_scope(Scope::from_map(bindings), |table| body.check_node(table))
+            }
+            ASTNode::LetRecNode {
+                meta,
+                bindings,
+                body,
+            } => {
+                check_recursive_bindings(bindings).map_err(|message| Error::CustomErrorSpan {
+                    message,
+                    span: meta.clone(),
+                })?;
+                let mut scope = HashMap::new();
+                for (name, data_type, _) in bindings {
+                    let data_type = data_type.as_ref().ok_or_else(|| Error::CustomErrorSpan {
+                        message: "Recursive binding needs a type annotation, its type can only be inferred"
+                            .to_string(),
+                        span: meta.clone(),
+                    })?;
+                    scope.insert(name.to_string(), data_type.clone());
+                }
+                table.with_scope(Scope::from_map(scope), |table| {
+                    for (_, data_type, value) in bindings {
+                        if Some(value.check_node(table)?) != *data_type {
+                            return Err(Error::CustomErrorSpan {
+                                message:
+                                    "Recursive binding should have the type it is annotated with"
+                                        .to_string(),
+                                span: meta.clone(),
+                            });
+                        }
+                    }
+                    body.check_node(table)
+                })
+            }
             ASTNode::FixNode { meta, point } => {
                 if let TypeAssignment::Arrow(from, to) = point.check_node(table)? {
                     if from == to {
@@ -214,3 +376,108 @@ impl<'a> ASTNode<'a> {
         }
     }
 }
+
+/// Checks that a pattern can match values of the given type and collects the types of the
+/// variables it binds. Returns a message describing the problem when the pattern is incorrect.
+///
+/// # Arguments
+/// * `pattern` - the pattern to check
+/// * `data_type` - the type of the values the pattern is matched against
+/// * `bindings` - the variables bound so far in the pattern
+pub fn check_pattern(
+    pattern: &Pattern,
+    data_type: &TypeAssignment,
+    bindings: &mut HashMap<String, TypeAssignment>,
+) -> Result<(), String> {
+    match pattern {
+        Pattern::Wildcard => Ok(()),
+        Pattern::Variable(name) => {
+            if bindings.contains_key(name) {
+                Err(format!(
+                    "Variable {} is bound more than once in a pattern",
+                    name
+                ))
+            } else {
+                bindings.insert(name.to_string(), data_type.clone());
+                Ok(())
+            }
+        }
+        Pattern::Value(Value::True) | Pattern::Value(Value::False) => {
+            if data_type == &TypeAssignment::Single(Type::Bool) {
+                Ok(())
+            } else {
+                Err("Boolean pattern should match a value of type Bool".to_string())
+            }
+        }
+        Pattern::Value(Value::Zero) => {
+            if data_type == &TypeAssignment::Single(Type::Nat) {
+                Ok(())
+            } else {
+                Err("Natural number pattern should match a value of type Nat".to_string())
+            }
+        }
+        Pattern::Succ(inner) => {
+            if data_type == &TypeAssignment::Single(Type::Nat) {
+                check_pattern(inner, data_type, bindings)
+            } else {
+                Err("Natural number pattern should match a value of type Nat".to_string())
+            }
+        }
+        Pattern::Tag(ident, inner) => {
+            if let TypeAssignment::Variant(variants, _) = data_type {
+                if let Some(variant_type) = variants.get(ident) {
+                    check_pattern(inner, variant_type, bindings)
+                } else {
+                    Err(format!(
+                        "Tag {} of pattern is not part of the variant type",
+                        ident
+                    ))
+                }
+            } else {
+                Err("Variant pattern should match a value of variant type".to_string())
+            }
+        }
+        Pattern::Record(fields) => {
+            if let TypeAssignment::Record(types, _) = data_type {
+                for (name, field) in fields {
+                    if let Some(field_type) = types.get(name) {
+                        check_pattern(field, field_type, bindings)?;
+                    } else {
+                        return Err(format!(
+                            "Field {} of pattern is not part of the record type",
+                            name
+                        ));
+                    }
+                }
+                Ok(())
+            } else {
+                Err("Record pattern should match a value of record type".to_string())
+            }
+        }
+    }
+}
+
+/// Checks that the bindings of a letrec expression bind distinct names to abstractions. Returns a
+/// message describing the problem when they don't.
+///
+/// # Arguments
+/// * `bindings` - the bindings of the letrec expression
+pub fn check_recursive_bindings(
+    bindings: &[(String, Option<TypeAssignment>, ASTNode<'_>)],
+) -> Result<(), String> {
+    for (index, (name, _, value)) in bindings.iter().enumerate() {
+        if bindings[..index].iter().any(|(other, _, _)| other == name) {
+            return Err(format!(
+                "Variable {} is bound more than once in a letrec expression",
+                name
+            ));
+        }
+        if !matches!(value, ASTNode::AbstractionNode { .. }) {
+            return Err(format!(
+                "Recursive binding of {} should be an abstraction",
+                name
+            ));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,563 @@
+use ast::{self, *};
+use matching::{self, Access, Constructor, Decision, Path};
+use nameless;
+use std::collections::{HashMap, HashSet};
+use std::fmt::*;
+use std::rc::Rc;
+
+/// A compiled term, computing a result from the slots of the function it is part of
+type Compiled<T> = Rc<dyn Fn(&mut [Value]) -> T>;
+
+/// A compiled term computing its value
+type Code = Compiled<Value>;
+
+/// A compiled body of a function, which leaves the call in its tail position to the caller
+type Body = Compiled<Step>;
+
+/// Result of running the body of a function
+enum Step {
+    Done(Value),
+    /// The function ends by calling another one, which `call` does without nesting it
+    Call(Rc<Function>, Value),
+}
+
+/// Values computed by compiled programs
+#[derive(Clone)]
+pub enum Value {
+    Nat(usize),
+    Bool(bool),
+    Func(Rc<Function>),
+    /// Fixpoint of a function, it is unfolded when it is read from a slot
+    Fix(Rc<Function>),
+    /// Member of a recursive group, it is unfolded when it is read from a slot
+    Rec(Rc<Group>, usize),
+    Record(Rc<HashMap<String, Value>>),
+    Variant(String, Rc<Value>),
+}
+
+/// Compiled function together with the values of the variables it captured. The captured values
+/// are placed in the first slots of a call, followed by the argument.
+pub struct Function {
+    code: Body,
+    slots: usize,
+    captured: Vec<Value>,
+}
+
+/// Members of a recursive group sharing their captured variables. The captured values are placed
+/// in the first slots when a member is unfolded, followed by the members of the group.
+pub struct Group {
+    members: Vec<(Code, usize)>,
+    captured: Vec<Value>,
+}
+
+/// A program compiled into a tree of closures, ready to be run any number of times
+pub struct CompiledProgram {
+    code: Code,
+    slots: usize,
+}
+
+impl CompiledProgram {
+    /// Runs the program and returns its value. Arguments are passed by value. Calls in tail
+    /// position run in constant space, other calls of the program recurse on the Rust stack.
+    ///
+    /// # Panics
+    /// Throws a panic when the program is not well typed, which would indicate a problem in the
+    /// typechecking logic.
+    pub fn run(&self) -> Value {
+        let mut slots = vec![Value::Nat(0); self.slots];
+        (self.code)(&mut slots)
+    }
+}
+
+/// Compiles a typechecked abstract syntax tree into closures. Every variable is resolved to a
+/// slot of the function it occurs in, so running the program does not look up any names.
+///
+/// # Panics
+/// Throws a panic when the tree refers to unknown variables, which the typechecker rules out
+pub fn compile(node: &ASTNode<'_>) -> CompiledProgram {
+    let mut scope = Scope::new(&[]);
+    let code = scope.compile(node);
+    CompiledProgram {
+        code,
+        slots: scope.slots,
+    }
+}
+
+/// Slots of the function that is being compiled, and the names bound to them
+struct Scope {
+    names: Vec<(String, usize)>,
+    next: usize,
+    slots: usize,
+}
+
+impl Scope {
+    fn new(names: &[String]) -> Scope {
+        Scope {
+            names: names
+                .iter()
+                .enumerate()
+                .map(|(slot, name)| (name.to_string(), slot))
+                .collect(),
+            next: names.len(),
+            slots: names.len(),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> usize {
+        self.names
+            .iter()
+            .rev()
+            .find(|(bound, _)| bound == name)
+            .map(|(_, slot)| *slot)
+            .expect("Bug in typechecker: came across unknown variable")
+    }
+
+    fn bind(&mut self, name: &str) -> usize {
+        let slot = self.next;
+        self.next += 1;
+        self.slots = self.slots.max(self.next);
+        self.names.push((name.to_string(), slot));
+        slot
+    }
+
+    /// Runs the function and drops the names it bound afterwards, their slots can be reused
+    fn within<R, F>(&mut self, inner: F) -> R
+    where
+        F: FnOnce(&mut Scope) -> R,
+    {
+        let (names, next) = (self.names.len(), self.next);
+        let result = inner(self);
+        self.names.truncate(names);
+        self.next = next;
+        result
+    }
+
+    fn compile(&mut self, node: &ASTNode<'_>) -> Code {
+        match node {
+            ASTNode::AbstractionNode { ident, body, .. } => {
+                let captures = free_vars(node);
+                let mut names = captures.clone();
+                names.push(ident.to_string());
+                let mut inner = Scope::new(&names);
+                let body = inner.tail(body);
+                let slots = inner.slots;
+                let captured: Vec<usize> = captures.iter().map(|name| self.lookup(name)).collect();
+                Rc::new(move |s: &mut [Value]| {
+                    Value::Func(Rc::new(Function {
+                        code: body.clone(),
+                        slots,
+                        captured: captured.iter().map(|slot| s[*slot].clone()).collect(),
+                    }))
+                })
+            }
+            ASTNode::ApplicationNode { left, right, .. } => {
+                let left = self.compile(left);
+                let right = self.compile(right);
+                Rc::new(move |s: &mut [Value]| {
+                    match left(s) {
+                    Value::Func(function) => call(&function, right(s)),
+                    _ => panic!("Bug in typechecker: in evaluation of application the left argument was not evaluated to a function"),
+                }
+                })
+            }
+            ASTNode::IdentifierNode { name, .. } => {
+                let slot = self.lookup(name);
+                Rc::new(move |s: &mut [Value]| unfold(s[slot].clone()))
+            }
+            ASTNode::ConditionNode {
+                clause,
+                then_arm,
+                else_arm,
+                ..
+            } => self.condition(clause, then_arm, else_arm, Scope::compile),
+            ASTNode::ArithmeticNode { op, expr, .. } => {
+                let expr = self.compile(expr);
+                let succ = *op == Operator::Succ;
+                Rc::new(move |s: &mut [Value]| {
+                    match expr(s) {
+                    Value::Nat(x) if succ => Value::Nat(x + 1),
+                    Value::Nat(x) => Value::Nat(x.saturating_sub(1)),
+                    _ => panic!("Bug in typechecker: in evaluation of pred/succ expr did not return variable of type Nat"),
+                }
+                })
+            }
+            ASTNode::IsZeroNode { expr, .. } => {
+                let expr = self.compile(expr);
+                Rc::new(move |s: &mut [Value]| {
+                    match expr(s) {
+                    Value::Nat(x) => Value::Bool(x == 0),
+                    _ => panic!("Bug in typechecker: in evaluation of iszero expr did not return variable of type Nat"),
+                }
+                })
+            }
+            ASTNode::ValueNode { value, .. } => {
+                let value = match value {
+                    ast::Value::True => Value::Bool(true),
+                    ast::Value::False => Value::Bool(false),
+                    ast::Value::Zero => Value::Nat(0),
+                };
+                Rc::new(move |_: &mut [Value]| value.clone())
+            }
+            ASTNode::ProjectionNode { target, attrib, .. } => {
+                let target = self.compile(target);
+                let attrib = attrib.to_string();
+                Rc::new(move |s: &mut [Value]| {
+                    match target(s) {
+                    Value::Record(records) => records.get(&attrib).cloned().expect(
+                        "Bug in typechecker: in evaluation of projection the attribute was not found",
+                    ),
+                    _ => panic!("Bug in typechecker: in evaluation of projection type target type was not a record"),
+                }
+                })
+            }
+            ASTNode::RecordNode { records, .. } => {
+                let fields = self.fields(records);
+                Rc::new(move |s: &mut [Value]| {
+                    let map = fields
+                        .iter()
+                        .map(|(name, field)| (name.to_string(), field(s)))
+                        .collect();
+                    Value::Record(Rc::new(map))
+                })
+            }
+            ASTNode::UpdateNode {
+                target, records, ..
+            }
+            | ASTNode::ExtensionNode {
+                target, records, ..
+            } => {
+                let target = self.compile(target);
+                let fields = self.fields(records);
+                Rc::new(move |s: &mut [Value]| match target(s) {
+                    Value::Record(records) => {
+                        let mut map = Rc::try_unwrap(records).unwrap_or_else(|r| (*r).clone());
+                        for (name, field) in &fields {
+                            map.insert(name.to_string(), field(s));
+                        }
+                        Value::Record(Rc::new(map))
+                    }
+                    _ => panic!(
+                        "Bug in typechecker: in evaluation of update the target was not a record"
+                    ),
+                })
+            }
+            ASTNode::RestrictionNode { target, attrib, .. } => {
+                let target = self.compile(target);
+                let attrib = attrib.to_string();
+                Rc::new(move |s: &mut [Value]| {
+                    match target(s) {
+                    Value::Record(records) => {
+                        let mut map = Rc::try_unwrap(records).unwrap_or_else(|r| (*r).clone());
+                        map.remove(&attrib);
+                        Value::Record(Rc::new(map))
+                    }
+                    _ => panic!("Bug in typechecker: in evaluation of restriction the target was not a record"),
+                }
+                })
+            }
+            ASTNode::MatchingNode {
+                to_match, cases, ..
+            } => {
+                let arms: Vec<(&Pattern, &ASTNode<'_>)> = cases
+                    .iter()
+                    .map(|(pattern, arm)| (pattern, &**arm))
+                    .collect();
+                self.matching(to_match, &arms, Scope::compile)
+            }
+            ASTNode::TaggingNode { ident, value, .. } => {
+                let value = self.compile(value);
+                let ident = ident.to_string();
+                Rc::new(move |s: &mut [Value]| Value::Variant(ident.to_string(), Rc::new(value(s))))
+            }
+            ASTNode::FixNode { point, .. } => {
+                let point = self.compile(point);
+                Rc::new(move |s: &mut [Value]| {
+                    match point(s) {
+                    Value::Func(function) => call(&function, Value::Fix(function.clone())),
+                    _ => panic!("Bug in typechecker: in evaluation of fixpoint the left argument was not evaluated to a function"),
+                }
+                })
+            }
+            ASTNode::LetNode {
+                pattern,
+                value,
+                body,
+                ..
+            } => self.matching(value, &[(pattern, body)], Scope::compile),
+            ASTNode::LetRecNode { bindings, body, .. } => {
+                self.letrec(bindings, body, Scope::compile)
+            }
+        }
+    }
+
+    /// Compiles the body of a function. An application in tail position is left to the caller,
+    /// and so are the ones in tail position of the arms and bodies the node ends with.
+    fn tail(&mut self, node: &ASTNode<'_>) -> Body {
+        match node {
+            ASTNode::ApplicationNode { left, right, .. } => {
+                let left = self.compile(left);
+                let right = self.compile(right);
+                Rc::new(move |s: &mut [Value]| {
+                    match left(s) {
+                    Value::Func(function) => Step::Call(function, right(s)),
+                    _ => panic!("Bug in typechecker: in evaluation of application the left argument was not evaluated to a function"),
+                }
+                })
+            }
+            ASTNode::ConditionNode {
+                clause,
+                then_arm,
+                else_arm,
+                ..
+            } => self.condition(clause, then_arm, else_arm, Scope::tail),
+            ASTNode::MatchingNode {
+                to_match, cases, ..
+            } => {
+                let arms: Vec<(&Pattern, &ASTNode<'_>)> = cases
+                    .iter()
+                    .map(|(pattern, arm)| (pattern, &**arm))
+                    .collect();
+                self.matching(to_match, &arms, Scope::tail)
+            }
+            ASTNode::LetNode {
+                pattern,
+                value,
+                body,
+                ..
+            } => self.matching(value, &[(pattern, body)], Scope::tail),
+            ASTNode::LetRecNode { bindings, body, .. } => self.letrec(bindings, body, Scope::tail),
+            _ => {
+                let code = self.compile(node);
+                Rc::new(move |s: &mut [Value]| Step::Done(code(s)))
+            }
+        }
+    }
+
+    /// Compiles a condition whose arms are compiled by the given function
+    fn condition<T: 'static>(
+        &mut self,
+        clause: &ASTNode<'_>,
+        then_arm: &ASTNode<'_>,
+        else_arm: &ASTNode<'_>,
+        compile: fn(&mut Scope, &ASTNode<'_>) -> Compiled<T>,
+    ) -> Compiled<T> {
+        let clause = self.compile(clause);
+        let then_arm = compile(self, then_arm);
+        let else_arm = compile(self, else_arm);
+        Rc::new(move |s: &mut [Value]| {
+            match clause(s) {
+            Value::Bool(true) => then_arm(s),
+            Value::Bool(false) => else_arm(s),
+            _ => panic!("Bug in typechecker: in evaluation of ifthenelse clause did not return variable of type Bool"),
+        }
+        })
+    }
+
+    /// Compiles the fields of a record in alphabetical order
+    fn fields(&mut self, records: &HashMap<String, ASTNode<'_>>) -> Vec<(String, Code)> {
+        let mut names: Vec<&String> = records.keys().collect();
+        names.sort();
+        names
+            .into_iter()
+            .map(|name| (name.to_string(), self.compile(&records[name])))
+            .collect()
+    }
+
+    /// Compiles a case expression, or a let which is a case with a single arm, whose arms are
+    /// compiled by the given function. The variables of each arm get their own slots, which are
+    /// filled before the arm runs.
+    fn matching<T: 'static>(
+        &mut self,
+        to_match: &ASTNode<'_>,
+        cases: &[(&Pattern, &ASTNode<'_>)],
+        compile: fn(&mut Scope, &ASTNode<'_>) -> Compiled<T>,
+    ) -> Compiled<T> {
+        let to_match = self.compile(to_match);
+        let tree = matching::compile(cases.iter().map(|(pattern, _)| *pattern), None);
+        let arms: Vec<(HashMap<String, usize>, Compiled<T>)> = cases
+            .iter()
+            .map(|(pattern, arm)| {
+                self.within(|scope| {
+                    let slots = nameless::pattern_vars(pattern)
+                        .iter()
+                        .map(|name| (name.to_string(), scope.bind(name)))
+                        .collect();
+                    (slots, compile(scope, arm))
+                })
+            })
+            .collect();
+        Rc::new(move |s: &mut [Value]| {
+            let value = to_match(s);
+            let (arm, bindings) = decide(&tree, &value);
+            let (slots, code) = &arms[arm];
+            for (name, path) in bindings {
+                s[slots[name]] = at(&value, path);
+            }
+            code(s)
+        })
+    }
+
+    /// Compiles a letrec whose body is compiled by the given function
+    fn letrec<T: 'static>(
+        &mut self,
+        bindings: &[(String, Option<TypeAssignment>, ASTNode<'_>)],
+        body: &ASTNode<'_>,
+        compile: fn(&mut Scope, &ASTNode<'_>) -> Compiled<T>,
+    ) -> Compiled<T> {
+        let names: Vec<String> = bindings
+            .iter()
+            .map(|(name, _, _)| name.to_string())
+            .collect();
+        let mut captures = HashSet::new();
+        for (_, _, value) in bindings {
+            captures.extend(free_vars(value));
+        }
+        let mut captures: Vec<String> = captures
+            .into_iter()
+            .filter(|name| !names.contains(name))
+            .collect();
+        captures.sort();
+
+        let mut inner_names = captures.clone();
+        inner_names.extend(names.iter().cloned());
+        let members: Vec<(Code, usize)> = bindings
+            .iter()
+            .map(|(_, _, value)| {
+                let mut inner = Scope::new(&inner_names);
+                let code = inner.compile(value);
+                (code, inner.slots)
+            })
+            .collect();
+        let captured: Vec<usize> = captures.iter().map(|name| self.lookup(name)).collect();
+        self.within(|scope| {
+            let slots: Vec<usize> = names.iter().map(|name| scope.bind(name)).collect();
+            let body = compile(scope, body);
+            Rc::new(move |s: &mut [Value]| {
+                let group = Rc::new(Group {
+                    members: members.clone(),
+                    captured: captured.iter().map(|slot| s[*slot].clone()).collect(),
+                });
+                for (index, slot) in slots.iter().enumerate() {
+                    s[*slot] = Value::Rec(group.clone(), index);
+                }
+                body(s)
+            }) as Compiled<T>
+        })
+    }
+}
+
+/// Returns the names of the free variables of the tree, sorted so captures get a stable order
+fn free_vars(node: &ASTNode<'_>) -> Vec<String> {
+    let mut names: Vec<String> = nameless::to_nameless(node)
+        .free_vars()
+        .into_iter()
+        .collect();
+    names.sort();
+    names
+}
+
+/// Calls the function, and then the functions it ends by calling, so a loop written as recursion
+/// runs in constant space
+fn call(function: &Function, argument: Value) -> Value {
+    let mut step = enter(function, argument);
+    loop {
+        match step {
+            Step::Done(value) => return value,
+            Step::Call(function, argument) => step = enter(&function, argument),
+        }
+    }
+}
+
+fn enter(function: &Function, argument: Value) -> Step {
+    let mut slots = Vec::with_capacity(function.slots);
+    slots.extend(function.captured.iter().cloned());
+    slots.push(argument);
+    slots.resize(function.slots, Value::Nat(0));
+    (function.code)(&mut slots)
+}
+
+/// Returns the value of a variable, recursive bindings are unfolded by running their code
+fn unfold(value: Value) -> Value {
+    match value {
+        Value::Fix(function) => call(&function, Value::Fix(function.clone())),
+        Value::Rec(group, index) => {
+            let (code, size) = &group.members[index];
+            let mut slots = Vec::with_capacity(*size);
+            slots.extend(group.captured.iter().cloned());
+            slots.extend((0..group.members.len()).map(|member| Value::Rec(group.clone(), member)));
+            slots.resize(*size, Value::Nat(0));
+            code(&mut slots)
+        }
+        value => value,
+    }
+}
+
+/// Walks the decision tree of a case expression and returns the selected arm together with the
+/// variables that have to be bound for it.
+fn decide<'t>(tree: &'t Decision, value: &Value) -> (usize, &'t [(String, Path)]) {
+    match tree {
+        Decision::Fail => panic!("Bug in typechecker: no arm of case matches the argument"),
+        Decision::Leaf { arm, bindings } => (*arm, bindings),
+        Decision::Switch {
+            path,
+            cases,
+            default,
+        } => {
+            let component = at(value, path);
+            if let Some((_, case)) = cases.iter().find(|(c, _)| has_constructor(&component, c)) {
+                decide(case, value)
+            } else if let Some(default) = default {
+                decide(default, value)
+            } else {
+                panic!("Bug in typechecker: no arm of case matches the argument")
+            }
+        }
+    }
+}
+
+/// Returns the component of the value at the given path
+fn at(value: &Value, path: &[Access]) -> Value {
+    let mut current = value.clone();
+    for access in path {
+        current = match (access, current) {
+            (Access::Tag(_), Value::Variant(_, value)) => (*value).clone(),
+            (Access::Pred, Value::Nat(x)) => Value::Nat(x - 1),
+            (Access::Field(name), Value::Record(records)) => records
+                .get(name)
+                .cloned()
+                .expect("Bug in typechecker: matched field was not found in record"),
+            _ => panic!("Bug in typechecker: matched value has an incorrect type"),
+        };
+    }
+    current
+}
+
+fn has_constructor(value: &Value, constructor: &Constructor) -> bool {
+    match (constructor, value) {
+        (Constructor::True, Value::Bool(x)) => *x,
+        (Constructor::False, Value::Bool(x)) => !*x,
+        (Constructor::Zero, Value::Nat(x)) => *x == 0,
+        (Constructor::Succ, Value::Nat(x)) => *x != 0,
+        (Constructor::Tag(tag), Value::Variant(ident, _)) => tag == ident,
+        _ => false,
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            Value::Nat(x) => write!(f, "{}", x),
+            Value::Bool(x) => write!(f, "{}", x),
+            Value::Func(_) | Value::Fix(_) | Value::Rec(_, _) => write!(f, "<function>"),
+            Value::Record(records) => {
+                let mut list: Vec<String> = records
+                    .iter()
+                    .map(|(name, val)| format!("{}={}", name, val))
+                    .collect();
+                list.sort();
+                write!(f, "{{{}}}", list.join(", "))
+            }
+            Value::Variant(ident, value) => write!(f, "<{}={}>", ident, value),
+        }
+    }
+}
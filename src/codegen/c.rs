@@ -0,0 +1,550 @@
+use ast::{self, *};
+use matching::{self, Access, Constructor, Decision};
+use nameless;
+use std::collections::HashSet;
+
+/// Objects, records, variants and closures every generated program is linked with
+const RUNTIME: &str = include_str!("runtime.c");
+
+/// Compiles a typechecked abstract syntax tree into a single C file. Every abstraction is lifted
+/// to a top-level C function, receiving the variables it captured in an environment next to its
+/// argument. Running the compiled program prints its value like the interpreter does, except that
+/// functions only print as `<function>` because their source is not kept.
+///
+/// Calls in tail position return to a trampoline instead of growing the C stack, and other calls
+/// nest on a thread with a large stack, so the file is built with `cc -std=c99 -pthread` at any
+/// optimization level.
+///
+/// # Panics
+/// Throws a panic when the tree refers to unknown variables, which the typechecker rules out
+pub fn compile(node: &ASTNode<'_>) -> String {
+    let mut generator = Generator::default();
+    let mut main = Function::new(&[]);
+    let result = generator.expr(&mut main, node);
+    main.line(format!("return {};", result));
+    let entry = generator.lift(main);
+
+    let mut out = String::new();
+    out.push_str("/* Generated by lambda-rs, build with cc -std=c99 -pthread */\n");
+    out.push_str("#define _POSIX_C_SOURCE 200112L\n");
+    out.push_str("#include <stdarg.h>\n#include <stdio.h>\n#include <stdlib.h>\n");
+    out.push_str("#include <string.h>\n");
+    out.push_str("#if defined(__unix__) || defined(__APPLE__)\n#include <pthread.h>\n#endif\n\n");
+    out.push_str(&names("field_names", &generator.fields));
+    out.push_str(&names("tag_names", &generator.tags));
+    out.push('\n');
+    out.push_str(RUNTIME);
+    out.push('\n');
+    for index in 0..generator.functions.len() {
+        out.push_str(&format!(
+            "static value f{}(value *env, value arg);\n",
+            index
+        ));
+    }
+    out.push('\n');
+    for group in &generator.groups {
+        out.push_str(group);
+    }
+    for function in &generator.functions {
+        out.push('\n');
+        out.push_str(function);
+    }
+
+    out.push_str("\nstatic void *program(void *unused) {\n    (void)unused;\n");
+    out.push_str(&format!("    print(f{}(NULL, NULL));\n", entry));
+    out.push_str("    putchar('\\n');\n    return NULL;\n}\n");
+    out.push_str("\nint main(void) {\n    run(program);\n    return 0;\n}\n");
+    out
+}
+
+/// Definition of a table of names, with a placeholder when it is empty because C does not allow
+/// empty arrays
+fn names(table: &str, names: &[String]) -> String {
+    let list: Vec<String> = if names.is_empty() {
+        vec!["\"\"".to_string()]
+    } else {
+        names.iter().map(|name| literal(name)).collect()
+    };
+    format!(
+        "static const char *{}[] = {{{}}};\n",
+        table,
+        list.join(", ")
+    )
+}
+
+fn literal(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// State shared by all functions of the program that is being generated
+#[derive(Default)]
+struct Generator {
+    /// Definitions of the lifted functions, the function at index `i` is called `fi`
+    functions: Vec<String>,
+    /// Definitions of the tables of code of the recursive groups
+    groups: Vec<String>,
+    fields: Vec<String>,
+    tags: Vec<String>,
+    temps: usize,
+}
+
+/// Where the statements emitted for an expression leave its value
+#[derive(Clone, Copy)]
+enum Destination<'r> {
+    /// Assigned to the variable
+    Variable(&'r str),
+    /// Returned from the function, which may return a call to make in its place
+    Return,
+}
+
+/// Body of a function that is being generated, with the C expressions the names in scope refer to.
+/// Names bound by patterns and letrecs refer to where their value is stored rather than a copy.
+struct Function {
+    lines: Vec<String>,
+    indent: usize,
+    scope: Vec<(String, String)>,
+}
+
+impl Function {
+    /// Creates a function whose environment holds the given captured variables
+    fn new(captures: &[String]) -> Function {
+        Function {
+            lines: Vec::new(),
+            indent: 1,
+            scope: captures
+                .iter()
+                .enumerate()
+                .map(|(index, name)| (name.to_string(), format!("env[{}]", index)))
+                .collect(),
+        }
+    }
+
+    fn line(&mut self, line: String) {
+        self.lines
+            .push(format!("{}{}", "    ".repeat(self.indent), line));
+    }
+
+    fn lookup(&self, name: &str) -> String {
+        self.scope
+            .iter()
+            .rev()
+            .find(|(bound, _)| bound == name)
+            .map(|(_, expr)| expr.to_string())
+            .expect("Bug in typechecker: came across unknown variable")
+    }
+}
+
+impl Generator {
+    fn temp(&mut self) -> String {
+        self.temps += 1;
+        format!("t{}", self.temps)
+    }
+
+    /// Adds the function to the program and returns its index
+    fn lift(&mut self, function: Function) -> usize {
+        let index = self.functions.len();
+        let mut definition = format!("static value f{}(value *env, value arg) {{\n", index);
+        definition.push_str("    (void)env;\n    (void)arg;\n");
+        for line in function.lines {
+            definition.push_str(&line);
+            definition.push('\n');
+        }
+        definition.push_str("}\n");
+        self.functions.push(definition);
+        index
+    }
+
+    /// Emits the statements computing the value of the node into the function, and returns the
+    /// variable holding it. Subexpressions are stored in variables in the order they are
+    /// evaluated, because C leaves the order of evaluating arguments unspecified.
+    fn expr(&mut self, f: &mut Function, node: &ASTNode<'_>) -> String {
+        let value = match node {
+            ASTNode::AbstractionNode { ident, body, .. } => {
+                let captures = free_vars(node);
+                let mut inner = Function::new(&captures);
+                inner.scope.push((ident.to_string(), "arg".to_string()));
+                self.deliver(&mut inner, body, Destination::Return);
+                let index = self.lift(inner);
+                let mut arguments = vec![format!("f{}", index), captures.len().to_string()];
+                arguments.extend(captures.iter().map(|name| f.lookup(name)));
+                format!("make_closure({})", arguments.join(", "))
+            }
+            ASTNode::ApplicationNode { left, right, .. } => {
+                let left = self.expr(f, left);
+                let right = self.expr(f, right);
+                format!("apply({}, {})", left, right)
+            }
+            ASTNode::IdentifierNode { name, .. } => format!("unfold({})", f.lookup(name)),
+            ASTNode::ConditionNode {
+                clause,
+                then_arm,
+                else_arm,
+                ..
+            } => {
+                let clause = self.expr(f, clause);
+                let result = self.temp();
+                f.line(format!("value {};", result));
+                self.condition(
+                    f,
+                    &clause,
+                    then_arm,
+                    else_arm,
+                    Destination::Variable(&result),
+                );
+                return result;
+            }
+            ASTNode::ArithmeticNode { op, expr, .. } => {
+                let expr = self.expr(f, expr);
+                match op {
+                    Operator::Succ => format!("succ({})", expr),
+                    Operator::Pred => format!("pred({})", expr),
+                }
+            }
+            ASTNode::IsZeroNode { expr, .. } => {
+                let expr = self.expr(f, expr);
+                format!("iszero({})", expr)
+            }
+            ASTNode::ValueNode { value, .. } => match value {
+                ast::Value::True => "make_bool(1)".to_string(),
+                ast::Value::False => "make_bool(0)".to_string(),
+                ast::Value::Zero => "make_nat(0)".to_string(),
+            },
+            ASTNode::ProjectionNode { target, attrib, .. } => {
+                let target = self.expr(f, target);
+                let field = intern(&mut self.fields, attrib);
+                format!("record_get({}, {})", target, field)
+            }
+            ASTNode::RecordNode { records, .. } => {
+                let mut arguments = vec![records.len().to_string()];
+                let mut names: Vec<&String> = records.keys().collect();
+                names.sort();
+                for name in names {
+                    let value = self.expr(f, &records[name]);
+                    arguments.push(intern(&mut self.fields, name).to_string());
+                    arguments.push(value);
+                }
+                format!("make_record({})", arguments.join(", "))
+            }
+            ASTNode::UpdateNode {
+                target, records, ..
+            }
+            | ASTNode::ExtensionNode {
+                target, records, ..
+            } => {
+                let mut record = self.expr(f, target);
+                let mut names: Vec<&String> = records.keys().collect();
+                names.sort();
+                for name in names {
+                    let value = self.expr(f, &records[name]);
+                    let field = intern(&mut self.fields, name);
+                    let updated = self.temp();
+                    f.line(format!(
+                        "value {} = record_set({}, {}, {});",
+                        updated, record, field, value
+                    ));
+                    record = updated;
+                }
+                return record;
+            }
+            ASTNode::RestrictionNode { target, attrib, .. } => {
+                let target = self.expr(f, target);
+                let field = intern(&mut self.fields, attrib);
+                format!("record_remove({}, {})", target, field)
+            }
+            ASTNode::MatchingNode {
+                to_match, cases, ..
+            } => {
+                let arms: Vec<(&Pattern, &ASTNode<'_>)> = cases
+                    .iter()
+                    .map(|(pattern, arm)| (pattern, &**arm))
+                    .collect();
+                let result = self.temp();
+                f.line(format!("value {};", result));
+                self.matching(f, to_match, &arms, Destination::Variable(&result));
+                return result;
+            }
+            ASTNode::TaggingNode { ident, value, .. } => {
+                let value = self.expr(f, value);
+                let tag = intern(&mut self.tags, ident);
+                format!("make_variant({}, {})", tag, value)
+            }
+            ASTNode::FixNode { point, .. } => {
+                let point = self.expr(f, point);
+                format!("fix({})", point)
+            }
+            ASTNode::LetNode {
+                pattern,
+                value,
+                body,
+                ..
+            } => {
+                let result = self.temp();
+                f.line(format!("value {};", result));
+                self.matching(f, value, &[(pattern, body)], Destination::Variable(&result));
+                return result;
+            }
+            ASTNode::LetRecNode { bindings, body, .. } => {
+                let bound = self.letrec(f, bindings);
+                let result = self.expr(f, body);
+                f.scope.truncate(bound);
+                return result;
+            }
+        };
+        let result = self.temp();
+        f.line(format!("value {} = {};", result, value));
+        result
+    }
+
+    /// Emits the statements passing the value of the node to the destination. Returning an
+    /// application leaves the call to the trampoline in `apply`, and the arms of conditions,
+    /// cases and the bodies of lets are in tail position as well.
+    fn deliver(&mut self, f: &mut Function, node: &ASTNode<'_>, destination: Destination<'_>) {
+        let result = match (node, destination) {
+            (_, Destination::Variable(result)) => {
+                let value = self.expr(f, node);
+                f.line(format!("{} = {};", result, value));
+                return;
+            }
+            (ASTNode::ApplicationNode { left, right, .. }, Destination::Return) => {
+                let left = self.expr(f, left);
+                let right = self.expr(f, right);
+                format!("tail_call({}, {})", left, right)
+            }
+            (
+                ASTNode::ConditionNode {
+                    clause,
+                    then_arm,
+                    else_arm,
+                    ..
+                },
+                Destination::Return,
+            ) => {
+                let clause = self.expr(f, clause);
+                return self.condition(f, &clause, then_arm, else_arm, destination);
+            }
+            (
+                ASTNode::MatchingNode {
+                    to_match, cases, ..
+                },
+                Destination::Return,
+            ) => {
+                let arms: Vec<(&Pattern, &ASTNode<'_>)> = cases
+                    .iter()
+                    .map(|(pattern, arm)| (pattern, &**arm))
+                    .collect();
+                return self.matching(f, to_match, &arms, destination);
+            }
+            (
+                ASTNode::LetNode {
+                    pattern,
+                    value,
+                    body,
+                    ..
+                },
+                Destination::Return,
+            ) => return self.matching(f, value, &[(pattern, body)], destination),
+            (ASTNode::LetRecNode { bindings, body, .. }, Destination::Return) => {
+                let bound = self.letrec(f, bindings);
+                self.deliver(f, body, destination);
+                f.scope.truncate(bound);
+                return;
+            }
+            (_, Destination::Return) => self.expr(f, node),
+        };
+        f.line(format!("return {};", result));
+    }
+
+    /// Emits a condition on the variable holding the value of the clause, passing the value of
+    /// the arm that is taken to the destination
+    fn condition(
+        &mut self,
+        f: &mut Function,
+        clause: &str,
+        then_arm: &ASTNode<'_>,
+        else_arm: &ASTNode<'_>,
+        destination: Destination<'_>,
+    ) {
+        f.line(format!("if ({}->as.boolean) {{", clause));
+        f.indent += 1;
+        self.deliver(f, then_arm, destination);
+        f.indent -= 1;
+        f.line("} else {".to_string());
+        f.indent += 1;
+        self.deliver(f, else_arm, destination);
+        f.indent -= 1;
+        f.line("}".to_string());
+    }
+
+    /// Emits a case expression, or a let which is a case with a single arm, by following the
+    /// decision tree of its patterns
+    fn matching(
+        &mut self,
+        f: &mut Function,
+        to_match: &ASTNode<'_>,
+        cases: &[(&Pattern, &ASTNode<'_>)],
+        destination: Destination<'_>,
+    ) {
+        let scrutinee = self.expr(f, to_match);
+        let tree = matching::compile(cases.iter().map(|(pattern, _)| *pattern), None);
+        self.decision(f, &tree, &scrutinee, cases, destination);
+    }
+
+    fn decision(
+        &mut self,
+        f: &mut Function,
+        tree: &Decision,
+        scrutinee: &str,
+        cases: &[(&Pattern, &ASTNode<'_>)],
+        destination: Destination<'_>,
+    ) {
+        match tree {
+            Decision::Fail => match destination {
+                Destination::Variable(_) => f.line("fail();".to_string()),
+                Destination::Return => f.line("return fail();".to_string()),
+            },
+            Decision::Leaf { arm, bindings } => {
+                let bound = f.scope.len();
+                for (name, path) in bindings {
+                    let access = self.path(scrutinee, path);
+                    f.scope.push((name.to_string(), access));
+                }
+                self.deliver(f, cases[*arm].1, destination);
+                f.scope.truncate(bound);
+            }
+            Decision::Switch {
+                path,
+                cases: switch,
+                default,
+            } => {
+                let component = self.temp();
+                let access = self.path(scrutinee, path);
+                f.line(format!("value {} = {};", component, access));
+                for (index, (constructor, case)) in switch.iter().enumerate() {
+                    let test = self.test(&component, constructor);
+                    if index == 0 {
+                        f.line(format!("if ({}) {{", test));
+                    } else {
+                        f.line(format!("}} else if ({}) {{", test));
+                    }
+                    f.indent += 1;
+                    self.decision(f, case, scrutinee, cases, destination);
+                    f.indent -= 1;
+                }
+                f.line("} else {".to_string());
+                f.indent += 1;
+                match default {
+                    Some(default) => self.decision(f, default, scrutinee, cases, destination),
+                    None => self.decision(f, &Decision::Fail, scrutinee, cases, destination),
+                }
+                f.indent -= 1;
+                f.line("}".to_string());
+            }
+        }
+    }
+
+    /// Returns the C expression reading the component of the scrutinee at the given path
+    fn path(&mut self, scrutinee: &str, path: &[Access]) -> String {
+        path.iter()
+            .fold(scrutinee.to_string(), |access, step| match step {
+                Access::Tag(_) => format!("untag({})", access),
+                Access::Pred => format!("pred({})", access),
+                Access::Field(name) => {
+                    format!("record_get({}, {})", access, intern(&mut self.fields, name))
+                }
+            })
+    }
+
+    fn test(&mut self, component: &str, constructor: &Constructor) -> String {
+        match constructor {
+            Constructor::True => format!("{}->as.boolean", component),
+            Constructor::False => format!("!{}->as.boolean", component),
+            Constructor::Zero => format!("{}->as.nat == 0", component),
+            Constructor::Succ => format!("{}->as.nat != 0", component),
+            Constructor::Tag(tag) => format!(
+                "{}->as.variant.tag == {}",
+                component,
+                intern(&mut self.tags, tag)
+            ),
+        }
+    }
+
+    /// Emits the group of a letrec and brings its names into scope, returning the size of the
+    /// scope before them. Each binding is lifted to a function computing its value from the
+    /// captured variables and the members of the group, which is run whenever the name is read.
+    fn letrec(
+        &mut self,
+        f: &mut Function,
+        bindings: &[(String, Option<TypeAssignment>, ASTNode<'_>)],
+    ) -> usize {
+        let names: Vec<String> = bindings
+            .iter()
+            .map(|(name, _, _)| name.to_string())
+            .collect();
+        let mut captures = HashSet::new();
+        for (_, _, value) in bindings {
+            captures.extend(free_vars(value));
+        }
+        let mut captures: Vec<String> = captures
+            .into_iter()
+            .filter(|name| !names.contains(name))
+            .collect();
+        captures.sort();
+
+        let mut environment = captures.clone();
+        environment.extend(names.iter().cloned());
+        let codes: Vec<String> = bindings
+            .iter()
+            .map(|(_, _, value)| {
+                let mut member = Function::new(&environment);
+                let result = self.expr(&mut member, value);
+                member.line(format!("return {};", result));
+                format!("f{}", self.lift(member))
+            })
+            .collect();
+        let table = format!("group{}", self.groups.len());
+        self.groups.push(format!(
+            "static code {}[] = {{{}}};\n",
+            table,
+            codes.join(", ")
+        ));
+
+        let group = self.temp();
+        f.line(format!(
+            "struct group *{} = make_group({}, {}, {});",
+            group,
+            table,
+            names.len(),
+            captures.len()
+        ));
+        for (index, name) in captures.iter().enumerate() {
+            let captured = f.lookup(name);
+            f.line(format!("{}->env[{}] = {};", group, index, captured));
+        }
+        let bound = f.scope.len();
+        for (index, name) in names.iter().enumerate() {
+            let member = format!("{}->env[{}]", group, captures.len() + index);
+            f.scope.push((name.to_string(), member));
+        }
+        bound
+    }
+}
+
+/// Returns the names of the free variables of the tree, sorted so captures get a stable order
+fn free_vars(node: &ASTNode<'_>) -> Vec<String> {
+    let mut names: Vec<String> = nameless::to_nameless(node)
+        .free_vars()
+        .into_iter()
+        .collect();
+    names.sort();
+    names
+}
+
+fn intern(table: &mut Vec<String>, name: &str) -> usize {
+    match table.iter().position(|known| known == name) {
+        Some(index) => index,
+        None => {
+            table.push(name.to_string());
+            table.len() - 1
+        }
+    }
+}
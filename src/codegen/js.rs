@@ -0,0 +1,758 @@
+use ast::{self, *};
+use matching::{self, Access, Constructor, Decision, Path};
+use nameless;
+use std::collections::HashMap;
+
+/// Names that can not be used for variables of the generated program, because they are reserved
+/// in strict mode JavaScript or refer to globals and helpers the program uses
+const RESERVED: &[&str] = &[
+    "arguments",
+    "await",
+    "break",
+    "call",
+    "case",
+    "catch",
+    "class",
+    "console",
+    "const",
+    "continue",
+    "debugger",
+    "default",
+    "delete",
+    "do",
+    "else",
+    "enum",
+    "Error",
+    "eval",
+    "export",
+    "extends",
+    "finally",
+    "fix",
+    "for",
+    "function",
+    "if",
+    "implements",
+    "import",
+    "in",
+    "Infinity",
+    "instanceof",
+    "interface",
+    "let",
+    "main",
+    "Math",
+    "NaN",
+    "new",
+    "null",
+    "Object",
+    "package",
+    "private",
+    "protected",
+    "public",
+    "return",
+    "show",
+    "static",
+    "String",
+    "super",
+    "switch",
+    "TailCall",
+    "this",
+    "throw",
+    "try",
+    "typeof",
+    "undefined",
+    "var",
+    "Variant",
+    "void",
+    "while",
+    "with",
+    "without",
+    "yield",
+];
+
+const VARIANT: &str = "class Variant {
+    constructor(tag, value) {
+        this.tag = tag;
+        this.value = value;
+    }
+}
+";
+
+const WITHOUT: &str = "function without(record, field) {
+    const { [field]: _, ...rest } = record;
+    return rest;
+}
+";
+
+const FIX: &str = "function fix(f) {
+    return call(f, (x) => fix(f)(x));
+}
+";
+
+/// Functions return calls in tail position as a `TailCall`, which `call` makes in a loop so
+/// recursion through tail calls runs in constant stack space
+const CALL: &str = "class TailCall {
+    constructor(callee, argument) {
+        this.callee = callee;
+        this.argument = argument;
+    }
+}
+
+function call(callee, argument) {
+    let result = callee(argument);
+    while (result instanceof TailCall) {
+        result = result.callee(result.argument);
+    }
+    return result;
+}
+";
+
+const SHOW: &str = "function show(value) {
+    if (typeof value === \"function\") {
+        return \"<function>\";
+    } else if (value instanceof Variant) {
+        return `<${value.tag}=${show(value.value)}>`;
+    } else if (typeof value === \"object\") {
+        const fields = Object.keys(value).sort().map((field) => `${field}=${show(value[field])}`);
+        return `{${fields.join(\", \")}}`;
+    }
+    return String(value);
+}
+";
+
+/// Recursion that is not in tail position nests calls on the stack, so under Node.js the program
+/// runs on a worker thread with a stack of a gigabyte
+const RUN: &str =
+    "const threads = typeof require === \"function\" ? require(\"worker_threads\") : null;
+if (threads && threads.isMainThread) {
+    new threads.Worker(__filename, { resourceLimits: { stackSizeMb: 1024 } });
+} else {
+    console.log(show(main()));
+}
+";
+
+/// Compiles a typechecked abstract syntax tree into a JavaScript program printing its value like
+/// the vm backend of the interpreter does. Abstractions become arrow functions, records become
+/// objects and variants become instances of a small `Variant` class. Recursive functions become
+/// named functions, which loop instead of calling themselves when that does not change their
+/// meaning. Other calls in tail position go through a trampoline, so JavaScript engines without
+/// proper tail calls run them in constant stack space too.
+///
+/// # Panics
+/// Throws a panic when the tree refers to unknown variables, which the typechecker rules out
+pub fn compile(node: &ASTNode<'_>) -> String {
+    let mut generator = Generator::default();
+    let mut lines = Vec::new();
+    generator.block(node, 1, Exit::Value, &mut lines);
+
+    let mut out = String::from("// Generated by lambda-rs\n\"use strict\";\n\n");
+    out.push_str(VARIANT);
+    if generator.uses_without {
+        out.push('\n');
+        out.push_str(WITHOUT);
+    }
+    if generator.uses_call || generator.uses_fix {
+        out.push('\n');
+        out.push_str(CALL);
+    }
+    if generator.uses_fix {
+        out.push('\n');
+        out.push_str(FIX);
+    }
+    out.push('\n');
+    out.push_str(SHOW);
+    out.push_str("\nfunction main() {\n");
+    for line in lines {
+        out.push_str(&line);
+        out.push('\n');
+    }
+    out.push_str("}\n\n");
+
+    out.push_str(RUN);
+    out
+}
+
+/// Precedences of the expressions that are generated, an operand is put in parentheses when its
+/// precedence is lower than the one its position requires
+const PRIMARY: u8 = 20;
+const ADDITIVE: u8 = 13;
+const EQUALITY: u8 = 10;
+const CONDITIONAL: u8 = 3;
+const FUNCTION: u8 = 2;
+
+/// Generated expression with its precedence
+struct Js {
+    text: String,
+    precedence: u8,
+}
+
+impl Js {
+    fn new(text: String, precedence: u8) -> Js {
+        Js { text, precedence }
+    }
+
+    fn at(self, precedence: u8) -> String {
+        if self.precedence < precedence {
+            format!("({})", self.text)
+        } else {
+            self.text
+        }
+    }
+}
+
+/// Name of the source program in scope, with the expression it is read with. Every binding gets
+/// an id, so calls of a looping function can be told apart from calls of a name shadowing it.
+struct Binding {
+    name: String,
+    js: String,
+    id: usize,
+}
+
+/// Recursive function that is generated as a loop, its tail calls assign the parameter
+struct Loop {
+    id: usize,
+    param: String,
+}
+
+/// How the statements that are generated return the value of a block
+#[derive(Clone, Copy)]
+enum Exit<'l> {
+    /// Returns a value, as `main` and the functions that are called right away do
+    Value,
+    /// Returns from a function of the source program, which returns the calls in tail position
+    /// for `call` to make instead of making them
+    Function,
+    /// Returns from a function generated as the loop, or continues the loop for calls of itself
+    Loop(&'l Loop),
+}
+
+#[derive(Default)]
+struct Generator {
+    /// How often each name has been declared, later declarations are numbered. Identifiers of
+    /// the source program do not contain digits, so the numbered names do not clash with them.
+    declared: HashMap<String, usize>,
+    scope: Vec<Binding>,
+    bindings: usize,
+    uses_call: bool,
+    uses_fix: bool,
+    uses_without: bool,
+}
+
+impl Generator {
+    /// Returns a JavaScript name for a new variable, distinct from all names declared before
+    fn declare(&mut self, name: &str) -> String {
+        let count = self.declared.entry(name.to_string()).or_insert(0);
+        *count += 1;
+        if *count == 1 && !RESERVED.contains(&name) {
+            name.to_string()
+        } else {
+            format!("{}{}", name, count)
+        }
+    }
+
+    /// Brings a name into scope and returns the id of the binding
+    fn bind(&mut self, name: &str, js: String) -> usize {
+        self.bindings += 1;
+        self.scope.push(Binding {
+            name: name.to_string(),
+            js,
+            id: self.bindings,
+        });
+        self.bindings
+    }
+
+    fn lookup(&self, name: &str) -> &Binding {
+        self.scope
+            .iter()
+            .rev()
+            .find(|binding| binding.name == name)
+            .expect("Bug in typechecker: came across unknown variable")
+    }
+
+    /// Emits statements returning the value of the node. Functions return calls in tail position
+    /// as a `TailCall`, except that in a looping function, calls of the function itself assign
+    /// its parameter and continue with the next iteration.
+    fn block(&mut self, node: &ASTNode<'_>, indent: usize, exit: Exit<'_>, out: &mut Vec<String>) {
+        let pad = "    ".repeat(indent);
+        match node {
+            ASTNode::ConditionNode {
+                clause,
+                then_arm,
+                else_arm,
+                ..
+            } => {
+                let clause = self.expr(clause, indent).text;
+                out.push(format!("{}if ({}) {{", pad, clause));
+                self.block(then_arm, indent + 1, exit, out);
+                let mut rest = &**else_arm;
+                while let ASTNode::ConditionNode {
+                    clause,
+                    then_arm,
+                    else_arm,
+                    ..
+                } = rest
+                {
+                    let clause = self.expr(clause, indent).text;
+                    out.push(format!("{}}} else if ({}) {{", pad, clause));
+                    self.block(then_arm, indent + 1, exit, out);
+                    rest = else_arm;
+                }
+                out.push(format!("{}}} else {{", pad));
+                self.block(rest, indent + 1, exit, out);
+                out.push(format!("{}}}", pad));
+            }
+            ASTNode::LetNode {
+                pattern: Pattern::Variable(name),
+                value,
+                body,
+                ..
+            } => {
+                let value = self.expr(value, indent).text;
+                let js = self.declare(name);
+                out.push(format!("{}const {} = {};", pad, js, value));
+                let bound = self.scope.len();
+                self.bind(name, js);
+                self.block(body, indent, exit, out);
+                self.scope.truncate(bound);
+            }
+            ASTNode::LetNode {
+                pattern,
+                value,
+                body,
+                ..
+            } => self.matching(value, &[(pattern, body)], indent, exit, out),
+            ASTNode::MatchingNode {
+                to_match, cases, ..
+            } => {
+                let arms: Vec<(&Pattern, &ASTNode<'_>)> = cases
+                    .iter()
+                    .map(|(pattern, arm)| (pattern, &**arm))
+                    .collect();
+                self.matching(to_match, &arms, indent, exit, out);
+            }
+            ASTNode::LetRecNode { bindings, body, .. } => {
+                let bound = self.scope.len();
+                let ids: Vec<usize> = bindings
+                    .iter()
+                    .map(|(name, _, value)| {
+                        let js = self.declare(name);
+                        match value {
+                            ASTNode::AbstractionNode { .. } => self.bind(name, js),
+                            _ => self.bind(name, format!("{}()", js)),
+                        }
+                    })
+                    .collect();
+                for ((_, _, value), id) in bindings.iter().zip(ids) {
+                    let js = self.scope.iter().find(|b| b.id == id).unwrap().js.clone();
+                    let js = js.trim_end_matches("()").to_string();
+                    out.extend(self.function(&js, id, value, indent));
+                    out.push(String::new());
+                }
+                self.block(body, indent, exit, out);
+                self.scope.truncate(bound);
+            }
+            ASTNode::ApplicationNode { left, right, .. } => match (left.as_ref(), exit) {
+                (ASTNode::IdentifierNode { name, .. }, Exit::Loop(lp))
+                    if self.lookup(name).id == lp.id =>
+                {
+                    let argument = self.expr(right, indent).text;
+                    out.push(format!("{}{} = {};", pad, lp.param, argument));
+                    out.push(format!("{}continue;", pad));
+                }
+                (_, Exit::Value) => {
+                    let value = self.expr(node, indent).text;
+                    out.push(format!("{}return {};", pad, value));
+                }
+                _ => {
+                    let call = self.tail_call(left, right, indent);
+                    out.push(format!("{}return {};", pad, call));
+                }
+            },
+            _ => {
+                let value = self.expr(node, indent).text;
+                out.push(format!("{}return {};", pad, value));
+            }
+        }
+    }
+
+    /// Emits a named function for a recursive binding. The name is already in scope when the
+    /// binding is an abstraction, bindings of other values are functions without parameters.
+    fn function(&mut self, js: &str, id: usize, value: &ASTNode<'_>, indent: usize) -> Vec<String> {
+        let pad = "    ".repeat(indent);
+        let mut out = Vec::new();
+        match value {
+            ASTNode::AbstractionNode { ident, body, .. } => {
+                let param = self.declare(ident);
+                out.push(format!("{}function {}({}) {{", pad, js, param));
+                let name = self.scope.iter().find(|b| b.id == id).unwrap().name.clone();
+                let bound = self.scope.len();
+                self.bind(ident, param.clone());
+                if has_tail_call(body, &name) && ident != &name && !captures(body, ident) {
+                    out.push(format!("{}    while (true) {{", pad));
+                    let lp = Loop { id, param };
+                    self.block(body, indent + 2, Exit::Loop(&lp), &mut out);
+                    out.push(format!("{}    }}", pad));
+                } else {
+                    self.block(body, indent + 1, Exit::Function, &mut out);
+                }
+                self.scope.truncate(bound);
+            }
+            _ => {
+                out.push(format!("{}function {}() {{", pad, js));
+                self.block(value, indent + 1, Exit::Value, &mut out);
+            }
+        }
+        out.push(format!("{}}}", pad));
+        out
+    }
+
+    /// Emits a case expression, or a let binding a pattern, by following the decision tree of its
+    /// patterns. The value that is matched is stored in a constant unless it is a variable.
+    fn matching(
+        &mut self,
+        to_match: &ASTNode<'_>,
+        cases: &[(&Pattern, &ASTNode<'_>)],
+        indent: usize,
+        exit: Exit<'_>,
+        out: &mut Vec<String>,
+    ) {
+        let scrutinee = match to_match {
+            ASTNode::IdentifierNode { name, .. } => self.lookup(name).js.clone(),
+            _ => {
+                let value = self.expr(to_match, indent).text;
+                let js = self.declare("matched");
+                out.push(format!(
+                    "{}const {} = {};",
+                    "    ".repeat(indent),
+                    js,
+                    value
+                ));
+                js
+            }
+        };
+        let tree = matching::compile(cases.iter().map(|(pattern, _)| *pattern), None);
+        self.decision(&tree, &scrutinee, cases, indent, exit, out);
+    }
+
+    fn decision(
+        &mut self,
+        tree: &Decision,
+        scrutinee: &str,
+        cases: &[(&Pattern, &ASTNode<'_>)],
+        indent: usize,
+        exit: Exit<'_>,
+        out: &mut Vec<String>,
+    ) {
+        let pad = "    ".repeat(indent);
+        match tree {
+            Decision::Fail => out.push(format!(
+                "{}throw new Error(\"No arm of case matches the argument\");",
+                pad
+            )),
+            Decision::Leaf { arm, bindings } => {
+                // The bindings come in the order the patterns were taken apart, which depends on
+                // the iteration order of record patterns, so they are sorted for stable output
+                let mut bindings: Vec<&(String, Path)> = bindings.iter().collect();
+                bindings.sort_by_key(|(name, _)| name);
+                let bound = self.scope.len();
+                for (name, path) in bindings {
+                    let js = self.declare(name);
+                    out.push(format!(
+                        "{}const {} = {};",
+                        pad,
+                        js,
+                        access(scrutinee, path)
+                    ));
+                    self.bind(name, js);
+                }
+                self.block(cases[*arm].1, indent, exit, out);
+                self.scope.truncate(bound);
+            }
+            Decision::Switch {
+                path,
+                cases: switch,
+                default,
+            } => {
+                let component = access(scrutinee, path);
+                for (index, (constructor, case)) in switch.iter().enumerate() {
+                    let test = test(&component, constructor);
+                    if index == 0 {
+                        out.push(format!("{}if ({}) {{", pad, test));
+                    } else {
+                        out.push(format!("{}}} else if ({}) {{", pad, test));
+                    }
+                    self.decision(case, scrutinee, cases, indent + 1, exit, out);
+                }
+                out.push(format!("{}}} else {{", pad));
+                match default {
+                    Some(default) => {
+                        self.decision(default, scrutinee, cases, indent + 1, exit, out)
+                    }
+                    None => out.push(format!(
+                        "{}    throw new Error(\"No arm of case matches the argument\");",
+                        pad
+                    )),
+                }
+                out.push(format!("{}}}", pad));
+            }
+        }
+    }
+
+    /// Returns the expression computing the value of the node. Terms that need statements are
+    /// put in a function that is called right away.
+    fn expr(&mut self, node: &ASTNode<'_>, indent: usize) -> Js {
+        match node {
+            ASTNode::AbstractionNode { ident, body, .. } => {
+                let param = self.declare(ident);
+                let bound = self.scope.len();
+                self.bind(ident, param.clone());
+                let text = match body.as_ref() {
+                    ASTNode::ApplicationNode { left, right, .. } => {
+                        format!("({}) => {}", param, self.tail_call(left, right, indent))
+                    }
+                    ASTNode::ConditionNode { .. } if calls_in_tail_position(body) => {
+                        let mut lines = Vec::new();
+                        self.block(body, indent + 1, Exit::Function, &mut lines);
+                        lines.push(format!("{}}}", "    ".repeat(indent)));
+                        format!("({}) => {{\n{}", param, lines.join("\n"))
+                    }
+                    ASTNode::LetNode { .. }
+                    | ASTNode::LetRecNode { .. }
+                    | ASTNode::MatchingNode { .. } => {
+                        let mut lines = Vec::new();
+                        self.block(body, indent + 1, Exit::Function, &mut lines);
+                        lines.push(format!("{}}}", "    ".repeat(indent)));
+                        format!("({}) => {{\n{}", param, lines.join("\n"))
+                    }
+                    _ => {
+                        let body = self.expr(body, indent);
+                        if body.text.starts_with('{') {
+                            format!("({}) => ({})", param, body.text)
+                        } else {
+                            format!("({}) => {}", param, body.at(FUNCTION))
+                        }
+                    }
+                };
+                self.scope.truncate(bound);
+                Js::new(text, FUNCTION)
+            }
+            ASTNode::ApplicationNode { left, right, .. } => {
+                self.uses_call = true;
+                let left = self.expr(left, indent).text;
+                let right = self.expr(right, indent).text;
+                Js::new(format!("call({}, {})", left, right), PRIMARY)
+            }
+            ASTNode::IdentifierNode { name, .. } => Js::new(self.lookup(name).js.clone(), PRIMARY),
+            ASTNode::ConditionNode {
+                clause,
+                then_arm,
+                else_arm,
+                ..
+            } => {
+                let clause = self.expr(clause, indent).at(CONDITIONAL + 1);
+                let then_arm = self.expr(then_arm, indent).at(CONDITIONAL);
+                let else_arm = self.expr(else_arm, indent).at(CONDITIONAL);
+                Js::new(
+                    format!("{} ? {} : {}", clause, then_arm, else_arm),
+                    CONDITIONAL,
+                )
+            }
+            ASTNode::ArithmeticNode { op, expr, .. } => {
+                let expr = self.expr(expr, indent);
+                match (op, expr.text.parse::<usize>()) {
+                    (Operator::Succ, Ok(x)) => Js::new((x + 1).to_string(), PRIMARY),
+                    (Operator::Pred, Ok(x)) => Js::new(x.saturating_sub(1).to_string(), PRIMARY),
+                    (Operator::Succ, _) => Js::new(format!("{} + 1", expr.at(ADDITIVE)), ADDITIVE),
+                    (Operator::Pred, _) => {
+                        Js::new(format!("Math.max({} - 1, 0)", expr.text), PRIMARY)
+                    }
+                }
+            }
+            ASTNode::IsZeroNode { expr, .. } => {
+                let expr = self.expr(expr, indent).at(EQUALITY + 1);
+                Js::new(format!("{} === 0", expr), EQUALITY)
+            }
+            ASTNode::ValueNode { value, .. } => {
+                let text = match value {
+                    ast::Value::True => "true",
+                    ast::Value::False => "false",
+                    ast::Value::Zero => "0",
+                };
+                Js::new(text.to_string(), PRIMARY)
+            }
+            ASTNode::ProjectionNode { target, attrib, .. } => {
+                let target = self.expr(target, indent).at(PRIMARY);
+                Js::new(format!("{}.{}", target, attrib), PRIMARY)
+            }
+            ASTNode::RecordNode { records, .. } => {
+                let fields = self.fields(records, indent);
+                if fields.is_empty() {
+                    Js::new("{}".to_string(), PRIMARY)
+                } else {
+                    Js::new(format!("{{ {} }}", fields.join(", ")), PRIMARY)
+                }
+            }
+            ASTNode::UpdateNode {
+                target, records, ..
+            }
+            | ASTNode::ExtensionNode {
+                target, records, ..
+            } => {
+                let mut fields = vec![format!("...{}", self.expr(target, indent).text)];
+                fields.extend(self.fields(records, indent));
+                Js::new(format!("{{ {} }}", fields.join(", ")), PRIMARY)
+            }
+            ASTNode::RestrictionNode { target, attrib, .. } => {
+                self.uses_without = true;
+                let target = self.expr(target, indent).text;
+                Js::new(format!("without({}, \"{}\")", target, attrib), PRIMARY)
+            }
+            ASTNode::TaggingNode { ident, value, .. } => {
+                let value = self.expr(value, indent).text;
+                Js::new(format!("new Variant(\"{}\", {})", ident, value), PRIMARY)
+            }
+            ASTNode::FixNode { point, .. } => match point.as_ref() {
+                ASTNode::AbstractionNode { ident, body, .. }
+                    if matches!(**body, ASTNode::AbstractionNode { .. }) =>
+                {
+                    let js = self.declare(ident);
+                    let bound = self.scope.len();
+                    let id = self.bind(ident, js.clone());
+                    let lines = self.function(&js, id, body, indent);
+                    self.scope.truncate(bound);
+                    Js::new(lines.join("\n").trim_start().to_string(), FUNCTION)
+                }
+                _ => {
+                    self.uses_fix = true;
+                    let point = self.expr(point, indent).text;
+                    Js::new(format!("fix({})", point), PRIMARY)
+                }
+            },
+            ASTNode::LetNode { .. } | ASTNode::LetRecNode { .. } | ASTNode::MatchingNode { .. } => {
+                let mut lines = Vec::new();
+                self.block(node, indent + 1, Exit::Value, &mut lines);
+                Js::new(
+                    format!(
+                        "(() => {{\n{}\n{}}})()",
+                        lines.join("\n"),
+                        "    ".repeat(indent)
+                    ),
+                    PRIMARY,
+                )
+            }
+        }
+    }
+
+    /// Returns the expression a function returns in place of making a call in tail position
+    fn tail_call(&mut self, left: &ASTNode<'_>, right: &ASTNode<'_>, indent: usize) -> String {
+        self.uses_call = true;
+        let left = self.expr(left, indent).text;
+        let right = self.expr(right, indent).text;
+        format!("new TailCall({}, {})", left, right)
+    }
+
+    /// Returns the fields of a record literal in alphabetical order
+    fn fields(&mut self, records: &HashMap<String, ASTNode<'_>>, indent: usize) -> Vec<String> {
+        let mut names: Vec<&String> = records.keys().collect();
+        names.sort();
+        names
+            .into_iter()
+            .map(|name| format!("{}: {}", name, self.expr(&records[name], indent).text))
+            .collect()
+    }
+}
+
+/// Returns the expression reading the component of the scrutinee at the given path
+fn access(scrutinee: &str, path: &[Access]) -> String {
+    path.iter()
+        .fold(scrutinee.to_string(), |access, step| match step {
+            Access::Tag(_) => format!("{}.value", access),
+            Access::Pred => format!("({} - 1)", access),
+            Access::Field(name) => format!("{}.{}", access, name),
+        })
+}
+
+fn test(component: &str, constructor: &Constructor) -> String {
+    match constructor {
+        Constructor::True => component.to_string(),
+        Constructor::False => format!("!{}", component),
+        Constructor::Zero => format!("{} === 0", component),
+        Constructor::Succ => format!("{} !== 0", component),
+        Constructor::Tag(tag) => format!("{}.tag === \"{}\"", component, tag),
+    }
+}
+
+/// Whether the function with the given name is called in a tail position of the tree
+fn has_tail_call(node: &ASTNode<'_>, name: &str) -> bool {
+    let binds = |pattern: &Pattern| nameless::pattern_vars(pattern).iter().any(|n| n == name);
+    match node {
+        ASTNode::ApplicationNode { left, .. } => {
+            matches!(left.as_ref(), ASTNode::IdentifierNode { name: callee, .. } if callee == name)
+        }
+        ASTNode::ConditionNode {
+            then_arm, else_arm, ..
+        } => has_tail_call(then_arm, name) || has_tail_call(else_arm, name),
+        ASTNode::LetNode { pattern, body, .. } => !binds(pattern) && has_tail_call(body, name),
+        ASTNode::MatchingNode { cases, .. } => cases
+            .iter()
+            .any(|(pattern, arm)| !binds(pattern) && has_tail_call(arm, name)),
+        ASTNode::LetRecNode { bindings, body, .. } => {
+            bindings.iter().all(|(bound, _, _)| bound != name) && has_tail_call(body, name)
+        }
+        _ => false,
+    }
+}
+
+/// Whether a call is made in a tail position of the tree
+fn calls_in_tail_position(node: &ASTNode<'_>) -> bool {
+    match node {
+        ASTNode::ApplicationNode { .. } => true,
+        ASTNode::ConditionNode {
+            then_arm, else_arm, ..
+        } => calls_in_tail_position(then_arm) || calls_in_tail_position(else_arm),
+        ASTNode::LetNode { body, .. } => calls_in_tail_position(body),
+        ASTNode::MatchingNode { cases, .. } => {
+            cases.iter().any(|(_, arm)| calls_in_tail_position(arm))
+        }
+        ASTNode::LetRecNode { body, .. } => calls_in_tail_position(body),
+        _ => false,
+    }
+}
+
+/// Whether a function inside the tree may capture the variable. Loops assign their parameter, so
+/// a function capturing it would see the value of a later iteration.
+fn captures(node: &ASTNode<'_>, name: &str) -> bool {
+    match node {
+        ASTNode::AbstractionNode { .. } | ASTNode::FixNode { .. } => node.mentions(name),
+        ASTNode::LetRecNode { bindings, body, .. } => {
+            bindings.iter().any(|(_, _, value)| value.mentions(name)) || captures(body, name)
+        }
+        ASTNode::IdentifierNode { .. } | ASTNode::ValueNode { .. } => false,
+        ASTNode::ApplicationNode { left, right, .. } => {
+            captures(left, name) || captures(right, name)
+        }
+        ASTNode::ConditionNode {
+            clause,
+            then_arm,
+            else_arm,
+            ..
+        } => captures(clause, name) || captures(then_arm, name) || captures(else_arm, name),
+        ASTNode::ArithmeticNode { expr, .. } | ASTNode::IsZeroNode { expr, .. } => {
+            captures(expr, name)
+        }
+        ASTNode::ProjectionNode { target, .. } | ASTNode::RestrictionNode { target, .. } => {
+            captures(target, name)
+        }
+        ASTNode::RecordNode { records, .. } => records.values().any(|n| captures(n, name)),
+        ASTNode::UpdateNode {
+            target, records, ..
+        }
+        | ASTNode::ExtensionNode {
+            target, records, ..
+        } => captures(target, name) || records.values().any(|n| captures(n, name)),
+        ASTNode::MatchingNode {
+            to_match, cases, ..
+        } => captures(to_match, name) || cases.iter().any(|(_, arm)| captures(arm, name)),
+        ASTNode::TaggingNode { value, .. } => captures(value, name),
+        ASTNode::LetNode { value, body, .. } => captures(value, name) || captures(body, name),
+    }
+}
@@ -0,0 +1,6 @@
+/// Translation of programs to C source, compiled into standalone executables
+pub mod c;
+/// Translation of programs to readable JavaScript
+pub mod js;
+/// Translation of programs to WebAssembly modules in text format
+pub mod wat;
@@ -0,0 +1,292 @@
+/* Runtime of programs compiled from lambda-rs. Every value is a pointer to an object on the
+ * heap. Objects are never freed, the programs are expected to be small and short lived. The
+ * runtime is not static, so programs that leave parts of it unused compile without warnings. The
+ * tables field_names and tag_names are defined by the generated code before the runtime. */
+
+enum kind { NAT, BOOL, CLOSURE, FIX, REC, RECORD, VARIANT };
+
+typedef struct object *value;
+
+/* Code of a lifted function, called with its captured variables and its argument */
+typedef value (*code)(value *env, value arg);
+
+/* Recursive bindings of a letrec, the environment holds the captured variables followed by
+ * the members of the group */
+struct group {
+    code *codes;
+    value *env;
+};
+
+struct field {
+    int id;
+    value value;
+};
+
+struct object {
+    enum kind kind;
+    union {
+        unsigned long nat;
+        int boolean;
+        struct {
+            code code;
+            value *env;
+        } closure;
+        /* Fixpoint of a closure, it is unfolded when it is read from a variable */
+        value fix;
+        /* Member of a recursive group, it is unfolded when it is read from a variable */
+        struct {
+            struct group *group;
+            int index;
+        } rec;
+        struct {
+            int size;
+            struct field *fields;
+        } record;
+        struct {
+            int tag;
+            value value;
+        } variant;
+    } as;
+};
+
+void *allocate(size_t size) {
+    void *memory = malloc(size ? size : 1);
+    if (!memory) {
+        fputs("Out of memory\n", stderr);
+        exit(1);
+    }
+    return memory;
+}
+
+value make(enum kind kind) {
+    value v = allocate(sizeof(struct object));
+    v->kind = kind;
+    return v;
+}
+
+value make_nat(unsigned long x) {
+    value v = make(NAT);
+    v->as.nat = x;
+    return v;
+}
+
+value make_bool(int x) {
+    value v = make(BOOL);
+    v->as.boolean = x;
+    return v;
+}
+
+value make_closure(code code, int size, ...) {
+    va_list captured;
+    int i;
+    value v = make(CLOSURE);
+    v->as.closure.code = code;
+    v->as.closure.env = allocate(size * sizeof(value));
+    va_start(captured, size);
+    for (i = 0; i < size; i++) {
+        v->as.closure.env[i] = va_arg(captured, value);
+    }
+    va_end(captured);
+    return v;
+}
+
+/* Creates a group with room for the captured variables, which the caller fills in */
+struct group *make_group(code *codes, int members, int captured) {
+    int i;
+    struct group *g = allocate(sizeof(struct group));
+    g->codes = codes;
+    g->env = allocate((captured + members) * sizeof(value));
+    for (i = 0; i < members; i++) {
+        value member = make(REC);
+        member->as.rec.group = g;
+        member->as.rec.index = i;
+        g->env[captured + i] = member;
+    }
+    return g;
+}
+
+/* Functions return this marker instead of making a call in tail position, leaving the callee
+ * and its argument in tail_function and tail_argument for apply to call */
+struct object tail_marker;
+value tail_function, tail_argument;
+
+value tail_call(value f, value arg) {
+    tail_function = f;
+    tail_argument = arg;
+    return &tail_marker;
+}
+
+/* Calls the closure, and then the calls it leaves in tail position, so a loop written as
+ * recursion runs in constant stack space */
+value apply(value f, value arg) {
+    value result = f->as.closure.code(f->as.closure.env, arg);
+    while (result == &tail_marker) {
+        result = tail_function->as.closure.code(tail_function->as.closure.env, tail_argument);
+    }
+    return result;
+}
+
+value fix(value f) {
+    value point = make(FIX);
+    point->as.fix = f;
+    return apply(f, point);
+}
+
+/* Returns the value of a variable, recursive bindings are unfolded by running their code */
+value unfold(value v) {
+    switch (v->kind) {
+    case FIX:
+        return apply(v->as.fix, v);
+    case REC:
+        return v->as.rec.group->codes[v->as.rec.index](v->as.rec.group->env, NULL);
+    default:
+        return v;
+    }
+}
+
+value succ(value v) {
+    return make_nat(v->as.nat + 1);
+}
+
+value pred(value v) {
+    return v->as.nat == 0 ? v : make_nat(v->as.nat - 1);
+}
+
+value iszero(value v) {
+    return make_bool(v->as.nat == 0);
+}
+
+/* Creates a record from pairs of field ids and values, the fields are kept sorted by id */
+value make_record(int size, ...) {
+    va_list fields;
+    int i, j;
+    value v = make(RECORD);
+    v->as.record.size = size;
+    v->as.record.fields = allocate(size * sizeof(struct field));
+    va_start(fields, size);
+    for (i = 0; i < size; i++) {
+        struct field field;
+        field.id = va_arg(fields, int);
+        field.value = va_arg(fields, value);
+        for (j = i; j > 0 && v->as.record.fields[j - 1].id > field.id; j--) {
+            v->as.record.fields[j] = v->as.record.fields[j - 1];
+        }
+        v->as.record.fields[j] = field;
+    }
+    va_end(fields);
+    return v;
+}
+
+value record_get(value r, int id) {
+    int i;
+    for (i = 0; i < r->as.record.size; i++) {
+        if (r->as.record.fields[i].id == id) {
+            return r->as.record.fields[i].value;
+        }
+    }
+    fputs("Projected field was not found in record\n", stderr);
+    exit(1);
+}
+
+/* Returns a copy of the record in which the field is set, adding it when it is missing */
+value record_set(value r, int id, value field) {
+    int i, j = 0, size = r->as.record.size;
+    value v = make(RECORD);
+    v->as.record.fields = allocate((size + 1) * sizeof(struct field));
+    for (i = 0; i < size && r->as.record.fields[i].id < id; i++) {
+        v->as.record.fields[j++] = r->as.record.fields[i];
+    }
+    v->as.record.fields[j].id = id;
+    v->as.record.fields[j++].value = field;
+    if (i < size && r->as.record.fields[i].id == id) {
+        i++;
+    }
+    for (; i < size; i++) {
+        v->as.record.fields[j++] = r->as.record.fields[i];
+    }
+    v->as.record.size = j;
+    return v;
+}
+
+value record_remove(value r, int id) {
+    int i, j = 0;
+    value v = make(RECORD);
+    v->as.record.fields = allocate(r->as.record.size * sizeof(struct field));
+    for (i = 0; i < r->as.record.size; i++) {
+        if (r->as.record.fields[i].id != id) {
+            v->as.record.fields[j++] = r->as.record.fields[i];
+        }
+    }
+    v->as.record.size = j;
+    return v;
+}
+
+value make_variant(int tag, value inner) {
+    value v = make(VARIANT);
+    v->as.variant.tag = tag;
+    v->as.variant.value = inner;
+    return v;
+}
+
+value untag(value v) {
+    return v->as.variant.value;
+}
+
+/* Returns a value so it can be returned where a function would return the value of an arm */
+value fail(void) {
+    fputs("No arm of case matches the argument\n", stderr);
+    exit(1);
+}
+
+int by_name(const void *left, const void *right) {
+    const struct field *l = left, *r = right;
+    return strcmp(field_names[l->id], field_names[r->id]);
+}
+
+void print(value v) {
+    int i;
+    struct field *fields;
+    switch (v->kind) {
+    case NAT:
+        printf("%lu", v->as.nat);
+        break;
+    case BOOL:
+        fputs(v->as.boolean ? "true" : "false", stdout);
+        break;
+    case RECORD:
+        fields = allocate(v->as.record.size * sizeof(struct field));
+        memcpy(fields, v->as.record.fields, v->as.record.size * sizeof(struct field));
+        qsort(fields, v->as.record.size, sizeof(struct field), by_name);
+        putchar('{');
+        for (i = 0; i < v->as.record.size; i++) {
+            printf("%s%s=", i ? ", " : "", field_names[fields[i].id]);
+            print(fields[i].value);
+        }
+        putchar('}');
+        break;
+    case VARIANT:
+        printf("<%s=", tag_names[v->as.variant.tag]);
+        print(v->as.variant.value);
+        putchar('>');
+        break;
+    default:
+        fputs("<function>", stdout);
+    }
+}
+
+/* Recursion that is not in tail position nests calls on the C stack, so the program runs on a
+ * thread with a stack of a gigabyte where POSIX threads are available, which most systems only
+ * reserve as it is used. Elsewhere it runs on the stack of main. */
+void run(void *(*program)(void *)) {
+#if defined(__unix__) || defined(__APPLE__)
+    pthread_attr_t attributes;
+    pthread_t thread;
+    if (pthread_attr_init(&attributes) == 0 &&
+        pthread_attr_setstacksize(&attributes, (size_t)1 << 30) == 0 &&
+        pthread_create(&thread, &attributes, program, NULL) == 0) {
+        pthread_join(thread, NULL);
+        return;
+    }
+#endif
+    program(NULL);
+}
@@ -0,0 +1,599 @@
+  ;; Runtime of programs compiled from lambda-rs. Every value is the address of an object on a
+  ;; bump allocated heap, starting with its kind:
+  ;;   0 Nat      [kind, number]
+  ;;   1 Bool     [kind, 0 or 1]
+  ;;   2 Closure  [kind, table index of the code, address of the captured variables]
+  ;;   3 Fix      [kind, closure], unfolded when it is read from a variable
+  ;;   4 Rec      [kind, table index of the code, address of the group], unfolded likewise
+  ;;   5 Record   [kind, size, (field id, value) sorted by field id...]
+  ;;   6 Variant  [kind, tag id, value]
+  ;; Objects are never freed, the heap grows until the program finishes.
+
+  (func $alloc (param $size i32) (result i32)
+    (local $address i32)
+    global.get $heap
+    local.set $address
+    global.get $heap
+    local.get $size
+    i32.add
+    global.set $heap
+    block $done
+      loop $grow
+        global.get $heap
+        memory.size
+        i32.const 16
+        i32.shl
+        i32.le_u
+        br_if $done
+        i32.const 1
+        memory.grow
+        i32.const -1
+        i32.eq
+        if
+          unreachable
+        end
+        br $grow
+      end
+    end
+    local.get $address)
+
+  (func $object (param $kind i32) (param $first i32) (param $second i32) (result i32)
+    (local $address i32)
+    i32.const 12
+    call $alloc
+    local.set $address
+    local.get $address
+    local.get $kind
+    i32.store
+    local.get $address
+    local.get $first
+    i32.store offset=4
+    local.get $address
+    local.get $second
+    i32.store offset=8
+    local.get $address)
+
+  (func $nat (param $x i32) (result i32)
+    i32.const 0
+    local.get $x
+    i32.const 0
+    call $object)
+
+  (func $bool (param $x i32) (result i32)
+    i32.const 1
+    local.get $x
+    i32.const 0
+    call $object)
+
+  (func $closure (param $code i32) (param $env i32) (result i32)
+    i32.const 2
+    local.get $code
+    local.get $env
+    call $object)
+
+  (func $rec (param $code i32) (param $group i32) (result i32)
+    i32.const 4
+    local.get $code
+    local.get $group
+    call $object)
+
+  (func $variant (param $tag i32) (param $value i32) (result i32)
+    i32.const 6
+    local.get $tag
+    local.get $value
+    call $object)
+
+  (func $apply (param $f i32) (param $arg i32) (result i32)
+    local.get $f
+    i32.load offset=8
+    local.get $arg
+    local.get $f
+    i32.load offset=4
+    call_indirect (type $code))
+
+  (func $fix (param $f i32) (result i32)
+    local.get $f
+    i32.const 3
+    local.get $f
+    i32.const 0
+    call $object
+    call $apply)
+
+  ;; Returns the value of a variable, recursive bindings are unfolded by running their code
+  (func $unfold (param $v i32) (result i32)
+    local.get $v
+    i32.load
+    i32.const 3
+    i32.eq
+    if
+      local.get $v
+      i32.load offset=4
+      local.get $v
+      call $apply
+      return
+    end
+    local.get $v
+    i32.load
+    i32.const 4
+    i32.eq
+    if
+      local.get $v
+      i32.load offset=8
+      i32.const 0
+      local.get $v
+      i32.load offset=4
+      call_indirect (type $code)
+      return
+    end
+    local.get $v)
+
+  (func $succ (param $v i32) (result i32)
+    local.get $v
+    i32.load offset=4
+    i32.const 1
+    i32.add
+    call $nat)
+
+  (func $pred (param $v i32) (result i32)
+    local.get $v
+    i32.load offset=4
+    i32.eqz
+    if
+      local.get $v
+      return
+    end
+    local.get $v
+    i32.load offset=4
+    i32.const 1
+    i32.sub
+    call $nat)
+
+  (func $iszero (param $v i32) (result i32)
+    local.get $v
+    i32.load offset=4
+    i32.eqz
+    call $bool)
+
+  ;; Allocates a record with room for the given number of fields, which the caller fills in
+  (func $record (param $size i32) (result i32)
+    (local $address i32)
+    local.get $size
+    i32.const 8
+    i32.mul
+    i32.const 8
+    i32.add
+    call $alloc
+    local.set $address
+    local.get $address
+    i32.const 5
+    i32.store
+    local.get $address
+    local.get $size
+    i32.store offset=4
+    local.get $address)
+
+  (func $record_get (param $r i32) (param $id i32) (result i32)
+    (local $field i32)
+    (local $end i32)
+    local.get $r
+    i32.const 8
+    i32.add
+    local.set $field
+    local.get $field
+    local.get $r
+    i32.load offset=4
+    i32.const 8
+    i32.mul
+    i32.add
+    local.set $end
+    loop $search
+      local.get $field
+      local.get $end
+      i32.ge_u
+      if
+        unreachable
+      end
+      local.get $field
+      i32.load
+      local.get $id
+      i32.eq
+      if
+        local.get $field
+        i32.load offset=4
+        return
+      end
+      local.get $field
+      i32.const 8
+      i32.add
+      local.set $field
+      br $search
+    end
+    unreachable)
+
+  ;; Returns a copy of the record in which the field is set, adding it when it is missing
+  (func $record_set (param $r i32) (param $id i32) (param $v i32) (result i32)
+    (local $copy i32)
+    (local $from i32)
+    (local $to i32)
+    (local $end i32)
+    (local $placed i32)
+    local.get $r
+    i32.load offset=4
+    i32.const 1
+    i32.add
+    call $record
+    local.set $copy
+    local.get $r
+    i32.const 8
+    i32.add
+    local.set $from
+    local.get $from
+    local.get $r
+    i32.load offset=4
+    i32.const 8
+    i32.mul
+    i32.add
+    local.set $end
+    local.get $copy
+    i32.const 8
+    i32.add
+    local.set $to
+    block $copied
+      loop $fields
+        local.get $from
+        local.get $end
+        i32.ge_u
+        br_if $copied
+        local.get $placed
+        i32.eqz
+        local.get $from
+        i32.load
+        local.get $id
+        i32.ge_u
+        i32.and
+        if
+          local.get $to
+          local.get $id
+          i32.store
+          local.get $to
+          local.get $v
+          i32.store offset=4
+          local.get $to
+          i32.const 8
+          i32.add
+          local.set $to
+          i32.const 1
+          local.set $placed
+          local.get $from
+          i32.load
+          local.get $id
+          i32.eq
+          if
+            local.get $from
+            i32.const 8
+            i32.add
+            local.set $from
+          end
+          br $fields
+        end
+        local.get $to
+        local.get $from
+        i32.load
+        i32.store
+        local.get $to
+        local.get $from
+        i32.load offset=4
+        i32.store offset=4
+        local.get $to
+        i32.const 8
+        i32.add
+        local.set $to
+        local.get $from
+        i32.const 8
+        i32.add
+        local.set $from
+        br $fields
+      end
+    end
+    local.get $placed
+    i32.eqz
+    if
+      local.get $to
+      local.get $id
+      i32.store
+      local.get $to
+      local.get $v
+      i32.store offset=4
+      local.get $to
+      i32.const 8
+      i32.add
+      local.set $to
+    end
+    local.get $copy
+    local.get $to
+    local.get $copy
+    i32.sub
+    i32.const 8
+    i32.sub
+    i32.const 8
+    i32.div_u
+    i32.store offset=4
+    local.get $copy)
+
+  (func $record_remove (param $r i32) (param $id i32) (result i32)
+    (local $copy i32)
+    (local $from i32)
+    (local $to i32)
+    (local $end i32)
+    local.get $r
+    i32.load offset=4
+    call $record
+    local.set $copy
+    local.get $r
+    i32.const 8
+    i32.add
+    local.set $from
+    local.get $from
+    local.get $r
+    i32.load offset=4
+    i32.const 8
+    i32.mul
+    i32.add
+    local.set $end
+    local.get $copy
+    i32.const 8
+    i32.add
+    local.set $to
+    block $copied
+      loop $fields
+        local.get $from
+        local.get $end
+        i32.ge_u
+        br_if $copied
+        local.get $from
+        i32.load
+        local.get $id
+        i32.ne
+        if
+          local.get $to
+          local.get $from
+          i32.load
+          i32.store
+          local.get $to
+          local.get $from
+          i32.load offset=4
+          i32.store offset=4
+          local.get $to
+          i32.const 8
+          i32.add
+          local.set $to
+        end
+        local.get $from
+        i32.const 8
+        i32.add
+        local.set $from
+        br $fields
+      end
+    end
+    local.get $copy
+    local.get $to
+    local.get $copy
+    i32.sub
+    i32.const 8
+    i32.sub
+    i32.const 8
+    i32.div_u
+    i32.store offset=4
+    local.get $copy)
+
+  ;; Appends a byte to the text that is being printed, which always lies at the end of the heap
+  (func $byte (param $b i32)
+    i32.const 1
+    call $alloc
+    local.get $b
+    i32.store8)
+
+  ;; Appends a string stored as its length followed by its bytes
+  (func $text (param $s i32)
+    (local $i i32)
+    block $written
+      loop $bytes
+        local.get $i
+        local.get $s
+        i32.load
+        i32.ge_u
+        br_if $written
+        local.get $s
+        local.get $i
+        i32.add
+        i32.load8_u offset=4
+        call $byte
+        local.get $i
+        i32.const 1
+        i32.add
+        local.set $i
+        br $bytes
+      end
+    end)
+
+  (func $digits (param $n i32)
+    local.get $n
+    i32.const 10
+    i32.ge_u
+    if
+      local.get $n
+      i32.const 10
+      i32.div_u
+      call $digits
+    end
+    local.get $n
+    i32.const 10
+    i32.rem_u
+    i32.const 48
+    i32.add
+    call $byte)
+
+  ;; Appends a value like the interpreter prints it, with the fields of records sorted by name
+  (func $show (param $v i32)
+    (local $kind i32)
+    (local $field i32)
+    (local $end i32)
+    (local $last i32)
+    (local $next i32)
+    (local $rank i32)
+    (local $printed i32)
+    local.get $v
+    i32.load
+    local.set $kind
+    local.get $kind
+    i32.eqz
+    if
+      local.get $v
+      i32.load offset=4
+      call $digits
+      return
+    end
+    local.get $kind
+    i32.const 1
+    i32.eq
+    if
+      global.get $text_true
+      global.get $text_false
+      local.get $v
+      i32.load offset=4
+      select
+      call $text
+      return
+    end
+    local.get $kind
+    i32.const 6
+    i32.eq
+    if
+      i32.const 60
+      call $byte
+      global.get $tag_names
+      local.get $v
+      i32.load offset=4
+      i32.const 4
+      i32.mul
+      i32.add
+      i32.load
+      call $text
+      i32.const 61
+      call $byte
+      local.get $v
+      i32.load offset=8
+      call $show
+      i32.const 62
+      call $byte
+      return
+    end
+    local.get $kind
+    i32.const 5
+    i32.ne
+    if
+      global.get $text_function
+      call $text
+      return
+    end
+    i32.const 123
+    call $byte
+    local.get $v
+    i32.const 8
+    i32.add
+    local.get $v
+    i32.load offset=4
+    i32.const 8
+    i32.mul
+    i32.add
+    local.set $end
+    i32.const -1
+    local.set $last
+    block $printed_all
+      loop $fields
+        ;; Finds the field with the smallest rank after the one printed last
+        i32.const 0
+        local.set $next
+        local.get $v
+        i32.const 8
+        i32.add
+        local.set $field
+        block $searched
+          loop $search
+            local.get $field
+            local.get $end
+            i32.ge_u
+            br_if $searched
+            global.get $field_ranks
+            local.get $field
+            i32.load
+            i32.const 4
+            i32.mul
+            i32.add
+            i32.load
+            local.set $rank
+            local.get $rank
+            local.get $last
+            i32.gt_s
+            local.get $next
+            i32.eqz
+            local.get $rank
+            local.get $next
+            i32.load
+            i32.const 4
+            i32.mul
+            global.get $field_ranks
+            i32.add
+            i32.load
+            i32.lt_s
+            i32.or
+            i32.and
+            if
+              local.get $field
+              local.set $next
+            end
+            local.get $field
+            i32.const 8
+            i32.add
+            local.set $field
+            br $search
+          end
+        end
+        local.get $next
+        i32.eqz
+        br_if $printed_all
+        local.get $printed
+        if
+          i32.const 44
+          call $byte
+          i32.const 32
+          call $byte
+        end
+        i32.const 1
+        local.set $printed
+        global.get $field_names
+        local.get $next
+        i32.load
+        i32.const 4
+        i32.mul
+        i32.add
+        i32.load
+        call $text
+        i32.const 61
+        call $byte
+        local.get $next
+        i32.load offset=4
+        call $show
+        global.get $field_ranks
+        local.get $next
+        i32.load
+        i32.const 4
+        i32.mul
+        i32.add
+        i32.load
+        local.set $last
+        br $fields
+      end
+    end
+    i32.const 125
+    call $byte)
@@ -0,0 +1,1186 @@
+use ast::{self, *};
+use matching::{self, Access, Constructor, Decision};
+use nameless;
+use std::collections::{HashMap, HashSet};
+
+/// Allocator, objects, records and the printer every generated module contains
+const RUNTIME: &str = include_str!("runtime.wat");
+
+/// Address of the first data segment, the first bytes are left unused so no object lives at 0
+const DATA_START: usize = 16;
+
+/// Compiles a typechecked abstract syntax tree into a WebAssembly module in text format. Every
+/// abstraction is lifted to a function in the table of the module, receiving the address of the
+/// variables it captured next to its argument. The module exports its memory and a function
+/// `main`, which runs the program and returns the address of the text the vm backend of the
+/// interpreter would print, stored as its length followed by its bytes. Applications are ordinary
+/// calls, so deep recursion is limited by the call stack of the engine running the module.
+///
+/// # Panics
+/// Throws a panic when the tree refers to unknown variables, which the typechecker rules out
+pub fn compile(node: &ASTNode<'_>) -> String {
+    let mut generator = Generator::default();
+    let mut main = Function::new(&[]);
+    generator.expr(&mut main, node);
+    let entry = generator.lift(main);
+
+    let mut data = Data::default();
+    let text_true = data.string("true");
+    let text_false = data.string("false");
+    let text_function = data.string("<function>");
+    let fields: Vec<usize> = generator.fields.iter().map(|n| data.string(n)).collect();
+    let field_names = data.words("field names", &fields);
+    let mut sorted: Vec<&String> = generator.fields.iter().collect();
+    sorted.sort();
+    let ranks: Vec<usize> = generator
+        .fields
+        .iter()
+        .map(|name| sorted.iter().position(|known| *known == name).unwrap())
+        .collect();
+    let field_ranks = data.words("ranks of the fields in alphabetical order", &ranks);
+    let tags: Vec<usize> = generator.tags.iter().map(|n| data.string(n)).collect();
+    let tag_names = data.words("tag names", &tags);
+
+    let mut out = String::from(";; Generated by lambda-rs\n(module\n");
+    out.push_str("  (type $code (func (param i32 i32) (result i32)))\n");
+    out.push_str("  (memory (export \"memory\") 1)\n");
+    out.push_str(&format!(
+        "  (global $heap (mut i32) (i32.const {}))\n",
+        data.end().div_ceil(8) * 8
+    ));
+    let globals = [
+        ("text_true", text_true),
+        ("text_false", text_false),
+        ("text_function", text_function),
+        ("field_names", field_names),
+        ("field_ranks", field_ranks),
+        ("tag_names", tag_names),
+    ];
+    for (name, address) in globals.iter() {
+        out.push_str(&format!(
+            "  (global ${} i32 (i32.const {}))\n",
+            name, address
+        ));
+    }
+    out.push_str(&format!(
+        "  (table {} funcref)\n",
+        generator.functions.len()
+    ));
+    let names: Vec<String> = (0..generator.functions.len())
+        .map(|index| format!("$f{}", index))
+        .collect();
+    out.push_str(&format!("  (elem (i32.const 0) {})\n", names.join(" ")));
+    for segment in &data.segments {
+        out.push_str(segment);
+    }
+    out.push('\n');
+    out.push_str(RUNTIME);
+    for function in &generator.functions {
+        out.push('\n');
+        out.push_str(function);
+    }
+
+    out.push_str("\n  (func (export \"main\") (result i32)\n");
+    out.push_str("    (local $output i32)\n");
+    out.push_str(&format!(
+        "    i32.const 0\n    i32.const 0\n    call $f{}\n",
+        entry
+    ));
+    out.push_str("    i32.const 4\n    call $alloc\n    local.set $output\n");
+    out.push_str("    call $show\n");
+    out.push_str("    local.get $output\n    global.get $heap\n    local.get $output\n");
+    out.push_str("    i32.sub\n    i32.const 4\n    i32.sub\n    i32.store\n");
+    out.push_str("    local.get $output)\n");
+    out.push_str(")\n");
+    out
+}
+
+/// Contents of the memory when the module starts, laid out from `DATA_START`
+#[derive(Default)]
+struct Data {
+    size: usize,
+    segments: Vec<String>,
+}
+
+impl Data {
+    fn end(&self) -> usize {
+        DATA_START + self.size
+    }
+
+    /// Adds a segment at the next address divisible by four and returns that address
+    fn segment(&mut self, comment: &str, bytes: &[u8]) -> usize {
+        self.size = self.size.div_ceil(4) * 4;
+        let address = self.end();
+        let mut text = String::new();
+        for byte in bytes {
+            match byte {
+                b' '..=b'~' if *byte != b'"' && *byte != b'\\' => text.push(*byte as char),
+                _ => text.push_str(&format!("\\{:02x}", byte)),
+            }
+        }
+        self.segments.push(format!(
+            "  (data (i32.const {}) \"{}\") ;; {}\n",
+            address, text, comment
+        ));
+        self.size += bytes.len();
+        address
+    }
+
+    /// Adds a string stored as its length followed by its bytes
+    fn string(&mut self, text: &str) -> usize {
+        let mut bytes = (text.len() as u32).to_le_bytes().to_vec();
+        bytes.extend(text.bytes());
+        self.segment(&format!("{:?}", text).replace('\n', " "), &bytes)
+    }
+
+    fn words(&mut self, comment: &str, words: &[usize]) -> usize {
+        let bytes: Vec<u8> = words
+            .iter()
+            .flat_map(|word| (*word as u32).to_le_bytes().to_vec())
+            .collect();
+        self.segment(comment, &bytes)
+    }
+}
+
+/// State shared by all functions of the module that is being generated
+#[derive(Default)]
+struct Generator {
+    /// Definitions of the lifted functions, the function at index `i` is called `$fi`
+    functions: Vec<String>,
+    fields: Vec<String>,
+    tags: Vec<String>,
+}
+
+/// Body of a function that is being generated. The names in scope refer to the instructions
+/// that push their value, which may still have to be unfolded.
+struct Function {
+    instrs: Vec<String>,
+    depth: usize,
+    locals: usize,
+    scope: Vec<(String, Vec<String>)>,
+}
+
+impl Function {
+    /// Creates a function whose environment holds the given captured variables
+    fn new(captures: &[String]) -> Function {
+        Function {
+            instrs: Vec::new(),
+            depth: 0,
+            locals: 0,
+            scope: captures
+                .iter()
+                .enumerate()
+                .map(|(index, name)| (name.to_string(), load("local.get $env", index)))
+                .collect(),
+        }
+    }
+
+    fn instr(&mut self, instr: &str) {
+        if instr == "end" || instr == "else" {
+            self.depth -= 1;
+        }
+        self.instrs
+            .push(format!("{}{}", "  ".repeat(self.depth), instr));
+        if instr.starts_with("if") || instr == "else" {
+            self.depth += 1;
+        }
+    }
+
+    fn instrs(&mut self, instrs: &[String]) {
+        for instr in instrs {
+            self.instr(instr);
+        }
+    }
+
+    fn local(&mut self) -> String {
+        self.locals += 1;
+        format!("$t{}", self.locals)
+    }
+
+    fn lookup(&self, name: &str) -> Vec<String> {
+        self.scope
+            .iter()
+            .rev()
+            .find(|(bound, _)| bound == name)
+            .map(|(_, instrs)| instrs.clone())
+            .expect("Bug in typechecker: came across unknown variable")
+    }
+}
+
+/// Instructions loading the value at the given index of the array at the address
+fn load(address: &str, index: usize) -> Vec<String> {
+    vec![address.to_string(), memory("i32.load", 4 * index)]
+}
+
+fn memory(instr: &str, offset: usize) -> String {
+    if offset == 0 {
+        instr.to_string()
+    } else {
+        format!("{} offset={}", instr, offset)
+    }
+}
+
+impl Generator {
+    /// Adds the function to the module and returns its index in the table
+    fn lift(&mut self, function: Function) -> usize {
+        let index = self.functions.len();
+        let mut definition = format!(
+            "  (func $f{} (type $code) (param $env i32) (param $arg i32) (result i32)",
+            index
+        );
+        for local in 1..=function.locals {
+            definition.push_str(&format!("\n    (local $t{} i32)", local));
+        }
+        for instr in function.instrs {
+            definition.push_str("\n    ");
+            definition.push_str(&instr);
+        }
+        definition.push_str(")\n");
+        self.functions.push(definition);
+        index
+    }
+
+    /// Emits the instructions pushing the value of the node. Operands are pushed in the order
+    /// they are evaluated, so the stack machine preserves the order of evaluation.
+    fn expr(&mut self, f: &mut Function, node: &ASTNode<'_>) {
+        match node {
+            ASTNode::AbstractionNode { ident, body, .. } => {
+                let captures = free_vars(node);
+                let mut inner = Function::new(&captures);
+                inner
+                    .scope
+                    .push((ident.to_string(), vec!["local.get $arg".to_string()]));
+                self.expr(&mut inner, body);
+                let index = self.lift(inner);
+                let env = self.environment(f, &captures, 0);
+                f.instr(&format!("i32.const {}", index));
+                f.instr(&env);
+                f.instr("call $closure");
+            }
+            ASTNode::ApplicationNode { left, right, .. } => {
+                self.expr(f, left);
+                self.expr(f, right);
+                f.instr("call $apply");
+            }
+            ASTNode::IdentifierNode { name, .. } => {
+                let instrs = f.lookup(name);
+                f.instrs(&instrs);
+                f.instr("call $unfold");
+            }
+            ASTNode::ConditionNode {
+                clause,
+                then_arm,
+                else_arm,
+                ..
+            } => {
+                self.expr(f, clause);
+                f.instr("i32.load offset=4");
+                f.instr("if (result i32)");
+                self.expr(f, then_arm);
+                f.instr("else");
+                self.expr(f, else_arm);
+                f.instr("end");
+            }
+            ASTNode::ArithmeticNode { op, expr, .. } => {
+                self.expr(f, expr);
+                f.instr(match op {
+                    Operator::Succ => "call $succ",
+                    Operator::Pred => "call $pred",
+                });
+            }
+            ASTNode::IsZeroNode { expr, .. } => {
+                self.expr(f, expr);
+                f.instr("call $iszero");
+            }
+            ASTNode::ValueNode { value, .. } => {
+                let (x, constructor) = match value {
+                    ast::Value::True => (1, "call $bool"),
+                    ast::Value::False => (0, "call $bool"),
+                    ast::Value::Zero => (0, "call $nat"),
+                };
+                f.instr(&format!("i32.const {}", x));
+                f.instr(constructor);
+            }
+            ASTNode::ProjectionNode { target, attrib, .. } => {
+                self.expr(f, target);
+                let field = intern(&mut self.fields, attrib);
+                f.instr(&format!("i32.const {}", field));
+                f.instr("call $record_get");
+            }
+            ASTNode::RecordNode { records, .. } => {
+                let mut names: Vec<&String> = records.keys().collect();
+                names.sort();
+                let mut fields: Vec<(usize, &ASTNode<'_>)> = names
+                    .into_iter()
+                    .map(|name| (intern(&mut self.fields, name), &records[name]))
+                    .collect();
+                fields.sort_by_key(|(field, _)| *field);
+                let record = f.local();
+                f.instr(&format!("i32.const {}", fields.len()));
+                f.instr("call $record");
+                f.instr(&format!("local.set {}", record));
+                for (index, (field, value)) in fields.into_iter().enumerate() {
+                    f.instr(&format!("local.get {}", record));
+                    f.instr(&format!("i32.const {}", field));
+                    f.instr(&memory("i32.store", 8 + 8 * index));
+                    f.instr(&format!("local.get {}", record));
+                    self.expr(f, value);
+                    f.instr(&memory("i32.store", 12 + 8 * index));
+                }
+                f.instr(&format!("local.get {}", record));
+            }
+            ASTNode::UpdateNode {
+                target, records, ..
+            }
+            | ASTNode::ExtensionNode {
+                target, records, ..
+            } => {
+                self.expr(f, target);
+                let mut names: Vec<&String> = records.keys().collect();
+                names.sort();
+                for name in names {
+                    let field = intern(&mut self.fields, name);
+                    f.instr(&format!("i32.const {}", field));
+                    self.expr(f, &records[name]);
+                    f.instr("call $record_set");
+                }
+            }
+            ASTNode::RestrictionNode { target, attrib, .. } => {
+                self.expr(f, target);
+                let field = intern(&mut self.fields, attrib);
+                f.instr(&format!("i32.const {}", field));
+                f.instr("call $record_remove");
+            }
+            ASTNode::MatchingNode {
+                to_match, cases, ..
+            } => {
+                let arms: Vec<(&Pattern, &ASTNode<'_>)> = cases
+                    .iter()
+                    .map(|(pattern, arm)| (pattern, &**arm))
+                    .collect();
+                self.matching(f, to_match, &arms);
+            }
+            ASTNode::TaggingNode { ident, value, .. } => {
+                let tag = intern(&mut self.tags, ident);
+                f.instr(&format!("i32.const {}", tag));
+                self.expr(f, value);
+                f.instr("call $variant");
+            }
+            ASTNode::FixNode { point, .. } => {
+                self.expr(f, point);
+                f.instr("call $fix");
+            }
+            ASTNode::LetNode {
+                pattern,
+                value,
+                body,
+                ..
+            } => self.matching(f, value, &[(pattern, body)]),
+            ASTNode::LetRecNode { bindings, body, .. } => self.letrec(f, bindings, body),
+        }
+    }
+
+    /// Allocates an array holding the captured variables followed by room for the given number
+    /// of values, and returns the instruction pushing its address
+    fn environment(&mut self, f: &mut Function, captures: &[String], extra: usize) -> String {
+        if captures.is_empty() && extra == 0 {
+            return "i32.const 0".to_string();
+        }
+        let env = f.local();
+        f.instr(&format!("i32.const {}", 4 * (captures.len() + extra)));
+        f.instr("call $alloc");
+        f.instr(&format!("local.set {}", env));
+        for (index, name) in captures.iter().enumerate() {
+            f.instr(&format!("local.get {}", env));
+            let instrs = f.lookup(name);
+            f.instrs(&instrs);
+            f.instr(&memory("i32.store", 4 * index));
+        }
+        format!("local.get {}", env)
+    }
+
+    /// Emits a case expression, or a let which is a case with a single arm, by following the
+    /// decision tree of its patterns
+    fn matching(
+        &mut self,
+        f: &mut Function,
+        to_match: &ASTNode<'_>,
+        cases: &[(&Pattern, &ASTNode<'_>)],
+    ) {
+        self.expr(f, to_match);
+        let scrutinee = f.local();
+        f.instr(&format!("local.set {}", scrutinee));
+        let tree = matching::compile(cases.iter().map(|(pattern, _)| *pattern), None);
+        self.decision(f, &tree, &scrutinee, cases);
+    }
+
+    fn decision(
+        &mut self,
+        f: &mut Function,
+        tree: &Decision,
+        scrutinee: &str,
+        cases: &[(&Pattern, &ASTNode<'_>)],
+    ) {
+        match tree {
+            Decision::Fail => f.instr("unreachable"),
+            Decision::Leaf { arm, bindings } => {
+                // Sorted so the fields are interned in the same order every time
+                let mut bindings: Vec<&(String, Vec<Access>)> = bindings.iter().collect();
+                bindings.sort_by_key(|(name, _)| name);
+                let bound = f.scope.len();
+                for (name, path) in bindings {
+                    let instrs = self.path(scrutinee, path);
+                    f.scope.push((name.to_string(), instrs));
+                }
+                self.expr(f, cases[*arm].1);
+                f.scope.truncate(bound);
+            }
+            Decision::Switch {
+                path,
+                cases: switch,
+                default,
+            } => {
+                let component = f.local();
+                let instrs = self.path(scrutinee, path);
+                f.instrs(&instrs);
+                f.instr(&format!("local.set {}", component));
+                for (constructor, case) in switch {
+                    f.instr(&format!("local.get {}", component));
+                    f.instr("i32.load offset=4");
+                    match constructor {
+                        Constructor::True | Constructor::Succ => {}
+                        Constructor::False | Constructor::Zero => f.instr("i32.eqz"),
+                        Constructor::Tag(tag) => {
+                            let tag = intern(&mut self.tags, tag);
+                            f.instr(&format!("i32.const {}", tag));
+                            f.instr("i32.eq");
+                        }
+                    }
+                    f.instr("if (result i32)");
+                    self.decision(f, case, scrutinee, cases);
+                    f.instr("else");
+                }
+                match default {
+                    Some(default) => self.decision(f, default, scrutinee, cases),
+                    None => f.instr("unreachable"),
+                }
+                for _ in switch {
+                    f.instr("end");
+                }
+            }
+        }
+    }
+
+    /// Returns the instructions pushing the component of the scrutinee at the given path
+    fn path(&mut self, scrutinee: &str, path: &[Access]) -> Vec<String> {
+        let mut instrs = vec![format!("local.get {}", scrutinee)];
+        for access in path {
+            match access {
+                Access::Tag(_) => instrs.push("i32.load offset=8".to_string()),
+                Access::Pred => instrs.push("call $pred".to_string()),
+                Access::Field(name) => {
+                    let field = intern(&mut self.fields, name);
+                    instrs.push(format!("i32.const {}", field));
+                    instrs.push("call $record_get".to_string());
+                }
+            }
+        }
+        instrs
+    }
+
+    /// Emits a letrec. Each binding is lifted to a function computing its value from the group,
+    /// an array holding the captured variables followed by the members of the group.
+    fn letrec(
+        &mut self,
+        f: &mut Function,
+        bindings: &[(String, Option<TypeAssignment>, ASTNode<'_>)],
+        body: &ASTNode<'_>,
+    ) {
+        let names: Vec<String> = bindings
+            .iter()
+            .map(|(name, _, _)| name.to_string())
+            .collect();
+        let mut captures = HashSet::new();
+        for (_, _, value) in bindings {
+            captures.extend(free_vars(value));
+        }
+        let mut captures: Vec<String> = captures
+            .into_iter()
+            .filter(|name| !names.contains(name))
+            .collect();
+        captures.sort();
+
+        let mut environment = captures.clone();
+        environment.extend(names.iter().cloned());
+        let codes: Vec<usize> = bindings
+            .iter()
+            .map(|(_, _, value)| {
+                let mut member = Function::new(&environment);
+                self.expr(&mut member, value);
+                self.lift(member)
+            })
+            .collect();
+
+        let group = self.environment(f, &captures, names.len());
+        for (index, code) in codes.iter().enumerate() {
+            f.instr(&group);
+            f.instr(&format!("i32.const {}", code));
+            f.instr(&group);
+            f.instr("call $rec");
+            f.instr(&memory("i32.store", 4 * (captures.len() + index)));
+        }
+        let bound = f.scope.len();
+        for (index, name) in names.iter().enumerate() {
+            f.scope
+                .push((name.to_string(), load(&group, captures.len() + index)));
+        }
+        self.expr(f, body);
+        f.scope.truncate(bound);
+    }
+}
+
+/// Returns the names of the free variables of the tree, sorted so captures get a stable order
+fn free_vars(node: &ASTNode<'_>) -> Vec<String> {
+    let mut names: Vec<String> = nameless::to_nameless(node)
+        .free_vars()
+        .into_iter()
+        .collect();
+    names.sort();
+    names
+}
+
+fn intern(table: &mut Vec<String>, name: &str) -> usize {
+    match table.iter().position(|known| known == name) {
+        Some(index) => index,
+        None => {
+            table.push(name.to_string());
+            table.len() - 1
+        }
+    }
+}
+
+/// Checks that the text is a well-formed module in the subset of the text format `compile`
+/// emits: every function, global, type and label that is referred to exists, globals that are
+/// set are mutable, data fits in the memory and the body of every function leaves exactly its
+/// results on the stack along every path, blocks included. Values are only ever `i32`, so the
+/// check follows the height of the stack rather than the types on it.
+///
+/// # Arguments
+/// * `module` - Source of the module in the text format
+///
+/// # Errors
+/// Returns a description of the first problem that was found
+pub fn validate(module: &str) -> Result<(), String> {
+    let mut chars = module.chars().peekable();
+    let tree = match sexp(&mut chars)? {
+        Some(tree) => tree,
+        None => return Err("The text contains no module".to_string()),
+    };
+    if sexp(&mut chars)?.is_some() {
+        return Err("The text continues after the module".to_string());
+    }
+    let fields = match tree {
+        Sexp::List(ref items) if items.first() == Some(&Sexp::atom("module")) => &items[1..],
+        _ => return Err("The text does not start with (module".to_string()),
+    };
+
+    let mut module = Module::default();
+    for field in fields {
+        module.declare(field)?;
+    }
+    for field in fields {
+        module.check(field)?;
+    }
+    Ok(())
+}
+
+/// Atom, string or parenthesized list of the text format
+#[derive(Debug, PartialEq)]
+enum Sexp {
+    Atom(String),
+    Str(Vec<u8>),
+    List(Vec<Sexp>),
+}
+
+impl Sexp {
+    fn atom(text: &str) -> Sexp {
+        Sexp::Atom(text.to_string())
+    }
+
+    fn as_atom(&self) -> Option<&str> {
+        match self {
+            Sexp::Atom(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// Returns the items of a list starting with the given keyword, without the keyword
+    fn form(&self, keyword: &str) -> Option<&[Sexp]> {
+        match self {
+            Sexp::List(items) if items.first() == Some(&Sexp::atom(keyword)) => Some(&items[1..]),
+            _ => None,
+        }
+    }
+}
+
+type Chars<'a> = ::std::iter::Peekable<::std::str::Chars<'a>>;
+
+/// Reads the next expression, skipping white space and comments, or `None` at the end of the
+/// text
+fn sexp(chars: &mut Chars<'_>) -> Result<Option<Sexp>, String> {
+    loop {
+        match chars.peek() {
+            Some(c) if c.is_whitespace() => {
+                chars.next();
+            }
+            Some(';') => {
+                chars.next();
+                if chars.next() != Some(';') {
+                    return Err("Expected a second ; to start a comment".to_string());
+                }
+                while chars.peek().is_some_and(|c| *c != '\n') {
+                    chars.next();
+                }
+            }
+            _ => break,
+        }
+    }
+    match chars.next() {
+        None => Ok(None),
+        Some(')') => Err("Unbalanced )".to_string()),
+        Some('(') => {
+            let mut items = Vec::new();
+            loop {
+                while chars.peek().is_some_and(|c| c.is_whitespace()) {
+                    chars.next();
+                }
+                if chars.peek() == Some(&')') {
+                    chars.next();
+                    return Ok(Some(Sexp::List(items)));
+                }
+                match sexp(chars)? {
+                    Some(item) => items.push(item),
+                    None => return Err("Missing )".to_string()),
+                }
+            }
+        }
+        Some('"') => {
+            let mut bytes = Vec::new();
+            loop {
+                match chars.next() {
+                    None => return Err("Unterminated string".to_string()),
+                    Some('"') => return Ok(Some(Sexp::Str(bytes))),
+                    Some('\\') => {
+                        let digits: String = chars.by_ref().take(2).collect();
+                        match u8::from_str_radix(&digits, 16) {
+                            Ok(byte) => bytes.push(byte),
+                            Err(_) => return Err(format!("Invalid escape \\{}", digits)),
+                        }
+                    }
+                    Some(c) => {
+                        let mut buffer = [0; 4];
+                        bytes.extend(c.encode_utf8(&mut buffer).bytes());
+                    }
+                }
+            }
+        }
+        Some(first) => {
+            let mut atom = first.to_string();
+            while let Some(c) = chars.peek() {
+                if c.is_whitespace() || *c == '(' || *c == ')' || *c == ';' {
+                    break;
+                }
+                atom.push(*c);
+                chars.next();
+            }
+            Ok(Some(Sexp::Atom(atom)))
+        }
+    }
+}
+
+/// Number of parameters and results of a function
+type Signature = (usize, usize);
+
+/// Everything a module declares, collected before the bodies of its functions are checked
+#[derive(Default)]
+struct Module {
+    types: HashMap<String, Signature>,
+    functions: HashMap<String, Signature>,
+    /// Whether each global is mutable
+    globals: HashMap<String, bool>,
+    exports: HashSet<String>,
+    memory: Option<usize>,
+    table: Option<usize>,
+}
+
+impl Module {
+    fn declare(&mut self, field: &Sexp) -> Result<(), String> {
+        if let Some(items) = field.form("type") {
+            let name = name(items.first(), "type")?;
+            let signature = match items.get(1).and_then(|item| item.form("func")) {
+                Some(func) => signature(func)?,
+                None => return Err(format!("Type {} is not a function type", name)),
+            };
+            if self.types.insert(name.clone(), signature).is_some() {
+                return Err(format!("Type {} is declared twice", name));
+            }
+        } else if let Some(items) = field.form("func") {
+            let name = match items.first().and_then(Sexp::as_atom) {
+                Some(name) if name.starts_with('$') => name.to_string(),
+                _ => format!("#{}", self.functions.len()),
+            };
+            let header = header(items);
+            if self
+                .functions
+                .insert(name.clone(), signature(header)?)
+                .is_some()
+            {
+                return Err(format!("Function {} is declared twice", name));
+            }
+            self.export(header)?;
+        } else if let Some(items) = field.form("global") {
+            let name = name(items.first(), "global")?;
+            let mutable = match items.get(1) {
+                Some(Sexp::Atom(kind)) if kind == "i32" => false,
+                Some(kind) if kind.form("mut") == Some(&[Sexp::atom("i32")]) => true,
+                _ => return Err(format!("Global {} is not an i32", name)),
+            };
+            constant(items.get(2))?;
+            if self.globals.insert(name.clone(), mutable).is_some() {
+                return Err(format!("Global {} is declared twice", name));
+            }
+        } else if let Some(items) = field.form("memory") {
+            if self.memory.is_some() {
+                return Err("The module declares more than one memory".to_string());
+            }
+            self.export(items)?;
+            self.memory = Some(number(items.last())?);
+        } else if let Some(items) = field.form("table") {
+            if self.table.is_some() {
+                return Err("The module declares more than one table".to_string());
+            }
+            if items.get(1) != Some(&Sexp::atom("funcref")) {
+                return Err("The table does not hold functions".to_string());
+            }
+            self.table = Some(number(items.first())?);
+        } else if field.form("elem").is_none() && field.form("data").is_none() {
+            return Err(format!("Unknown field {:?} in the module", field));
+        }
+        Ok(())
+    }
+
+    /// Records the inline export among the items, if any
+    fn export(&mut self, items: &[Sexp]) -> Result<(), String> {
+        for item in items {
+            if let Some(export) = item.form("export") {
+                match export {
+                    [Sexp::Str(name)] => {
+                        let name = String::from_utf8_lossy(name).to_string();
+                        if !self.exports.insert(name.clone()) {
+                            return Err(format!("{} is exported twice", name));
+                        }
+                    }
+                    _ => return Err("An export needs exactly one name".to_string()),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check(&self, field: &Sexp) -> Result<(), String> {
+        if let Some(items) = field.form("func") {
+            self.check_function(items)
+        } else if let Some(items) = field.form("elem") {
+            let size = match self.table {
+                Some(size) => size,
+                None => return Err("Elements are given without a table".to_string()),
+            };
+            let offset = constant(items.first())?;
+            if offset + items.len() - 1 > size {
+                return Err("The elements do not fit in the table".to_string());
+            }
+            for item in &items[1..] {
+                let name = name(Some(item), "function in the table")?;
+                if !self.functions.contains_key(&name) {
+                    return Err(format!("The table refers to unknown function {}", name));
+                }
+            }
+            Ok(())
+        } else if let Some(items) = field.form("data") {
+            let pages = match self.memory {
+                Some(pages) => pages,
+                None => return Err("Data is given without a memory".to_string()),
+            };
+            let offset = constant(items.first())?;
+            let bytes = match items.get(1) {
+                Some(Sexp::Str(bytes)) if items.len() == 2 => bytes,
+                _ => return Err(format!("The data at {} is not a single string", offset)),
+            };
+            if offset + bytes.len() > pages * 65536 {
+                return Err(format!("The data at {} does not fit in the memory", offset));
+            }
+            Ok(())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn check_function(&self, items: &[Sexp]) -> Result<(), String> {
+        let name = items
+            .first()
+            .and_then(Sexp::as_atom)
+            .unwrap_or("without a name");
+        let header = header(items);
+        let (_, results) = signature(header)?;
+        let mut locals = HashSet::new();
+        for item in header {
+            if let Some(declared) = item.form("type") {
+                let declared = declared.first().and_then(Sexp::as_atom).unwrap_or("");
+                match self.types.get(declared) {
+                    Some(signature) if *signature == self::signature(header)? => {}
+                    Some(_) => return Err(format!("Function {} does not match its type", name)),
+                    None => return Err(format!("Function {} has unknown type {}", name, declared)),
+                }
+            } else if let Some(local) = item.form("param").or_else(|| item.form("local")) {
+                match local {
+                    [Sexp::Atom(local), Sexp::Atom(kind)] if local.starts_with('$') => {
+                        if kind != "i32" || !locals.insert(local.as_str()) {
+                            return Err(format!("Function {} declares {} wrongly", name, local));
+                        }
+                    }
+                    _ => return Err(format!("Function {} has a local without a name", name)),
+                }
+            }
+        }
+
+        let mut stack = Stack {
+            function: name,
+            height: 0,
+            frames: vec![Frame {
+                label: None,
+                kind: "func",
+                results,
+                base: 0,
+                unreachable: false,
+            }],
+        };
+        let mut instrs = items[header.len()..].iter();
+        while let Some(instr) = instrs.next() {
+            let opcode = match instr {
+                Sexp::Atom(opcode) => opcode.as_str(),
+                _ => return Err(format!("Function {} has a folded instruction", name)),
+            };
+            match opcode {
+                "i32.eqz" => stack.apply(1, 1)?,
+                "i32.add" | "i32.sub" | "i32.mul" | "i32.div_u" | "i32.div_s" | "i32.rem_u"
+                | "i32.rem_s" | "i32.and" | "i32.or" | "i32.xor" | "i32.shl" | "i32.shr_u"
+                | "i32.shr_s" | "i32.eq" | "i32.ne" | "i32.lt_u" | "i32.lt_s" | "i32.gt_u"
+                | "i32.gt_s" | "i32.le_u" | "i32.le_s" | "i32.ge_u" | "i32.ge_s" => {
+                    stack.apply(2, 1)?
+                }
+                "drop" => stack.apply(1, 0)?,
+                "select" => stack.apply(3, 1)?,
+                "i32.const" => {
+                    let immediate = instrs.next().and_then(Sexp::as_atom).unwrap_or("");
+                    if immediate.parse::<i32>().is_err() && immediate.parse::<u32>().is_err() {
+                        return Err(format!("Function {} has a bad constant", name));
+                    }
+                    stack.apply(0, 1)?
+                }
+                "i32.load" | "i32.load8_u" | "i32.load8_s" | "i32.store" | "i32.store8" => {
+                    if self.memory.is_none() {
+                        return Err(format!("Function {} uses memory without one", name));
+                    }
+                    if let Some(Sexp::Atom(offset)) = instrs.clone().next() {
+                        if let Some(value) = offset.strip_prefix("offset=") {
+                            if value.parse::<u32>().is_err() {
+                                return Err(format!("Function {} has a bad {}", name, offset));
+                            }
+                            instrs.next();
+                        }
+                    }
+                    if opcode.contains("load") {
+                        stack.apply(1, 1)?
+                    } else {
+                        stack.apply(2, 0)?
+                    }
+                }
+                "memory.size" | "memory.grow" => {
+                    if self.memory.is_none() {
+                        return Err(format!("Function {} uses memory without one", name));
+                    }
+                    stack.apply(if opcode == "memory.grow" { 1 } else { 0 }, 1)?
+                }
+                "local.get" | "local.set" | "local.tee" => {
+                    let local = instrs.next().and_then(Sexp::as_atom).unwrap_or("");
+                    if !locals.contains(local) {
+                        return Err(format!("Function {} has no local {}", name, local));
+                    }
+                    match opcode {
+                        "local.get" => stack.apply(0, 1)?,
+                        "local.set" => stack.apply(1, 0)?,
+                        _ => stack.apply(1, 1)?,
+                    }
+                }
+                "global.get" | "global.set" => {
+                    let global = instrs.next().and_then(Sexp::as_atom).unwrap_or("");
+                    match self.globals.get(global) {
+                        None => {
+                            return Err(format!("Function {} uses unknown global {}", name, global))
+                        }
+                        Some(false) if opcode == "global.set" => {
+                            return Err(format!(
+                                "Function {} sets immutable global {}",
+                                name, global
+                            ))
+                        }
+                        _ if opcode == "global.set" => stack.apply(1, 0)?,
+                        _ => stack.apply(0, 1)?,
+                    }
+                }
+                "call" => {
+                    let callee = instrs.next().and_then(Sexp::as_atom).unwrap_or("");
+                    match self.functions.get(callee) {
+                        Some((params, results)) => stack.apply(*params, *results)?,
+                        None => return Err(format!("Function {} calls unknown {}", name, callee)),
+                    }
+                }
+                "call_indirect" => {
+                    let declared = instrs
+                        .next()
+                        .and_then(|item| item.form("type"))
+                        .and_then(|items| items.first())
+                        .and_then(Sexp::as_atom)
+                        .unwrap_or("");
+                    if self.table.is_none() {
+                        return Err(format!("Function {} calls through a missing table", name));
+                    }
+                    match self.types.get(declared) {
+                        Some((params, results)) => stack.apply(params + 1, *results)?,
+                        None => {
+                            return Err(format!("Function {} uses unknown type {}", name, declared))
+                        }
+                    }
+                }
+                "block" | "loop" | "if" => {
+                    if opcode == "if" {
+                        stack.apply(1, 0)?;
+                    }
+                    let mut label = None;
+                    if let Some(Sexp::Atom(next)) = instrs.clone().next() {
+                        if next.starts_with('$') {
+                            label = Some(next.as_str());
+                            instrs.next();
+                        }
+                    }
+                    let mut results = 0;
+                    if let Some(result) = instrs.clone().next().and_then(|item| item.form("result"))
+                    {
+                        if result != [Sexp::atom("i32")] {
+                            return Err(format!("Function {} has a block of other results", name));
+                        }
+                        results = 1;
+                        instrs.next();
+                    }
+                    let base = stack.height;
+                    stack.frames.push(Frame {
+                        label,
+                        kind: opcode,
+                        results,
+                        base,
+                        unreachable: false,
+                    });
+                }
+                "else" => {
+                    stack.close()?;
+                    let frame = stack.frames.last_mut().unwrap();
+                    if frame.kind != "if" {
+                        return Err(format!("Function {} has an else outside an if", name));
+                    }
+                    frame.kind = "else";
+                    frame.unreachable = false;
+                    stack.height = frame.base;
+                }
+                "end" => {
+                    stack.close()?;
+                    if stack.frames.len() == 1 {
+                        return Err(format!("Function {} has an end without a block", name));
+                    }
+                    let frame = stack.frames.pop().unwrap();
+                    if frame.kind == "if" && frame.results > 0 {
+                        return Err(format!(
+                            "Function {} has an if with a result but no else",
+                            name
+                        ));
+                    }
+                    stack.height = frame.base + frame.results;
+                }
+                "br" | "br_if" => {
+                    let target = instrs.next().and_then(Sexp::as_atom).unwrap_or("");
+                    let depth = match stack
+                        .frames
+                        .iter()
+                        .rev()
+                        .position(|frame| frame.label == Some(target))
+                    {
+                        Some(depth) => depth,
+                        None => match target.parse::<usize>() {
+                            Ok(depth) if depth < stack.frames.len() => depth,
+                            _ => {
+                                return Err(format!(
+                                    "Function {} branches to unknown {}",
+                                    name, target
+                                ))
+                            }
+                        },
+                    };
+                    let frame = &stack.frames[stack.frames.len() - 1 - depth];
+                    let arity = if frame.kind == "loop" {
+                        0
+                    } else {
+                        frame.results
+                    };
+                    if opcode == "br_if" {
+                        stack.apply(1, 0)?;
+                        stack.apply(arity, arity)?;
+                    } else {
+                        stack.apply(arity, 0)?;
+                        stack.unreachable();
+                    }
+                }
+                "return" => {
+                    stack.apply(results, 0)?;
+                    stack.unreachable();
+                }
+                "unreachable" => stack.unreachable(),
+                _ => {
+                    return Err(format!(
+                        "Function {} uses unknown instruction {}",
+                        name, opcode
+                    ))
+                }
+            }
+        }
+        if stack.frames.len() > 1 {
+            return Err(format!("Function {} is missing an end", name));
+        }
+        stack.close()
+    }
+}
+
+/// Block, loop, if or function body that is being checked
+struct Frame<'a> {
+    label: Option<&'a str>,
+    kind: &'a str,
+    results: usize,
+    /// Height of the stack when the frame was entered
+    base: usize,
+    /// Whether the rest of the frame can never run, which lets it pop values it never pushed
+    unreachable: bool,
+}
+
+/// Height of the operand stack and the frames of a function that is being checked
+struct Stack<'a> {
+    function: &'a str,
+    height: usize,
+    frames: Vec<Frame<'a>>,
+}
+
+impl<'a> Stack<'a> {
+    /// Pops the operands of an instruction and pushes its results
+    fn apply(&mut self, pops: usize, pushes: usize) -> Result<(), String> {
+        let frame = self.frames.last().unwrap();
+        if self.height < frame.base + pops {
+            if !frame.unreachable {
+                return Err(format!(
+                    "Function {} pops more values than it pushed",
+                    self.function
+                ));
+            }
+            self.height = frame.base + pops;
+        }
+        self.height = self.height - pops + pushes;
+        Ok(())
+    }
+
+    fn unreachable(&mut self) {
+        let frame = self.frames.last_mut().unwrap();
+        frame.unreachable = true;
+        self.height = frame.base;
+    }
+
+    /// Checks that the innermost frame leaves exactly its results on the stack
+    fn close(&mut self) -> Result<(), String> {
+        let results = self.frames.last().unwrap().results;
+        self.apply(results, 0)?;
+        let frame = self.frames.last().unwrap();
+        if self.height != frame.base {
+            return Err(format!(
+                "Function {} leaves {} values in a {} that has {} results",
+                self.function,
+                self.height - frame.base + results,
+                frame.kind,
+                results
+            ));
+        }
+        self.height += results;
+        Ok(())
+    }
+}
+
+/// Returns the `$name` of a field
+fn name(item: Option<&Sexp>, what: &str) -> Result<String, String> {
+    match item.and_then(Sexp::as_atom) {
+        Some(name) if name.starts_with('$') => Ok(name.to_string()),
+        _ => Err(format!("Expected the name of a {}", what)),
+    }
+}
+
+fn number(item: Option<&Sexp>) -> Result<usize, String> {
+    match item.and_then(Sexp::as_atom).map(str::parse) {
+        Some(Ok(number)) => Ok(number),
+        _ => Err(format!("Expected a number instead of {:?}", item)),
+    }
+}
+
+/// Returns the value of an `(i32.const n)` initializer
+fn constant(item: Option<&Sexp>) -> Result<usize, String> {
+    match item.and_then(|item| item.form("i32.const")) {
+        Some([value]) => number(Some(value)),
+        _ => Err(format!("Expected (i32.const n) instead of {:?}", item)),
+    }
+}
+
+/// Returns the name, type, export, parameters, results and locals that start a function
+fn header(items: &[Sexp]) -> &[Sexp] {
+    let length = items
+        .iter()
+        .enumerate()
+        .take_while(|(index, item)| match item {
+            Sexp::List(_) => true,
+            Sexp::Atom(name) => *index == 0 && name.starts_with('$'),
+            Sexp::Str(_) => false,
+        })
+        .count();
+    &items[..length]
+}
+
+/// Counts the parameters and results among the items of a function or function type
+fn signature(items: &[Sexp]) -> Result<Signature, String> {
+    let mut signature = (0, 0);
+    for item in items {
+        let (kinds, count) = match (item.form("param"), item.form("result")) {
+            (Some(kinds), _) => (kinds, &mut signature.0),
+            (_, Some(kinds)) => (kinds, &mut signature.1),
+            _ => continue,
+        };
+        for kind in kinds {
+            match kind.as_atom() {
+                Some("i32") => *count += 1,
+                Some(name) if name.starts_with('$') => {}
+                _ => return Err(format!("Unsupported value type {:?}", kind)),
+            }
+        }
+    }
+    Ok(signature)
+}
@@ -1,33 +1,180 @@
 use ast::*;
+use machine::State;
+use matching::{Access, Constructor, Decision, Path};
+use nameless::{self, Term};
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fmt::*;
+use std::rc::Rc;
+use std::result;
+use std::str::FromStr;
 use sym_tab::*;
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug)]
 pub enum OutputValue<'a> {
     Nat(usize),
     Bool(bool),
-    Func(String, Box<ASTNode<'a>>, SymbolTable<OutputValue<'a>>),
+    /// An abstraction together with the environment it was evaluated in
+    Func(&'a ASTNode<'a>, SymbolTable<Binding<'a>>),
     Record(HashMap<String, OutputValue<'a>>),
-    Variant(String, Box<OutputValue<'a>>),
-    Fix(Box<OutputValue<'a>>),
+    /// A tag with the value it carries and the variant type it was given
+    Variant(String, Box<OutputValue<'a>>, &'a TypeAssignment),
+}
+
+/// Entries of the environment in which terms are evaluated
+#[derive(Clone, Debug)]
+pub enum Binding<'a> {
+    /// A value that was computed before it was bound
+    Value(OutputValue<'a>),
+    /// A term bound by a recursive definition, with the type it was declared with. It is evaluated
+    /// when it is looked up, in the environment the definition was bound in, so it can refer to
+    /// itself.
+    Recursive(&'a ASTNode<'a>, Option<&'a TypeAssignment>),
+    /// An argument passed by name, evaluated in the given environment every time it is looked up
+    Delayed(&'a ASTNode<'a>, SymbolTable<Binding<'a>>),
+    /// An argument passed by need, evaluated the first time it is looked up and shared afterwards
+    Shared(Rc<RefCell<Thunk<'a>>>),
+}
+
+/// State of an argument that is passed by need
+#[derive(Clone, Debug)]
+pub enum Thunk<'a> {
+    Delayed(&'a ASTNode<'a>, SymbolTable<Binding<'a>>),
+    Forced(OutputValue<'a>),
+}
+
+/// The ways in which the argument of an application can be passed to the function. A let passes
+/// its value to a variable pattern in the same way, other patterns need the value to match it.
+/// Fields of records and payloads of variants are evaluated when they are built, under every
+/// strategy.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Strategy {
+    /// The argument is evaluated before the function is applied
+    #[default]
+    CallByValue,
+    /// The argument is evaluated every time the function uses it
+    CallByName,
+    /// The argument is evaluated the first time the function uses it
+    CallByNeed,
+}
+
+impl FromStr for Strategy {
+    type Err = String;
+
+    fn from_str(name: &str) -> result::Result<Strategy, String> {
+        match name {
+            "value" => Ok(Strategy::CallByValue),
+            "name" => Ok(Strategy::CallByName),
+            "need" => Ok(Strategy::CallByNeed),
+            _ => Err(format!(
+                "Unknown evaluation strategy {}, expected value, name or need",
+                name
+            )),
+        }
+    }
+}
+
+/// Limits on the work done by the evaluator, so programs that do not terminate are stopped
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct EvalConfig {
+    /// Maximum number of terms that may be evaluated, unlimited when `None`
+    pub max_steps: Option<usize>,
+    /// Maximum number of evaluations that may be nested in each other, unlimited when `None`
+    pub max_depth: Option<usize>,
+}
+
+/// Reasons for the evaluator to give up on a program
+#[derive(Clone, Debug, PartialEq)]
+pub enum EvalError {
+    /// The evaluation took more steps than the configured maximum
+    OutOfFuel { steps: usize },
+    /// The evaluation nested deeper than the configured maximum, which is the depth it reports
+    TooDeep { depth: usize },
+}
+
+impl Display for EvalError {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            EvalError::OutOfFuel { steps } => {
+                write!(f, "Evaluation ran out of fuel after {} steps", steps)
+            }
+            EvalError::TooDeep { depth } => {
+                write!(f, "Evaluation exceeded the maximum depth of {}", depth)
+            }
+        }
+    }
+}
+
+impl<'a, 'b> PartialEq<OutputValue<'b>> for OutputValue<'a> {
+    /// Functions are equal when their code is alpha-equivalent and they captured equal values for
+    /// its free variables. Values resulting from different trees can be compared.
+    fn eq(&self, other: &OutputValue<'b>) -> bool {
+        match (self, other) {
+            (OutputValue::Nat(a), OutputValue::Nat(b)) => a == b,
+            (OutputValue::Bool(a), OutputValue::Bool(b)) => a == b,
+            (OutputValue::Record(a), OutputValue::Record(b)) => {
+                a.len() == b.len()
+                    && a.iter()
+                        .all(|(name, a)| b.get(name).is_some_and(|b| a == b))
+            }
+            (OutputValue::Variant(a_tag, a, _), OutputValue::Variant(b_tag, b, _)) => {
+                a_tag == b_tag && **a == **b
+            }
+            (OutputValue::Func(a_function, a_env), OutputValue::Func(b_function, b_env)) => {
+                let a_term = nameless::to_nameless(a_function);
+                let b_term = nameless::to_nameless(b_function);
+                nameless::alpha_eq(&a_term, &b_term)
+                    && a_term.free_vars().iter().all(|name| {
+                        match (a_env.lookup(name), b_env.lookup(name)) {
+                            (Some(a), Some(b)) => a == b,
+                            (a, b) => a.is_none() && b.is_none(),
+                        }
+                    })
+            }
+            _ => false,
+        }
+    }
+}
+
+impl<'a, 'b> PartialEq<Binding<'b>> for Binding<'a> {
+    /// Recursive definitions and arguments that are not evaluated yet are compared by their code
+    /// only, the environment they were bound in is not taken into account.
+    fn eq(&self, other: &Binding<'b>) -> bool {
+        match (self, other) {
+            (Binding::Value(a), Binding::Value(b)) => a == b,
+            (Binding::Recursive(a, _), Binding::Recursive(b, _))
+            | (Binding::Delayed(a, _), Binding::Delayed(b, _)) => code_eq(a, b),
+            (Binding::Shared(a), Binding::Shared(b)) => match (&*a.borrow(), &*b.borrow()) {
+                (Thunk::Forced(a), Thunk::Forced(b)) => a == b,
+                (Thunk::Delayed(a, _), Thunk::Delayed(b, _)) => code_eq(a, b),
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+}
+
+/// Returns whether two trees are equal up to the names of their bound variables
+fn code_eq(left: &ASTNode<'_>, right: &ASTNode<'_>) -> bool {
+    nameless::alpha_eq(&nameless::to_nameless(left), &nameless::to_nameless(right))
 }
 
 impl<'a> Display for OutputValue<'a> {
+    /// Prints the value the way every backend prints it, functions only show that they are one
     fn fmt(&self, f: &mut Formatter) -> Result {
         match self {
             OutputValue::Nat(x) => write!(f, "{}", x),
             OutputValue::Bool(x) => write!(f, "{}", x),
             OutputValue::Record(records) => {
-                let list: Vec<String> = records
+                let mut list: Vec<String> = records
                     .iter()
                     .map(|(name, val)| format!("{}={}", name, val))
                     .collect();
+                list.sort();
                 write!(f, "{{{}}}", list.join(", "))
             }
-            OutputValue::Func(par, body, _) => write!(f, "@ {}. {:?}", par, body),
-            OutputValue::Variant(ident, value) => write!(f, "<{}={}>", ident, value),
-            OutputValue::Fix(body) => write!(f, "fix {}", body),
+            OutputValue::Func(_, _) => write!(f, "<function>"),
+            OutputValue::Variant(ident, value, _) => write!(f, "<{}={}>", ident, value),
         }
     }
 }
@@ -38,151 +185,189 @@ impl<'a> ASTNode<'a> {
     /// # Panics
     /// Throws a panic when encountering an invalid tree structure or an invalid type. This would indicate a problem
     /// in the typechecking logic.
-    pub fn eval(&self) -> OutputValue {
-        self.eval_node(&mut SymbolTable::new())
+    pub fn eval(&self) -> OutputValue<'_> {
+        self.eval_using(Strategy::CallByValue)
+    }
+
+    /// Evaluates an abstract syntax tree using the given strategy to pass arguments to functions.
+    ///
+    /// # Panics
+    /// Throws a panic when encountering an invalid tree structure or an invalid type. This would indicate a problem
+    /// in the typechecking logic.
+    pub fn eval_using(&self, strategy: Strategy) -> OutputValue<'_> {
+        self.eval_with(strategy, EvalConfig::default())
+            .expect("Evaluation without limits ran out of fuel")
     }
 
-    fn eval_node(&self, table: &mut SymbolTable<OutputValue<'a>>) -> OutputValue<'a> {
+    /// Evaluates an abstract syntax tree using the given strategy, giving up with an error when
+    /// the evaluation exceeds one of the limits of the configuration. The evaluation does not
+    /// recurse on the Rust stack, so deeply nested computations do not overflow it.
+    ///
+    /// Applications in tail position, the body of a function or of a let and the arms of a
+    /// conditional or case, are evaluated without leaving a continuation behind, so loops written
+    /// as tail recursive functions run in constant space.
+    ///
+    /// # Errors
+    /// Returns `EvalError::OutOfFuel` when more steps are taken than allowed, and
+    /// `EvalError::TooDeep` when more continuations are waiting for a value than allowed
+    ///
+    /// # Panics
+    /// Throws a panic when encountering an invalid tree structure or an invalid type. This would indicate a problem
+    /// in the typechecking logic.
+    pub fn eval_with(
+        &self,
+        strategy: Strategy,
+        config: EvalConfig,
+    ) -> result::Result<OutputValue<'_>, EvalError> {
+        State::new(self).run(strategy, config, |_| ())
+    }
+}
+
+impl<'a> OutputValue<'a> {
+    /// Returns a closed term that evaluates to the value. A function becomes its abstraction,
+    /// wrapped in lets binding its free variables to what they were bound to in its environment,
+    /// and in letrecs for the recursive definitions it refers to. Normalizing the term gives the
+    /// source a function value is printed as.
+    pub fn quote(&self) -> Term {
         match self {
-            ASTNode::AbstractionNode {
-                meta: _,
-                ident,
-                data_type: _,
-                body,
-            } => OutputValue::Func(ident.to_string(), body.clone(), table.clone()),
-            ASTNode::ApplicationNode { meta, left, right } => {
-                let left_val = left.eval_node(table);
-                if let OutputValue::Func(ident, body, mut func_table) = left_val {
-                    let right_val = right.eval_node(table);
-                    func_table.push(Scope::new(ident, right_val));
-                    body.eval_node(&mut func_table)
-                } else if let OutputValue::Fix(func) = left_val {
-                    let destr = *func;
-                    if let OutputValue::Func(ident, body, mut _func_table) = destr {
-                        table.remove(&ident);
-                        let newnode = ASTNode::ApplicationNode {
-                            meta: meta.clone(),
-                            left: Box::new(ASTNode::FixNode {
-                                meta: meta.clone(),
-                                point: Box::new(ASTNode::AbstractionNode {
-                                    meta: meta.clone(),
-                                    ident,
-                                    body,
-                                    data_type: TypeAssignment::Single(Type::Bool),
-                                }),
-                            }),
-                            right: right.clone(),
-                        };
-                        newnode.eval_node(table)
-                    } else {
-                        panic!("Bug in typechecker: in evaluation of application the left argument was not evaluated to a function");
-                    }
-                } else {
-                    panic!("Bug in typechecker: in evaluation of application the left argument was not evaluated to a function");
-                }
-            }
-            ASTNode::ArithmeticNode { meta: _, op, expr } => {
-                if let OutputValue::Nat(x) = expr.eval_node(table) {
-                    match op {
-                        Operator::Pred => if x == 0 {
-                            OutputValue::Nat(0)
-                        } else {
-                            OutputValue::Nat(x - 1)
-                        },
-                        Operator::Succ => OutputValue::Nat(x + 1),
-                    }
-                } else {
-                    panic!("Bug in typechecker: in evaluation of pred/succ expr did not return variable of type Nat");
-                }
-            }
-            ASTNode::ConditionNode {
-                meta: _,
-                clause,
-                then_arm,
-                else_arm,
-            } => {
-                if let OutputValue::Bool(x) = clause.eval_node(table) {
-                    if x {
-                        then_arm.eval_node(table)
-                    } else {
-                        else_arm.eval_node(table)
-                    }
-                } else {
-                    panic!("Bug in typechecker: in evaluation of ifthenelse clause did not return variable of type Bool");
-                }
-            }
-            ASTNode::IdentifierNode { meta: _, name } => {
-                let value = table
-                    .lookup(name)
-                    .expect("Bug in typechecker: came across unknown variable");
+            OutputValue::Nat(x) => (0..*x).fold(Term::Value(Value::Zero), |term, _| {
+                Term::Arith(Operator::Succ, Box::new(term))
+            }),
+            OutputValue::Bool(true) => Term::Value(Value::True),
+            OutputValue::Bool(false) => Term::Value(Value::False),
+            OutputValue::Func(function, env) => close(nameless::to_nameless(function), env),
+            OutputValue::Record(records) => Term::Record(
+                records
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.quote()))
+                    .collect(),
+            ),
+            OutputValue::Variant(ident, value, data_type) => Term::Tag(
+                ident.to_string(),
+                Box::new(value.quote()),
+                (*data_type).clone(),
+            ),
+        }
+    }
 
-                value.clone()
-            }
-            ASTNode::IsZeroNode { meta: _, expr } => {
-                if let OutputValue::Nat(x) = expr.eval_node(table) {
-                    OutputValue::Bool(x == 0)
-                } else {
-                    panic!("Bug in typechecker: in evaluation of iszero expr did not return variable of type Nat");
-                }
-            }
-            ASTNode::ValueNode { meta: _, value } => match value {
-                Value::True => OutputValue::Bool(true),
-                Value::False => OutputValue::Bool(false),
-                Value::Zero => OutputValue::Nat(0),
-            },
-            ASTNode::ProjectionNode {
-                meta: _,
-                target,
-                attrib,
-            } => {
-                if let OutputValue::Record(records) = target.eval_node(table) {
-                    if let Some(output) = records.get(attrib) {
-                        output.clone()
-                    } else {
-                        panic!("Bug in typechecker: in evaluation of projection the attribute was not found")
-                    }
-                } else {
-                    panic!("Bug in typechecker: in evaluation of projection type target type was not a record")
-                }
-            }
-            ASTNode::RecordNode { meta: _, records } => {
-                let mut map = HashMap::new();
-                for (name, node) in records {
-                    map.insert(name.to_string(), node.eval_node(table));
-                }
-                OutputValue::Record(map)
-            }
-            ASTNode::MatchingNode {
-                meta: _,
-                to_match,
+    /// Selects the arm of a decision tree that matches the value and returns it together with a
+    /// scope containing the variables bound by its pattern.
+    ///
+    /// # Arguments
+    /// * `tree` - the compiled patterns the value is matched against
+    pub fn select(&self, tree: &Decision) -> (usize, Scope<Binding<'a>>) {
+        let (arm, bindings) = self.decide(tree);
+        let mut scope = HashMap::new();
+        for (name, path) in bindings {
+            scope.insert(name.to_string(), Binding::Value(self.at(path)));
+        }
+        (arm, Scope::from_map(scope))
+    }
+
+    /// Walks the decision tree of a case expression and returns the selected arm together with the
+    /// variables that have to be bound for it.
+    ///
+    /// # Panics
+    /// Throws a panic when no arm matches the value, the typechecker makes sure case expressions
+    /// are exhaustive.
+    fn decide<'t>(&self, tree: &'t Decision) -> (usize, &'t [(String, Path)]) {
+        match tree {
+            Decision::Fail => panic!("Bug in typechecker: no arm of case matches the argument"),
+            Decision::Leaf { arm, bindings } => (*arm, bindings),
+            Decision::Switch {
+                path,
                 cases,
+                default,
             } => {
-                if let OutputValue::Variant(ident, value) = to_match.eval_node(table) {
-                    if let Some((case, arm)) = cases.get(&ident) {
-                        table.push(Scope::new(case.to_string(), *value));
-                        arm.eval_node(table)
-                    } else {
-                        panic!("Bug in typechecker: argument of case has no corresponding arm")
-                    }
-                } else {
-                    panic!("Bug in typechecker: argument of case was not a variant")
-                }
-            }
-            ASTNode::TaggingNode {
-                meta: _,
-                ident,
-                value,
-                data_type: _,
-            } => OutputValue::Variant(ident.to_string(), Box::new(value.eval_node(table))),
-            ASTNode::FixNode { meta: _, point } => {
-                let left_val = point.eval_node(table);
-                if let OutputValue::Func(ident, body, mut table) = left_val.clone() {
-                    table.push(Scope::new(ident, OutputValue::Fix(Box::new(left_val))));
-                    body.eval_node(&mut table)
+                let value = self.at(path);
+                if let Some((_, case)) = cases.iter().find(|(c, _)| value.has_constructor(c)) {
+                    self.decide(case)
+                } else if let Some(default) = default {
+                    self.decide(default)
                 } else {
-                    panic!("Bug in typechecker: in evaluation of fixpoint the left argument was not evaluated to a function");
+                    panic!("Bug in typechecker: no arm of case matches the argument")
                 }
             }
         }
     }
+
+    /// Returns the component of the value at the given path
+    fn at(&self, path: &[Access]) -> OutputValue<'a> {
+        let mut current = self.clone();
+        for access in path {
+            current = match (access, current) {
+                (Access::Tag(_), OutputValue::Variant(_, value, _)) => *value,
+                (Access::Pred, OutputValue::Nat(x)) => OutputValue::Nat(x - 1),
+                (Access::Field(name), OutputValue::Record(mut records)) => records
+                    .remove(name)
+                    .expect("Bug in typechecker: matched field was not found in record"),
+                _ => panic!("Bug in typechecker: matched value has an incorrect type"),
+            };
+        }
+        current
+    }
+
+    fn has_constructor(&self, constructor: &Constructor) -> bool {
+        match (constructor, self) {
+            (Constructor::True, OutputValue::Bool(x)) => *x,
+            (Constructor::False, OutputValue::Bool(x)) => !*x,
+            (Constructor::Zero, OutputValue::Nat(x)) => *x == 0,
+            (Constructor::Succ, OutputValue::Nat(x)) => *x != 0,
+            (Constructor::Tag(tag), OutputValue::Variant(ident, _, _)) => tag == ident,
+            _ => false,
+        }
+    }
+}
+
+/// Binds the free variables of the term to closed terms for their bindings in the environment
+fn close(term: Term, env: &SymbolTable<Binding<'_>>) -> Term {
+    let mut names: Vec<String> = term.free_vars().into_iter().collect();
+    names.sort();
+    names.into_iter().rev().fold(term, |body, name| {
+        let value = quote_binding(&name, env);
+        let body = body.bind_free(std::slice::from_ref(&name));
+        Term::Let(Pattern::Variable(name), Box::new(value), Box::new(body))
+    })
+}
+
+/// Returns a closed term evaluating to the value of the variable in the environment. A recursive
+/// definition is bound together with the other definitions of its letrec, in the environment the
+/// letrec was evaluated in.
+///
+/// # Panics
+/// Throws a panic when the variable is not bound in the environment
+fn quote_binding(name: &str, env: &SymbolTable<Binding<'_>>) -> Term {
+    let binding = env
+        .lookup(name)
+        .expect("Bug in typechecker: came across unknown variable");
+    match binding {
+        Binding::Value(value) => value.quote(),
+        Binding::Delayed(term, env) => close(nameless::to_nameless(term), env),
+        Binding::Shared(thunk) => match &*thunk.borrow() {
+            Thunk::Forced(value) => value.quote(),
+            Thunk::Delayed(term, env) => close(nameless::to_nameless(term), env),
+        },
+        Binding::Recursive(_, _) => {
+            let mut env = env.up_to(name);
+            let group = env
+                .innermost()
+                .expect("Bug in evaluator: recursive binding outside of a scope")
+                .clone();
+            env.pop();
+            let names = group.names();
+            let bindings = names
+                .iter()
+                .map(|member| match group.get(member) {
+                    Some(Binding::Recursive(term, data_type)) => (
+                        member.to_string(),
+                        data_type.cloned(),
+                        nameless::to_nameless(term).bind_free(&names),
+                    ),
+                    _ => panic!("Bug in evaluator: recursive binding next to another binding"),
+                })
+                .collect();
+            let index = names.len() - 1 - names.iter().position(|n| n == name).unwrap();
+            close(Term::LetRec(bindings, Box::new(Term::Var(index))), &env)
+        }
+    }
 }
@@ -0,0 +1,975 @@
+use ast::*;
+use check::check_recursive_bindings;
+use matching;
+use matching::Decision;
+use pest::Error;
+use pest::Span;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::marker::PhantomData;
+use sym_tab::*;
+
+/// A type in which the listed type and row variables can be instantiated to any type or row
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scheme {
+    pub vars: Vec<String>,
+    pub data_type: TypeAssignment,
+}
+
+impl Scheme {
+    /// Creates a scheme without quantified variables
+    ///
+    /// # Arguments
+    /// * `data_type` - the type of the scheme
+    pub fn mono(data_type: TypeAssignment) -> Scheme {
+        Scheme {
+            vars: Vec::new(),
+            data_type,
+        }
+    }
+}
+
+impl fmt::Display for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.vars.is_empty() {
+            write!(f, "{}", self.data_type)
+        } else {
+            write!(f, "forall {}. {}", self.vars.join(" "), self.data_type)
+        }
+    }
+}
+
+/// Types inferred for the nodes of a tree, which are identified by their address and are
+/// therefore borrowed for as long as the types are used
+pub struct Typing<'n> {
+    types: HashMap<usize, TypeAssignment>,
+    tree: PhantomData<&'n ()>,
+}
+
+impl<'n> Typing<'n> {
+    /// Returns the type of a node. A node whose type mentions variables is polymorphic, or lies
+    /// in the value of a polymorphic let binding.
+    ///
+    /// # Panics
+    /// Throws a panic when the node is not part of the tree the types were inferred for
+    pub fn type_of(&self, node: &ASTNode<'_>) -> &TypeAssignment {
+        self.types
+            .get(&(node as *const ASTNode<'_> as usize))
+            .expect("Node is not part of the tree the types were inferred for")
+    }
+}
+
+impl<'a> ASTNode<'a> {
+    /// Infers the most general type of the abstract syntax tree and returns it or an error
+    /// specifying the problem encountered. Arguments of abstractions don't need type annotations,
+    /// variables bound by a let expression are generalized over their free type and row variables.
+    ///
+    /// Type and row variables only appear in inferred types. The grammar has no syntax for them,
+    /// so an annotation like `{x: Nat | r}` can not be written in a program, and annotated
+    /// records and variants are always closed.
+    pub fn infer<R: Copy>(&self) -> Result<Scheme, Error<'_, R>> {
+        let mut inference = Inference::new();
+        inference.enter();
+        let data_type = self.infer_node(&mut inference, &mut SymbolTable::new())?;
+        inference.leave();
+        Ok(inference.generalize(&data_type).pretty())
+    }
+
+    /// Infers the types of the abstract syntax tree like `infer` and returns the type found for
+    /// every one of its nodes. Variables that are left open, because the program or one of its
+    /// let bindings is polymorphic, are renamed like the variables of a scheme.
+    ///
+    /// # Errors
+    /// Returns the same errors as `infer`
+    pub fn infer_types<R: Copy>(&self) -> Result<Typing<'_>, Error<'_, R>> {
+        let mut inference = Inference::new();
+        inference.enter();
+        self.infer_node(&mut inference, &mut SymbolTable::new())?;
+        inference.leave();
+
+        let resolved: Vec<(usize, TypeAssignment)> = inference
+            .types
+            .iter()
+            .map(|(node, data_type)| (*node, inference.resolve(data_type)))
+            .collect();
+        let mut vars = Vec::new();
+        for (_, data_type) in &resolved {
+            inference.free_vars(data_type, &mut vars);
+        }
+        let pretty = Scheme {
+            vars: vars.clone(),
+            data_type: TypeAssignment::Single(Type::Nat),
+        }
+        .pretty();
+        let renaming = vars.into_iter().zip(pretty.vars).collect();
+        Ok(Typing {
+            types: resolved
+                .into_iter()
+                .map(|(node, data_type)| (node, rename(&data_type, &renaming)))
+                .collect(),
+            tree: PhantomData,
+        })
+    }
+
+    fn infer_node<R: Copy>(
+        &self,
+        inf: &mut Inference,
+        table: &mut SymbolTable<Scheme>,
+    ) -> Result<TypeAssignment, Error<'_, R>> {
+        let data_type = self.infer_shape(inf, table)?;
+        inf.types
+            .push((self as *const ASTNode<'_> as usize, data_type.clone()));
+        Ok(data_type)
+    }
+
+    fn infer_shape<R: Copy>(
+        &self,
+        inf: &mut Inference,
+        table: &mut SymbolTable<Scheme>,
+    ) -> Result<TypeAssignment, Error<'_, R>> {
+        match self {
+            ASTNode::ValueNode { meta: _, value } => match value {
+                Value::True | Value::False => Ok(TypeAssignment::Single(Type::Bool)),
+                Value::Zero => Ok(TypeAssignment::Single(Type::Nat)),
+            },
+            ASTNode::IsZeroNode { meta, expr } => {
+                let expr_type = expr.infer_node(inf, table)?;
+                inf.unify(&expr_type, &TypeAssignment::Single(Type::Nat))
+                    .map_err(|m| {
+                        error(
+                            meta,
+                            "The argument of a zero check should be of type Nat",
+                            m,
+                        )
+                    })?;
+                Ok(TypeAssignment::Single(Type::Bool))
+            }
+            ASTNode::IdentifierNode { meta, name } => {
+                if let Some(scheme) = table.lookup(name) {
+                    Ok(inf.instantiate(scheme))
+                } else {
+                    Err(Error::CustomErrorSpan {
+                        message: "Identifier is not defined".to_string(),
+                        span: meta.clone(),
+                    })
+                }
+            }
+            ASTNode::ConditionNode {
+                meta,
+                clause,
+                then_arm,
+                else_arm,
+            } => {
+                let clause_type = clause.infer_node(inf, table)?;
+                inf.unify(&clause_type, &TypeAssignment::Single(Type::Bool))
+                    .map_err(|m| {
+                        error(
+                            meta,
+                            "The clause of an if expression should be of type Bool",
+                            m,
+                        )
+                    })?;
+                let then_type = then_arm.infer_node(inf, table)?;
+                let else_type = else_arm.infer_node(inf, table)?;
+                inf.unify(&then_type, &else_type).map_err(|m| {
+                    error(
+                        meta,
+                        "The different outcomes of an if expression should have the same type",
+                        m,
+                    )
+                })?;
+                Ok(then_type)
+            }
+            ASTNode::ArithmeticNode { meta, op: _, expr } => {
+                let expr_type = expr.infer_node(inf, table)?;
+                inf.unify(&expr_type, &TypeAssignment::Single(Type::Nat))
+                    .map_err(|m| error(meta, "Arithmetic expression should have Nat as type", m))?;
+                Ok(TypeAssignment::Single(Type::Nat))
+            }
+            ASTNode::ApplicationNode { meta, left, right } => {
+                let left_type = left.infer_node(inf, table)?;
+                let right_type = right.infer_node(inf, table)?;
+                let result = inf.fresh_var();
+                let expected =
+                    TypeAssignment::Arrow(Box::new(right_type), Box::new(result.clone()));
+                inf.unify(&left_type, &expected).map_err(|m| {
+                    error(
+                        meta,
+                        "Left argument of an application should be a function accepting the right argument",
+                        m,
+                    )
+                })?;
+                Ok(result)
+            }
+            ASTNode::AbstractionNode {
+                meta: _,
+                ident,
+                data_type,
+                body,
+            } => {
+                let data_type = match data_type {
+                    Some(data_type) => data_type.clone(),
+                    None => inf.fresh_var(),
+                };
+                let scope = Scope::new(ident.to_string(), Scheme::mono(data_type.clone()));
+                let body_type = table.with_scope(scope, |table| body.infer_node(inf, table))?;
+                Ok(TypeAssignment::Arrow(
+                    Box::new(data_type),
+                    Box::new(body_type),
+                ))
+            }
+            ASTNode::ProjectionNode {
+                meta,
+                target,
+                attrib,
+            } => {
+                let target_type = target.infer_node(inf, table)?;
+                let attrib_type = inf.fresh_var();
+                let mut fields = HashMap::new();
+                fields.insert(attrib.to_string(), attrib_type.clone());
+                let expected = TypeAssignment::Record(fields, Some(inf.fresh_row()));
+                inf.unify(&target_type, &expected).map_err(|m| {
+                    error(
+                        meta,
+                        "Target of a projection should be a record containing the attribute",
+                        m,
+                    )
+                })?;
+                Ok(attrib_type)
+            }
+            ASTNode::RecordNode { meta: _, records } => {
+                let mut types = HashMap::new();
+                for (name, node) in sorted(records) {
+                    types.insert(name.to_string(), node.infer_node(inf, table)?);
+                }
+                Ok(TypeAssignment::Record(types, None))
+            }
+            ASTNode::UpdateNode {
+                meta,
+                target,
+                records,
+            } => {
+                let target_type = target.infer_node(inf, table)?;
+                let mut types = HashMap::new();
+                for (name, node) in sorted(records) {
+                    types.insert(name.to_string(), node.infer_node(inf, table)?);
+                }
+                let expected = TypeAssignment::Record(types, Some(inf.fresh_row()));
+                inf.unify(&target_type, &expected).map_err(|m| {
+                    error(
+                        meta,
+                        "Target of an update should be a record containing the updated attributes with the same type",
+                        m,
+                    )
+                })?;
+                Ok(inf.resolve(&target_type))
+            }
+            ASTNode::ExtensionNode {
+                meta,
+                target,
+                records,
+            } => {
+                let target_type = target.infer_node(inf, table)?;
+                let expected = TypeAssignment::Record(HashMap::new(), Some(inf.fresh_row()));
+                inf.unify(&target_type, &expected).map_err(|m| {
+                    error(meta, "Target of an extension should be of type record", m)
+                })?;
+                if let TypeAssignment::Record(mut types, row) = inf.resolve(&target_type) {
+                    for (name, node) in sorted(records) {
+                        if types.contains_key(name) {
+                            return Err(Error::CustomErrorSpan {
+                                message:
+                                    "Attribute of extension is already part of the record type"
+                                        .to_string(),
+                                span: meta.clone(),
+                            });
+                        }
+                        if let Some(row) = &row {
+                            inf.lacks(row, name);
+                        }
+                        types.insert(name.to_string(), node.infer_node(inf, table)?);
+                    }
+                    Ok(TypeAssignment::Record(types, row))
+                } else {
+                    panic!("Bug in type inference: unified record type did not resolve to a record")
+                }
+            }
+            ASTNode::RestrictionNode {
+                meta,
+                target,
+                attrib,
+            } => {
+                let target_type = target.infer_node(inf, table)?;
+                let row = inf.fresh_row();
+                inf.lacks(&row, attrib);
+                let mut fields = HashMap::new();
+                fields.insert(attrib.to_string(), inf.fresh_var());
+                let expected = TypeAssignment::Record(fields, Some(row.clone()));
+                inf.unify(&target_type, &expected).map_err(|m| {
+                    error(
+                        meta,
+                        "Target of a restriction should be a record containing the attribute",
+                        m,
+                    )
+                })?;
+                Ok(inf.resolve(&TypeAssignment::Record(HashMap::new(), Some(row))))
+            }
+            ASTNode::MatchingNode {
+                meta: _,
+                to_match,
+                cases,
+            } => {
+                let match_type = to_match.infer_node(inf, table)?;
+                let arm_type = inf.fresh_var();
+                for (pattern, arm) in cases {
+                    let mut bindings = HashMap::new();
+                    inf.infer_pattern(pattern, &match_type, &mut bindings)
+                        .map_err(|m| {
+                            error(
+                                &arm.span(),
+                                "Pattern does not match the type of the argument",
+                                m,
+                            )
+                        })?;
+                    let scope = bindings
+                        .into_iter()
+                        .map(|(name, data_type)| (name, Scheme::mono(data_type)))
+                        .collect();
+                    let case_type = table
+                        .with_scope(Scope::from_map(scope), |table| arm.infer_node(inf, table))?;
+                    inf.unify(&arm_type, &case_type).map_err(|m| {
+                        error(
+                            &arm.span(),
+                            "All outcomes of a case expression should result in the same type",
+                            m,
+                        )
+                    })?;
+                }
+
+                let patterns: Vec<&Pattern> = cases.iter().map(|(pattern, _)| pattern).collect();
+                let (tree, missing) = inf.exhaustive(&patterns, &match_type);
+                if let Some(missing) = missing {
+                    return Err(Error::CustomErrorSpan {
+                        message: format!(
+                            "Case expression is not exhaustive, values matching {} are not handled",
+                            missing
+                        ),
+                        span: to_match.span(),
+                    });
+                }
+                if let Some(arm) = tree.reachable_arms(cases.len()).iter().position(|r| !r) {
+                    return Err(Error::CustomErrorSpan {
+                        message: format!(
+                            "Arm {} of the case expression can never be reached",
+                            arm + 1
+                        ),
+                        span: cases[arm].1.span(),
+                    });
+                }
+                Ok(arm_type)
+            }
+            ASTNode::TaggingNode {
+                meta,
+                ident,
+                value,
+                data_type,
+            } => {
+                let value_type = value.infer_node(inf, table)?;
+                if let TypeAssignment::Variant(variants, _) = data_type {
+                    if let Some(variant_type) = variants.get(ident) {
+                        inf.unify(&value_type, variant_type).map_err(|m| {
+                            error(meta, "Type of tagged value is not part of the variant", m)
+                        })?;
+                        return Ok(data_type.clone());
+                    }
+                }
+                Err(Error::CustomErrorSpan {
+                    message: "Type of tagged value is not part of the variant".to_string(),
+                    span: meta.clone(),
+                })
+            }
+            ASTNode::LetRecNode {
+                meta,
+                bindings,
+                body,
+            } => {
+                check_recursive_bindings(bindings).map_err(|message| Error::CustomErrorSpan {
+                    message,
+                    span: meta.clone(),
+                })?;
+                inf.enter();
+                let mut types = HashMap::new();
+                for (name, data_type, _) in bindings {
+                    let data_type = match data_type {
+                        Some(data_type) => data_type.clone(),
+                        None => inf.fresh_var(),
+                    };
+                    types.insert(name.to_string(), data_type);
+                }
+                let scope = types
+                    .iter()
+                    .map(|(name, data_type)| (name.to_string(), Scheme::mono(data_type.clone())))
+                    .collect();
+                table.with_scope(Scope::from_map(scope), |table| {
+                    for (name, _, value) in bindings {
+                        let value_type = value.infer_node(inf, table)?;
+                        inf.unify(&types[name], &value_type).map_err(|m| {
+                            error(
+                                meta,
+                                "Recursive binding should have the type it is annotated with",
+                                m,
+                            )
+                        })?;
+                    }
+                    Ok(())
+                })?;
+                inf.leave();
+
+                let scope = types
+                    .iter()
+                    .map(|(name, data_type)| (name.to_string(), inf.generalize(data_type)))
+                    .collect();
+                table.with_scope(Scope::from_map(scope), |table| body.infer_node(inf, table))
+            }
+            ASTNode::FixNode { meta, point } => {
+                let point_type = point.infer_node(inf, table)?;
+                let result = inf.fresh_var();
+                let expected =
+                    TypeAssignment::Arrow(Box::new(result.clone()), Box::new(result.clone()));
+                inf.unify(&point_type, &expected).map_err(|m| {
+                    error(
+                        meta,
+                        "Argument of fixpoint should be a function resulting in the same type",
+                        m,
+                    )
+                })?;
+                Ok(result)
+            }
+            ASTNode::LetNode {
+                meta,
+                pattern,
+                value,
+                body,
+            } => {
+                inf.enter();
+                let value_type = value.infer_node(inf, table)?;
+                let mut bindings = HashMap::new();
+                inf.infer_pattern(pattern, &value_type, &mut bindings)
+                    .map_err(|m| error(meta, "Pattern does not match the type of the value", m))?;
+                if let (_, Some(missing)) = inf.exhaustive(&[pattern], &value_type) {
+                    return Err(Error::CustomErrorSpan {
+                        message: format!(
+                            "Pattern of a let expression should match every value, values matching {} are not handled",
+                            missing
+                        ),
+                        span: meta.clone(),
+                    });
+                }
+                inf.leave();
+
+                let scope = bindings
+                    .into_iter()
+                    .map(|(name, data_type)| {
+                        let scheme = inf.generalize(&data_type);
+                        (name, scheme)
+                    })
+                    .collect();
+                table.with_scope(Scope::from_map(scope), |table| body.infer_node(inf, table))
+            }
+        }
+    }
+}
+
+/// Creates an error for a failed unification
+///
+/// # Arguments
+/// * `meta` - the span of the node in which the unification failed
+/// * `context` - description of what was expected
+/// * `reason` - the problem reported by the unification
+fn error<'s, R>(meta: &Span<'s>, context: &str, reason: String) -> Error<'s, R> {
+    Error::CustomErrorSpan {
+        message: format!("{}: {}", context, reason),
+        span: meta.clone(),
+    }
+}
+
+/// State of the inference: the substitution found so far for type and row variables, together
+/// with the let nesting level at which every variable was created. Variables created at a deeper
+/// level than the current one are not referenced by the environment and can be generalized.
+struct Inference {
+    substitution: HashMap<String, TypeAssignment>,
+    levels: HashMap<String, usize>,
+    lacking: HashMap<String, HashSet<String>>,
+    level: usize,
+    counter: usize,
+    /// Type found for every node that was inferred, identified by its address
+    types: Vec<(usize, TypeAssignment)>,
+}
+
+impl Inference {
+    fn new() -> Inference {
+        Inference {
+            substitution: HashMap::new(),
+            levels: HashMap::new(),
+            lacking: HashMap::new(),
+            level: 0,
+            counter: 0,
+            types: Vec::new(),
+        }
+    }
+
+    fn enter(&mut self) {
+        self.level += 1;
+    }
+
+    fn leave(&mut self) {
+        self.level -= 1;
+    }
+
+    /// Creates a new variable, type variables start with `t` and row variables with `r`
+    fn fresh(&mut self, prefix: &str) -> String {
+        self.counter += 1;
+        let name = format!("{}{}", prefix, self.counter);
+        self.levels.insert(name.clone(), self.level);
+        name
+    }
+
+    fn fresh_var(&mut self) -> TypeAssignment {
+        TypeAssignment::Var(self.fresh("t"))
+    }
+
+    fn fresh_row(&mut self) -> String {
+        self.fresh("r")
+    }
+
+    /// Records that the row variable can never be instantiated to a row containing the attribute
+    fn lacks(&mut self, row: &str, attrib: &str) {
+        self.lacking
+            .entry(row.to_string())
+            .or_default()
+            .insert(attrib.to_string());
+    }
+
+    /// Applies the substitution to the type
+    fn resolve(&self, data_type: &TypeAssignment) -> TypeAssignment {
+        match data_type {
+            TypeAssignment::Var(name) => match self.substitution.get(name) {
+                Some(bound) => self.resolve(bound),
+                None => data_type.clone(),
+            },
+            TypeAssignment::Single(_) => data_type.clone(),
+            TypeAssignment::Arrow(from, to) => {
+                TypeAssignment::Arrow(Box::new(self.resolve(from)), Box::new(self.resolve(to)))
+            }
+            TypeAssignment::Record(fields, row) => {
+                let (fields, row) = self.resolve_row(fields, row);
+                TypeAssignment::Record(fields, row)
+            }
+            TypeAssignment::Variant(tags, row) => {
+                let (tags, row) = self.resolve_row(tags, row);
+                TypeAssignment::Variant(tags, row)
+            }
+        }
+    }
+
+    /// Applies the substitution to the elements of a record or variant and flattens the rows its
+    /// row variable is bound to
+    fn resolve_row(
+        &self,
+        fields: &HashMap<String, TypeAssignment>,
+        row: &Option<String>,
+    ) -> (HashMap<String, TypeAssignment>, Option<String>) {
+        let mut fields = fields.clone();
+        let mut row = row.clone();
+        while let Some(name) = row.clone() {
+            match self.substitution.get(&name) {
+                Some(TypeAssignment::Record(more, tail))
+                | Some(TypeAssignment::Variant(more, tail)) => {
+                    fields.extend(more.clone());
+                    row = tail.clone();
+                }
+                _ => break,
+            }
+        }
+        let fields = fields
+            .iter()
+            .map(|(name, data_type)| (name.to_string(), self.resolve(data_type)))
+            .collect();
+        (fields, row)
+    }
+
+    /// Collects the free type and row variables of a resolved type in order of appearance
+    fn free_vars(&self, data_type: &TypeAssignment, vars: &mut Vec<String>) {
+        match data_type {
+            TypeAssignment::Var(name) => {
+                if !vars.contains(name) {
+                    vars.push(name.to_string());
+                }
+            }
+            TypeAssignment::Single(_) => {}
+            TypeAssignment::Arrow(from, to) => {
+                self.free_vars(from, vars);
+                self.free_vars(to, vars);
+            }
+            TypeAssignment::Record(fields, row) | TypeAssignment::Variant(fields, row) => {
+                let mut names: Vec<&String> = fields.keys().collect();
+                names.sort();
+                for name in names {
+                    self.free_vars(&fields[name], vars);
+                }
+                if let Some(row) = row {
+                    if !vars.contains(row) {
+                        vars.push(row.to_string());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Unifies two types, extending the substitution so both become equal
+    fn unify(&mut self, first: &TypeAssignment, second: &TypeAssignment) -> Result<(), String> {
+        let first = self.resolve(first);
+        let second = self.resolve(second);
+        match (&first, &second) {
+            (TypeAssignment::Var(x), TypeAssignment::Var(y)) if x == y => Ok(()),
+            (TypeAssignment::Var(x), other) | (other, TypeAssignment::Var(x)) => {
+                self.bind(x, other.clone())
+            }
+            (TypeAssignment::Single(x), TypeAssignment::Single(y)) if x == y => Ok(()),
+            (TypeAssignment::Arrow(from1, to1), TypeAssignment::Arrow(from2, to2)) => {
+                self.unify(from1, from2)?;
+                self.unify(to1, to2)
+            }
+            (TypeAssignment::Record(fields1, row1), TypeAssignment::Record(fields2, row2)) => {
+                self.unify_rows(fields1, row1, fields2, row2, true)
+            }
+            (TypeAssignment::Variant(tags1, row1), TypeAssignment::Variant(tags2, row2)) => {
+                self.unify_rows(tags1, row1, tags2, row2, false)
+            }
+            _ => Err(mismatch(&first, &second)),
+        }
+    }
+
+    /// Unifies the rows of two records or two variants. The elements only present on one side are
+    /// moved into the row variable of the other side, both rows then continue in a fresh row.
+    fn unify_rows(
+        &mut self,
+        fields1: &HashMap<String, TypeAssignment>,
+        row1: &Option<String>,
+        fields2: &HashMap<String, TypeAssignment>,
+        row2: &Option<String>,
+        record: bool,
+    ) -> Result<(), String> {
+        let only1: HashMap<String, TypeAssignment> = fields1
+            .iter()
+            .filter(|(name, _)| !fields2.contains_key(*name))
+            .map(|(name, t)| (name.to_string(), t.clone()))
+            .collect();
+        let only2: HashMap<String, TypeAssignment> = fields2
+            .iter()
+            .filter(|(name, _)| !fields1.contains_key(*name))
+            .map(|(name, t)| (name.to_string(), t.clone()))
+            .collect();
+
+        match (row1, row2) {
+            (None, None) if only1.is_empty() && only2.is_empty() => {}
+            (Some(row1), None) if only1.is_empty() => self.bind_row(row1, only2, None, record)?,
+            (None, Some(row2)) if only2.is_empty() => self.bind_row(row2, only1, None, record)?,
+            (Some(row1), Some(row2)) if row1 == row2 && only1.is_empty() && only2.is_empty() => {}
+            (Some(row1), Some(row2)) if row1 != row2 => {
+                let level = self.levels[row1].min(self.levels[row2]);
+                let tail = self.fresh_row();
+                self.levels.insert(tail.clone(), level);
+                self.bind_row(row1, only2, Some(tail.clone()), record)?;
+                self.bind_row(row2, only1, Some(tail), record)?;
+            }
+            _ => {
+                return Err(mismatch(
+                    &make_row(fields1.clone(), row1.clone(), record),
+                    &make_row(fields2.clone(), row2.clone(), record),
+                ))
+            }
+        }
+
+        for (name, data_type) in sorted(fields1) {
+            if let Some(other) = fields2.get(name) {
+                self.unify(data_type, other)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Binds a type variable to a type
+    fn bind(&mut self, name: &str, data_type: TypeAssignment) -> Result<(), String> {
+        let mut vars = Vec::new();
+        self.free_vars(&data_type, &mut vars);
+        if vars.iter().any(|var| var == name) {
+            return Err(format!(
+                "Type {} can't be equal to {} as it occurs in it",
+                name, data_type
+            ));
+        }
+        self.adjust_levels(&vars, self.levels[name]);
+        self.substitution.insert(name.to_string(), data_type);
+        Ok(())
+    }
+
+    /// Binds a row variable to the given elements followed by a row
+    fn bind_row(
+        &mut self,
+        name: &str,
+        fields: HashMap<String, TypeAssignment>,
+        tail: Option<String>,
+        record: bool,
+    ) -> Result<(), String> {
+        if let Some(lacking) = self.lacking.get(name).cloned() {
+            if let Some((attrib, _)) = sorted(&fields)
+                .into_iter()
+                .find(|(attrib, _)| lacking.contains(*attrib))
+            {
+                return Err(format!(
+                    "Attribute {} is already part of the record type",
+                    attrib
+                ));
+            }
+            if let Some(tail) = &tail {
+                for attrib in lacking {
+                    self.lacks(tail, &attrib);
+                }
+            }
+        }
+        let row = make_row(fields, tail, record);
+        let mut vars = Vec::new();
+        self.free_vars(&row, &mut vars);
+        if vars.iter().any(|var| var == name) {
+            return Err(format!(
+                "Row {} can't be equal to {} as it occurs in it",
+                name, row
+            ));
+        }
+        self.adjust_levels(&vars, self.levels[name]);
+        self.substitution.insert(name.to_string(), row);
+        Ok(())
+    }
+
+    /// Makes sure variables that became part of a binding are not generalized at a deeper level
+    /// than the bound variable
+    fn adjust_levels(&mut self, vars: &[String], level: usize) {
+        for var in vars {
+            if let Some(current) = self.levels.get_mut(var) {
+                *current = (*current).min(level);
+            }
+        }
+    }
+
+    /// Replaces the quantified variables of a scheme by fresh variables
+    fn instantiate(&mut self, scheme: &Scheme) -> TypeAssignment {
+        let renaming: HashMap<String, String> = scheme
+            .vars
+            .iter()
+            .map(|var| {
+                let prefix = if var.starts_with('r') { "r" } else { "t" };
+                (var.to_string(), self.fresh(prefix))
+            })
+            .collect();
+        for (var, fresh) in &renaming {
+            if let Some(lacking) = self.lacking.get(var).cloned() {
+                for attrib in lacking {
+                    self.lacks(fresh, &attrib);
+                }
+            }
+        }
+        rename(&scheme.data_type, &renaming)
+    }
+
+    /// Quantifies over the variables of the type that were created deeper than the current level
+    fn generalize(&self, data_type: &TypeAssignment) -> Scheme {
+        let data_type = self.resolve(data_type);
+        let mut vars = Vec::new();
+        self.free_vars(&data_type, &mut vars);
+        vars.retain(|var| self.levels[var] > self.level);
+        Scheme { vars, data_type }
+    }
+
+    /// Infers the type of the values matched by a pattern and collects the types of the
+    /// variables it binds
+    fn infer_pattern(
+        &mut self,
+        pattern: &Pattern,
+        data_type: &TypeAssignment,
+        bindings: &mut HashMap<String, TypeAssignment>,
+    ) -> Result<(), String> {
+        match pattern {
+            Pattern::Wildcard => Ok(()),
+            Pattern::Variable(name) => {
+                if bindings.contains_key(name) {
+                    Err(format!(
+                        "Variable {} is bound more than once in a pattern",
+                        name
+                    ))
+                } else {
+                    bindings.insert(name.to_string(), data_type.clone());
+                    Ok(())
+                }
+            }
+            Pattern::Value(Value::True) | Pattern::Value(Value::False) => {
+                self.unify(data_type, &TypeAssignment::Single(Type::Bool))
+            }
+            Pattern::Value(Value::Zero) => {
+                self.unify(data_type, &TypeAssignment::Single(Type::Nat))
+            }
+            Pattern::Succ(inner) => {
+                self.unify(data_type, &TypeAssignment::Single(Type::Nat))?;
+                self.infer_pattern(inner, data_type, bindings)
+            }
+            Pattern::Tag(ident, inner) => {
+                let tag_type = self.fresh_var();
+                let mut tags = HashMap::new();
+                tags.insert(ident.to_string(), tag_type.clone());
+                let expected = TypeAssignment::Variant(tags, Some(self.fresh_row()));
+                self.unify(data_type, &expected)?;
+                self.infer_pattern(inner, &tag_type, bindings)
+            }
+            Pattern::Record(fields) => {
+                let types: HashMap<String, TypeAssignment> = sorted(fields)
+                    .into_iter()
+                    .map(|(name, _)| (name.to_string(), self.fresh_var()))
+                    .collect();
+                let expected = TypeAssignment::Record(types.clone(), Some(self.fresh_row()));
+                self.unify(data_type, &expected)?;
+                for (name, field) in sorted(fields) {
+                    self.infer_pattern(field, &types[name], bindings)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Compiles patterns against a value of the given type and returns the resulting decision
+    /// tree together with an example of a value that is not handled. When the patterns only
+    /// handle the listed tags of a variant whose row is still open, the row is closed.
+    fn exhaustive(
+        &mut self,
+        patterns: &[&Pattern],
+        scrutinee: &TypeAssignment,
+    ) -> (Decision, Option<Pattern>) {
+        let data_type = self.resolve(scrutinee);
+        let tree = matching::compile(patterns.iter().cloned(), Some(&data_type));
+        if tree.missing(&data_type).is_none() {
+            return (tree, None);
+        }
+
+        self.close_variants(&data_type);
+        let data_type = self.resolve(scrutinee);
+        let tree = matching::compile(patterns.iter().cloned(), Some(&data_type));
+        let missing = tree.missing(&data_type);
+        (tree, missing)
+    }
+
+    /// Binds the open rows of the variants in a resolved type to the empty row
+    fn close_variants(&mut self, data_type: &TypeAssignment) {
+        match data_type {
+            TypeAssignment::Variant(tags, row) => {
+                if let Some(row) = row {
+                    self.substitution.insert(
+                        row.to_string(),
+                        TypeAssignment::Variant(HashMap::new(), None),
+                    );
+                }
+                for tag_type in tags.values() {
+                    self.close_variants(tag_type);
+                }
+            }
+            TypeAssignment::Record(fields, _) => {
+                for field_type in fields.values() {
+                    self.close_variants(field_type);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Scheme {
+    /// Renames the quantified variables to short names, type variables get letters starting from
+    /// `a` and row variables names starting with `r`
+    fn pretty(&self) -> Scheme {
+        let mut renaming = HashMap::new();
+        let mut vars = Vec::new();
+        let (mut types, mut rows) = (0, 0);
+        for var in &self.vars {
+            let name = if var.starts_with('r') {
+                rows += 1;
+                if rows == 1 {
+                    "r".to_string()
+                } else {
+                    format!("r{}", rows - 1)
+                }
+            } else {
+                types += 1;
+                if types <= 17 {
+                    ((b'a' + types as u8 - 1) as char).to_string()
+                } else {
+                    format!("a{}", types - 17)
+                }
+            };
+            renaming.insert(var.to_string(), name.clone());
+            vars.push(name);
+        }
+        Scheme {
+            vars,
+            data_type: rename(&self.data_type, &renaming),
+        }
+    }
+}
+
+/// Renames the type and row variables of a type
+fn rename(data_type: &TypeAssignment, renaming: &HashMap<String, String>) -> TypeAssignment {
+    let rename_var = |name: &String| renaming.get(name).unwrap_or(name).to_string();
+    match data_type {
+        TypeAssignment::Var(name) => TypeAssignment::Var(rename_var(name)),
+        TypeAssignment::Single(_) => data_type.clone(),
+        TypeAssignment::Arrow(from, to) => TypeAssignment::Arrow(
+            Box::new(rename(from, renaming)),
+            Box::new(rename(to, renaming)),
+        ),
+        TypeAssignment::Record(fields, row) => TypeAssignment::Record(
+            fields
+                .iter()
+                .map(|(name, t)| (name.to_string(), rename(t, renaming)))
+                .collect(),
+            row.as_ref().map(rename_var),
+        ),
+        TypeAssignment::Variant(tags, row) => TypeAssignment::Variant(
+            tags.iter()
+                .map(|(name, t)| (name.to_string(), rename(t, renaming)))
+                .collect(),
+            row.as_ref().map(rename_var),
+        ),
+    }
+}
+
+/// Returns the entries of a map sorted by name, so fresh variables are created and unifications
+/// are made in the same order on every run
+fn sorted<V>(map: &HashMap<String, V>) -> Vec<(&String, &V)> {
+    let mut entries: Vec<(&String, &V)> = map.iter().collect();
+    entries.sort_by_key(|(name, _)| *name);
+    entries
+}
+
+/// Creates a record type or a variant type from its elements and row
+fn make_row(
+    fields: HashMap<String, TypeAssignment>,
+    row: Option<String>,
+    record: bool,
+) -> TypeAssignment {
+    if record {
+        TypeAssignment::Record(fields, row)
+    } else {
+        TypeAssignment::Variant(fields, row)
+    }
+}
+
+fn mismatch(first: &TypeAssignment, second: &TypeAssignment) -> String {
+    format!("type {} does not match type {}", first, second)
+}
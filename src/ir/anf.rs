@@ -0,0 +1,991 @@
+use ast::{self, *};
+use infer::Typing;
+use matching::{self, Access, Constructor, Decision, Path};
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::rc::Rc;
+
+/// Operand that needs no evaluation, a variable or a literal
+#[derive(Debug, Clone, PartialEq)]
+pub enum Atom {
+    Var(String),
+    Value(ast::Value),
+}
+
+/// Expression whose operands are all atoms. Only functions and the arms of conditionals
+/// contain terms.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Atom(Atom),
+    Succ(Atom),
+    Pred(Atom),
+    IsZero(Atom),
+    Apply(Atom, Atom),
+    /// Applies the function to its own fixed point, which is unfolded whenever it is read
+    Fix(Atom),
+    Function(Box<Function>),
+    /// Record with its fields sorted by name
+    Record(Vec<(String, Atom)>),
+    Project(Atom, String),
+    Update(Atom, Vec<(String, Atom)>),
+    Extend(Atom, Vec<(String, Atom)>),
+    Restrict(Atom, String),
+    Tag(String, Atom),
+    /// The value carried by a variant that is known to have the tag
+    Untag(Atom, String),
+    If(Atom, Box<Term>, Box<Term>),
+    /// Continues with the case for the head constructor of the value
+    Switch(Atom, Vec<(Constructor, Term)>, Option<Box<Term>>),
+    /// No arm of a case expression matches, which the typechecker rules out
+    Fail,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub param: String,
+    pub param_type: TypeAssignment,
+    pub result_type: TypeAssignment,
+    pub body: Term,
+}
+
+/// Term in A-normal form: a sequence of bindings ending in the expression whose value is the
+/// value of the term
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    Let {
+        name: String,
+        data_type: TypeAssignment,
+        value: Expr,
+        body: Box<Term>,
+    },
+    /// Binds mutually recursive functions
+    LetRec {
+        functions: Vec<(String, Function)>,
+        body: Box<Term>,
+    },
+    Expr(Expr),
+}
+
+/// Converts a typechecked abstract syntax tree into A-normal form. Every operand that is not a
+/// variable or a literal is bound to a temporary first, in the order the operands are evaluated.
+/// Bound variables are renamed apart, with a suffix like `_2` when their name was already used,
+/// and every binding is annotated with its inferred type.
+///
+/// # Panics
+/// Throws a panic when the tree is not well typed
+pub fn convert(node: &ASTNode<'_>) -> Term {
+    let typing = node
+        .infer_types::<i32>()
+        .expect("Only typechecked programs can be converted to A-normal form");
+    let mut builder = Builder {
+        typing: &typing,
+        counts: HashMap::new(),
+        temps: 0,
+    };
+    builder.term(node, &Env::default())
+}
+
+/// Builds the rest of a term once the expression or atoms for the converted nodes are known
+type Resume<'n, T> = Box<dyn FnOnce(&mut Builder<'n>, T) -> Term + 'n>;
+
+/// Atoms the variables in scope of the source program stand for
+#[derive(Clone, Default)]
+struct Env(Option<Rc<(String, Atom, Env)>>);
+
+impl Env {
+    fn bind(&self, name: &str, atom: Atom) -> Env {
+        Env(Some(Rc::new((name.to_string(), atom, self.clone()))))
+    }
+
+    fn lookup(&self, name: &str) -> Atom {
+        let mut env = self;
+        while let Some(binding) = &env.0 {
+            if binding.0 == name {
+                return binding.1.clone();
+            }
+            env = &binding.2;
+        }
+        panic!("Bug in typechecker: came across unknown variable")
+    }
+}
+
+struct Builder<'n> {
+    typing: &'n Typing<'n>,
+    /// How often every name of the source program was bound
+    counts: HashMap<String, usize>,
+    temps: usize,
+}
+
+impl<'n> Builder<'n> {
+    fn type_of(&self, node: &ASTNode<'n>) -> TypeAssignment {
+        self.typing.type_of(node).clone()
+    }
+
+    /// Returns the name for a variable bound by the source program, unique in the converted term
+    fn rename(&mut self, name: &str) -> String {
+        let count = self.counts.entry(name.to_string()).or_insert(0);
+        *count += 1;
+        if *count == 1 {
+            name.to_string()
+        } else {
+            format!("{}_{}", name, count)
+        }
+    }
+
+    /// Creates a name for a temporary, which ends in digits and so never clashes with the
+    /// names of the source program
+    fn fresh(&mut self) -> String {
+        self.temps += 1;
+        format!("t{}", self.temps)
+    }
+
+    /// Converts a node in tail position, the value of its expression is the value of the term
+    fn term(&mut self, node: &'n ASTNode<'n>, env: &Env) -> Term {
+        self.expr(node, env, Box::new(|_, expr| Term::Expr(expr)))
+    }
+
+    /// Converts a node into an atom, binding its expression to a temporary when needed
+    fn atom(&mut self, node: &'n ASTNode<'n>, env: &Env, next: Resume<'n, Atom>) -> Term {
+        let data_type = self.type_of(node);
+        self.expr(
+            node,
+            env,
+            Box::new(move |b, expr| match expr {
+                Expr::Atom(atom) => next(b, atom),
+                value => {
+                    let name = b.fresh();
+                    let body = next(b, Atom::Var(name.clone()));
+                    Term::Let {
+                        name,
+                        data_type,
+                        value,
+                        body: Box::new(body),
+                    }
+                }
+            }),
+        )
+    }
+
+    /// Converts a node into an expression that is passed to `next`, preceded by the bindings of
+    /// its operands
+    fn expr(&mut self, node: &'n ASTNode<'n>, env: &Env, next: Resume<'n, Expr>) -> Term {
+        match node {
+            ASTNode::ValueNode { value, .. } => next(self, Expr::Atom(Atom::Value(value.clone()))),
+            ASTNode::IdentifierNode { name, .. } => next(self, Expr::Atom(env.lookup(name))),
+            ASTNode::AbstractionNode { .. } => {
+                let function = self.function(node, env);
+                next(self, Expr::Function(Box::new(function)))
+            }
+            ASTNode::ApplicationNode { left, right, .. } => {
+                let env = env.clone();
+                self.atom(
+                    left,
+                    &env.clone(),
+                    Box::new(move |b, function| {
+                        b.atom(
+                            right,
+                            &env,
+                            Box::new(move |b, arg| next(b, Expr::Apply(function, arg))),
+                        )
+                    }),
+                )
+            }
+            ASTNode::ConditionNode {
+                clause,
+                then_arm,
+                else_arm,
+                ..
+            } => {
+                let env = env.clone();
+                self.atom(
+                    clause,
+                    &env.clone(),
+                    Box::new(move |b, clause| {
+                        let then_arm = b.term(then_arm, &env);
+                        let else_arm = b.term(else_arm, &env);
+                        next(b, Expr::If(clause, Box::new(then_arm), Box::new(else_arm)))
+                    }),
+                )
+            }
+            ASTNode::ArithmeticNode { op, expr, .. } => {
+                let op = op.clone();
+                self.atom(
+                    expr,
+                    env,
+                    Box::new(move |b, atom| match op {
+                        Operator::Succ => next(b, Expr::Succ(atom)),
+                        Operator::Pred => next(b, Expr::Pred(atom)),
+                    }),
+                )
+            }
+            ASTNode::IsZeroNode { expr, .. } => self.atom(
+                expr,
+                env,
+                Box::new(move |b, atom| next(b, Expr::IsZero(atom))),
+            ),
+            ASTNode::ProjectionNode { target, attrib, .. } => self.atom(
+                target,
+                env,
+                Box::new(move |b, atom| next(b, Expr::Project(atom, attrib.to_string()))),
+            ),
+            ASTNode::RestrictionNode { target, attrib, .. } => self.atom(
+                target,
+                env,
+                Box::new(move |b, atom| next(b, Expr::Restrict(atom, attrib.to_string()))),
+            ),
+            ASTNode::RecordNode { records, .. } => self.fields(
+                records,
+                env,
+                Box::new(move |b, fields| next(b, Expr::Record(fields))),
+            ),
+            ASTNode::UpdateNode {
+                target, records, ..
+            }
+            | ASTNode::ExtensionNode {
+                target, records, ..
+            } => {
+                let env = env.clone();
+                let update = matches!(node, ASTNode::UpdateNode { .. });
+                self.atom(
+                    target,
+                    &env.clone(),
+                    Box::new(move |b, target| {
+                        b.fields(
+                            records,
+                            &env,
+                            Box::new(move |b, fields| {
+                                if update {
+                                    next(b, Expr::Update(target, fields))
+                                } else {
+                                    next(b, Expr::Extend(target, fields))
+                                }
+                            }),
+                        )
+                    }),
+                )
+            }
+            ASTNode::TaggingNode { ident, value, .. } => self.atom(
+                value,
+                env,
+                Box::new(move |b, atom| next(b, Expr::Tag(ident.to_string(), atom))),
+            ),
+            ASTNode::MatchingNode {
+                to_match, cases, ..
+            } => {
+                let env = env.clone();
+                let scrutinee_type = self.type_of(to_match);
+                self.atom(
+                    to_match,
+                    &env.clone(),
+                    Box::new(move |b, scrutinee| {
+                        let patterns = cases.iter().map(|(pattern, _)| pattern).collect();
+                        let arms = cases.iter().map(|(_, arm)| &**arm).collect();
+                        b.matching((scrutinee, scrutinee_type), patterns, arms, &env, next)
+                    }),
+                )
+            }
+            ASTNode::LetNode {
+                pattern: Pattern::Variable(name),
+                value,
+                body,
+                ..
+            } => {
+                // The variable keeps its name unless the value is an atom it can be replaced by
+                let env = env.clone();
+                let data_type = self.type_of(value);
+                self.expr(
+                    value,
+                    &env.clone(),
+                    Box::new(move |b, expr| match expr {
+                        Expr::Atom(atom) => b.expr(body, &env.bind(name, atom), next),
+                        value => {
+                            let renamed = b.rename(name);
+                            let scope = env.bind(name, Atom::Var(renamed.clone()));
+                            Term::Let {
+                                name: renamed,
+                                data_type,
+                                value,
+                                body: Box::new(b.expr(body, &scope, next)),
+                            }
+                        }
+                    }),
+                )
+            }
+            ASTNode::LetNode {
+                pattern,
+                value,
+                body,
+                ..
+            } => {
+                let env = env.clone();
+                let value_type = self.type_of(value);
+                self.atom(
+                    value,
+                    &env.clone(),
+                    Box::new(move |b, atom| {
+                        let matched = (atom, value_type);
+                        b.matching(matched, vec![pattern], vec![body], &env, next)
+                    }),
+                )
+            }
+            ASTNode::LetRecNode { bindings, body, .. } => {
+                let mut scope = env.clone();
+                let mut names = Vec::new();
+                for (name, _, _) in bindings {
+                    let renamed = self.rename(name);
+                    scope = scope.bind(name, Atom::Var(renamed.clone()));
+                    names.push(renamed);
+                }
+                let functions = names
+                    .into_iter()
+                    .zip(bindings)
+                    .map(|(name, (_, _, value))| (name, self.function(value, &scope)))
+                    .collect();
+                Term::LetRec {
+                    functions,
+                    body: Box::new(self.expr(body, &scope, next)),
+                }
+            }
+            ASTNode::FixNode { point, .. } => {
+                // The fixed point of a function building a function is a recursive function
+                if let ASTNode::AbstractionNode { ident, body, .. } = &**point {
+                    if let ASTNode::AbstractionNode { .. } = **body {
+                        let name = self.rename(ident);
+                        let scope = env.bind(ident, Atom::Var(name.clone()));
+                        let function = self.function(body, &scope);
+                        return Term::LetRec {
+                            functions: vec![(name.clone(), function)],
+                            body: Box::new(next(self, Expr::Atom(Atom::Var(name)))),
+                        };
+                    }
+                }
+                self.atom(
+                    point,
+                    env,
+                    Box::new(move |b, function| next(b, Expr::Fix(function))),
+                )
+            }
+        }
+    }
+
+    fn function(&mut self, node: &'n ASTNode<'n>, env: &Env) -> Function {
+        match (node, self.type_of(node)) {
+            (ASTNode::AbstractionNode { ident, body, .. }, TypeAssignment::Arrow(from, to)) => {
+                let param = self.rename(ident);
+                let scope = env.bind(ident, Atom::Var(param.clone()));
+                Function {
+                    param,
+                    param_type: *from,
+                    result_type: *to,
+                    body: self.term(body, &scope),
+                }
+            }
+            _ => panic!("Bug in typechecker: recursive binding is not a function"),
+        }
+    }
+
+    /// Converts the fields of a record from left to right in the order of their names
+    fn fields(
+        &mut self,
+        records: &'n HashMap<String, ASTNode<'n>>,
+        env: &Env,
+        done: Resume<'n, Vec<(String, Atom)>>,
+    ) -> Term {
+        let mut names: Vec<&String> = records.keys().collect();
+        names.sort();
+        self.sequence(
+            names
+                .into_iter()
+                .map(|name| (name.to_string(), &records[name]))
+                .collect(),
+            Vec::new(),
+            env,
+            done,
+        )
+    }
+
+    fn sequence(
+        &mut self,
+        mut nodes: Vec<(String, &'n ASTNode<'n>)>,
+        mut atoms: Vec<(String, Atom)>,
+        env: &Env,
+        done: Resume<'n, Vec<(String, Atom)>>,
+    ) -> Term {
+        if nodes.is_empty() {
+            return done(self, atoms);
+        }
+        let (name, node) = nodes.remove(0);
+        let scope = env.clone();
+        self.atom(
+            node,
+            env,
+            Box::new(move |b, atom| {
+                atoms.push((name, atom));
+                b.sequence(nodes, atoms, &scope, done)
+            }),
+        )
+    }
+
+    /// Converts a case expression, or a let which is a case with a single arm, into tests on the
+    /// components of the matched value. When a single arm can be reached its bindings precede the
+    /// arm, otherwise the tests form a switch whose arms are terms.
+    fn matching(
+        &mut self,
+        (scrutinee, scrutinee_type): (Atom, TypeAssignment),
+        patterns: Vec<&'n Pattern>,
+        arms: Vec<&'n ASTNode<'n>>,
+        env: &Env,
+        next: Resume<'n, Expr>,
+    ) -> Term {
+        let tree = matching::compile(patterns, Some(&scrutinee_type));
+        let known = vec![(Vec::new(), scrutinee, scrutinee_type)];
+        if leaves(&tree) == 1 {
+            self.decide(&tree, known, &arms, env, &mut Some(next))
+        } else {
+            let term = self.decide(&tree, known, &arms, env, &mut None);
+            self.splice(term, next)
+        }
+    }
+
+    /// Passes the final expression of the term to `next`, keeping the bindings before it. The
+    /// bound names are unique, so moving bindings in front of the rest of the term is safe.
+    fn splice(&mut self, term: Term, next: Resume<'n, Expr>) -> Term {
+        match term {
+            Term::Let {
+                name,
+                data_type,
+                value,
+                body,
+            } => Term::Let {
+                name,
+                data_type,
+                value,
+                body: Box::new(self.splice(*body, next)),
+            },
+            Term::LetRec { functions, body } => Term::LetRec {
+                functions,
+                body: Box::new(self.splice(*body, next)),
+            },
+            Term::Expr(expr) => next(self, expr),
+        }
+    }
+
+    /// Converts a decision tree. The atoms standing for components of the matched value are
+    /// known for the paths that were accessed on the way to the current node. The arm of a leaf
+    /// is passed to `next` when it is given, and is a term of its own otherwise.
+    fn decide(
+        &mut self,
+        tree: &Decision,
+        mut known: Vec<(Path, Atom, TypeAssignment)>,
+        arms: &[&'n ASTNode<'n>],
+        env: &Env,
+        next: &mut Option<Resume<'n, Expr>>,
+    ) -> Term {
+        let mut lets = Vec::new();
+        let term = match tree {
+            Decision::Fail => Term::Expr(Expr::Fail),
+            Decision::Leaf { arm, bindings } => {
+                let mut bindings = bindings.clone();
+                bindings.sort_by(|(first, _), (second, _)| first.cmp(second));
+                let mut scope = env.clone();
+                for (name, path) in &bindings {
+                    let atom = self.access(path, &mut known, &mut lets);
+                    scope = scope.bind(name, atom);
+                }
+                match next.take() {
+                    Some(next) => self.expr(arms[*arm], &scope, next),
+                    None => self.term(arms[*arm], &scope),
+                }
+            }
+            Decision::Switch {
+                path,
+                cases,
+                default,
+            } => {
+                let scrutinee = self.access(path, &mut known, &mut lets);
+                let cases = cases
+                    .iter()
+                    .map(|(constructor, tree)| {
+                        let case = self.decide(tree, known.clone(), arms, env, next);
+                        (constructor.clone(), case)
+                    })
+                    .collect();
+                let default = default
+                    .as_ref()
+                    .map(|tree| Box::new(self.decide(tree, known.clone(), arms, env, next)));
+                Term::Expr(Expr::Switch(scrutinee, cases, default))
+            }
+        };
+        lets.into_iter()
+            .rev()
+            .fold(term, |body, (name, data_type, value)| Term::Let {
+                name,
+                data_type,
+                value,
+                body: Box::new(body),
+            })
+    }
+
+    /// Returns the atom for the component at the path, adding the bindings that access the
+    /// components which are not known yet
+    fn access(
+        &mut self,
+        path: &[Access],
+        known: &mut Vec<(Path, Atom, TypeAssignment)>,
+        lets: &mut Vec<(String, TypeAssignment, Expr)>,
+    ) -> Atom {
+        if let Some((_, atom, _)) = known.iter().find(|(known, _, _)| known[..] == path[..]) {
+            return atom.clone();
+        }
+        let (last, prefix) = path
+            .split_last()
+            .expect("Bug in pattern matching: matched value is not known");
+        let parent = self.access(prefix, known, lets);
+        let parent_type = known
+            .iter()
+            .find(|(known, _, _)| known[..] == prefix[..])
+            .map(|(_, _, data_type)| data_type.clone())
+            .unwrap();
+        let (value, data_type) = match (last, parent_type) {
+            (Access::Field(name), TypeAssignment::Record(fields, _)) => (
+                Expr::Project(parent, name.to_string()),
+                fields[name].clone(),
+            ),
+            (Access::Tag(tag), TypeAssignment::Variant(tags, _)) => {
+                (Expr::Untag(parent, tag.to_string()), tags[tag].clone())
+            }
+            (Access::Pred, _) => (Expr::Pred(parent), TypeAssignment::Single(Type::Nat)),
+            _ => panic!("Bug in typechecker: pattern does not match the type of the value"),
+        };
+        let name = self.fresh();
+        lets.push((name.clone(), data_type.clone(), value));
+        known.push((path.to_vec(), Atom::Var(name.clone()), data_type));
+        Atom::Var(name)
+    }
+}
+
+/// Counts the arms a decision tree selects, an arm selected at different leaves counts once per
+/// leaf
+fn leaves(tree: &Decision) -> usize {
+    match tree {
+        Decision::Fail => 0,
+        Decision::Leaf { .. } => 1,
+        Decision::Switch { cases, default, .. } => {
+            cases.iter().map(|(_, tree)| leaves(tree)).sum::<usize>()
+                + default.as_ref().map_or(0, |tree| leaves(tree))
+        }
+    }
+}
+
+impl Display for Atom {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Atom::Var(name) => write!(f, "{}", name),
+            Atom::Value(ast::Value::True) => write!(f, "true"),
+            Atom::Value(ast::Value::False) => write!(f, "false"),
+            Atom::Value(ast::Value::Zero) => write!(f, "0"),
+        }
+    }
+}
+
+/// Writes fields separated by commas, like `a=x, b=0`
+fn write_fields(f: &mut Formatter, fields: &[(String, Atom)]) -> fmt::Result {
+    for (index, (name, atom)) in fields.iter().enumerate() {
+        if index > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}={}", name, atom)?;
+    }
+    Ok(())
+}
+
+impl Display for Term {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        self.write_term(f, 0)
+    }
+}
+
+impl Term {
+    /// Writes the term with every line indented by the given number of spaces. Bindings are
+    /// written one per line, the terms they scope over follow on the next lines.
+    fn write_term(&self, f: &mut Formatter, indent: usize) -> fmt::Result {
+        let pad = " ".repeat(indent);
+        match self {
+            Term::Let {
+                name,
+                data_type,
+                value,
+                body,
+            } => {
+                write!(f, "{}let {}: {} =", pad, name, data_type)?;
+                match value {
+                    Expr::If(..) | Expr::Switch(..) => {
+                        write!(f, "\n{}  ", pad)?;
+                        value.write_expr(f, indent + 2)?;
+                        write!(f, "\n{}in\n", pad)?;
+                    }
+                    Expr::Function(_) => {
+                        write!(f, " ")?;
+                        value.write_expr(f, indent)?;
+                        write!(f, "\n{}in\n", pad)?;
+                    }
+                    _ => {
+                        write!(f, " ")?;
+                        value.write_expr(f, indent)?;
+                        writeln!(f, " in")?;
+                    }
+                }
+                body.write_term(f, indent)
+            }
+            Term::LetRec { functions, body } => {
+                writeln!(f, "{}letrec", pad)?;
+                for (name, function) in functions {
+                    write!(
+                        f,
+                        "{}  {}: ({} -> {}) = ",
+                        pad, name, function.param_type, function.result_type
+                    )?;
+                    function.write_function(f, indent + 2)?;
+                    writeln!(f)?;
+                }
+                writeln!(f, "{}in", pad)?;
+                body.write_term(f, indent)
+            }
+            Term::Expr(expr) => {
+                write!(f, "{}", pad)?;
+                expr.write_expr(f, indent)
+            }
+        }
+    }
+}
+
+impl Expr {
+    /// Writes the expression starting on the current line, which is indented by the given
+    /// number of spaces. Functions and conditionals continue on the lines below.
+    fn write_expr(&self, f: &mut Formatter, indent: usize) -> fmt::Result {
+        let pad = " ".repeat(indent);
+        match self {
+            Expr::Atom(atom) => write!(f, "{}", atom),
+            Expr::Succ(atom) => write!(f, "succ {}", atom),
+            Expr::Pred(atom) => write!(f, "pred {}", atom),
+            Expr::IsZero(atom) => write!(f, "iszero {}", atom),
+            Expr::Apply(function, arg) => write!(f, "{} {}", function, arg),
+            Expr::Fix(function) => write!(f, "fix {}", function),
+            Expr::Function(function) => function.write_function(f, indent),
+            Expr::Record(fields) => {
+                write!(f, "{{")?;
+                write_fields(f, fields)?;
+                write!(f, "}}")
+            }
+            Expr::Project(atom, attrib) => write!(f, "{}.{}", atom, attrib),
+            Expr::Update(atom, fields) => {
+                write!(f, "{{{} with ", atom)?;
+                write_fields(f, fields)?;
+                write!(f, "}}")
+            }
+            Expr::Extend(atom, fields) => {
+                write!(f, "{{")?;
+                write_fields(f, fields)?;
+                write!(f, " | {}}}", atom)
+            }
+            Expr::Restrict(atom, attrib) => write!(f, "{}\\{}", atom, attrib),
+            Expr::Tag(tag, atom) => write!(f, "<{}={}>", tag, atom),
+            Expr::Untag(atom, tag) => write!(f, "untag {} <{}>", atom, tag),
+            Expr::If(clause, then_arm, else_arm) => {
+                writeln!(f, "if {} then", clause)?;
+                then_arm.write_term(f, indent + 2)?;
+                writeln!(f, "\n{}else", pad)?;
+                else_arm.write_term(f, indent + 2)
+            }
+            Expr::Switch(scrutinee, cases, default) => {
+                write!(f, "case {} of", scrutinee)?;
+                for (constructor, case) in cases {
+                    let constructor = match constructor {
+                        Constructor::True => "true".to_string(),
+                        Constructor::False => "false".to_string(),
+                        Constructor::Zero => "0".to_string(),
+                        Constructor::Succ => "succ".to_string(),
+                        Constructor::Tag(tag) => format!("<{}>", tag),
+                    };
+                    writeln!(f, "\n{}| {} ->", pad, constructor)?;
+                    case.write_term(f, indent + 2)?;
+                }
+                if let Some(default) = default {
+                    writeln!(f, "\n{}| _ ->", pad)?;
+                    default.write_term(f, indent + 2)?;
+                }
+                Ok(())
+            }
+            Expr::Fail => write!(f, "fail"),
+        }
+    }
+}
+
+impl Function {
+    /// Writes the function starting on the current line, its body is indented below it
+    fn write_function(&self, f: &mut Formatter, indent: usize) -> fmt::Result {
+        writeln!(f, "fun ({}: {}) =", self.param, self.param_type)?;
+        self.body.write_term(f, indent + 2)
+    }
+}
+
+/// Values computed by a term in A-normal form
+#[derive(Clone)]
+pub enum Value<'t> {
+    Nat(u64),
+    Bool(bool),
+    Func(Rc<Closure<'t>>),
+    /// Record with its fields sorted by name
+    Record(Rc<Vec<(String, Value<'t>)>>),
+    Variant(String, Rc<Value<'t>>),
+}
+
+/// A function together with the variables it captured
+pub struct Closure<'t> {
+    function: &'t Function,
+    env: Scope<'t>,
+}
+
+/// What a variable of a running term is bound to
+#[derive(Clone)]
+enum Slot<'t> {
+    Value(Value<'t>),
+    /// Fixed point of the function, which is computed again whenever the variable is read
+    Fix(Value<'t>),
+    /// Member of a group of recursive functions bound in the scope
+    Rec(&'t [(String, Function)], Scope<'t>, usize),
+}
+
+/// Variables of a running term
+#[derive(Clone, Default)]
+struct Scope<'t>(Option<Rc<(&'t str, Slot<'t>, Scope<'t>)>>);
+
+impl<'t> Scope<'t> {
+    fn bind(&self, name: &'t str, slot: Slot<'t>) -> Scope<'t> {
+        Scope(Some(Rc::new((name, slot, self.clone()))))
+    }
+
+    fn lookup(&self, name: &str) -> &Slot<'t> {
+        let mut env = self;
+        while let Some(binding) = &env.0 {
+            if binding.0 == name {
+                return &binding.1;
+            }
+            env = &binding.2;
+        }
+        panic!("Term in A-normal form refers to unknown variable {}", name)
+    }
+}
+
+/// Runs a term in A-normal form and returns its value. Calls and conditionals in tail position
+/// continue in the same loop, so tail recursion runs in constant stack space while other calls
+/// use the stack of the interpreter.
+///
+/// # Panics
+/// Throws a panic when the term is not well typed or fails to match a value
+pub fn run(term: &Term) -> Value<'_> {
+    execute(term, Scope::default())
+}
+
+fn execute<'t>(mut term: &'t Term, mut env: Scope<'t>) -> Value<'t> {
+    loop {
+        match term {
+            Term::Let {
+                name, value, body, ..
+            } => {
+                let value = evaluate(value, &env);
+                env = env.bind(name, Slot::Value(value));
+                term = body;
+            }
+            Term::LetRec { functions, body } => {
+                let group = env.clone();
+                for (index, (name, _)) in functions.iter().enumerate() {
+                    env = env.bind(name, Slot::Rec(functions, group.clone(), index));
+                }
+                term = body;
+            }
+            Term::Expr(Expr::Apply(function, arg)) => {
+                let arg = Slot::Value(value(arg, &env));
+                let (body, scope) = enter(value(function, &env), arg);
+                term = body;
+                env = scope;
+            }
+            Term::Expr(Expr::Fix(function)) => {
+                let function = value(function, &env);
+                let (body, scope) = enter(function.clone(), Slot::Fix(function));
+                term = body;
+                env = scope;
+            }
+            Term::Expr(Expr::If(clause, then_arm, else_arm)) => {
+                term = branch(clause, then_arm, else_arm, &env);
+            }
+            Term::Expr(Expr::Switch(scrutinee, cases, default)) => {
+                term = case(scrutinee, cases, default, &env);
+            }
+            Term::Expr(expr) => return evaluate(expr, &env),
+        }
+    }
+}
+
+/// Starts running the body of a function, returning it with the scope it runs in
+fn enter<'t>(function: Value<'t>, arg: Slot<'t>) -> (&'t Term, Scope<'t>) {
+    match function {
+        Value::Func(closure) => {
+            let function = closure.function;
+            (&function.body, closure.env.bind(&function.param, arg))
+        }
+        _ => panic!("Only functions can be applied"),
+    }
+}
+
+fn branch<'t>(clause: &Atom, then_arm: &'t Term, else_arm: &'t Term, env: &Scope<'t>) -> &'t Term {
+    match value(clause, env) {
+        Value::Bool(true) => then_arm,
+        Value::Bool(false) => else_arm,
+        _ => panic!("Clause of a conditional is not a boolean"),
+    }
+}
+
+fn case<'t>(
+    scrutinee: &Atom,
+    cases: &'t [(Constructor, Term)],
+    default: &'t Option<Box<Term>>,
+    env: &Scope<'t>,
+) -> &'t Term {
+    let scrutinee = value(scrutinee, env);
+    cases
+        .iter()
+        .find(|(constructor, _)| has_constructor(&scrutinee, constructor))
+        .map(|(_, case)| case)
+        .or(default.as_deref())
+        .expect("No case matches the value")
+}
+
+fn value<'t>(atom: &Atom, env: &Scope<'t>) -> Value<'t> {
+    match atom {
+        Atom::Value(ast::Value::True) => Value::Bool(true),
+        Atom::Value(ast::Value::False) => Value::Bool(false),
+        Atom::Value(ast::Value::Zero) => Value::Nat(0),
+        Atom::Var(name) => match env.lookup(name) {
+            Slot::Value(value) => value.clone(),
+            Slot::Fix(function) => {
+                let (body, scope) = enter(function.clone(), Slot::Fix(function.clone()));
+                execute(body, scope)
+            }
+            Slot::Rec(functions, group, index) => {
+                let mut scope = group.clone();
+                for (member, (name, _)) in functions.iter().enumerate() {
+                    scope = scope.bind(name, Slot::Rec(functions, group.clone(), member));
+                }
+                Value::Func(Rc::new(Closure {
+                    function: &functions[*index].1,
+                    env: scope,
+                }))
+            }
+        },
+    }
+}
+
+fn evaluate<'t>(expr: &'t Expr, env: &Scope<'t>) -> Value<'t> {
+    let nat = |atom| match value(atom, env) {
+        Value::Nat(n) => n,
+        _ => panic!("Arithmetic on a value that is not a number"),
+    };
+    let record = |atom| match value(atom, env) {
+        Value::Record(fields) => (*fields).clone(),
+        _ => panic!("Record operation on a value that is not a record"),
+    };
+    let set = |fields: &mut Vec<(String, Value<'t>)>, updates: &[(String, Atom)]| {
+        for (name, atom) in updates {
+            let value = value(atom, env);
+            match fields.iter().position(|(field, _)| field == name) {
+                Some(index) => fields[index].1 = value,
+                None => fields.push((name.to_string(), value)),
+            }
+        }
+        fields.sort_by(|(first, _), (second, _)| first.cmp(second));
+    };
+    match expr {
+        Expr::Atom(atom) => value(atom, env),
+        Expr::Succ(atom) => Value::Nat(nat(atom) + 1),
+        Expr::Pred(atom) => Value::Nat(nat(atom).saturating_sub(1)),
+        Expr::IsZero(atom) => Value::Bool(nat(atom) == 0),
+        Expr::Apply(function, arg) => {
+            let (body, scope) = enter(value(function, env), Slot::Value(value(arg, env)));
+            execute(body, scope)
+        }
+        Expr::Fix(function) => {
+            let function = value(function, env);
+            let (body, scope) = enter(function.clone(), Slot::Fix(function));
+            execute(body, scope)
+        }
+        Expr::Function(function) => Value::Func(Rc::new(Closure {
+            function,
+            env: env.clone(),
+        })),
+        Expr::Record(fields) => {
+            let mut record = Vec::new();
+            set(&mut record, fields);
+            Value::Record(Rc::new(record))
+        }
+        Expr::Update(atom, fields) | Expr::Extend(atom, fields) => {
+            let mut record = record(atom);
+            set(&mut record, fields);
+            Value::Record(Rc::new(record))
+        }
+        Expr::Project(atom, attrib) => record(atom)
+            .into_iter()
+            .find(|(name, _)| name == attrib)
+            .map(|(_, value)| value)
+            .expect("Projection on a record without the attribute"),
+        Expr::Restrict(atom, attrib) => {
+            let mut record = record(atom);
+            record.retain(|(name, _)| name != attrib);
+            Value::Record(Rc::new(record))
+        }
+        Expr::Tag(tag, atom) => Value::Variant(tag.to_string(), Rc::new(value(atom, env))),
+        Expr::Untag(atom, tag) => match value(atom, env) {
+            Value::Variant(ref found, ref value) if found == tag => (**value).clone(),
+            _ => panic!("Value is not a variant with tag {}", tag),
+        },
+        Expr::If(clause, then_arm, else_arm) => {
+            execute(branch(clause, then_arm, else_arm, env), env.clone())
+        }
+        Expr::Switch(scrutinee, cases, default) => {
+            execute(case(scrutinee, cases, default, env), env.clone())
+        }
+        Expr::Fail => panic!("No arm of a case expression matches the value"),
+    }
+}
+
+fn has_constructor(value: &Value<'_>, constructor: &Constructor) -> bool {
+    match (constructor, value) {
+        (Constructor::True, Value::Bool(x)) => *x,
+        (Constructor::False, Value::Bool(x)) => !*x,
+        (Constructor::Zero, Value::Nat(n)) => *n == 0,
+        (Constructor::Succ, Value::Nat(n)) => *n > 0,
+        (Constructor::Tag(tag), Value::Variant(found, _)) => tag == found,
+        _ => false,
+    }
+}
+
+impl<'t> Display for Value<'t> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Value::Nat(n) => write!(f, "{}", n),
+            Value::Bool(x) => write!(f, "{}", x),
+            Value::Func(_) => write!(f, "<function>"),
+            Value::Record(fields) => {
+                write!(f, "{{")?;
+                for (index, (name, value)) in fields.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}={}", name, value)?;
+                }
+                write!(f, "}}")
+            }
+            Value::Variant(tag, value) => write!(f, "<{}={}>", tag, value),
+        }
+    }
+}
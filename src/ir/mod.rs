@@ -0,0 +1,4 @@
+/// Programs in A-normal form, where every intermediate result is bound by a let
+pub mod anf;
+/// Simplification of programs in A-normal form
+pub mod opt;
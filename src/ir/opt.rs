@@ -0,0 +1,605 @@
+use ast::Value;
+use ir::anf::{Atom, Expr, Function, Term};
+use matching::Constructor;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+/// Functions whose bodies have at most this many bindings and expressions are inlined at every
+/// call, larger ones only when they are called once
+const INLINE_SIZE: usize = 8;
+
+/// Upper bound on the rounds of simplification, every round after the first only starts when
+/// the previous one changed the term
+const MAX_ROUNDS: usize = 16;
+
+/// Upper bound on the calls inlined in a round, which keeps the size of the term in check
+const MAX_INLINES: usize = 1000;
+
+/// Optimizes a term in A-normal form without changing its value. Every round of simplification
+/// propagates atoms bound to variables, folds `succ`, `pred`, `iszero` and conditionals on
+/// known values, selects the case for values with a known constructor, projects fields of
+/// records built in the term, inlines calls to small functions and functions called only once,
+/// and then removes the bindings whose value is not used and can be computed without effects.
+/// Rounds are repeated until the term no longer changes.
+///
+/// Recursive functions are never inlined, and calls are only removed when they are inlined, so
+/// optimizing neither makes a program loop nor keeps it from looping.
+///
+/// # Arguments
+/// * `term` - A term produced by `anf::convert`, whose bound variables have distinct names
+pub fn optimize(mut term: Term) -> Term {
+    for _ in 0..MAX_ROUNDS {
+        let mut reserved = HashSet::new();
+        binders(&term, &mut reserved);
+        let mut uses = HashMap::new();
+        count_term(&term, &mut uses, 1);
+        let mut simplifier = Simplifier {
+            uses,
+            reserved,
+            emitted: HashSet::new(),
+            inlines: 0,
+        };
+        let simplified = simplifier.term(term.clone(), &Env::default(), tail());
+        let mut uses = HashMap::new();
+        count_term(&simplified, &mut uses, 1);
+        let swept = sweep(simplified, &mut uses);
+        if swept == term {
+            break;
+        }
+        term = swept;
+    }
+    term
+}
+
+/// Builds the rest of the term from the final expression of a term and the variables in scope
+/// at that point
+type Next = Box<dyn FnOnce(&mut Simplifier, Expr, &Env) -> Term>;
+
+/// Ends a term with its final expression
+fn tail() -> Next {
+    Box::new(|_, expr, _| Term::Expr(expr))
+}
+
+/// What is known about the value of a variable
+#[derive(Clone)]
+enum Shape {
+    Succ(Atom),
+    Record(Vec<(String, Atom)>),
+    Tag(String, Atom),
+    /// Function that may be inlined
+    Function(Rc<Function>),
+}
+
+/// Atom a variable is replaced by together with what is known about its value
+type Known = (Atom, Option<Shape>);
+
+/// What the variables of the original term stand for in the optimized one
+#[derive(Clone, Default)]
+struct Env(Option<Rc<(String, Known, Env)>>);
+
+impl Env {
+    fn bind(&self, name: &str, atom: Atom, shape: Option<Shape>) -> Env {
+        Env(Some(Rc::new((
+            name.to_string(),
+            (atom, shape),
+            self.clone(),
+        ))))
+    }
+
+    /// Returns the atom the variable is replaced by and what is known about its value.
+    /// Variables introduced by the optimizer itself are not bound and stand for themselves.
+    fn lookup(&self, name: &str) -> Known {
+        let mut env = self;
+        while let Some(binding) = &env.0 {
+            if binding.0 == name {
+                return binding.1.clone();
+            }
+            env = &binding.2;
+        }
+        (Atom::Var(name.to_string()), None)
+    }
+
+    fn atom(&self, atom: &Atom) -> Atom {
+        match atom {
+            Atom::Var(name) => self.lookup(name).0,
+            value => value.clone(),
+        }
+    }
+
+    fn shape(&self, atom: &Atom) -> Option<Shape> {
+        match atom {
+            Atom::Var(name) => self.lookup(name).1,
+            _ => None,
+        }
+    }
+
+    /// Returns the constructor of the value of the atom when it is known
+    fn constructor(&self, atom: &Atom) -> Option<Constructor> {
+        match (self.atom(atom), self.shape(atom)) {
+            (Atom::Value(Value::True), _) => Some(Constructor::True),
+            (Atom::Value(Value::False), _) => Some(Constructor::False),
+            (Atom::Value(Value::Zero), _) => Some(Constructor::Zero),
+            (_, Some(Shape::Succ(_))) => Some(Constructor::Succ),
+            (_, Some(Shape::Tag(tag, _))) => Some(Constructor::Tag(tag)),
+            _ => None,
+        }
+    }
+
+    /// Returns the fields of the value of the atom when it is a known record
+    fn record(&self, atom: &Atom) -> Option<Vec<(String, Atom)>> {
+        match self.shape(atom) {
+            Some(Shape::Record(fields)) => Some(fields),
+            _ => None,
+        }
+    }
+
+    fn fields(&self, fields: Vec<(String, Atom)>) -> Vec<(String, Atom)> {
+        fields
+            .into_iter()
+            .map(|(name, atom)| {
+                let atom = self.atom(&atom);
+                (name, atom)
+            })
+            .collect()
+    }
+}
+
+struct Simplifier {
+    /// How often every variable occurs in the term of the current round
+    uses: HashMap<String, usize>,
+    /// Names bound in the term of the current round, fresh names avoid them
+    reserved: HashSet<String>,
+    /// Names already bound in the optimized term
+    emitted: HashSet<String>,
+    inlines: usize,
+}
+
+impl Simplifier {
+    /// Returns the name for a variable bound in the optimized term. The name is kept unless
+    /// it was bound before, which happens when the body of a function is inlined a second time.
+    fn rename(&mut self, name: &str) -> String {
+        let mut renamed = name.to_string();
+        if self.emitted.contains(name) {
+            let base = match name.rfind('_') {
+                Some(index) if name[index + 1..].bytes().all(|b| b.is_ascii_digit()) => {
+                    &name[..index]
+                }
+                _ => name,
+            };
+            let mut count = 2;
+            while {
+                renamed = format!("{}_{}", base, count);
+                self.reserved.contains(&renamed) || self.emitted.contains(&renamed)
+            } {
+                count += 1;
+            }
+        }
+        self.emitted.insert(renamed.clone());
+        renamed
+    }
+
+    fn term(&mut self, term: Term, env: &Env, next: Next) -> Term {
+        match term {
+            Term::Let {
+                name,
+                data_type,
+                value,
+                body,
+            } => self.expr(
+                value,
+                env,
+                Box::new(move |s, value, env| match value {
+                    Expr::Atom(atom) => {
+                        let shape = env.shape(&atom);
+                        s.term(*body, &env.bind(&name, atom, shape), next)
+                    }
+                    value => {
+                        let renamed = s.rename(&name);
+                        let shape = s.shape(&name, &value);
+                        let atom = Atom::Var(renamed.clone());
+                        let scope = env
+                            .bind(&name, atom.clone(), shape.clone())
+                            .bind(&renamed, atom, shape);
+                        Term::Let {
+                            name: renamed,
+                            data_type,
+                            value,
+                            body: Box::new(s.term(*body, &scope, next)),
+                        }
+                    }
+                }),
+            ),
+            Term::LetRec { functions, body } => {
+                let mut scope = env.clone();
+                let mut names = Vec::new();
+                for (name, _) in &functions {
+                    let renamed = self.rename(name);
+                    let atom = Atom::Var(renamed.clone());
+                    scope = scope
+                        .bind(name, atom.clone(), None)
+                        .bind(&renamed, atom, None);
+                    names.push(renamed);
+                }
+                let functions = names
+                    .into_iter()
+                    .zip(functions)
+                    .map(|(name, (_, function))| (name, self.function(function, &scope)))
+                    .collect();
+                Term::LetRec {
+                    functions,
+                    body: Box::new(self.term(*body, &scope, next)),
+                }
+            }
+            Term::Expr(expr) => self.expr(expr, env, next),
+        }
+    }
+
+    fn function(&mut self, function: Function, env: &Env) -> Function {
+        let param = self.rename(&function.param);
+        let atom = Atom::Var(param.clone());
+        let scope = env
+            .bind(&function.param, atom.clone(), None)
+            .bind(&param, atom, None);
+        Function {
+            param,
+            body: self.term(function.body, &scope, tail()),
+            ..function
+        }
+    }
+
+    /// Returns what is known about the value of an expression bound to a variable
+    fn shape(&self, name: &str, value: &Expr) -> Option<Shape> {
+        match value {
+            Expr::Succ(atom) => Some(Shape::Succ(atom.clone())),
+            Expr::Record(fields) => Some(Shape::Record(fields.clone())),
+            Expr::Tag(tag, atom) => Some(Shape::Tag(tag.to_string(), atom.clone())),
+            Expr::Function(function)
+                if self.uses.get(name) == Some(&1) || size(&function.body) <= INLINE_SIZE =>
+            {
+                Some(Shape::Function(Rc::new((**function).clone())))
+            }
+            _ => None,
+        }
+    }
+
+    /// Simplifies an expression and passes the result to `next`, bindings that were inlined
+    /// or selected by a known value precede it
+    fn expr(&mut self, expr: Expr, env: &Env, next: Next) -> Term {
+        let atom = |atom: &Atom| env.atom(atom);
+        let simplified = match expr {
+            Expr::Atom(a) => Expr::Atom(atom(&a)),
+            Expr::Succ(a) => Expr::Succ(atom(&a)),
+            Expr::Pred(a) => match (atom(&a), env.shape(&a)) {
+                (Atom::Value(Value::Zero), _) => Expr::Atom(Atom::Value(Value::Zero)),
+                (_, Some(Shape::Succ(inner))) => Expr::Atom(inner),
+                (a, _) => Expr::Pred(a),
+            },
+            Expr::IsZero(a) => match env.constructor(&a) {
+                Some(Constructor::Zero) => Expr::Atom(Atom::Value(Value::True)),
+                Some(Constructor::Succ) => Expr::Atom(Atom::Value(Value::False)),
+                _ => Expr::IsZero(atom(&a)),
+            },
+            Expr::Apply(function, arg) => {
+                let arg = atom(&arg);
+                match env.shape(&function) {
+                    Some(Shape::Function(function)) if self.inlines < MAX_INLINES => {
+                        self.inlines += 1;
+                        let shape = env.shape(&arg);
+                        let scope = env.bind(&function.param, arg, shape);
+                        return self.term(function.body.clone(), &scope, next);
+                    }
+                    _ => Expr::Apply(atom(&function), arg),
+                }
+            }
+            Expr::Fix(function) => Expr::Fix(atom(&function)),
+            Expr::Function(function) => Expr::Function(Box::new(self.function(*function, env))),
+            Expr::Record(fields) => Expr::Record(env.fields(fields)),
+            Expr::Project(a, attrib) => match env.record(&a) {
+                Some(fields) => {
+                    let field = fields.into_iter().find(|(name, _)| *name == attrib);
+                    Expr::Atom(
+                        field
+                            .expect("Projection on a record without the attribute")
+                            .1,
+                    )
+                }
+                _ => Expr::Project(atom(&a), attrib),
+            },
+            // Changing a known record builds a new one
+            Expr::Update(a, fields) | Expr::Extend(a, fields) if env.record(&a).is_some() => {
+                let mut record = env.record(&a).unwrap();
+                for (name, field) in env.fields(fields) {
+                    match record.iter().position(|(known, _)| *known == name) {
+                        Some(index) => record[index].1 = field,
+                        None => record.push((name, field)),
+                    }
+                }
+                record.sort_by(|(first, _), (second, _)| first.cmp(second));
+                Expr::Record(record)
+            }
+            Expr::Update(a, fields) => Expr::Update(atom(&a), env.fields(fields)),
+            Expr::Extend(a, fields) => Expr::Extend(atom(&a), env.fields(fields)),
+            Expr::Restrict(a, attrib) => match env.record(&a) {
+                Some(mut record) => {
+                    record.retain(|(name, _)| *name != attrib);
+                    Expr::Record(record)
+                }
+                None => Expr::Restrict(atom(&a), attrib),
+            },
+            Expr::Tag(tag, a) => Expr::Tag(tag, atom(&a)),
+            Expr::Untag(a, tag) => match env.shape(&a) {
+                Some(Shape::Tag(ref known, ref inner)) if *known == tag => {
+                    Expr::Atom(inner.clone())
+                }
+                _ => Expr::Untag(atom(&a), tag),
+            },
+            Expr::If(clause, then_arm, else_arm) => match atom(&clause) {
+                Atom::Value(Value::True) => return self.term(*then_arm, env, next),
+                Atom::Value(Value::False) => return self.term(*else_arm, env, next),
+                clause => {
+                    // Within the arms the value of the clause is known
+                    let known = |value| match &clause {
+                        Atom::Var(name) => env.bind(name, Atom::Value(value), None),
+                        _ => env.clone(),
+                    };
+                    let then_arm = self.term(*then_arm, &known(Value::True), tail());
+                    let else_arm = self.term(*else_arm, &known(Value::False), tail());
+                    Expr::If(clause, Box::new(then_arm), Box::new(else_arm))
+                }
+            },
+            Expr::Switch(scrutinee, cases, default) => match env.constructor(&scrutinee) {
+                Some(known) => {
+                    let case = cases
+                        .into_iter()
+                        .find(|(constructor, _)| *constructor == known)
+                        .map(|(_, case)| case)
+                        .or_else(|| default.map(|default| *default))
+                        .unwrap_or(Term::Expr(Expr::Fail));
+                    return self.term(case, env, next);
+                }
+                None => {
+                    let cases = cases
+                        .into_iter()
+                        .map(|(constructor, case)| (constructor, self.term(case, env, tail())))
+                        .collect();
+                    let default = default.map(|default| Box::new(self.term(*default, env, tail())));
+                    Expr::Switch(atom(&scrutinee), cases, default)
+                }
+            },
+            Expr::Fail => Expr::Fail,
+        };
+        next(self, simplified, env)
+    }
+}
+
+/// Removes the bindings whose variables do not occur and whose values are computed without
+/// effects, innermost first so that the variables they used may become unused as well
+///
+/// # Arguments
+/// * `term` - The term to remove the bindings from
+/// * `uses` - How often every variable occurs in the whole term
+fn sweep(term: Term, uses: &mut HashMap<String, usize>) -> Term {
+    match term {
+        Term::Let {
+            name,
+            data_type,
+            value,
+            body,
+        } => {
+            let body = sweep(*body, uses);
+            if uses.get(&name).is_none_or(|count| *count == 0) && pure(&value) {
+                count_expr(&value, uses, -1);
+                body
+            } else {
+                Term::Let {
+                    name,
+                    data_type,
+                    value: sweep_expr(value, uses),
+                    body: Box::new(body),
+                }
+            }
+        }
+        Term::LetRec { functions, body } => {
+            let body = sweep(*body, uses);
+            // Uses of the functions among themselves do not keep the group alive
+            let mut inner = HashMap::new();
+            for (_, function) in &functions {
+                count_term(&function.body, &mut inner, 1);
+            }
+            let dead = functions.iter().all(|(name, _)| {
+                uses.get(name).copied().unwrap_or(0) == inner.get(name).copied().unwrap_or(0)
+            });
+            if dead {
+                for (_, function) in &functions {
+                    count_term(&function.body, uses, -1);
+                }
+                body
+            } else {
+                let functions = functions
+                    .into_iter()
+                    .map(|(name, function)| {
+                        let body = sweep(function.body, uses);
+                        (name, Function { body, ..function })
+                    })
+                    .collect();
+                Term::LetRec {
+                    functions,
+                    body: Box::new(body),
+                }
+            }
+        }
+        Term::Expr(expr) => Term::Expr(sweep_expr(expr, uses)),
+    }
+}
+
+fn sweep_expr(expr: Expr, uses: &mut HashMap<String, usize>) -> Expr {
+    match expr {
+        Expr::Function(function) => {
+            let body = sweep(function.body, uses);
+            Expr::Function(Box::new(Function { body, ..*function }))
+        }
+        Expr::If(clause, then_arm, else_arm) => Expr::If(
+            clause,
+            Box::new(sweep(*then_arm, uses)),
+            Box::new(sweep(*else_arm, uses)),
+        ),
+        Expr::Switch(scrutinee, cases, default) => Expr::Switch(
+            scrutinee,
+            cases
+                .into_iter()
+                .map(|(constructor, case)| (constructor, sweep(case, uses)))
+                .collect(),
+            default.map(|default| Box::new(sweep(*default, uses))),
+        ),
+        expr => expr,
+    }
+}
+
+/// Checks whether computing the expression always terminates without failing. Calls may loop,
+/// so they are not pure.
+fn pure(expr: &Expr) -> bool {
+    match expr {
+        Expr::Apply(..) | Expr::Fix(_) | Expr::Fail => false,
+        Expr::If(_, then_arm, else_arm) => pure_term(then_arm) && pure_term(else_arm),
+        Expr::Switch(_, cases, default) => {
+            cases.iter().all(|(_, case)| pure_term(case))
+                && default.as_ref().is_none_or(|default| pure_term(default))
+        }
+        _ => true,
+    }
+}
+
+fn pure_term(term: &Term) -> bool {
+    match term {
+        Term::Let { value, body, .. } => pure(value) && pure_term(body),
+        Term::LetRec { body, .. } => pure_term(body),
+        Term::Expr(expr) => pure(expr),
+    }
+}
+
+/// Adds `delta` to the count of every occurrence of a variable in the term
+fn count_term(term: &Term, uses: &mut HashMap<String, usize>, delta: isize) {
+    match term {
+        Term::Let { value, body, .. } => {
+            count_expr(value, uses, delta);
+            count_term(body, uses, delta);
+        }
+        Term::LetRec { functions, body } => {
+            for (_, function) in functions {
+                count_term(&function.body, uses, delta);
+            }
+            count_term(body, uses, delta);
+        }
+        Term::Expr(expr) => count_expr(expr, uses, delta),
+    }
+}
+
+fn count_expr(expr: &Expr, uses: &mut HashMap<String, usize>, delta: isize) {
+    let mut count = |atom: &Atom| {
+        if let Atom::Var(name) = atom {
+            let count = uses.entry(name.to_string()).or_insert(0);
+            *count = count.wrapping_add_signed(delta);
+        }
+    };
+    match expr {
+        Expr::Atom(a)
+        | Expr::Succ(a)
+        | Expr::Pred(a)
+        | Expr::IsZero(a)
+        | Expr::Fix(a)
+        | Expr::Project(a, _)
+        | Expr::Restrict(a, _)
+        | Expr::Tag(_, a)
+        | Expr::Untag(a, _) => count(a),
+        Expr::Apply(function, arg) => {
+            count(function);
+            count(arg);
+        }
+        Expr::Record(fields) => fields.iter().for_each(|(_, a)| count(a)),
+        Expr::Update(a, fields) | Expr::Extend(a, fields) => {
+            count(a);
+            fields.iter().for_each(|(_, a)| count(a));
+        }
+        Expr::Function(function) => count_term(&function.body, uses, delta),
+        Expr::If(clause, then_arm, else_arm) => {
+            count(clause);
+            count_term(then_arm, uses, delta);
+            count_term(else_arm, uses, delta);
+        }
+        Expr::Switch(scrutinee, cases, default) => {
+            count(scrutinee);
+            for (_, case) in cases {
+                count_term(case, uses, delta);
+            }
+            if let Some(default) = default {
+                count_term(default, uses, delta);
+            }
+        }
+        Expr::Fail => {}
+    }
+}
+
+/// Collects the names of all variables bound in the term
+fn binders(term: &Term, names: &mut HashSet<String>) {
+    let expr = |expr: &Expr, names: &mut HashSet<String>| match expr {
+        Expr::Function(function) => {
+            names.insert(function.param.to_string());
+            binders(&function.body, names);
+        }
+        Expr::If(_, then_arm, else_arm) => {
+            binders(then_arm, names);
+            binders(else_arm, names);
+        }
+        Expr::Switch(_, cases, default) => {
+            for (_, case) in cases {
+                binders(case, names);
+            }
+            if let Some(default) = default {
+                binders(default, names);
+            }
+        }
+        _ => {}
+    };
+    match term {
+        Term::Let {
+            name, value, body, ..
+        } => {
+            names.insert(name.to_string());
+            expr(value, names);
+            binders(body, names);
+        }
+        Term::LetRec { functions, body } => {
+            for (name, function) in functions {
+                names.insert(name.to_string());
+                names.insert(function.param.to_string());
+                binders(&function.body, names);
+            }
+            binders(body, names);
+        }
+        Term::Expr(value) => expr(value, names),
+    }
+}
+
+/// Counts the bindings and expressions of a term, including those in nested terms
+fn size(term: &Term) -> usize {
+    let expr = |expr: &Expr| match expr {
+        Expr::Function(function) => 1 + size(&function.body),
+        Expr::If(_, then_arm, else_arm) => 1 + size(then_arm) + size(else_arm),
+        Expr::Switch(_, cases, default) => {
+            1 + cases.iter().map(|(_, case)| size(case)).sum::<usize>()
+                + default.as_ref().map_or(0, |default| size(default))
+        }
+        _ => 1,
+    };
+    match term {
+        Term::Let { value, body, .. } => expr(value) + size(body),
+        Term::LetRec { functions, body } => {
+            functions
+                .iter()
+                .map(|(_, function)| 1 + size(&function.body))
+                .sum::<usize>()
+                + size(body)
+        }
+        Term::Expr(value) => expr(value),
+    }
+}
@@ -3,10 +3,22 @@ extern crate pest;
 extern crate pest_derive;
 
 pub mod ast;
+pub mod bytecode;
 pub mod check;
+pub mod closure;
+pub mod codegen;
 pub mod eval;
+pub mod infer;
+pub mod ir;
+pub mod machine;
+pub mod matching;
+pub mod nameless;
+pub mod nbe;
 pub mod parser;
+pub mod small_step;
 pub mod sym_tab;
+pub mod transform;
+pub mod vm;
 
 use std::error::Error;
 use std::fs::File;
@@ -19,7 +31,7 @@ use std::io::prelude::*;
 ///
 /// # Errors
 /// Passes errors through thrown from IO methods
-pub fn read_file(path: &str) -> Result<String, Box<Error>> {
+pub fn read_file(path: &str) -> Result<String, Box<dyn Error>> {
     let mut f = File::open(path)?;
 
     let mut contents = String::new();
@@ -0,0 +1,695 @@
+use ast::*;
+use eval::{Binding, EvalConfig, EvalError, OutputValue, Strategy, Thunk};
+use matching::{self, Decision};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::*;
+use std::rc::Rc;
+use std::result;
+use sym_tab::*;
+
+/// State of a CEK machine evaluating a program: the control is the term under evaluation or the
+/// value it produced, the environment gives the bindings of the term, and the continuations
+/// (innermost last) describe what remains to be done with its value.
+///
+/// Every call of `step` makes a single transition, so the evaluation can be followed state by
+/// state, printed with `Display` or recorded with `to_json`.
+pub struct State<'a> {
+    pub control: Control<'a>,
+    pub env: SymbolTable<Binding<'a>>,
+    pub kont: Vec<Continuation<'a>>,
+    decisions: Decisions,
+}
+
+/// Decision trees of the case expressions and let bindings the machine came across, by the
+/// address of their first pattern, so that every tree is only compiled once
+#[derive(Default)]
+struct Decisions(HashMap<*const Pattern, Rc<Decision>>);
+
+impl Decisions {
+    fn get<'p, I>(&mut self, patterns: I) -> Rc<Decision>
+    where
+        I: IntoIterator<Item = &'p Pattern> + Clone,
+    {
+        let first = patterns
+            .clone()
+            .into_iter()
+            .next()
+            .expect("Bug in parser: found a case expression without arms");
+        self.0
+            .entry(first)
+            .or_insert_with(|| Rc::new(matching::compile(patterns, None)))
+            .clone()
+    }
+}
+
+/// Progress of an evaluation, compared against its configured limits
+struct Fuel {
+    config: EvalConfig,
+    steps: usize,
+}
+
+impl Fuel {
+    /// Accounts for the evaluation of another term
+    ///
+    /// # Arguments
+    /// * `depth` - The number of continuations waiting for the value of the term
+    fn burn(&mut self, depth: usize) -> result::Result<(), EvalError> {
+        if self.config.max_steps.is_some_and(|max| self.steps >= max) {
+            return Err(EvalError::OutOfFuel { steps: self.steps });
+        }
+        if let Some(max) = self.config.max_depth.filter(|max| depth > *max) {
+            return Err(EvalError::TooDeep { depth: max });
+        }
+        self.steps += 1;
+        Ok(())
+    }
+}
+
+/// What the machine is working on: a term to evaluate in the environment of the state, or a value
+/// to hand to the innermost continuation
+pub enum Control<'a> {
+    Term(&'a ASTNode<'a>),
+    Value(OutputValue<'a>),
+}
+
+/// Work that is left to do once the term under evaluation has a value. Continuations are kept on
+/// a stack on the heap, so the depth of an evaluation is only bounded by memory.
+pub enum Continuation<'a> {
+    /// Pass the argument to the function that is the value
+    Argument(&'a ASTNode<'a>, SymbolTable<Binding<'a>>),
+    /// Call the function with the value as argument
+    Call(String, &'a ASTNode<'a>, SymbolTable<Binding<'a>>),
+    Arithmetic(&'a Operator),
+    IsZero,
+    Condition(&'a ASTNode<'a>, &'a ASTNode<'a>, SymbolTable<Binding<'a>>),
+    Projection(&'a str),
+    Restriction(&'a str),
+    /// Store the value as a field and evaluate the remaining fields
+    Field(
+        HashMap<String, OutputValue<'a>>,
+        &'a str,
+        Vec<(&'a String, &'a ASTNode<'a>)>,
+        SymbolTable<Binding<'a>>,
+    ),
+    /// Add the fields to the record that is the value
+    Update(&'a HashMap<String, ASTNode<'a>>, SymbolTable<Binding<'a>>),
+    Matching(&'a [(Pattern, Box<ASTNode<'a>>)], SymbolTable<Binding<'a>>),
+    Tagging(&'a str, &'a TypeAssignment),
+    Let(&'a Pattern, &'a ASTNode<'a>, SymbolTable<Binding<'a>>),
+    Fix,
+    /// Store the value in a thunk that was passed by need
+    Force(Rc<RefCell<Thunk<'a>>>),
+}
+
+impl<'a> State<'a> {
+    /// Returns the initial state for evaluating a program, in an empty environment without any
+    /// continuations
+    pub fn new(program: &'a ASTNode<'a>) -> State<'a> {
+        State::term(program, SymbolTable::new(), Vec::new())
+    }
+
+    fn term(
+        node: &'a ASTNode<'a>,
+        env: SymbolTable<Binding<'a>>,
+        kont: Vec<Continuation<'a>>,
+    ) -> State<'a> {
+        State {
+            control: Control::Term(node),
+            env,
+            kont,
+            decisions: Decisions::default(),
+        }
+    }
+
+    fn value(value: OutputValue<'a>, kont: Vec<Continuation<'a>>) -> State<'a> {
+        State {
+            control: Control::Value(value),
+            env: SymbolTable::new(),
+            kont,
+            decisions: Decisions::default(),
+        }
+    }
+
+    /// Returns whether the machine halted, which is when it has a value and no continuation is
+    /// waiting for it
+    pub fn is_final(&self) -> bool {
+        match self.control {
+            Control::Value(_) => self.kont.is_empty(),
+            Control::Term(_) => false,
+        }
+    }
+
+    /// Returns the value the machine halted with, or `None` when it did not halt yet
+    pub fn result(self) -> Option<OutputValue<'a>> {
+        match self.control {
+            Control::Value(value) if self.kont.is_empty() => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Makes a single transition of the machine
+    ///
+    /// # Arguments
+    /// * `strategy` - The way arguments are passed to functions
+    ///
+    /// # Panics
+    /// Throws a panic when the machine already halted, or when encountering an invalid tree
+    /// structure or an invalid type. The latter would indicate a problem in the typechecking logic.
+    pub fn step(self, strategy: Strategy) -> State<'a> {
+        let State {
+            control,
+            env,
+            mut kont,
+            mut decisions,
+        } = self;
+        let mut state = match control {
+            Control::Term(node) => eval_step(node, strategy, env, kont),
+            Control::Value(value) => {
+                let continuation = kont.pop().expect("Stepped a machine that already halted");
+                resume(value, continuation, strategy, kont, &mut decisions)
+            }
+        };
+        state.decisions = decisions;
+        state
+    }
+
+    /// Makes transitions until the machine halts, giving up with an error when the evaluation
+    /// exceeds one of the limits of the configuration. Every transition that starts evaluating a
+    /// term counts as a step.
+    ///
+    /// # Arguments
+    /// * `strategy` - The way arguments are passed to functions
+    /// * `config` - The limits on the number of steps and the number of continuations
+    /// * `observe` - Called with every state the machine passes through after this one
+    ///
+    /// # Errors
+    /// Returns `EvalError::OutOfFuel` when more steps are taken than allowed, and
+    /// `EvalError::TooDeep` when more continuations are waiting for a value than allowed
+    pub fn run<F>(
+        self,
+        strategy: Strategy,
+        config: EvalConfig,
+        mut observe: F,
+    ) -> result::Result<OutputValue<'a>, EvalError>
+    where
+        F: FnMut(&State<'a>),
+    {
+        let mut fuel = Fuel { config, steps: 0 };
+        let mut state = self;
+        while !state.is_final() {
+            if let Control::Term(_) = state.control {
+                fuel.burn(state.kont.len())?;
+            }
+            state = state.step(strategy);
+            observe(&state);
+        }
+        Ok(state.result().expect("Machine halted without a value"))
+    }
+
+    /// Serializes the state as a JSON object with the fields `control`, `env` and `kont`. Terms
+    /// and values are given as source text, the environment lists the visible bindings from the
+    /// innermost outwards and the continuations are listed from the innermost outwards.
+    pub fn to_json(&self) -> String {
+        let control = match &self.control {
+            Control::Term(node) => format!("{{\"term\": {}}}", json_string(&node.source())),
+            Control::Value(value) => {
+                format!("{{\"value\": {}}}", json_string(&ValueDisplay(value)))
+            }
+        };
+        let env: Vec<String> = self
+            .env
+            .visible()
+            .into_iter()
+            .map(|(name, binding)| {
+                format!(
+                    "{{\"name\": {}, \"binding\": {}}}",
+                    json_string(&name),
+                    json_string(&BindingDisplay(binding))
+                )
+            })
+            .collect();
+        let kont: Vec<String> = self.kont.iter().rev().map(json_string).collect();
+        format!(
+            "{{\"control\": {}, \"env\": [{}], \"kont\": [{}]}}",
+            control,
+            env.join(", "),
+            kont.join(", ")
+        )
+    }
+}
+
+impl<'a> Display for State<'a> {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match &self.control {
+            Control::Term(node) => writeln!(f, "control: {}", node.source())?,
+            Control::Value(value) => writeln!(f, "control: value {}", ValueDisplay(value))?,
+        }
+        let env: Vec<String> = self
+            .env
+            .visible()
+            .into_iter()
+            .map(|(name, binding)| format!("{} = {}", name, BindingDisplay(binding)))
+            .collect();
+        if env.is_empty() {
+            writeln!(f, "env:     empty")?;
+        } else {
+            writeln!(f, "env:     {}", env.join(", "))?;
+        }
+        write!(f, "kont:")?;
+        if self.kont.is_empty() {
+            write!(f, "    halt")?;
+        }
+        for continuation in self.kont.iter().rev() {
+            write!(f, "\n  - {}", continuation)?;
+        }
+        Ok(())
+    }
+}
+
+/// Helper to print the binding of a variable in the environment of a state
+struct BindingDisplay<'b, 'a: 'b>(&'b Binding<'a>);
+
+impl<'b, 'a> Display for BindingDisplay<'b, 'a> {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self.0 {
+            Binding::Value(value) => write!(f, "{}", ValueDisplay(value)),
+            Binding::Recursive(term, _) => write!(f, "rec {}", term.source()),
+            Binding::Delayed(term, _) => write!(f, "delayed {}", term.source()),
+            Binding::Shared(thunk) => match &*thunk.borrow() {
+                Thunk::Delayed(term, _) => write!(f, "shared {}", term.source()),
+                Thunk::Forced(value) => write!(f, "{}", ValueDisplay(value)),
+            },
+        }
+    }
+}
+
+/// Helper to print a value in a state, unlike the result of a program the functions show their code
+struct ValueDisplay<'v, 'a: 'v>(&'v OutputValue<'a>);
+
+impl<'v, 'a> Display for ValueDisplay<'v, 'a> {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self.0 {
+            OutputValue::Func(function, _) => write!(f, "{}", function.source()),
+            OutputValue::Record(records) => {
+                let mut list: Vec<String> = records
+                    .iter()
+                    .map(|(name, value)| format!("{}={}", name, ValueDisplay(value)))
+                    .collect();
+                list.sort();
+                write!(f, "{{{}}}", list.join(", "))
+            }
+            OutputValue::Variant(ident, value, _) => {
+                write!(f, "<{}={}>", ident, ValueDisplay(value))
+            }
+            value => write!(f, "{}", value),
+        }
+    }
+}
+
+impl<'a> Display for Continuation<'a> {
+    /// Prints the continuation as the term that remains to be evaluated, with `[]` marking the
+    /// place of the value it is waiting for
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            Continuation::Argument(right, _) => write!(f, "[] ({})", right.source()),
+            Continuation::Call(ident, body, _) => write!(f, "(@ {}. {}) []", ident, body.source()),
+            Continuation::Arithmetic(Operator::Succ) => write!(f, "succ []"),
+            Continuation::Arithmetic(Operator::Pred) => write!(f, "pred []"),
+            Continuation::IsZero => write!(f, "iszero []"),
+            Continuation::Condition(then_arm, else_arm, _) => write!(
+                f,
+                "if [] then {} else {}",
+                then_arm.source(),
+                else_arm.source()
+            ),
+            Continuation::Projection(attrib) => write!(f, "[].{}", attrib),
+            Continuation::Restriction(attrib) => write!(f, "[]\\{}", attrib),
+            Continuation::Field(done, name, remaining, _) => {
+                // Fields of an updated record that are still pending only show their new term
+                let pending =
+                    |field: &str| field == *name || remaining.iter().any(|(n, _)| *n == field);
+                let mut fields: Vec<String> = done
+                    .iter()
+                    .filter(|(field, _)| !pending(field))
+                    .map(|(name, value)| format!("{}={}", name, ValueDisplay(value)))
+                    .collect();
+                fields.sort();
+                fields.push(format!("{}=[]", name));
+                fields.extend(
+                    remaining
+                        .iter()
+                        .map(|(name, node)| format!("{}={}", name, node.source())),
+                );
+                write!(f, "{{{}}}", fields.join(", "))
+            }
+            Continuation::Update(records, _) => {
+                let mut fields: Vec<String> = records
+                    .iter()
+                    .map(|(name, node)| format!("{}={}", name, node.source()))
+                    .collect();
+                fields.sort();
+                write!(f, "[] with {{{}}}", fields.join(", "))
+            }
+            Continuation::Matching(cases, _) => {
+                let arms: Vec<String> = cases
+                    .iter()
+                    .map(|(pattern, arm)| format!("{} => {}", pattern, arm.source()))
+                    .collect();
+                write!(f, "case [] of {}", arms.join(" | "))
+            }
+            Continuation::Tagging(ident, _) => write!(f, "<{}=[]>", ident),
+            Continuation::Let(pattern, body, _) => {
+                write!(f, "let {} = [] in {}", pattern, body.source())
+            }
+            Continuation::Fix => write!(f, "fix |[]|"),
+            Continuation::Force(_) => write!(f, "share []"),
+        }
+    }
+}
+
+/// Returns the displayed value as a JSON string literal
+fn json_string<T: Display>(value: &T) -> String {
+    let mut escaped = String::from("\"");
+    for c in value.to_string().chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// Takes a single step in the evaluation of the node, either producing its value right away
+/// or pushing the work that remains after evaluating one of its sub-terms.
+fn eval_step<'a>(
+    node: &'a ASTNode<'a>,
+    strategy: Strategy,
+    mut table: SymbolTable<Binding<'a>>,
+    mut kont: Vec<Continuation<'a>>,
+) -> State<'a> {
+    match node {
+        ASTNode::AbstractionNode { .. } => State::value(OutputValue::Func(node, table), kont),
+        ASTNode::ApplicationNode {
+            meta: _,
+            left,
+            right,
+        } => {
+            kont.push(Continuation::Argument(right, table.clone()));
+            State::term(left, table, kont)
+        }
+        ASTNode::ArithmeticNode { meta: _, op, expr } => {
+            kont.push(Continuation::Arithmetic(op));
+            State::term(expr, table, kont)
+        }
+        ASTNode::ConditionNode {
+            meta: _,
+            clause,
+            then_arm,
+            else_arm,
+        } => {
+            kont.push(Continuation::Condition(then_arm, else_arm, table.clone()));
+            State::term(clause, table, kont)
+        }
+        ASTNode::IdentifierNode { meta: _, name } => {
+            let binding = table
+                .lookup(name)
+                .expect("Bug in typechecker: came across unknown variable");
+
+            match binding {
+                Binding::Value(value) => State::value(value.clone(), kont),
+                Binding::Recursive(term, _) => State::term(term, table.up_to(name), kont),
+                Binding::Delayed(term, env) => State::term(term, env.clone(), kont),
+                Binding::Shared(thunk) => match &*thunk.borrow() {
+                    Thunk::Forced(value) => State::value(value.clone(), kont),
+                    Thunk::Delayed(term, env) => {
+                        kont.push(Continuation::Force(thunk.clone()));
+                        State::term(term, env.clone(), kont)
+                    }
+                },
+            }
+        }
+        ASTNode::IsZeroNode { meta: _, expr } => {
+            kont.push(Continuation::IsZero);
+            State::term(expr, table, kont)
+        }
+        ASTNode::ValueNode { meta: _, value } => {
+            let value = match value {
+                Value::True => OutputValue::Bool(true),
+                Value::False => OutputValue::Bool(false),
+                Value::Zero => OutputValue::Nat(0),
+            };
+            State::value(value, kont)
+        }
+        ASTNode::ProjectionNode {
+            meta: _,
+            target,
+            attrib,
+        } => {
+            kont.push(Continuation::Projection(attrib));
+            State::term(target, table, kont)
+        }
+        ASTNode::RecordNode { meta: _, records } => {
+            next_field(HashMap::new(), records.iter().collect(), table, kont)
+        }
+        ASTNode::UpdateNode {
+            meta: _,
+            target,
+            records,
+        }
+        | ASTNode::ExtensionNode {
+            meta: _,
+            target,
+            records,
+        } => {
+            kont.push(Continuation::Update(records, table.clone()));
+            State::term(target, table, kont)
+        }
+        ASTNode::RestrictionNode {
+            meta: _,
+            target,
+            attrib,
+        } => {
+            kont.push(Continuation::Restriction(attrib));
+            State::term(target, table, kont)
+        }
+        ASTNode::MatchingNode {
+            meta: _,
+            to_match,
+            cases,
+        } => {
+            kont.push(Continuation::Matching(cases, table.clone()));
+            State::term(to_match, table, kont)
+        }
+        ASTNode::TaggingNode {
+            meta: _,
+            ident,
+            value,
+            data_type,
+        } => {
+            kont.push(Continuation::Tagging(ident, data_type));
+            State::term(value, table, kont)
+        }
+        ASTNode::LetNode {
+            meta: _,
+            pattern,
+            value,
+            body,
+        } => {
+            // Variables bind the value the way arguments are passed, other patterns need the
+            // value to decide whether it matches
+            let binding = match pattern {
+                Pattern::Variable(name) => delay(strategy, value, &table).map(|b| (name, b)),
+                _ => None,
+            };
+            if let Some((name, binding)) = binding {
+                table.push(Scope::new(name.to_string(), binding));
+                return State::term(body, table, kont);
+            }
+            kont.push(Continuation::Let(pattern, body, table.clone()));
+            State::term(value, table, kont)
+        }
+        ASTNode::LetRecNode {
+            meta: _,
+            bindings,
+            body,
+        } => {
+            let mut scope = HashMap::new();
+            for (name, data_type, value) in bindings {
+                let binding = Binding::Recursive(value, data_type.as_ref());
+                scope.insert(name.to_string(), binding);
+            }
+            table.push(Scope::from_map(scope));
+            State::term(body, table, kont)
+        }
+        ASTNode::FixNode { meta: _, point } => {
+            kont.push(Continuation::Fix);
+            State::term(point, table, kont)
+        }
+    }
+}
+
+/// Returns the name and type of the parameter and the body of an abstraction
+///
+/// # Panics
+/// Throws a panic when the node is not an abstraction, function values are only built from those
+fn abstraction<'a>(
+    node: &'a ASTNode<'a>,
+) -> (&'a str, Option<&'a TypeAssignment>, &'a ASTNode<'a>) {
+    match node {
+        ASTNode::AbstractionNode {
+            ident,
+            data_type,
+            body,
+            ..
+        } => (ident, data_type.as_ref(), body),
+        _ => panic!("Bug in evaluator: function value of a term that is not an abstraction"),
+    }
+}
+
+/// Binds a term without evaluating it, the way the strategy passes arguments to functions.
+/// Returns `None` for call-by-value, under which the term has to be evaluated before it is bound.
+///
+/// # Arguments
+/// * `strategy` - The way arguments are passed to functions
+/// * `term` - The term to bind
+/// * `table` - The environment the term is evaluated in once it is needed
+fn delay<'a>(
+    strategy: Strategy,
+    term: &'a ASTNode<'a>,
+    table: &SymbolTable<Binding<'a>>,
+) -> Option<Binding<'a>> {
+    match strategy {
+        Strategy::CallByValue => None,
+        Strategy::CallByName => Some(Binding::Delayed(term, table.clone())),
+        Strategy::CallByNeed => Some(Binding::Shared(Rc::new(RefCell::new(Thunk::Delayed(
+            term,
+            table.clone(),
+        ))))),
+    }
+}
+
+/// Evaluates the next of the remaining fields of a record, or returns the record when all fields
+/// have a value
+fn next_field<'a>(
+    map: HashMap<String, OutputValue<'a>>,
+    mut remaining: Vec<(&'a String, &'a ASTNode<'a>)>,
+    table: SymbolTable<Binding<'a>>,
+    mut kont: Vec<Continuation<'a>>,
+) -> State<'a> {
+    match remaining.pop() {
+        Some((name, node)) => {
+            kont.push(Continuation::Field(map, name, remaining, table.clone()));
+            State::term(node, table, kont)
+        }
+        None => State::value(OutputValue::Record(map), kont),
+    }
+}
+
+/// Hands the value to the continuation that was waiting for it
+///
+/// # Arguments
+/// * `continuation` - The work that remains to be done with the value
+/// * `strategy` - The way arguments are passed to functions
+/// * `kont` - The continuations waiting for the result of the continuation
+/// * `decisions` - The decision trees compiled so far
+///
+/// # Panics
+/// Throws a panic when the value is of a type the continuation does not expect. This would
+/// indicate a problem in the typechecking logic.
+fn resume<'a>(
+    value: OutputValue<'a>,
+    continuation: Continuation<'a>,
+    strategy: Strategy,
+    mut kont: Vec<Continuation<'a>>,
+    decisions: &mut Decisions,
+) -> State<'a> {
+    match (continuation, value) {
+        (Continuation::Argument(right, table), OutputValue::Func(function, mut func_table)) => {
+            let (ident, _, body) = abstraction(function);
+            match delay(strategy, right, &table) {
+                Some(argument) => {
+                    func_table.push(Scope::new(ident.to_string(), argument));
+                    State::term(body, func_table, kont)
+                }
+                None => {
+                    kont.push(Continuation::Call(ident.to_string(), body, func_table));
+                    State::term(right, table, kont)
+                }
+            }
+        }
+        (Continuation::Argument(_, _), _) => panic!("Bug in typechecker: in evaluation of application the left argument was not evaluated to a function"),
+        (Continuation::Call(ident, body, mut func_table), value) => {
+            // The body takes the place of the application, nothing is left to do with its value
+            // here, so calls in tail position do not grow the continuations
+            func_table.push(Scope::new(ident, Binding::Value(value)));
+            State::term(body, func_table, kont)
+        }
+        (Continuation::Arithmetic(op), OutputValue::Nat(x)) => {
+            let value = match op {
+                Operator::Pred => OutputValue::Nat(x.saturating_sub(1)),
+                Operator::Succ => OutputValue::Nat(x + 1),
+            };
+            State::value(value, kont)
+        }
+        (Continuation::Arithmetic(_), _) => panic!("Bug in typechecker: in evaluation of pred/succ expr did not return variable of type Nat"),
+        (Continuation::IsZero, OutputValue::Nat(x)) => State::value(OutputValue::Bool(x == 0), kont),
+        (Continuation::IsZero, _) => panic!("Bug in typechecker: in evaluation of iszero expr did not return variable of type Nat"),
+        (Continuation::Condition(then_arm, else_arm, table), OutputValue::Bool(x)) => {
+            State::term(if x { then_arm } else { else_arm }, table, kont)
+        }
+        (Continuation::Condition(_, _, _), _) => panic!("Bug in typechecker: in evaluation of ifthenelse clause did not return variable of type Bool"),
+        (Continuation::Projection(attrib), OutputValue::Record(mut records)) => {
+            if let Some(output) = records.remove(attrib) {
+                State::value(output, kont)
+            } else {
+                panic!("Bug in typechecker: in evaluation of projection the attribute was not found")
+            }
+        }
+        (Continuation::Projection(_), _) => panic!("Bug in typechecker: in evaluation of projection type target type was not a record"),
+        (Continuation::Field(mut map, name, remaining, table), value) => {
+            map.insert(name.to_string(), value);
+            next_field(map, remaining, table, kont)
+        }
+        (Continuation::Update(records, table), OutputValue::Record(map)) => {
+            next_field(map, records.iter().collect(), table, kont)
+        }
+        (Continuation::Update(_, _), _) => panic!("Bug in typechecker: in evaluation of update the target was not a record"),
+        (Continuation::Restriction(attrib), OutputValue::Record(mut map)) => {
+            map.remove(attrib);
+            State::value(OutputValue::Record(map), kont)
+        }
+        (Continuation::Restriction(_), _) => panic!("Bug in typechecker: in evaluation of restriction the target was not a record"),
+        (Continuation::Matching(cases, mut table), value) => {
+            let tree = decisions.get(cases.iter().map(|(pattern, _)| pattern));
+            let (arm, scope) = value.select(&tree);
+            table.push(scope);
+            State::term(&cases[arm].1, table, kont)
+        }
+        (Continuation::Tagging(ident, data_type), value) => {
+            let variant = OutputValue::Variant(ident.to_string(), Box::new(value), data_type);
+            State::value(variant, kont)
+        }
+        (Continuation::Let(pattern, body, mut table), value) => {
+            let (_, scope) = value.select(&decisions.get(vec![pattern]));
+            table.push(scope);
+            State::term(body, table, kont)
+        }
+        (Continuation::Fix, OutputValue::Func(function, mut table)) => {
+            let (ident, data_type, body) = abstraction(function);
+            let binding = Binding::Recursive(body, data_type);
+            table.push(Scope::new(ident.to_string(), binding));
+            State::term(body, table, kont)
+        }
+        (Continuation::Fix, _) => panic!("Bug in typechecker: in evaluation of fixpoint the left argument was not evaluated to a function"),
+        (Continuation::Force(thunk), value) => {
+            *thunk.borrow_mut() = Thunk::Forced(value.clone());
+            State::value(value, kont)
+        }
+    }
+}
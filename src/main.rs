@@ -2,7 +2,13 @@ extern crate lambda_rs;
 extern crate pest;
 
 use lambda_rs::ast::*;
+use lambda_rs::eval::{EvalConfig, EvalError, OutputValue, Strategy};
+use lambda_rs::machine::State;
+use lambda_rs::nameless;
+use lambda_rs::nbe;
 use lambda_rs::parser::*;
+use lambda_rs::transform::specialize::{specialize, SpecializeConfig};
+use lambda_rs::{bytecode, closure, codegen, ir, transform, vm};
 use pest::iterators::Pair;
 use std::env;
 
@@ -10,15 +16,102 @@ use std::process;
 
 fn main() {
     // Setup environment
-    let args: Vec<String> = env::args().collect();
+    let mut args = env::args().skip(1);
+    let mut filename = None;
+    let mut strategy = Strategy::default();
+    let mut config = EvalConfig::default();
+    let mut machine_trace = false;
+    let mut dump_cps = false;
+    let mut dump_anf = false;
+    let mut optimize = false;
+    let mut backend = Backend::Eval;
+    let mut compiling = false;
+    let mut specializing = false;
+    let mut specialize_config = SpecializeConfig::default();
+    let mut target = None;
+    let mut run_flags = Vec::new();
 
-    if args.len() != 2 {
-        println!("This interpreter takes exactly one argument: the filename of the lambda code");
+    while let Some(arg) = args.next() {
+        if RUN_FLAGS.contains(&arg.as_str()) {
+            run_flags.push(arg.clone());
+        }
+        match arg.as_str() {
+            "--strategy" => {
+                let name = args.next().unwrap_or_else(|| usage());
+                strategy = name.parse().unwrap_or_else(|e| {
+                    println!("{}", e);
+                    process::exit(1);
+                });
+            }
+            "--max-steps" => config.max_steps = Some(limit(args.next())),
+            "--max-depth" => config.max_depth = Some(limit(args.next())),
+            "--machine-trace" => machine_trace = true,
+            "--dump-cps" => dump_cps = true,
+            "--dump-anf" => dump_anf = true,
+            "-O" => optimize = true,
+            "--backend" => {
+                backend = match args.next().as_deref() {
+                    Some("eval") => Backend::Eval,
+                    Some("vm") => Backend::Vm,
+                    Some("closure") => Backend::Closure,
+                    Some("anf") => Backend::Anf,
+                    Some(name) => {
+                        println!(
+                            "Unknown backend {}, expected eval, vm, closure or anf",
+                            name
+                        );
+                        process::exit(1);
+                    }
+                    None => usage(),
+                }
+            }
+            "--target" => {
+                target = match args.next().as_deref() {
+                    Some("c") => Some(Target::C),
+                    Some("js") => Some(Target::Js),
+                    Some("wat") => Some(Target::Wat),
+                    Some(name) => {
+                        println!("Unknown target {}, expected c, js or wat", name);
+                        process::exit(1);
+                    }
+                    None => usage(),
+                }
+            }
+            "--binding-time" => {
+                let annotation = args.next().unwrap_or_else(|| usage());
+                let annotation = annotation.parse().unwrap_or_else(|e| {
+                    println!("{}", e);
+                    process::exit(1);
+                });
+                specialize_config.annotations.push(annotation);
+            }
+            "compile" if !compiling && !specializing && filename.is_none() => compiling = true,
+            "specialize" if !compiling && !specializing && filename.is_none() => {
+                specializing = true
+            }
+            _ if filename.is_none() && !arg.starts_with("--") => filename = Some(arg),
+            _ => usage(),
+        }
+    }
+
+    if let Some(flag) = run_flags.first().filter(|_| compiling || specializing) {
+        let command = if compiling { "compile" } else { "specialize" };
+        println!("The {} flag does not apply to {}", flag, command);
+        process::exit(1);
+    }
+
+    if !compiling && target.is_some() {
+        println!("Targets only apply to compile");
+        process::exit(1);
+    }
+
+    if !specializing && !specialize_config.annotations.is_empty() {
+        println!("Binding times only apply to specialize");
         process::exit(1);
     }
 
     // Read file contents
-    let filename = args[1].clone();
+    let filename = filename.unwrap_or_else(|| usage());
     let contents = lambda_rs::read_file(&filename).unwrap_or_else(|e| {
         println!("Encountered an error when reading file: {}", e);
         process::exit(1);
@@ -42,13 +135,220 @@ fn main() {
     // println!("{}", ast_tree);
 
     // Perform typechecking on the syntax tree
-    let _tree_type = ast_tree.check::<i32>().unwrap_or_else(|e| {
+    ast_tree.infer::<i32>().unwrap_or_else(|e| {
         println!("Encountered an error when typechecking:\n{}", e);
         process::exit(1);
     });
 
+    if specializing {
+        let residual = specialize(&ast_tree, &specialize_config);
+        println!("{}", residual.source());
+        return;
+    }
+
+    if optimize && (dump_cps || (!dump_anf && backend != Backend::Anf)) {
+        println!("Only the anf backend and --dump-anf optimize programs");
+        process::exit(1);
+    }
+
+    if machine_trace && (dump_cps || dump_anf || backend != Backend::Eval) {
+        println!("Only the eval backend traces the abstract machine");
+        process::exit(1);
+    }
+
+    if dump_cps {
+        println!("{}", transform::cps::convert(&ast_tree));
+        return;
+    }
+
+    if dump_anf {
+        println!("{}", anf_term(&ast_tree, optimize));
+        return;
+    }
+
+    if compiling {
+        match target.unwrap_or(Target::C) {
+            Target::C => print!("{}", codegen::c::compile(&ast_tree)),
+            Target::Js => print!("{}", codegen::js::compile(&ast_tree)),
+            Target::Wat => print!("{}", codegen::wat::compile(&ast_tree)),
+        }
+        return;
+    }
+
+    if backend != Backend::Eval && strategy != Strategy::CallByValue {
+        println!(
+            "The {} backend only passes arguments by value",
+            backend.name()
+        );
+        process::exit(1);
+    }
+
+    if (backend == Backend::Closure || backend == Backend::Anf) && config != EvalConfig::default() {
+        println!(
+            "The {} backend does not support step or depth limits",
+            backend.name()
+        );
+        process::exit(1);
+    }
+
+    if backend == Backend::Vm {
+        let program = bytecode::compile(&ast_tree);
+        let value = vm::run(&program, config).unwrap_or_else(|e| {
+            println!("{}", e);
+            process::exit(1);
+        });
+        println!("{}", vm::Show(&value, &program));
+        return;
+    }
+
+    if backend == Backend::Closure {
+        println!("{}", closure::compile(&ast_tree).run());
+        return;
+    }
+
+    if backend == Backend::Anf {
+        let term = anf_term(&ast_tree, optimize);
+        println!("{}", ir::anf::run(&term));
+        return;
+    }
+
     // Evaluate the Abstract Syntax tree
-    println!("{}", ast_tree.eval());
+    let value = if machine_trace {
+        trace(&ast_tree, strategy, config)
+    } else {
+        ast_tree.eval_with(strategy, config)
+    };
+    let value = value.unwrap_or_else(|e| {
+        println!("{}", e);
+        process::exit(1);
+    });
+
+    // Functions are printed as the normal form of the closure they evaluated to, unfolding
+    // recursive definitions within the same limits as the evaluation
+    if let OutputValue::Func(_, _) = value {
+        let normal = nbe::normalize_with(&value.quote(), config);
+        println!("{}", nameless::from_nameless(&normal).source());
+    } else {
+        println!("{}", value);
+    }
+}
+
+/// The flags that change how a program is run, which neither compiling nor specializing use
+const RUN_FLAGS: [&str; 8] = [
+    "--strategy",
+    "--max-steps",
+    "--max-depth",
+    "--machine-trace",
+    "--dump-cps",
+    "--dump-anf",
+    "-O",
+    "--backend",
+];
+
+/// The ways in which a program can be run
+#[derive(Clone, Copy, PartialEq)]
+enum Backend {
+    /// Evaluate the syntax tree directly
+    Eval,
+    /// Compile the syntax tree to bytecode and run it on the virtual machine
+    Vm,
+    /// Compile the syntax tree to closures with resolved variables and call them
+    Closure,
+    /// Convert the syntax tree to A-normal form and interpret the result
+    Anf,
+}
+
+impl Backend {
+    /// Returns the name the backend is selected by on the command line
+    fn name(self) -> &'static str {
+        match self {
+            Backend::Eval => "eval",
+            Backend::Vm => "vm",
+            Backend::Closure => "closure",
+            Backend::Anf => "anf",
+        }
+    }
+}
+
+/// The languages programs can be compiled to
+#[derive(Clone, Copy, PartialEq)]
+enum Target {
+    /// A single C file including its runtime
+    C,
+    /// A JavaScript program that runs under node
+    Js,
+    /// A WebAssembly module in text format exporting its memory and a main function
+    Wat,
+}
+
+/// Converts the tree to A-normal form
+///
+/// # Arguments
+/// * `ast_tree` - The typechecked program to convert
+/// * `optimize` - Whether the converted program is simplified
+fn anf_term(ast_tree: &ASTNode<'_>, optimize: bool) -> ir::anf::Term {
+    let term = ir::anf::convert(ast_tree);
+    if optimize {
+        ir::opt::optimize(term)
+    } else {
+        term
+    }
+}
+
+/// Evaluates the tree on the abstract machine, printing every state it passes through
+///
+/// # Arguments
+/// * `ast_tree` - The program to evaluate
+/// * `strategy` - The way arguments are passed to functions
+/// * `config` - The limits on the number of steps and the number of continuations
+fn trace<'a>(
+    ast_tree: &'a ASTNode<'a>,
+    strategy: Strategy,
+    config: EvalConfig,
+) -> Result<OutputValue<'a>, EvalError> {
+    let state = State::new(ast_tree);
+    let mut transitions = 0;
+    println!("{}\n", state);
+    state.run(strategy, config, |state| {
+        transitions += 1;
+        println!("--> {}\n{}\n", transitions, state);
+    })
+}
+
+/// Parses the value given to one of the limit flags, exits when it is missing or not a number
+///
+/// # Arguments
+/// * `arg` - The argument following the flag
+fn limit(arg: Option<String>) -> usize {
+    let arg = arg.unwrap_or_else(|| usage());
+    arg.parse().unwrap_or_else(|_| {
+        println!("Expected a number as limit, found {}", arg);
+        process::exit(1);
+    })
+}
+
+/// Prints how the interpreter should be called and exits
+fn usage() -> ! {
+    println!("Usage: lambda-rs [options] <filename>");
+    println!("       lambda-rs compile [--target c|js|wat] <filename>");
+    println!("       lambda-rs specialize [--binding-time name=S|D]... <filename>");
+    println!();
+    println!("Runs the program in <filename> and prints its value. The eval backend prints a");
+    println!("function as the normal form of its source, the others print <function>. compile");
+    println!("prints the program in another language, by default C, and specialize prints the");
+    println!("program specialized to its static inputs. Compiled C is built with");
+    println!("cc -std=c99 -pthread.");
+    println!();
+    println!("Options for running a program:");
+    println!("  --strategy value|name|need     how arguments are passed (eval backend only)");
+    println!("  --backend eval|vm|closure|anf  what runs the program (default eval)");
+    println!("  --max-steps n                  stop after n steps (eval and vm only)");
+    println!("  --max-depth n                  stop beyond n pending calls (eval and vm only)");
+    println!("  --machine-trace                print every machine state (eval backend only)");
+    println!("  --dump-cps                     print the program in continuation-passing style");
+    println!("  --dump-anf                     print the program in A-normal form");
+    println!("  -O                             optimize the A-normal form (anf and --dump-anf)");
+    process::exit(1);
 }
 
 /// Debug method to print result of parsing
@@ -56,6 +356,7 @@ fn main() {
 /// # Arguments
 /// * `pair` - Structure used by parser
 /// * `level` - Variable to control indentation, so inner blocks get printed nicely indented
+#[allow(dead_code)]
 fn recursive_print(pair: Pair<'_, Rule>, level: usize) {
     let span = pair.clone().into_span();
     println!("{}Rule:    {:?}", "\t".repeat(level), pair.as_rule());
@@ -0,0 +1,458 @@
+use ast::*;
+use std::collections::HashMap;
+
+/// A single step from a value to one of its components
+#[derive(Debug, Clone, PartialEq)]
+pub enum Access {
+    /// The value carried by a variant with the given tag
+    Tag(String),
+    /// The predecessor of a non zero natural number
+    Pred,
+    /// The value of the given field of a record
+    Field(String),
+}
+
+/// The location of a component inside the value that is being matched
+pub type Path = Vec<Access>;
+
+/// The head constructors a case expression can test a value against
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constructor {
+    True,
+    False,
+    Zero,
+    Succ,
+    Tag(String),
+}
+
+/// Decision tree resulting from compiling the arms of a case expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decision {
+    /// No arm matches the value
+    Fail,
+    /// The arm with the given index matches, its variables are bound to the components at the paths
+    Leaf {
+        arm: usize,
+        bindings: Vec<(String, Path)>,
+    },
+    /// Tests the head constructor of the component at the path
+    Switch {
+        path: Path,
+        cases: Vec<(Constructor, Decision)>,
+        default: Option<Box<Decision>>,
+    },
+}
+
+static WILDCARD: Pattern = Pattern::Wildcard;
+
+/// A row of the pattern matrix, the patterns are tested against the columns of the matrix
+#[derive(Clone)]
+struct Row<'p> {
+    patterns: Vec<&'p Pattern>,
+    bindings: Vec<(String, Path)>,
+    arm: usize,
+}
+
+/// Compiles the patterns of a case expression into a decision tree. Arms are tried in order, the
+/// first one that matches is selected.
+///
+/// # Arguments
+/// * `patterns` - the patterns of the arms of the case expression
+/// * `scrutinee` - the type of the matched value, if known. Without a type, tests on variants can
+///   never be assumed to be exhaustive and always get a default branch.
+pub fn compile<'p, I>(patterns: I, scrutinee: Option<&TypeAssignment>) -> Decision
+where
+    I: IntoIterator<Item = &'p Pattern>,
+{
+    let rows = patterns
+        .into_iter()
+        .enumerate()
+        .map(|(arm, pattern)| Row {
+            patterns: vec![pattern],
+            bindings: Vec::new(),
+            arm,
+        })
+        .collect();
+    compile_rows(rows, vec![Vec::new()], scrutinee)
+}
+
+impl Decision {
+    /// Returns for every one of the given number of arms whether it can be selected by the tree
+    ///
+    /// # Arguments
+    /// * `arms` - the number of arms in the case expression
+    pub fn reachable_arms(&self, arms: usize) -> Vec<bool> {
+        let mut reachable = vec![false; arms];
+        self.mark_reachable(&mut reachable);
+        reachable
+    }
+
+    fn mark_reachable(&self, reachable: &mut Vec<bool>) {
+        match self {
+            Decision::Fail => {}
+            Decision::Leaf { arm, bindings: _ } => reachable[*arm] = true,
+            Decision::Switch {
+                path: _,
+                cases,
+                default,
+            } => {
+                for (_, case) in cases {
+                    case.mark_reachable(reachable);
+                }
+                if let Some(default) = default {
+                    default.mark_reachable(reachable);
+                }
+            }
+        }
+    }
+
+    /// Returns an example of a value that is not handled by the tree, or `None` if the tree is
+    /// exhaustive.
+    ///
+    /// # Arguments
+    /// * `scrutinee` - the type of the matched value
+    pub fn missing(&self, scrutinee: &TypeAssignment) -> Option<Pattern> {
+        self.find_fail(&mut Vec::new())
+            .map(|constraints| witness(&Vec::new(), &constraints, Some(scrutinee)))
+    }
+
+    fn find_fail(
+        &self,
+        constraints: &mut Vec<(Path, Constraint)>,
+    ) -> Option<Vec<(Path, Constraint)>> {
+        match self {
+            Decision::Fail => Some(constraints.clone()),
+            Decision::Leaf { .. } => None,
+            Decision::Switch {
+                path,
+                cases,
+                default,
+            } => {
+                for (constructor, case) in cases {
+                    constraints.push((path.clone(), Constraint::Is(constructor.clone())));
+                    let found = case.find_fail(constraints);
+                    constraints.pop();
+                    if found.is_some() {
+                        return found;
+                    }
+                }
+                if let Some(default) = default {
+                    let excluded = cases.iter().map(|(c, _)| c.clone()).collect();
+                    constraints.push((path.clone(), Constraint::Not(excluded)));
+                    let found = default.find_fail(constraints);
+                    constraints.pop();
+                    return found;
+                }
+                None
+            }
+        }
+    }
+}
+
+/// What is known about the component at a path on the way to a failure in the tree
+#[derive(Debug, Clone)]
+enum Constraint {
+    Is(Constructor),
+    Not(Vec<Constructor>),
+}
+
+fn compile_rows(rows: Vec<Row>, paths: Vec<Path>, scrutinee: Option<&TypeAssignment>) -> Decision {
+    let first = match rows.first() {
+        Some(first) => first,
+        None => return Decision::Fail,
+    };
+    let column = match first.patterns.iter().position(|p| !is_irrefutable(p)) {
+        Some(column) => column,
+        None => {
+            let mut bindings = first.bindings.clone();
+            for (pattern, path) in first.patterns.iter().zip(paths.iter()) {
+                bind_irrefutable(pattern, path, &mut bindings);
+            }
+            return Decision::Leaf {
+                arm: first.arm,
+                bindings,
+            };
+        }
+    };
+    if let Pattern::Record(_) = first.patterns[column] {
+        return expand_record(rows, paths, column, scrutinee);
+    }
+
+    let path = paths[column].clone();
+    let mut heads: Vec<Constructor> = Vec::new();
+    for row in &rows {
+        if let Some(head) = head_constructor(row.patterns[column]) {
+            if !heads.contains(&head) {
+                heads.push(head);
+            }
+        }
+    }
+
+    let cases = heads
+        .iter()
+        .map(|head| {
+            let rows = rows
+                .iter()
+                .filter_map(|row| specialize(row, column, &path, head))
+                .collect();
+            let mut sub_paths = paths.clone();
+            sub_paths.splice(column..column + 1, sub_paths_of(&path, head));
+            (head.clone(), compile_rows(rows, sub_paths, scrutinee))
+        })
+        .collect();
+
+    let complete = match signature(&heads[0], type_at(scrutinee, &path)) {
+        Some(all) => all.iter().all(|c| heads.contains(c)),
+        None => false,
+    };
+    let default = if complete {
+        None
+    } else {
+        let rows = rows
+            .iter()
+            .filter_map(|row| default_row(row, column, &path))
+            .collect();
+        let mut sub_paths = paths.clone();
+        sub_paths.remove(column);
+        Some(Box::new(compile_rows(rows, sub_paths, scrutinee)))
+    };
+
+    Decision::Switch {
+        path,
+        cases,
+        default,
+    }
+}
+
+/// Replaces a column of record patterns by one column for every field that is mentioned
+fn expand_record(
+    rows: Vec<Row>,
+    mut paths: Vec<Path>,
+    column: usize,
+    scrutinee: Option<&TypeAssignment>,
+) -> Decision {
+    let mut fields: Vec<&String> = Vec::new();
+    for row in &rows {
+        if let Pattern::Record(map) = row.patterns[column] {
+            for name in map.keys() {
+                if !fields.contains(&name) {
+                    fields.push(name);
+                }
+            }
+        }
+    }
+    fields.sort();
+
+    let path = paths[column].clone();
+    let expanded = rows
+        .iter()
+        .map(|row| {
+            let mut row = row.clone();
+            let subs: Vec<&Pattern> = match row.patterns[column] {
+                Pattern::Record(map) => fields
+                    .iter()
+                    .map(|name| map.get(*name).unwrap_or(&WILDCARD))
+                    .collect(),
+                Pattern::Variable(name) => {
+                    row.bindings.push((name.to_string(), path.clone()));
+                    vec![&WILDCARD; fields.len()]
+                }
+                Pattern::Wildcard => vec![&WILDCARD; fields.len()],
+                _ => panic!("Bug in typechecker: record pattern mixed with other patterns"),
+            };
+            row.patterns.splice(column..column + 1, subs);
+            row
+        })
+        .collect();
+    let sub_paths: Vec<Path> = fields
+        .iter()
+        .map(|name| extend(&path, Access::Field(name.to_string())))
+        .collect();
+    paths.splice(column..column + 1, sub_paths);
+    compile_rows(expanded, paths, scrutinee)
+}
+
+/// Returns the row that remains when the value in the column has the given head constructor
+fn specialize<'p>(
+    row: &Row<'p>,
+    column: usize,
+    path: &Path,
+    head: &Constructor,
+) -> Option<Row<'p>> {
+    let mut row = row.clone();
+    let subs: Vec<&Pattern> = match row.patterns[column] {
+        Pattern::Wildcard => vec![&WILDCARD; arity(head)],
+        Pattern::Variable(name) => {
+            row.bindings.push((name.to_string(), path.clone()));
+            vec![&WILDCARD; arity(head)]
+        }
+        Pattern::Succ(inner) if head == &Constructor::Succ => vec![&**inner],
+        Pattern::Tag(ident, inner) if head == &Constructor::Tag(ident.to_string()) => {
+            vec![&**inner]
+        }
+        pattern => {
+            if head_constructor(pattern).as_ref() == Some(head) {
+                Vec::new()
+            } else {
+                return None;
+            }
+        }
+    };
+    row.patterns.splice(column..column + 1, subs);
+    Some(row)
+}
+
+/// Returns the row that remains when the value in the column has none of the tested constructors
+fn default_row<'p>(row: &Row<'p>, column: usize, path: &Path) -> Option<Row<'p>> {
+    let mut row = row.clone();
+    match row.patterns[column] {
+        Pattern::Wildcard => {}
+        Pattern::Variable(name) => row.bindings.push((name.to_string(), path.clone())),
+        _ => return None,
+    }
+    row.patterns.remove(column);
+    Some(row)
+}
+
+fn is_irrefutable(pattern: &Pattern) -> bool {
+    match pattern {
+        Pattern::Wildcard | Pattern::Variable(_) => true,
+        Pattern::Record(fields) => fields.values().all(is_irrefutable),
+        _ => false,
+    }
+}
+
+fn bind_irrefutable(pattern: &Pattern, path: &Path, bindings: &mut Vec<(String, Path)>) {
+    match pattern {
+        Pattern::Variable(name) => bindings.push((name.to_string(), path.clone())),
+        Pattern::Record(fields) => {
+            for (name, field) in fields {
+                bind_irrefutable(
+                    field,
+                    &extend(path, Access::Field(name.to_string())),
+                    bindings,
+                );
+            }
+        }
+        _ => {}
+    }
+}
+
+fn head_constructor(pattern: &Pattern) -> Option<Constructor> {
+    match pattern {
+        Pattern::Value(Value::True) => Some(Constructor::True),
+        Pattern::Value(Value::False) => Some(Constructor::False),
+        Pattern::Value(Value::Zero) => Some(Constructor::Zero),
+        Pattern::Succ(_) => Some(Constructor::Succ),
+        Pattern::Tag(ident, _) => Some(Constructor::Tag(ident.to_string())),
+        _ => None,
+    }
+}
+
+fn arity(constructor: &Constructor) -> usize {
+    match constructor {
+        Constructor::Succ | Constructor::Tag(_) => 1,
+        _ => 0,
+    }
+}
+
+fn sub_paths_of(path: &Path, constructor: &Constructor) -> Vec<Path> {
+    match constructor {
+        Constructor::Succ => vec![extend(path, Access::Pred)],
+        Constructor::Tag(ident) => vec![extend(path, Access::Tag(ident.to_string()))],
+        _ => Vec::new(),
+    }
+}
+
+fn extend(path: &Path, access: Access) -> Path {
+    let mut path = path.clone();
+    path.push(access);
+    path
+}
+
+/// Returns all constructors of the type the given constructor belongs to, if they are known
+fn signature(
+    constructor: &Constructor,
+    data_type: Option<&TypeAssignment>,
+) -> Option<Vec<Constructor>> {
+    match constructor {
+        Constructor::True | Constructor::False => Some(vec![Constructor::True, Constructor::False]),
+        Constructor::Zero | Constructor::Succ => Some(vec![Constructor::Zero, Constructor::Succ]),
+        Constructor::Tag(_) => match data_type {
+            Some(TypeAssignment::Variant(variants, None)) => {
+                let mut tags: Vec<&String> = variants.keys().collect();
+                tags.sort();
+                Some(
+                    tags.iter()
+                        .map(|tag| Constructor::Tag(tag.to_string()))
+                        .collect(),
+                )
+            }
+            _ => None,
+        },
+    }
+}
+
+/// Returns the type of the component at the path inside a value of the given type
+fn type_at<'t>(
+    data_type: Option<&'t TypeAssignment>,
+    path: &[Access],
+) -> Option<&'t TypeAssignment> {
+    let mut current = data_type?;
+    for access in path {
+        current = match (access, current) {
+            (Access::Tag(ident), TypeAssignment::Variant(variants, _)) => variants.get(ident)?,
+            (Access::Field(name), TypeAssignment::Record(fields, _)) => fields.get(name)?,
+            (Access::Pred, _) => current,
+            _ => return None,
+        };
+    }
+    Some(current)
+}
+
+/// Builds a pattern matching the values that satisfy the constraints collected on the way to a
+/// failure in the decision tree
+fn witness(
+    path: &Path,
+    constraints: &[(Path, Constraint)],
+    scrutinee: Option<&TypeAssignment>,
+) -> Pattern {
+    let constructor = match constraints.iter().find(|(p, _)| p == path) {
+        Some((_, Constraint::Is(constructor))) => Some(constructor.clone()),
+        Some((_, Constraint::Not(excluded))) => {
+            let all = signature(&excluded[0], type_at(scrutinee, path)).unwrap_or_default();
+            all.into_iter().find(|c| !excluded.contains(c))
+        }
+        None => None,
+    };
+    match constructor {
+        Some(Constructor::True) => Pattern::Value(Value::True),
+        Some(Constructor::False) => Pattern::Value(Value::False),
+        Some(Constructor::Zero) => Pattern::Value(Value::Zero),
+        Some(Constructor::Succ) => Pattern::Succ(Box::new(witness(
+            &extend(path, Access::Pred),
+            constraints,
+            scrutinee,
+        ))),
+        Some(Constructor::Tag(ident)) => {
+            let sub_path = extend(path, Access::Tag(ident.to_string()));
+            Pattern::Tag(ident, Box::new(witness(&sub_path, constraints, scrutinee)))
+        }
+        None => {
+            let mut fields = HashMap::new();
+            for (p, _) in constraints {
+                if p.len() > path.len() && p.starts_with(path) {
+                    if let Access::Field(name) = &p[path.len()] {
+                        let sub_path = extend(path, Access::Field(name.to_string()));
+                        fields.insert(name.to_string(), witness(&sub_path, constraints, scrutinee));
+                    }
+                }
+            }
+            if fields.is_empty() {
+                Pattern::Wildcard
+            } else {
+                Pattern::Record(fields)
+            }
+        }
+    }
+}
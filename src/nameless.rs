@@ -0,0 +1,727 @@
+use ast::*;
+use pest::Position;
+use pest::Span;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+
+/// Core terms in which bound variables are replaced by their De Bruijn index, the number of
+/// binders between the occurrence of a variable and the binder it refers to. Binders keep the name
+/// they had in the source, but only as a hint for converting the term back; it is ignored when
+/// comparing terms with `alpha_eq`.
+///
+/// Patterns bind their variables in the order given by `pattern_vars`, so in the arm of
+/// `case t of {x=a, y=b} => ...` the variable `b` has index 0 and `a` index 1. The bindings of a
+/// `letrec` are all in scope of each of its values and its body, the last binding having index 0.
+#[derive(Debug, Clone)]
+pub enum Term {
+    /// A bound variable, given by its De Bruijn index
+    Var(usize),
+    /// A variable that is not bound in the term
+    Free(String),
+    Abs(String, Option<TypeAssignment>, Box<Term>),
+    App(Box<Term>, Box<Term>),
+    If(Box<Term>, Box<Term>, Box<Term>),
+    Arith(Operator, Box<Term>),
+    IsZero(Box<Term>),
+    Value(Value),
+    Projection(Box<Term>, String),
+    Record(HashMap<String, Term>),
+    Update(Box<Term>, HashMap<String, Term>),
+    Extension(Box<Term>, HashMap<String, Term>),
+    Restriction(Box<Term>, String),
+    Case(Box<Term>, Vec<(Pattern, Term)>),
+    Tag(String, Box<Term>, TypeAssignment),
+    Fix(Box<Term>),
+    Let(Pattern, Box<Term>, Box<Term>),
+    LetRec(Vec<(String, Option<TypeAssignment>, Term)>, Box<Term>),
+}
+
+impl Term {
+    /// Returns the names of the variables that are not bound in the term
+    pub fn free_vars(&self) -> HashSet<String> {
+        let mut names = HashSet::new();
+        self.collect_free(&mut names);
+        names
+    }
+
+    fn collect_free(&self, names: &mut HashSet<String>) {
+        match self {
+            Term::Free(name) => {
+                names.insert(name.to_string());
+            }
+            Term::Var(_) | Term::Value(_) => {}
+            Term::Abs(_, _, body)
+            | Term::Arith(_, body)
+            | Term::IsZero(body)
+            | Term::Projection(body, _)
+            | Term::Restriction(body, _)
+            | Term::Tag(_, body, _)
+            | Term::Fix(body) => body.collect_free(names),
+            Term::App(left, right) | Term::Let(_, left, right) => {
+                left.collect_free(names);
+                right.collect_free(names);
+            }
+            Term::If(clause, then_arm, else_arm) => {
+                clause.collect_free(names);
+                then_arm.collect_free(names);
+                else_arm.collect_free(names);
+            }
+            Term::Record(records) => records.values().for_each(|t| t.collect_free(names)),
+            Term::Update(target, records) | Term::Extension(target, records) => {
+                target.collect_free(names);
+                records.values().for_each(|t| t.collect_free(names));
+            }
+            Term::Case(to_match, cases) => {
+                to_match.collect_free(names);
+                cases.iter().for_each(|(_, arm)| arm.collect_free(names));
+            }
+            Term::LetRec(bindings, body) => {
+                bindings.iter().for_each(|(_, _, t)| t.collect_free(names));
+                body.collect_free(names);
+            }
+        }
+    }
+
+    /// Returns the indices of the variables that refer to binders outside of the term
+    pub fn loose_vars(&self) -> HashSet<usize> {
+        let indices = RefCell::new(HashSet::new());
+        self.map_vars(0, &|index, depth| {
+            if index >= depth {
+                indices.borrow_mut().insert(index - depth);
+            }
+            Term::Var(index)
+        });
+        indices.into_inner()
+    }
+
+    /// Increases the indices of the variables that are not bound in the term, as is needed when
+    /// it is moved below the given number of binders.
+    pub fn shift(&self, by: usize) -> Term {
+        if by == 0 {
+            return self.clone();
+        }
+        self.map_vars(0, &|index, depth| {
+            if index >= depth {
+                Term::Var(index + by)
+            } else {
+                Term::Var(index)
+            }
+        })
+    }
+
+    /// Substitutes the terms for the variables of the binders directly enclosing the term, the
+    /// last term replacing the variable with index 0. The binders themselves are removed, so the
+    /// indices of the other variables that are not bound in the term decrease.
+    ///
+    /// # Arguments
+    /// * `values` - the terms bound to the variables, in the order in which they were bound
+    pub fn instantiate(&self, values: &[Term]) -> Term {
+        let count = values.len();
+        self.map_vars(0, &|index, depth| {
+            if index < depth {
+                Term::Var(index)
+            } else if index < depth + count {
+                values[count - 1 - (index - depth)].shift(depth)
+            } else {
+                Term::Var(index - count)
+            }
+        })
+    }
+
+    /// Replaces the free variables with the given names by the variables of binders placed
+    /// directly around the term, the last name getting index 0. This undoes `instantiate` for
+    /// values that stood in for these binders as free variables.
+    ///
+    /// # Arguments
+    /// * `names` - the names of the free variables, in the order in which they are bound
+    pub fn bind_free(&self, names: &[String]) -> Term {
+        let count = names.len();
+        self.map_leaves(0, &|leaf, depth| match leaf {
+            Term::Var(index) if *index >= depth => Term::Var(index + count),
+            Term::Free(name) => match names.iter().position(|n| n == name) {
+                Some(position) => Term::Var(depth + count - 1 - position),
+                None => leaf.clone(),
+            },
+            _ => leaf.clone(),
+        })
+    }
+
+    /// Rebuilds the term with every variable replaced by the result of the function, which is
+    /// given the index of the variable and the number of binders between it and the root.
+    fn map_vars(&self, depth: usize, f: &dyn Fn(usize, usize) -> Term) -> Term {
+        self.map_leaves(depth, &|leaf, depth| match leaf {
+            Term::Var(index) => f(*index, depth),
+            _ => leaf.clone(),
+        })
+    }
+
+    /// Rebuilds the term with every bound and free variable replaced by the result of the
+    /// function, which is given the variable and the number of binders between it and the root.
+    fn map_leaves(&self, depth: usize, f: &dyn Fn(&Term, usize) -> Term) -> Term {
+        let map = |term: &Term| Box::new(term.map_leaves(depth, f));
+        let map_records = |records: &HashMap<String, Term>| {
+            records
+                .iter()
+                .map(|(name, term)| (name.to_string(), term.map_leaves(depth, f)))
+                .collect()
+        };
+        match self {
+            Term::Var(_) | Term::Free(_) => f(self, depth),
+            Term::Value(_) => self.clone(),
+            Term::Abs(hint, data_type, body) => Term::Abs(
+                hint.to_string(),
+                data_type.clone(),
+                Box::new(body.map_leaves(depth + 1, f)),
+            ),
+            Term::App(left, right) => Term::App(map(left), map(right)),
+            Term::If(clause, then_arm, else_arm) => {
+                Term::If(map(clause), map(then_arm), map(else_arm))
+            }
+            Term::Arith(op, expr) => Term::Arith(op.clone(), map(expr)),
+            Term::IsZero(expr) => Term::IsZero(map(expr)),
+            Term::Projection(target, attrib) => Term::Projection(map(target), attrib.to_string()),
+            Term::Record(records) => Term::Record(map_records(records)),
+            Term::Update(target, records) => Term::Update(map(target), map_records(records)),
+            Term::Extension(target, records) => Term::Extension(map(target), map_records(records)),
+            Term::Restriction(target, attrib) => Term::Restriction(map(target), attrib.to_string()),
+            Term::Case(to_match, cases) => Term::Case(
+                map(to_match),
+                cases
+                    .iter()
+                    .map(|(pattern, arm)| {
+                        let inner = depth + pattern_vars(pattern).len();
+                        (pattern.clone(), arm.map_leaves(inner, f))
+                    })
+                    .collect(),
+            ),
+            Term::Tag(ident, value, data_type) => {
+                Term::Tag(ident.to_string(), map(value), data_type.clone())
+            }
+            Term::Fix(point) => Term::Fix(map(point)),
+            Term::Let(pattern, value, body) => {
+                let inner = depth + pattern_vars(pattern).len();
+                Term::Let(
+                    pattern.clone(),
+                    map(value),
+                    Box::new(body.map_leaves(inner, f)),
+                )
+            }
+            Term::LetRec(bindings, body) => {
+                let inner = depth + bindings.len();
+                Term::LetRec(
+                    bindings
+                        .iter()
+                        .map(|(name, data_type, value)| {
+                            (
+                                name.to_string(),
+                                data_type.clone(),
+                                value.map_leaves(inner, f),
+                            )
+                        })
+                        .collect(),
+                    Box::new(body.map_leaves(inner, f)),
+                )
+            }
+        }
+    }
+}
+
+/// Converts an abstract syntax tree to a nameless term, identifiers that are not bound in the tree
+/// become free variables.
+pub fn to_nameless(node: &ASTNode<'_>) -> Term {
+    convert(node, &mut Vec::new())
+}
+
+/// Converts a nameless term back to an abstract syntax tree. Bound variables are named after the
+/// hints of their binders, which get a number as suffix where they would otherwise capture a
+/// variable that the scope of the binder refers to.
+pub fn from_nameless<'a>(term: &Term) -> ASTNode<'a> {
+    restore(term, &mut Vec::new())
+}
+
+/// Returns whether two terms are equal up to the names of their bound variables
+pub fn alpha_eq(left: &Term, right: &Term) -> bool {
+    match (left, right) {
+        (Term::Var(a), Term::Var(b)) => a == b,
+        (Term::Free(a), Term::Free(b)) => a == b,
+        (Term::Abs(_, a_type, a), Term::Abs(_, b_type, b)) => a_type == b_type && alpha_eq(a, b),
+        (Term::App(a_left, a_right), Term::App(b_left, b_right)) => {
+            alpha_eq(a_left, b_left) && alpha_eq(a_right, b_right)
+        }
+        (Term::If(a_clause, a_then, a_else), Term::If(b_clause, b_then, b_else)) => {
+            alpha_eq(a_clause, b_clause) && alpha_eq(a_then, b_then) && alpha_eq(a_else, b_else)
+        }
+        (Term::Arith(a_op, a), Term::Arith(b_op, b)) => a_op == b_op && alpha_eq(a, b),
+        (Term::IsZero(a), Term::IsZero(b)) | (Term::Fix(a), Term::Fix(b)) => alpha_eq(a, b),
+        (Term::Value(a), Term::Value(b)) => a == b,
+        (Term::Projection(a, a_attrib), Term::Projection(b, b_attrib))
+        | (Term::Restriction(a, a_attrib), Term::Restriction(b, b_attrib)) => {
+            a_attrib == b_attrib && alpha_eq(a, b)
+        }
+        (Term::Record(a), Term::Record(b)) => records_eq(a, b),
+        (Term::Update(a, a_records), Term::Update(b, b_records))
+        | (Term::Extension(a, a_records), Term::Extension(b, b_records)) => {
+            alpha_eq(a, b) && records_eq(a_records, b_records)
+        }
+        (Term::Case(a, a_cases), Term::Case(b, b_cases)) => {
+            alpha_eq(a, b)
+                && a_cases.len() == b_cases.len()
+                && a_cases
+                    .iter()
+                    .zip(b_cases)
+                    .all(|((a_pat, a_arm), (b_pat, b_arm))| {
+                        same_shape(a_pat, b_pat) && alpha_eq(a_arm, b_arm)
+                    })
+        }
+        (Term::Tag(a_tag, a, a_type), Term::Tag(b_tag, b, b_type)) => {
+            a_tag == b_tag && a_type == b_type && alpha_eq(a, b)
+        }
+        (Term::Let(a_pat, a_value, a_body), Term::Let(b_pat, b_value, b_body)) => {
+            same_shape(a_pat, b_pat) && alpha_eq(a_value, b_value) && alpha_eq(a_body, b_body)
+        }
+        (Term::LetRec(a_bindings, a_body), Term::LetRec(b_bindings, b_body)) => {
+            a_bindings.len() == b_bindings.len()
+                && a_bindings
+                    .iter()
+                    .zip(b_bindings)
+                    .all(|((_, a_type, a), (_, b_type, b))| a_type == b_type && alpha_eq(a, b))
+                && alpha_eq(a_body, b_body)
+        }
+        _ => false,
+    }
+}
+
+/// Returns the variables bound by a pattern in the order in which they are bound
+pub fn pattern_vars(pattern: &Pattern) -> Vec<String> {
+    let mut names = Vec::new();
+    collect_pattern_vars(pattern, &mut names);
+    names
+}
+
+fn collect_pattern_vars(pattern: &Pattern, names: &mut Vec<String>) {
+    match pattern {
+        Pattern::Wildcard | Pattern::Value(_) => {}
+        Pattern::Variable(name) => names.push(name.to_string()),
+        Pattern::Succ(inner) | Pattern::Tag(_, inner) => collect_pattern_vars(inner, names),
+        Pattern::Record(fields) => {
+            let mut labels: Vec<&String> = fields.keys().collect();
+            labels.sort();
+            for label in labels {
+                collect_pattern_vars(&fields[label], names);
+            }
+        }
+    }
+}
+
+fn records_eq(left: &HashMap<String, Term>, right: &HashMap<String, Term>) -> bool {
+    left.len() == right.len()
+        && left
+            .iter()
+            .all(|(name, a)| right.get(name).is_some_and(|b| alpha_eq(a, b)))
+}
+
+/// Returns whether two patterns only differ in the names of their variables
+fn same_shape(left: &Pattern, right: &Pattern) -> bool {
+    match (left, right) {
+        (Pattern::Wildcard, Pattern::Wildcard) | (Pattern::Variable(_), Pattern::Variable(_)) => {
+            true
+        }
+        (Pattern::Value(a), Pattern::Value(b)) => a == b,
+        (Pattern::Succ(a), Pattern::Succ(b)) => same_shape(a, b),
+        (Pattern::Tag(a_tag, a), Pattern::Tag(b_tag, b)) => a_tag == b_tag && same_shape(a, b),
+        (Pattern::Record(a), Pattern::Record(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .all(|(name, a)| b.get(name).is_some_and(|b| same_shape(a, b)))
+        }
+        _ => false,
+    }
+}
+
+/// Converts a node in which the names in the context are bound, the innermost binding last
+fn convert(node: &ASTNode<'_>, context: &mut Vec<String>) -> Term {
+    match node {
+        ASTNode::IdentifierNode { meta: _, name } => {
+            match context.iter().rev().position(|bound| bound == name) {
+                Some(index) => Term::Var(index),
+                None => Term::Free(name.to_string()),
+            }
+        }
+        ASTNode::AbstractionNode {
+            meta: _,
+            ident,
+            data_type,
+            body,
+        } => {
+            let body = within(context, vec![ident.to_string()], |context| {
+                convert(body, context)
+            });
+            Term::Abs(ident.to_string(), data_type.clone(), Box::new(body))
+        }
+        ASTNode::ApplicationNode {
+            meta: _,
+            left,
+            right,
+        } => Term::App(
+            Box::new(convert(left, context)),
+            Box::new(convert(right, context)),
+        ),
+        ASTNode::ConditionNode {
+            meta: _,
+            clause,
+            then_arm,
+            else_arm,
+        } => Term::If(
+            Box::new(convert(clause, context)),
+            Box::new(convert(then_arm, context)),
+            Box::new(convert(else_arm, context)),
+        ),
+        ASTNode::ArithmeticNode { meta: _, op, expr } => {
+            Term::Arith(op.clone(), Box::new(convert(expr, context)))
+        }
+        ASTNode::IsZeroNode { meta: _, expr } => Term::IsZero(Box::new(convert(expr, context))),
+        ASTNode::ValueNode { meta: _, value } => Term::Value(value.clone()),
+        ASTNode::ProjectionNode {
+            meta: _,
+            target,
+            attrib,
+        } => Term::Projection(Box::new(convert(target, context)), attrib.to_string()),
+        ASTNode::RecordNode { meta: _, records } => Term::Record(convert_records(records, context)),
+        ASTNode::UpdateNode {
+            meta: _,
+            target,
+            records,
+        } => Term::Update(
+            Box::new(convert(target, context)),
+            convert_records(records, context),
+        ),
+        ASTNode::ExtensionNode {
+            meta: _,
+            target,
+            records,
+        } => Term::Extension(
+            Box::new(convert(target, context)),
+            convert_records(records, context),
+        ),
+        ASTNode::RestrictionNode {
+            meta: _,
+            target,
+            attrib,
+        } => Term::Restriction(Box::new(convert(target, context)), attrib.to_string()),
+        ASTNode::MatchingNode {
+            meta: _,
+            to_match,
+            cases,
+        } => {
+            let to_match = convert(to_match, context);
+            let cases = cases
+                .iter()
+                .map(|(pattern, arm)| {
+                    let arm = within(context, pattern_vars(pattern), |context| {
+                        convert(arm, context)
+                    });
+                    (pattern.clone(), arm)
+                })
+                .collect();
+            Term::Case(Box::new(to_match), cases)
+        }
+        ASTNode::TaggingNode {
+            meta: _,
+            ident,
+            value,
+            data_type,
+        } => Term::Tag(
+            ident.to_string(),
+            Box::new(convert(value, context)),
+            data_type.clone(),
+        ),
+        ASTNode::FixNode { meta: _, point } => Term::Fix(Box::new(convert(point, context))),
+        ASTNode::LetNode {
+            meta: _,
+            pattern,
+            value,
+            body,
+        } => {
+            let value = convert(value, context);
+            let body = within(context, pattern_vars(pattern), |context| {
+                convert(body, context)
+            });
+            Term::Let(pattern.clone(), Box::new(value), Box::new(body))
+        }
+        ASTNode::LetRecNode {
+            meta: _,
+            bindings,
+            body,
+        } => {
+            let names = bindings
+                .iter()
+                .map(|(name, _, _)| name.to_string())
+                .collect();
+            within(context, names, |context| {
+                let bindings = bindings
+                    .iter()
+                    .map(|(name, data_type, value)| {
+                        (name.to_string(), data_type.clone(), convert(value, context))
+                    })
+                    .collect();
+                Term::LetRec(bindings, Box::new(convert(body, context)))
+            })
+        }
+    }
+}
+
+fn convert_records(
+    records: &HashMap<String, ASTNode<'_>>,
+    context: &mut Vec<String>,
+) -> HashMap<String, Term> {
+    records
+        .iter()
+        .map(|(name, node)| (name.to_string(), convert(node, context)))
+        .collect()
+}
+
+/// Runs the given function with the names added to the context and removes them afterwards
+fn within<R, F>(context: &mut Vec<String>, names: Vec<String>, inner: F) -> R
+where
+    F: FnOnce(&mut Vec<String>) -> R,
+{
+    let length = context.len();
+    context.extend(names);
+    let result = inner(context);
+    context.truncate(length);
+    result
+}
+
+/// Span of nodes that are not part of any source text
+pub fn no_span() -> Span<'static> {
+    let start = Position::from_start("");
+    start.clone().span(&start)
+}
+
+/// Returns the names of the variables a term refers to that are bound outside of it, either by a
+/// name in the context or not at all. Binders around the term must not take these names.
+///
+/// # Arguments
+/// * `term` - the scope of the binders
+/// * `binders` - the number of variables bound directly around the term, not yet in the context
+/// * `context` - the names of the enclosing binders
+fn outer_names(term: &Term, binders: usize, context: &[String]) -> HashSet<String> {
+    let mut names = term.free_vars();
+    names.extend(
+        term.loose_vars()
+            .into_iter()
+            .filter(|index| *index >= binders)
+            .map(|index| context[context.len() - 1 - (index - binders)].to_string()),
+    );
+    names
+}
+
+/// Returns names for the variables bound together by a binder. Each keeps its hint unless it would
+/// capture one of the taken names or clash with another variable of the binder, in which case
+/// the hint gets the smallest number as suffix that avoids both.
+///
+/// # Arguments
+/// * `hints` - the names the variables had in the source
+/// * `taken` - the names the scope of the binder refers to, as given by `outer_names`
+fn fresh(hints: &[String], mut taken: HashSet<String>) -> Vec<String> {
+    let mut names = Vec::new();
+    for (position, hint) in hints.iter().enumerate() {
+        let clashes = |name: &String| taken.contains(name) || hints[position + 1..].contains(name);
+        let name = if clashes(hint) {
+            let stem = hint.trim_end_matches(|c: char| c.is_ascii_digit());
+            (1..)
+                .map(|number| format!("{}{}", stem, number))
+                .find(|name| !clashes(name))
+                .expect("Bug in restore: ran out of numbers")
+        } else {
+            hint.to_string()
+        };
+        taken.insert(name.to_string());
+        names.push(name);
+    }
+    names
+}
+
+/// Replaces the variables of a pattern with the given names, in the order given by `pattern_vars`
+fn rename_pattern<I: Iterator<Item = String>>(pattern: &Pattern, names: &mut I) -> Pattern {
+    match pattern {
+        Pattern::Wildcard | Pattern::Value(_) => pattern.clone(),
+        Pattern::Variable(_) => Pattern::Variable(
+            names
+                .next()
+                .expect("Bug in restore: too few names for pattern"),
+        ),
+        Pattern::Succ(inner) => Pattern::Succ(Box::new(rename_pattern(inner, names))),
+        Pattern::Tag(tag, inner) => {
+            Pattern::Tag(tag.to_string(), Box::new(rename_pattern(inner, names)))
+        }
+        Pattern::Record(fields) => {
+            let mut labels: Vec<&String> = fields.keys().collect();
+            labels.sort();
+            let mut renamed = HashMap::new();
+            for label in labels {
+                renamed.insert(label.to_string(), rename_pattern(&fields[label], names));
+            }
+            Pattern::Record(renamed)
+        }
+    }
+}
+
+/// Restores the arm of a case or the body of a let, binding the variables of the pattern
+fn restore_binding<'a>(
+    pattern: &Pattern,
+    scope: &Term,
+    context: &mut Vec<String>,
+) -> (Pattern, ASTNode<'a>) {
+    let hints = pattern_vars(pattern);
+    let names = fresh(&hints, outer_names(scope, hints.len(), context));
+    let pattern = rename_pattern(pattern, &mut names.iter().cloned());
+    let scope = within(context, names, |context| restore(scope, context));
+    (pattern, scope)
+}
+
+/// Converts a term in which the names in the context are bound back to a node
+fn restore<'a>(term: &Term, context: &mut Vec<String>) -> ASTNode<'a> {
+    let meta = no_span();
+    match term {
+        Term::Var(index) => ASTNode::IdentifierNode {
+            meta,
+            name: context[context.len() - 1 - index].to_string(),
+        },
+        Term::Free(name) => ASTNode::IdentifierNode {
+            meta,
+            name: name.to_string(),
+        },
+        Term::Abs(hint, data_type, body) => {
+            let ident = fresh(&[hint.to_string()], outer_names(body, 1, context)).remove(0);
+            let body = within(context, vec![ident.to_string()], |context| {
+                restore(body, context)
+            });
+            ASTNode::AbstractionNode {
+                meta,
+                ident,
+                data_type: data_type.clone(),
+                body: Box::new(body),
+            }
+        }
+        Term::App(left, right) => ASTNode::ApplicationNode {
+            meta,
+            left: Box::new(restore(left, context)),
+            right: Box::new(restore(right, context)),
+        },
+        Term::If(clause, then_arm, else_arm) => ASTNode::ConditionNode {
+            meta,
+            clause: Box::new(restore(clause, context)),
+            then_arm: Box::new(restore(then_arm, context)),
+            else_arm: Box::new(restore(else_arm, context)),
+        },
+        Term::Arith(op, expr) => ASTNode::ArithmeticNode {
+            meta,
+            op: op.clone(),
+            expr: Box::new(restore(expr, context)),
+        },
+        Term::IsZero(expr) => ASTNode::IsZeroNode {
+            meta,
+            expr: Box::new(restore(expr, context)),
+        },
+        Term::Value(value) => ASTNode::ValueNode {
+            meta,
+            value: value.clone(),
+        },
+        Term::Projection(target, attrib) => ASTNode::ProjectionNode {
+            meta,
+            target: Box::new(restore(target, context)),
+            attrib: attrib.to_string(),
+        },
+        Term::Record(records) => ASTNode::RecordNode {
+            meta,
+            records: restore_records(records, context),
+        },
+        Term::Update(target, records) => ASTNode::UpdateNode {
+            meta,
+            target: Box::new(restore(target, context)),
+            records: restore_records(records, context),
+        },
+        Term::Extension(target, records) => ASTNode::ExtensionNode {
+            meta,
+            target: Box::new(restore(target, context)),
+            records: restore_records(records, context),
+        },
+        Term::Restriction(target, attrib) => ASTNode::RestrictionNode {
+            meta,
+            target: Box::new(restore(target, context)),
+            attrib: attrib.to_string(),
+        },
+        Term::Case(to_match, cases) => {
+            let to_match = restore(to_match, context);
+            let cases = cases
+                .iter()
+                .map(|(pattern, arm)| {
+                    let (pattern, arm) = restore_binding(pattern, arm, context);
+                    (pattern, Box::new(arm))
+                })
+                .collect();
+            ASTNode::MatchingNode {
+                meta,
+                to_match: Box::new(to_match),
+                cases,
+            }
+        }
+        Term::Tag(ident, value, data_type) => ASTNode::TaggingNode {
+            meta,
+            ident: ident.to_string(),
+            value: Box::new(restore(value, context)),
+            data_type: data_type.clone(),
+        },
+        Term::Fix(point) => ASTNode::FixNode {
+            meta,
+            point: Box::new(restore(point, context)),
+        },
+        Term::Let(pattern, value, body) => {
+            let value = restore(value, context);
+            let (pattern, body) = restore_binding(pattern, body, context);
+            ASTNode::LetNode {
+                meta,
+                pattern,
+                value: Box::new(value),
+                body: Box::new(body),
+            }
+        }
+        Term::LetRec(bindings, body) => {
+            let hints: Vec<String> = bindings
+                .iter()
+                .map(|(hint, _, _)| hint.to_string())
+                .collect();
+            let mut taken = outer_names(body, hints.len(), context);
+            for (_, _, value) in bindings {
+                taken.extend(outer_names(value, hints.len(), context));
+            }
+            let names = fresh(&hints, taken);
+            let (bindings, body) = within(context, names.clone(), |context| {
+                let bindings = bindings
+                    .iter()
+                    .zip(names)
+                    .map(|((_, data_type, value), name)| {
+                        (name, data_type.clone(), restore(value, context))
+                    })
+                    .collect();
+                (bindings, restore(body, context))
+            });
+            ASTNode::LetRecNode {
+                meta,
+                bindings,
+                body: Box::new(body),
+            }
+        }
+    }
+}
+
+fn restore_records<'a>(
+    records: &HashMap<String, Term>,
+    context: &mut Vec<String>,
+) -> HashMap<String, ASTNode<'a>> {
+    records
+        .iter()
+        .map(|(name, term)| (name.to_string(), restore(term, context)))
+        .collect()
+}
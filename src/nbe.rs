@@ -0,0 +1,594 @@
+use ast::*;
+use eval::EvalConfig;
+use nameless::*;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+/// Number of times recursive functions are unfolded when the configuration sets no limit
+const UNFOLDINGS: usize = 10_000;
+
+/// Number of unfoldings that may be nested in each other when the configuration sets no limit
+const NESTING: usize = 200;
+
+/// Environment of a semantic value, the last value belongs to the innermost binder
+type Env = Rc<Vec<Sem>>;
+
+/// Bindings of a single letrec
+type Bindings = Vec<(String, Option<TypeAssignment>, Term)>;
+
+/// Group of recursive bindings introduced by a single letrec
+type Group = Rc<Bindings>;
+
+/// Semantic values that terms are evaluated into during normalization. Computations that can not
+/// continue because they depend on a variable without a value are kept as neutral values.
+#[derive(Clone, Debug)]
+enum Sem {
+    Closure(Rc<Closure>),
+    Fix(Box<Sem>),
+    Rec(Env, Group, usize),
+    Bool(bool),
+    Zero,
+    Succ(Box<Sem>),
+    Record(HashMap<String, Sem>),
+    Tag(String, Box<Sem>, TypeAssignment),
+    Neutral(Box<Neutral>),
+}
+
+/// Body of an abstraction together with the environment it was created in
+#[derive(Debug)]
+struct Closure {
+    hint: String,
+    data_type: Option<TypeAssignment>,
+    env: Env,
+    body: Term,
+}
+
+/// Computations that are stuck on a variable. Variables are counted by De Bruijn levels, so they
+/// do not have to be shifted when the value moves under another binder. The arms of conditionals
+/// and cases are only evaluated when they are read back.
+#[derive(Clone, Debug)]
+enum Neutral {
+    Var(usize),
+    Free(String),
+    Apply(Sem, Sem),
+    If(Sem, Env, Term, Term),
+    Pred(Sem),
+    IsZero(Sem),
+    Projection(Sem, String),
+    Restriction(Sem, String),
+    Update(Sem, HashMap<String, Sem>),
+    Extension(Sem, HashMap<String, Sem>),
+    Case(Sem, Env, Vec<(Pattern, Term)>),
+}
+
+/// Outcome of matching a semantic value against a pattern
+enum Match {
+    Yes(Vec<Sem>),
+    No,
+    Unknown,
+}
+
+/// Recursive group that does not depend on unknown values. It is read back once, as a letrec
+/// around the normal form, and its uses refer to it by the placeholder names of its bindings.
+struct Hoisted {
+    names: Vec<String>,
+    bindings: Bindings,
+}
+
+/// Identifies a recursive group evaluated in a particular environment by the addresses of both.
+/// It keeps them alive, so the addresses are not reused while it exists.
+struct GroupKey(Group, Env);
+
+impl PartialEq for GroupKey {
+    fn eq(&self, other: &GroupKey) -> bool {
+        Rc::ptr_eq(&self.0, &other.0) && Rc::ptr_eq(&self.1, &other.1)
+    }
+}
+
+impl Eq for GroupKey {}
+
+impl Hash for GroupKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Rc::as_ptr(&self.0).hash(state);
+        Rc::as_ptr(&self.1).hash(state);
+    }
+}
+
+/// State of a normalization
+struct Normalizer {
+    /// Number of recursive functions that may still be unfolded
+    steps: Cell<usize>,
+    /// Number of unfoldings that may still be nested in the current one
+    depth: Cell<usize>,
+    /// Placeholder names of the bindings of the hoisted groups
+    groups: RefCell<HashMap<GroupKey, Vec<String>>>,
+    /// Groups that have been read back, each after the groups it refers to
+    hoisted: RefCell<Vec<Hoisted>>,
+}
+
+/// Reduces a term to its beta-normal form, also reducing under abstractions, by evaluating it into
+/// semantic values and reading those back as a term. Recursive functions are unfolded as often
+/// as the default limits allow, see `normalize_with`.
+///
+/// # Panics
+/// When the term contains variables that are not bound within it, free variables should be given
+/// as `Term::Free`
+pub fn normalize(term: &Term) -> Term {
+    normalize_with(term, EvalConfig::default())
+}
+
+/// Reduces a term to its beta-normal form, unfolding recursive functions at most `max_steps`
+/// times and at most `max_depth` unfoldings deep. Without limits in the configuration, 10000
+/// unfoldings nested at most 200 deep are allowed.
+///
+/// A recursive function is only unfolded when it is applied to all of its arguments, and only
+/// when that decides the conditional or case its body starts with. Otherwise the call is kept as
+/// it is, as are the calls that exceed the limits, so the normal form is always finite. Recursive
+/// definitions that do not depend on a variable without a value are bound once around the normal
+/// form instead of at every use.
+///
+/// # Arguments
+/// * `term` - the term to normalize
+/// * `config` - the limits on unfolding recursive functions
+///
+/// # Panics
+/// When the term contains variables that are not bound within it, free variables should be given
+/// as `Term::Free`
+pub fn normalize_with(term: &Term, config: EvalConfig) -> Term {
+    let normalizer = Normalizer {
+        steps: Cell::new(config.max_steps.unwrap_or(UNFOLDINGS)),
+        depth: Cell::new(config.max_depth.unwrap_or(NESTING)),
+        groups: RefCell::new(HashMap::new()),
+        hoisted: RefCell::new(Vec::new()),
+    };
+    let value = normalizer.evaluate(term, &Rc::new(Vec::new()));
+    let body = normalizer.read_back(&value, 0);
+    normalizer.bind_hoisted(body)
+}
+
+/// Returns the environment with the values appended as the innermost bindings
+fn extend(env: &Env, values: Vec<Sem>) -> Env {
+    let mut env = (**env).clone();
+    env.extend(values);
+    Rc::new(env)
+}
+
+fn neutral(neutral: Neutral) -> Sem {
+    Sem::Neutral(Box::new(neutral))
+}
+
+/// Returns a value for every member of a recursive group, in the order of the bindings
+fn members(env: &Env, group: &Group) -> Vec<Sem> {
+    (0..group.len())
+        .map(|index| Sem::Rec(env.clone(), group.clone(), index))
+        .collect()
+}
+
+/// Splits an application into the function that is applied and its arguments
+fn spine(function: &Sem) -> (&Sem, Vec<Sem>) {
+    if let Sem::Neutral(stuck) = function {
+        if let Neutral::Apply(left, right) = &**stuck {
+            let (head, mut arguments) = spine(left);
+            arguments.push(right.clone());
+            return (head, arguments);
+        }
+    }
+    (function, Vec::new())
+}
+
+/// Returns the names and types of the arguments a recursive function takes before its body
+fn parameters(function: &Sem) -> Vec<(String, Option<TypeAssignment>)> {
+    let mut term = match function {
+        Sem::Fix(point) => match &**point {
+            Sem::Closure(closure) => &closure.body,
+            _ => return Vec::new(),
+        },
+        Sem::Rec(_, group, index) => &group[*index].2,
+        _ => return Vec::new(),
+    };
+    let mut parameters = Vec::new();
+    while let Term::Abs(hint, data_type, body) = term {
+        parameters.push((hint.to_string(), data_type.clone()));
+        term = body;
+    }
+    parameters
+}
+
+impl Normalizer {
+    fn evaluate(&self, term: &Term, env: &Env) -> Sem {
+        match term {
+            Term::Var(index) => env[env.len() - 1 - index].clone(),
+            Term::Free(name) => neutral(Neutral::Free(name.to_string())),
+            Term::Abs(hint, data_type, body) => Sem::Closure(Rc::new(Closure {
+                hint: hint.to_string(),
+                data_type: data_type.clone(),
+                env: env.clone(),
+                body: (**body).clone(),
+            })),
+            Term::App(left, right) => {
+                self.apply(self.evaluate(left, env), self.evaluate(right, env))
+            }
+            Term::If(clause, then_arm, else_arm) => match self.evaluate(clause, env) {
+                Sem::Bool(true) => self.evaluate(then_arm, env),
+                Sem::Bool(false) => self.evaluate(else_arm, env),
+                clause => neutral(Neutral::If(
+                    clause,
+                    env.clone(),
+                    (**then_arm).clone(),
+                    (**else_arm).clone(),
+                )),
+            },
+            Term::Arith(Operator::Succ, expr) => Sem::Succ(Box::new(self.evaluate(expr, env))),
+            Term::Arith(Operator::Pred, expr) => match self.evaluate(expr, env) {
+                Sem::Zero => Sem::Zero,
+                Sem::Succ(inner) => *inner,
+                expr => neutral(Neutral::Pred(expr)),
+            },
+            Term::IsZero(expr) => match self.evaluate(expr, env) {
+                Sem::Zero => Sem::Bool(true),
+                Sem::Succ(_) => Sem::Bool(false),
+                expr => neutral(Neutral::IsZero(expr)),
+            },
+            Term::Value(Value::True) => Sem::Bool(true),
+            Term::Value(Value::False) => Sem::Bool(false),
+            Term::Value(Value::Zero) => Sem::Zero,
+            Term::Projection(target, attrib) => project(self.evaluate(target, env), attrib),
+            Term::Record(records) => Sem::Record(self.evaluate_records(records, env)),
+            Term::Update(target, records) | Term::Extension(target, records) => {
+                let records = self.evaluate_records(records, env);
+                match self.evaluate(target, env) {
+                    Sem::Record(mut fields) => {
+                        fields.extend(records);
+                        Sem::Record(fields)
+                    }
+                    target => match term {
+                        Term::Update(_, _) => neutral(Neutral::Update(target, records)),
+                        _ => neutral(Neutral::Extension(target, records)),
+                    },
+                }
+            }
+            Term::Restriction(target, attrib) => match self.evaluate(target, env) {
+                Sem::Record(mut fields) => {
+                    fields.remove(attrib);
+                    Sem::Record(fields)
+                }
+                target => neutral(Neutral::Restriction(target, attrib.to_string())),
+            },
+            Term::Case(to_match, cases) => self.select(self.evaluate(to_match, env), env, cases),
+            Term::Tag(ident, value, data_type) => Sem::Tag(
+                ident.to_string(),
+                Box::new(self.evaluate(value, env)),
+                data_type.clone(),
+            ),
+            Term::Fix(point) => Sem::Fix(Box::new(self.evaluate(point, env))),
+            // Patterns of a let are irrefutable, only a variant of an unknown value can not be
+            // matched yet, in which case the let is kept as a case with a single arm
+            Term::Let(pattern, value, body) => self.select(
+                self.evaluate(value, env),
+                env,
+                &[(pattern.clone(), (**body).clone())],
+            ),
+            Term::LetRec(bindings, body) => {
+                let group = Rc::new(bindings.clone());
+                self.evaluate(body, &extend(env, members(env, &group)))
+            }
+        }
+    }
+
+    fn evaluate_records(&self, records: &HashMap<String, Term>, env: &Env) -> HashMap<String, Sem> {
+        records
+            .iter()
+            .map(|(name, term)| (name.to_string(), self.evaluate(term, env)))
+            .collect()
+    }
+
+    fn apply(&self, function: Sem, argument: Sem) -> Sem {
+        if let Sem::Closure(closure) = &function {
+            return self.evaluate(&closure.body, &extend(&closure.env, vec![argument]));
+        }
+        let (head, mut arguments) = spine(&function);
+        arguments.push(argument.clone());
+        self.unfold(head, &arguments)
+            .unwrap_or_else(|| neutral(Neutral::Apply(function, argument)))
+    }
+
+    /// Unfolds a recursive function that is applied to all of its arguments. Returns `None` when
+    /// the limits do not allow another unfolding, or when the body of the function gets stuck on
+    /// its first conditional or case. Unfolding the calls in the arms of that decision again
+    /// could go on forever.
+    fn unfold(&self, function: &Sem, arguments: &[Sem]) -> Option<Sem> {
+        let count = parameters(function).len();
+        if count == 0 || count != arguments.len() || self.steps.get() == 0 || self.depth.get() == 0
+        {
+            return None;
+        }
+        self.steps.set(self.steps.get() - 1);
+        self.depth.set(self.depth.get() - 1);
+        let mut value = match function {
+            Sem::Fix(point) => self.apply((**point).clone(), function.clone()),
+            Sem::Rec(env, group, index) => {
+                self.evaluate(&group[*index].2, &extend(env, members(env, group)))
+            }
+            _ => panic!("Bug in normalizer: unfolded a value that is not recursive"),
+        };
+        for argument in arguments {
+            value = self.apply(value, argument.clone());
+        }
+        self.depth.set(self.depth.get() + 1);
+        if let Sem::Neutral(stuck) = &value {
+            if let Neutral::If(_, _, _, _) | Neutral::Case(_, _, _) = **stuck {
+                return None;
+            }
+        }
+        Some(value)
+    }
+
+    /// Evaluates the arm of the first matching case, or keeps the case as a neutral value when it
+    /// is not yet known which case matches
+    fn select(&self, to_match: Sem, env: &Env, cases: &[(Pattern, Term)]) -> Sem {
+        for (pattern, arm) in cases {
+            match matches(pattern, &to_match) {
+                Match::Yes(values) => return self.evaluate(arm, &extend(env, values)),
+                Match::No => continue,
+                Match::Unknown => break,
+            }
+        }
+        neutral(Neutral::Case(to_match, env.clone(), cases.to_vec()))
+    }
+
+    /// Turns a semantic value back into a term, `level` is the number of binders the term is
+    /// placed under
+    fn read_back(&self, value: &Sem, level: usize) -> Term {
+        match value {
+            Sem::Closure(closure) => {
+                let env = extend(&closure.env, vec![neutral(Neutral::Var(level))]);
+                Term::Abs(
+                    closure.hint.to_string(),
+                    closure.data_type.clone(),
+                    Box::new(self.read_back(&self.evaluate(&closure.body, &env), level + 1)),
+                )
+            }
+            Sem::Fix(point) => Term::Fix(Box::new(self.read_back(point, level))),
+            Sem::Rec(env, group, index) if is_known_group(env, group) => {
+                Term::Free(self.hoist(env, group)[*index].to_string())
+            }
+            Sem::Rec(env, group, index) => {
+                let count = group.len();
+                let vars = (level..level + count)
+                    .map(|var| neutral(Neutral::Var(var)))
+                    .collect();
+                let env = extend(env, vars);
+                let bindings = group
+                    .iter()
+                    .map(|(name, data_type, value)| {
+                        let value = self.read_back(&self.evaluate(value, &env), level + count);
+                        (name.to_string(), data_type.clone(), value)
+                    })
+                    .collect();
+                Term::LetRec(bindings, Box::new(Term::Var(count - 1 - index)))
+            }
+            Sem::Bool(true) => Term::Value(Value::True),
+            Sem::Bool(false) => Term::Value(Value::False),
+            Sem::Zero => Term::Value(Value::Zero),
+            Sem::Succ(value) => Term::Arith(Operator::Succ, Box::new(self.read_back(value, level))),
+            Sem::Record(fields) => Term::Record(self.read_back_records(fields, level)),
+            Sem::Tag(ident, value, data_type) => Term::Tag(
+                ident.to_string(),
+                Box::new(self.read_back(value, level)),
+                data_type.clone(),
+            ),
+            Sem::Neutral(stuck) => self.read_back_neutral(stuck, level),
+        }
+    }
+
+    fn read_back_neutral(&self, stuck: &Neutral, level: usize) -> Term {
+        let back = |value: &Sem| Box::new(self.read_back(value, level));
+        match stuck {
+            Neutral::Var(var) => Term::Var(level - 1 - var),
+            Neutral::Free(name) => Term::Free(name.to_string()),
+            Neutral::Apply(function, argument) => self
+                .expand(stuck, level)
+                .unwrap_or_else(|| Term::App(back(function), back(argument))),
+            Neutral::If(clause, env, then_arm, else_arm) => Term::If(
+                back(clause),
+                back(&self.evaluate(then_arm, env)),
+                back(&self.evaluate(else_arm, env)),
+            ),
+            Neutral::Pred(expr) => Term::Arith(Operator::Pred, back(expr)),
+            Neutral::IsZero(expr) => Term::IsZero(back(expr)),
+            Neutral::Projection(target, attrib) => {
+                Term::Projection(back(target), attrib.to_string())
+            }
+            Neutral::Restriction(target, attrib) => {
+                Term::Restriction(back(target), attrib.to_string())
+            }
+            Neutral::Update(target, records) => {
+                Term::Update(back(target), self.read_back_records(records, level))
+            }
+            Neutral::Extension(target, records) => {
+                Term::Extension(back(target), self.read_back_records(records, level))
+            }
+            Neutral::Case(to_match, env, cases) => {
+                let cases = cases
+                    .iter()
+                    .map(|(pattern, arm)| {
+                        let count = pattern_vars(pattern).len();
+                        let vars = (level..level + count)
+                            .map(|var| neutral(Neutral::Var(var)))
+                            .collect();
+                        let arm = self.evaluate(arm, &extend(env, vars));
+                        (pattern.clone(), self.read_back(&arm, level + count))
+                    })
+                    .collect();
+                Term::Case(back(to_match), cases)
+            }
+        }
+    }
+
+    /// Reads back a recursive function applied to only some of its arguments as an abstraction
+    /// over the others, if applying it to all of them unfolds it
+    fn expand(&self, application: &Neutral, level: usize) -> Option<Term> {
+        let application = neutral(application.clone());
+        let (function, mut arguments) = spine(&application);
+        let parameters = parameters(function);
+        let missing = &parameters[arguments.len().min(parameters.len())..];
+        if missing.is_empty() {
+            return None;
+        }
+        arguments.extend((level..level + missing.len()).map(|var| neutral(Neutral::Var(var))));
+        let body = self.unfold(function, &arguments)?;
+        let body = self.read_back(&body, level + missing.len());
+        Some(missing.iter().rev().fold(body, |body, (hint, data_type)| {
+            Term::Abs(hint.to_string(), data_type.clone(), Box::new(body))
+        }))
+    }
+
+    fn read_back_records(
+        &self,
+        records: &HashMap<String, Sem>,
+        level: usize,
+    ) -> HashMap<String, Term> {
+        records
+            .iter()
+            .map(|(name, value)| (name.to_string(), self.read_back(value, level)))
+            .collect()
+    }
+
+    /// Reads back the bindings of a recursive group that does not depend on unknown values, the
+    /// first time it is used, and returns the placeholder names of its bindings
+    fn hoist(&self, env: &Env, group: &Group) -> Vec<String> {
+        let key = GroupKey(group.clone(), env.clone());
+        if let Some(names) = self.groups.borrow().get(&key) {
+            return names.clone();
+        }
+        let number = self.groups.borrow().len();
+        let names: Vec<String> = group
+            .iter()
+            .map(|(name, _, _)| format!("{}#{}", name, number))
+            .collect();
+        self.groups.borrow_mut().insert(key, names.clone());
+        // The members refer to each other by their placeholders as well
+        let env = extend(env, members(env, group));
+        let bindings = group
+            .iter()
+            .map(|(name, data_type, value)| {
+                let value = self.read_back(&self.evaluate(value, &env), 0);
+                (name.to_string(), data_type.clone(), value)
+            })
+            .collect();
+        self.hoisted.borrow_mut().push(Hoisted {
+            names: names.clone(),
+            bindings,
+        });
+        names
+    }
+
+    /// Binds the hoisted groups around the normal form, each inside the groups it refers to
+    fn bind_hoisted(self, body: Term) -> Term {
+        let hoisted = self.hoisted.into_inner();
+        let names: Vec<String> = hoisted
+            .iter()
+            .flat_map(|group| group.names.iter().cloned())
+            .collect();
+        let mut bound = names.len();
+        let mut term = body.bind_free(&names);
+        for group in hoisted.iter().rev() {
+            let bindings = group
+                .bindings
+                .iter()
+                .map(|(name, data_type, value)| {
+                    (
+                        name.to_string(),
+                        data_type.clone(),
+                        value.bind_free(&names[..bound]),
+                    )
+                })
+                .collect();
+            bound -= group.names.len();
+            term = Term::LetRec(bindings, Box::new(term));
+        }
+        term
+    }
+}
+
+fn project(target: Sem, attrib: &str) -> Sem {
+    match target {
+        Sem::Record(mut fields) => fields
+            .remove(attrib)
+            .unwrap_or_else(|| panic!("Record does not have a field {}", attrib)),
+        Sem::Neutral(stuck) => match *stuck {
+            Neutral::Update(_, ref records) | Neutral::Extension(_, ref records)
+                if records.contains_key(attrib) =>
+            {
+                records[attrib].clone()
+            }
+            stuck => neutral(Neutral::Projection(
+                Sem::Neutral(Box::new(stuck)),
+                attrib.to_string(),
+            )),
+        },
+        target => neutral(Neutral::Projection(target, attrib.to_string())),
+    }
+}
+
+/// Matches a semantic value against a pattern, the values are given in the order of
+/// `pattern_vars`. Records patterns of unknown records are matched by projecting the fields.
+fn matches(pattern: &Pattern, value: &Sem) -> Match {
+    match (pattern, value) {
+        (Pattern::Wildcard, _) => Match::Yes(Vec::new()),
+        (Pattern::Variable(_), _) => Match::Yes(vec![value.clone()]),
+        (Pattern::Record(fields), _) => {
+            let mut names: Vec<&String> = fields.keys().collect();
+            names.sort();
+            let mut values = Vec::new();
+            for name in names {
+                match matches(&fields[name], &project(value.clone(), name)) {
+                    Match::Yes(found) => values.extend(found),
+                    other => return other,
+                }
+            }
+            Match::Yes(values)
+        }
+        (_, Sem::Neutral(_)) | (_, Sem::Fix(_)) | (_, Sem::Rec(_, _, _)) => Match::Unknown,
+        (Pattern::Value(Value::True), Sem::Bool(true))
+        | (Pattern::Value(Value::False), Sem::Bool(false))
+        | (Pattern::Value(Value::Zero), Sem::Zero) => Match::Yes(Vec::new()),
+        (Pattern::Succ(inner), Sem::Succ(value)) => matches(inner, value),
+        (Pattern::Tag(tag, inner), Sem::Tag(ident, value, _)) if tag == ident => {
+            matches(inner, value)
+        }
+        _ => Match::No,
+    }
+}
+
+/// Returns whether a value does not depend on any variable without a value, only then can a
+/// recursive group referring to it be bound around the whole normal form
+fn is_known(value: &Sem) -> bool {
+    match value {
+        Sem::Closure(closure) => closure
+            .body
+            .loose_vars()
+            .into_iter()
+            .filter(|index| *index > 0)
+            .all(|index| is_known(&closure.env[closure.env.len() - index])),
+        Sem::Fix(point) => is_known(point),
+        Sem::Rec(env, group, _) => is_known_group(env, group),
+        Sem::Bool(_) | Sem::Zero => true,
+        Sem::Succ(value) | Sem::Tag(_, value, _) => is_known(value),
+        Sem::Record(fields) => fields.values().all(is_known),
+        Sem::Neutral(_) => false,
+    }
+}
+
+fn is_known_group(env: &Env, group: &Group) -> bool {
+    let count = group.len();
+    group.iter().all(|(_, _, value)| {
+        value
+            .loose_vars()
+            .into_iter()
+            .filter(|index| *index >= count)
+            .all(|index| is_known(&env[env.len() - 1 - (index - count)]))
+    })
+}
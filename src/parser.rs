@@ -1,11 +1,43 @@
 use pest::iterators::Pairs;
 use pest::Error;
 use pest::Parser;
+use std::collections::HashSet;
 
 #[derive(Parser)]
 #[grammar = "grammar.pest"]
 pub struct LambdaParser;
 
-pub fn parse_file(contents: &str) -> Result<Pairs<Rule>, Error<Rule>> {
-    LambdaParser::parse(Rule::program, contents)
+/// Parses a program and checks that no record pattern matches a field twice, which the syntax
+/// tree can not tell because it keeps the fields of a pattern in a map
+pub fn parse_file(contents: &str) -> Result<Pairs<'_, Rule>, Error<'_, Rule>> {
+    let pairs = LambdaParser::parse(Rule::program, contents)?;
+    distinct_pattern_fields(pairs.clone())?;
+    Ok(pairs)
+}
+
+/// Returns an error pointing at the first field that occurs twice in a record pattern
+fn distinct_pattern_fields(pairs: Pairs<'_, Rule>) -> Result<(), Error<'_, Rule>> {
+    for pair in pairs {
+        if pair.as_rule() == Rule::pat_record {
+            let mut names = HashSet::new();
+            for field in pair.clone().into_inner() {
+                let name = field
+                    .into_inner()
+                    .next()
+                    .expect("Bug in parser: got a record pattern element without a name")
+                    .into_span();
+                if !names.insert(name.as_str()) {
+                    return Err(Error::CustomErrorSpan {
+                        message: format!(
+                            "Field {} is matched more than once in a record pattern",
+                            name.as_str()
+                        ),
+                        span: name,
+                    });
+                }
+            }
+        }
+        distinct_pattern_fields(pair.into_inner())?;
+    }
+    Ok(())
 }
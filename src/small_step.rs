@@ -0,0 +1,226 @@
+use ast::*;
+use nameless::*;
+use std::collections::HashMap;
+
+/// Performs a single step of call-by-value reduction on a nameless term, following the evaluation
+/// rules of the language. The names of the rules are given in the comments.
+///
+/// Returns `None` when the term is a value, or when it is stuck, which can not happen to closed
+/// terms that passed the typechecker.
+pub fn step(term: &Term) -> Option<Term> {
+    match term {
+        Term::App(left, right) => {
+            if !is_value(left) {
+                // E-App1
+                Some(Term::App(Box::new(step(left)?), right.clone()))
+            } else if !is_value(right) {
+                // E-App2
+                Some(Term::App(left.clone(), Box::new(step(right)?)))
+            } else if let Term::Abs(_, _, body) = &**left {
+                // E-AppAbs
+                Some(body.instantiate(&[(**right).clone()]))
+            } else {
+                None
+            }
+        }
+        Term::If(clause, then_arm, else_arm) => match **clause {
+            // E-IfTrue
+            Term::Value(Value::True) => Some((**then_arm).clone()),
+            // E-IfFalse
+            Term::Value(Value::False) => Some((**else_arm).clone()),
+            // E-If
+            _ => Some(Term::If(
+                Box::new(step(clause)?),
+                then_arm.clone(),
+                else_arm.clone(),
+            )),
+        },
+        // E-Succ
+        Term::Arith(Operator::Succ, expr) => {
+            Some(Term::Arith(Operator::Succ, Box::new(step(expr)?)))
+        }
+        Term::Arith(Operator::Pred, expr) => match &**expr {
+            // E-PredZero
+            Term::Value(Value::Zero) => Some(Term::Value(Value::Zero)),
+            // E-PredSucc
+            Term::Arith(Operator::Succ, inner) if is_value(inner) => Some((**inner).clone()),
+            // E-Pred
+            _ => Some(Term::Arith(Operator::Pred, Box::new(step(expr)?))),
+        },
+        Term::IsZero(expr) => match &**expr {
+            // E-IsZeroZero
+            Term::Value(Value::Zero) => Some(Term::Value(Value::True)),
+            // E-IsZeroSucc
+            Term::Arith(Operator::Succ, inner) if is_value(inner) => {
+                Some(Term::Value(Value::False))
+            }
+            // E-IsZero
+            _ => Some(Term::IsZero(Box::new(step(expr)?))),
+        },
+        Term::Projection(target, attrib) => match &**target {
+            // E-ProjRcd
+            Term::Record(records) if is_value(target) => records.get(attrib).cloned(),
+            // E-Proj
+            _ => Some(Term::Projection(
+                Box::new(step(target)?),
+                attrib.to_string(),
+            )),
+        },
+        // E-Rcd
+        Term::Record(records) => Some(Term::Record(step_records(records)?)),
+        Term::Update(target, records) | Term::Extension(target, records) => {
+            let rebuild = |target: Box<Term>, records: HashMap<String, Term>| match term {
+                Term::Update(_, _) => Term::Update(target, records),
+                _ => Term::Extension(target, records),
+            };
+            if !is_value(target) {
+                // E-Update / E-Extend
+                Some(rebuild(Box::new(step(target)?), records.clone()))
+            } else if !records.values().all(is_value) {
+                // E-Update / E-Extend
+                Some(rebuild(target.clone(), step_records(records)?))
+            } else if let Term::Record(fields) = &**target {
+                // E-UpdateRcd / E-ExtendRcd
+                let mut fields = fields.clone();
+                fields.extend(records.clone());
+                Some(Term::Record(fields))
+            } else {
+                None
+            }
+        }
+        Term::Restriction(target, attrib) => match &**target {
+            // E-RestrictRcd
+            Term::Record(records) if is_value(target) => {
+                let mut records = records.clone();
+                records.remove(attrib);
+                Some(Term::Record(records))
+            }
+            // E-Restrict
+            _ => Some(Term::Restriction(
+                Box::new(step(target)?),
+                attrib.to_string(),
+            )),
+        },
+        // E-Variant
+        Term::Tag(ident, value, data_type) => Some(Term::Tag(
+            ident.to_string(),
+            Box::new(step(value)?),
+            data_type.clone(),
+        )),
+        Term::Case(to_match, cases) => {
+            if !is_value(to_match) {
+                // E-Case
+                Some(Term::Case(Box::new(step(to_match)?), cases.clone()))
+            } else {
+                // E-CaseMatch
+                cases.iter().find_map(|(pattern, arm)| {
+                    let mut values = Vec::new();
+                    if matches(pattern, to_match, &mut values) {
+                        Some(arm.instantiate(&values))
+                    } else {
+                        None
+                    }
+                })
+            }
+        }
+        Term::Let(pattern, value, body) => {
+            if !is_value(value) {
+                // E-Let
+                Some(Term::Let(
+                    pattern.clone(),
+                    Box::new(step(value)?),
+                    body.clone(),
+                ))
+            } else {
+                // E-LetV
+                let mut values = Vec::new();
+                if matches(pattern, value, &mut values) {
+                    Some(body.instantiate(&values))
+                } else {
+                    None
+                }
+            }
+        }
+        Term::Fix(point) => match &**point {
+            // E-FixBeta
+            Term::Abs(_, _, body) => Some(body.instantiate(std::slice::from_ref(term))),
+            // E-Fix
+            _ => Some(Term::Fix(Box::new(step(point)?))),
+        },
+        // E-LetRec
+        Term::LetRec(bindings, body) => Some(body.instantiate(&unfold(bindings))),
+        Term::Var(_) | Term::Free(_) | Term::Abs(_, _, _) | Term::Value(_) => None,
+    }
+}
+
+/// Reduces the term step by step until it is a value and returns that value. Terms that do not
+/// terminate make this function loop forever.
+pub fn normalize(term: &Term) -> Term {
+    let mut current = term.clone();
+    while let Some(next) = step(&current) {
+        current = next;
+    }
+    current
+}
+
+/// Returns whether the term is a value, a term that can not be reduced any further
+pub fn is_value(term: &Term) -> bool {
+    match term {
+        Term::Abs(_, _, _) | Term::Value(_) => true,
+        Term::Arith(Operator::Succ, expr) => is_value(expr),
+        Term::Record(records) => records.values().all(is_value),
+        Term::Tag(_, value, _) => is_value(value),
+        _ => false,
+    }
+}
+
+/// Steps the first field of the record, in alphabetical order, that is not yet a value
+fn step_records(records: &HashMap<String, Term>) -> Option<HashMap<String, Term>> {
+    let mut names: Vec<&String> = records.keys().collect();
+    names.sort();
+    let name = names.into_iter().find(|name| !is_value(&records[*name]))?;
+    let mut records = records.clone();
+    let next = step(&records[name])?;
+    records.insert(name.to_string(), next);
+    Some(records)
+}
+
+/// Returns the functions of a group of recursive bindings, in which every reference to one of the
+/// bindings is replaced by a copy of the group selecting that binding.
+fn unfold(bindings: &[(String, Option<TypeAssignment>, Term)]) -> Vec<Term> {
+    let count = bindings.len();
+    let selections: Vec<Term> = (0..count)
+        .map(|position| Term::LetRec(bindings.to_vec(), Box::new(Term::Var(count - 1 - position))))
+        .collect();
+    bindings
+        .iter()
+        .map(|(_, _, value)| value.instantiate(&selections))
+        .collect()
+}
+
+/// Matches a value against a pattern, collecting the components bound by its variables in the
+/// order given by `pattern_vars`.
+fn matches(pattern: &Pattern, value: &Term, values: &mut Vec<Term>) -> bool {
+    match (pattern, value) {
+        (Pattern::Wildcard, _) => true,
+        (Pattern::Variable(_), _) => {
+            values.push(value.clone());
+            true
+        }
+        (Pattern::Value(expected), Term::Value(actual)) => expected == actual,
+        (Pattern::Succ(inner), Term::Arith(Operator::Succ, expr)) => matches(inner, expr, values),
+        (Pattern::Tag(tag, inner), Term::Tag(ident, expr, _)) => {
+            tag == ident && matches(inner, expr, values)
+        }
+        (Pattern::Record(fields), Term::Record(records)) => {
+            let mut names: Vec<&String> = fields.keys().collect();
+            names.sort();
+            names.into_iter().all(|name| {
+                records
+                    .get(name)
+                    .is_some_and(|field| matches(&fields[name], field, values))
+            })
+        }
+        _ => false,
+    }
+}
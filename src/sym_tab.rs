@@ -1,8 +1,17 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
 
-#[derive(Clone, Debug, PartialEq)]
+/// Stack of scopes in which names are looked up. The scopes are shared between copies of the
+/// table, so cloning it (e.g. to capture it in a closure) does not copy any of its scopes.
+#[derive(Debug, PartialEq)]
 pub struct SymbolTable<T> {
-    table: Vec<Scope<T>>,
+    top: Option<Rc<Frame<T>>>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Frame<T> {
+    scope: Scope<T>,
+    parent: Option<Rc<Frame<T>>>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -12,30 +21,132 @@ pub struct Scope<T> {
 
 impl<T> SymbolTable<T> {
     pub fn new() -> SymbolTable<T> {
-        SymbolTable { table: Vec::new() }
+        SymbolTable { top: None }
     }
 
     pub fn lookup(&self, name: &str) -> Option<&T> {
-        for scope in self.table.iter().rev() {
-            if let Some(data_type) = scope.get(name) {
-                return Some(data_type);
+        self.frames().find_map(|frame| frame.scope.get(name))
+    }
+
+    /// Returns the table as it was right after the scope in which the name is bound was pushed
+    pub fn up_to(&self, name: &str) -> SymbolTable<T> {
+        let mut current = &self.top;
+        while let Some(frame) = current {
+            if frame.scope.get(name).is_some() {
+                break;
             }
+            current = &frame.parent;
         }
-        None
+        SymbolTable {
+            top: current.clone(),
+        }
+    }
+
+    /// Returns the scope that was pushed last, `None` when the table has no scopes
+    pub fn innermost(&self) -> Option<&Scope<T>> {
+        self.top.as_ref().map(|frame| &frame.scope)
     }
 
     pub fn push(&mut self, scope: Scope<T>) {
-        self.table.push(scope);
+        let parent = self.top.take();
+        self.top = Some(Rc::new(Frame { scope, parent }));
+    }
+
+    /// Removes the innermost scope, restoring the table to how it was before that scope was pushed
+    ///
+    /// # Panics
+    /// Throws a panic when the table has no scopes.
+    pub fn pop(&mut self) {
+        let frame = self
+            .top
+            .take()
+            .expect("Popped a scope of an empty symbol table");
+        self.top = frame.parent.clone();
+    }
+
+    /// Runs the given function with the scope pushed onto the table and removes the scope again
+    /// afterwards, so bindings can not leak out of the term they are bound in.
+    ///
+    /// # Arguments
+    /// * `scope` - the bindings that are visible while the function runs
+    /// * `inner` - the function, it is handed the table with the scope pushed
+    pub fn with_scope<R, F>(&mut self, scope: Scope<T>, inner: F) -> R
+    where
+        F: FnOnce(&mut SymbolTable<T>) -> R,
+    {
+        self.push(scope);
+        let result = inner(self);
+        self.pop();
+        result
+    }
+
+    /// Returns the bindings that are visible, from the innermost scope outwards, leaving out the
+    /// ones that are shadowed. The bindings of a single scope are sorted by name.
+    pub fn visible(&self) -> Vec<(&str, &T)> {
+        let mut seen = HashSet::new();
+        let mut bindings = Vec::new();
+        for frame in self.frames() {
+            let mut names: Vec<&String> = frame.scope.map.keys().collect();
+            names.sort();
+            for name in names {
+                if seen.insert(name) {
+                    bindings.push((name.as_str(), &frame.scope.map[name]));
+                }
+            }
+        }
+        bindings
+    }
+
+    fn frames(&self) -> Frames<'_, T> {
+        Frames {
+            current: self.top.as_deref(),
+        }
     }
+}
+
+impl<T> Clone for SymbolTable<T> {
+    fn clone(&self) -> SymbolTable<T> {
+        SymbolTable {
+            top: self.top.clone(),
+        }
+    }
+}
 
-    pub fn remove(&mut self, name: &str) {
-        let to_remove = self.table.iter().position(|el| el.get(name).is_some());
-        if let Some(to_remove) = to_remove {
-            self.table.remove(to_remove);
+impl<T> Default for SymbolTable<T> {
+    fn default() -> SymbolTable<T> {
+        SymbolTable::new()
+    }
+}
+
+impl<T> Drop for SymbolTable<T> {
+    /// Releases the frames only this table refers to one after the other, so dropping a deep table
+    /// does not recurse once per scope.
+    fn drop(&mut self) {
+        let mut current = self.top.take();
+        while let Some(frame) = current {
+            current = match Rc::try_unwrap(frame) {
+                Ok(mut frame) => frame.parent.take(),
+                Err(_) => None,
+            };
         }
     }
 }
 
+/// Iterator over the frames of a table, from the innermost scope outwards
+struct Frames<'t, T: 't> {
+    current: Option<&'t Frame<T>>,
+}
+
+impl<'t, T> Iterator for Frames<'t, T> {
+    type Item = &'t Frame<T>;
+
+    fn next(&mut self) -> Option<&'t Frame<T>> {
+        let frame = self.current?;
+        self.current = frame.parent.as_deref();
+        Some(frame)
+    }
+}
+
 impl<T> Scope<T> {
     pub fn new(name: String, contents: T) -> Scope<T> {
         let mut map = HashMap::new();
@@ -43,7 +154,18 @@ impl<T> Scope<T> {
         Scope { map }
     }
 
+    pub fn from_map(map: HashMap<String, T>) -> Scope<T> {
+        Scope { map }
+    }
+
     pub fn get(&self, name: &str) -> Option<&T> {
         self.map.get(name)
     }
+
+    /// Returns the names bound in the scope, sorted
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.map.keys().cloned().collect();
+        names.sort();
+        names
+    }
 }
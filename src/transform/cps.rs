@@ -0,0 +1,1098 @@
+use ast::{self, *};
+use infer::Typing;
+use matching::{self, Access, Constructor, Decision, Path};
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::rc::Rc;
+
+/// Operand that needs no evaluation, a variable or a literal
+#[derive(Debug, Clone, PartialEq)]
+pub enum Atom {
+    Var(String),
+    Value(ast::Value),
+}
+
+/// Continuation a value is passed to
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cont {
+    /// Continuation bound by a `letcont` or received by a function
+    Var(String),
+    /// Ends the program with the value it receives
+    Halt,
+}
+
+/// Computation of a single step whose result is bound by a `let`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Primitive {
+    Succ(Atom),
+    Pred(Atom),
+    IsZero(Atom),
+    Function(Box<Function>),
+    /// Record with its fields sorted by name
+    Record(Vec<(String, Atom)>),
+    Project(Atom, String),
+    Update(Atom, Vec<(String, Atom)>),
+    Extend(Atom, Vec<(String, Atom)>),
+    Restrict(Atom, String),
+    Tag(String, Atom),
+    /// The value carried by a variant that is known to have the tag
+    Untag(Atom, String),
+}
+
+/// Function receiving its argument and the continuation its result is passed to
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub param: String,
+    pub param_type: TypeAssignment,
+    pub cont: String,
+    pub result_type: TypeAssignment,
+    pub body: Term,
+}
+
+/// Term in continuation-passing style. Every term ends by calling a function or passing a value
+/// to a continuation, so no term returns and evaluation never needs a stack.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    Let {
+        name: String,
+        data_type: TypeAssignment,
+        value: Primitive,
+        body: Box<Term>,
+    },
+    /// Binds a continuation taking a value of the given type
+    LetCont {
+        name: String,
+        param: String,
+        data_type: TypeAssignment,
+        body: Box<Term>,
+        rest: Box<Term>,
+    },
+    /// Binds mutually recursive functions
+    LetRec {
+        functions: Vec<(String, Function)>,
+        body: Box<Term>,
+    },
+    Apply {
+        function: Atom,
+        arg: Atom,
+        cont: Cont,
+    },
+    /// Applies the function to its own fixed point, which is unfolded whenever it is read
+    Fix {
+        function: Atom,
+        cont: Cont,
+    },
+    Continue {
+        cont: Cont,
+        arg: Atom,
+    },
+    If {
+        clause: Atom,
+        then_arm: Box<Term>,
+        else_arm: Box<Term>,
+    },
+    /// Continues with the case for the head constructor of the value
+    Switch {
+        scrutinee: Atom,
+        cases: Vec<(Constructor, Term)>,
+        default: Option<Box<Term>>,
+    },
+    /// No arm of a case expression matches, which the typechecker rules out
+    Fail,
+}
+
+/// Converts a typechecked abstract syntax tree into continuation-passing style. Continuations
+/// that are known while converting are applied right away instead of being bound, so the result
+/// contains no administrative redexes, and only the join points of conditionals and case
+/// expressions get a `letcont`. Bound variables are renamed apart, with a suffix like `_2` when
+/// their name was already used, and every binding is annotated with its inferred type.
+///
+/// # Panics
+/// Throws a panic when the tree is not well typed
+pub fn convert(node: &ASTNode<'_>) -> Term {
+    let typing = node
+        .infer_types::<i32>()
+        .expect("Only typechecked programs can be converted to continuation-passing style");
+    let mut converter = Converter {
+        typing: &typing,
+        counts: HashMap::new(),
+        temps: 0,
+    };
+    converter.convert(node, &Env::default(), Next::Dynamic(Cont::Halt))
+}
+
+/// Continuation of the conversion of a node. A static continuation builds the rest of the term
+/// from the atom standing for the value of the node, a dynamic one is the name of a continuation
+/// in the converted term.
+enum Next<'n> {
+    Static(Resume<'n, Atom>),
+    Dynamic(Cont),
+}
+
+/// Builds the rest of a term once the atoms for the converted nodes are known
+type Resume<'n, T> = Box<dyn FnOnce(&mut Converter<'n>, T) -> Term + 'n>;
+
+/// Atoms the variables in scope of the source program stand for
+#[derive(Clone, Default)]
+struct Env(Option<Rc<(String, Atom, Env)>>);
+
+impl Env {
+    fn bind(&self, name: &str, atom: Atom) -> Env {
+        Env(Some(Rc::new((name.to_string(), atom, self.clone()))))
+    }
+
+    fn lookup(&self, name: &str) -> Atom {
+        let mut env = self;
+        while let Some(binding) = &env.0 {
+            if binding.0 == name {
+                return binding.1.clone();
+            }
+            env = &binding.2;
+        }
+        panic!("Bug in typechecker: came across unknown variable")
+    }
+}
+
+struct Converter<'n> {
+    typing: &'n Typing<'n>,
+    /// How often every name of the source program was bound
+    counts: HashMap<String, usize>,
+    temps: usize,
+}
+
+impl<'n> Converter<'n> {
+    fn type_of(&self, node: &ASTNode<'n>) -> TypeAssignment {
+        self.typing.type_of(node).clone()
+    }
+
+    /// Returns the name for a variable bound by the source program, unique in the converted term
+    fn rename(&mut self, name: &str) -> String {
+        let count = self.counts.entry(name.to_string()).or_insert(0);
+        *count += 1;
+        if *count == 1 {
+            name.to_string()
+        } else {
+            format!("{}_{}", name, count)
+        }
+    }
+
+    /// Creates a name for a temporary, which ends in digits and so never clashes with the
+    /// names of the source program
+    fn fresh(&mut self, prefix: &str) -> String {
+        self.temps += 1;
+        format!("{}{}", prefix, self.temps)
+    }
+
+    /// Passes the atom to the continuation
+    fn resume(&mut self, next: Next<'n>, atom: Atom) -> Term {
+        match next {
+            Next::Static(build) => build(self, atom),
+            Next::Dynamic(cont) => Term::Continue { cont, arg: atom },
+        }
+    }
+
+    /// Builds a term that refers to the continuation by name, binding it with a `letcont` when it
+    /// is static
+    fn reify<F>(&mut self, next: Next<'n>, data_type: TypeAssignment, build: F) -> Term
+    where
+        F: FnOnce(&mut Self, Cont) -> Term,
+    {
+        match next {
+            Next::Dynamic(cont) => build(self, cont),
+            Next::Static(resume) => {
+                let name = self.fresh("k");
+                let param = self.fresh("x");
+                let body = resume(self, Atom::Var(param.clone()));
+                let rest = build(self, Cont::Var(name.clone()));
+                Term::LetCont {
+                    name,
+                    param,
+                    data_type,
+                    body: Box::new(body),
+                    rest: Box::new(rest),
+                }
+            }
+        }
+    }
+
+    /// Binds the result of the primitive to a temporary that is passed to the continuation
+    fn bind(&mut self, data_type: TypeAssignment, value: Primitive, next: Next<'n>) -> Term {
+        let name = self.fresh("t");
+        let body = self.resume(next, Atom::Var(name.clone()));
+        Term::Let {
+            name,
+            data_type,
+            value,
+            body: Box::new(body),
+        }
+    }
+
+    fn convert(&mut self, node: &'n ASTNode<'n>, env: &Env, next: Next<'n>) -> Term {
+        let data_type = self.type_of(node);
+        match node {
+            ASTNode::ValueNode { value, .. } => self.resume(next, Atom::Value(value.clone())),
+            ASTNode::IdentifierNode { name, .. } => self.resume(next, env.lookup(name)),
+            ASTNode::AbstractionNode { .. } => {
+                let function = self.function(node, env);
+                self.bind(data_type, Primitive::Function(Box::new(function)), next)
+            }
+            ASTNode::ApplicationNode { left, right, .. } => {
+                let env = env.clone();
+                self.convert(
+                    left,
+                    &env.clone(),
+                    Next::Static(Box::new(move |c, function| {
+                        c.convert(
+                            right,
+                            &env,
+                            Next::Static(Box::new(move |c, arg| {
+                                c.reify(next, data_type, |_, cont| Term::Apply {
+                                    function,
+                                    arg,
+                                    cont,
+                                })
+                            })),
+                        )
+                    })),
+                )
+            }
+            ASTNode::ConditionNode {
+                clause,
+                then_arm,
+                else_arm,
+                ..
+            } => {
+                let env = env.clone();
+                self.convert(
+                    clause,
+                    &env.clone(),
+                    Next::Static(Box::new(move |c, clause| {
+                        c.reify(next, data_type, |c, cont| Term::If {
+                            clause,
+                            then_arm: Box::new(c.convert(
+                                then_arm,
+                                &env,
+                                Next::Dynamic(cont.clone()),
+                            )),
+                            else_arm: Box::new(c.convert(else_arm, &env, Next::Dynamic(cont))),
+                        })
+                    })),
+                )
+            }
+            ASTNode::ArithmeticNode { op, expr, .. } => {
+                let op = op.clone();
+                self.convert(
+                    expr,
+                    env,
+                    Next::Static(Box::new(move |c, atom| {
+                        let value = match op {
+                            Operator::Succ => Primitive::Succ(atom),
+                            Operator::Pred => Primitive::Pred(atom),
+                        };
+                        c.bind(data_type, value, next)
+                    })),
+                )
+            }
+            ASTNode::IsZeroNode { expr, .. } => self.convert(
+                expr,
+                env,
+                Next::Static(Box::new(move |c, atom| {
+                    c.bind(data_type, Primitive::IsZero(atom), next)
+                })),
+            ),
+            ASTNode::ProjectionNode { target, attrib, .. } => self.convert(
+                target,
+                env,
+                Next::Static(Box::new(move |c, atom| {
+                    c.bind(
+                        data_type,
+                        Primitive::Project(atom, attrib.to_string()),
+                        next,
+                    )
+                })),
+            ),
+            ASTNode::RestrictionNode { target, attrib, .. } => self.convert(
+                target,
+                env,
+                Next::Static(Box::new(move |c, atom| {
+                    c.bind(
+                        data_type,
+                        Primitive::Restrict(atom, attrib.to_string()),
+                        next,
+                    )
+                })),
+            ),
+            ASTNode::RecordNode { records, .. } => self.fields(
+                records,
+                env,
+                Box::new(move |c, fields| c.bind(data_type, Primitive::Record(fields), next)),
+            ),
+            ASTNode::UpdateNode {
+                target, records, ..
+            }
+            | ASTNode::ExtensionNode {
+                target, records, ..
+            } => {
+                let env = env.clone();
+                let update = matches!(node, ASTNode::UpdateNode { .. });
+                self.convert(
+                    target,
+                    &env.clone(),
+                    Next::Static(Box::new(move |c, target| {
+                        c.fields(
+                            records,
+                            &env,
+                            Box::new(move |c, fields| {
+                                let value = if update {
+                                    Primitive::Update(target, fields)
+                                } else {
+                                    Primitive::Extend(target, fields)
+                                };
+                                c.bind(data_type, value, next)
+                            }),
+                        )
+                    })),
+                )
+            }
+            ASTNode::TaggingNode { ident, value, .. } => self.convert(
+                value,
+                env,
+                Next::Static(Box::new(move |c, atom| {
+                    c.bind(data_type, Primitive::Tag(ident.to_string(), atom), next)
+                })),
+            ),
+            ASTNode::MatchingNode {
+                to_match, cases, ..
+            } => {
+                let env = env.clone();
+                let scrutinee_type = self.type_of(to_match);
+                self.convert(
+                    to_match,
+                    &env.clone(),
+                    Next::Static(Box::new(move |c, scrutinee| {
+                        let patterns = cases.iter().map(|(pattern, _)| pattern).collect();
+                        let arms = cases.iter().map(|(_, arm)| &**arm).collect();
+                        let matched = (scrutinee, scrutinee_type);
+                        c.matching(matched, patterns, arms, &env, data_type, next)
+                    })),
+                )
+            }
+            ASTNode::LetNode {
+                pattern,
+                value,
+                body,
+                ..
+            } => {
+                let env = env.clone();
+                let value_type = self.type_of(value);
+                self.convert(
+                    value,
+                    &env.clone(),
+                    Next::Static(Box::new(move |c, atom| {
+                        let matched = (atom, value_type);
+                        c.matching(matched, vec![pattern], vec![body], &env, data_type, next)
+                    })),
+                )
+            }
+            ASTNode::LetRecNode { bindings, body, .. } => {
+                let mut scope = env.clone();
+                let mut names = Vec::new();
+                for (name, _, _) in bindings {
+                    let renamed = self.rename(name);
+                    scope = scope.bind(name, Atom::Var(renamed.clone()));
+                    names.push(renamed);
+                }
+                let functions = names
+                    .into_iter()
+                    .zip(bindings)
+                    .map(|(name, (_, _, value))| (name, self.function(value, &scope)))
+                    .collect();
+                Term::LetRec {
+                    functions,
+                    body: Box::new(self.convert(body, &scope, next)),
+                }
+            }
+            ASTNode::FixNode { point, .. } => {
+                // The fixed point of a function building a function is a recursive function
+                if let ASTNode::AbstractionNode { ident, body, .. } = &**point {
+                    if let ASTNode::AbstractionNode { .. } = **body {
+                        let name = self.rename(ident);
+                        let scope = env.bind(ident, Atom::Var(name.clone()));
+                        let function = self.function(body, &scope);
+                        return Term::LetRec {
+                            functions: vec![(name.clone(), function)],
+                            body: Box::new(self.resume(next, Atom::Var(name))),
+                        };
+                    }
+                }
+                self.convert(
+                    point,
+                    env,
+                    Next::Static(Box::new(move |c, function| {
+                        c.reify(next, data_type, |_, cont| Term::Fix { function, cont })
+                    })),
+                )
+            }
+        }
+    }
+
+    /// Converts an abstraction into a function whose body passes its result to the continuation
+    /// the function receives
+    fn function(&mut self, node: &'n ASTNode<'n>, env: &Env) -> Function {
+        match (node, self.type_of(node)) {
+            (ASTNode::AbstractionNode { ident, body, .. }, TypeAssignment::Arrow(from, to)) => {
+                let param = self.rename(ident);
+                let cont = self.fresh("k");
+                let scope = env.bind(ident, Atom::Var(param.clone()));
+                let body = self.convert(body, &scope, Next::Dynamic(Cont::Var(cont.clone())));
+                Function {
+                    param,
+                    param_type: *from,
+                    cont,
+                    result_type: *to,
+                    body,
+                }
+            }
+            _ => panic!("Bug in typechecker: recursive binding is not a function"),
+        }
+    }
+
+    /// Converts the fields of a record from left to right in the order of their names
+    fn fields(
+        &mut self,
+        records: &'n HashMap<String, ASTNode<'n>>,
+        env: &Env,
+        done: Resume<'n, Vec<(String, Atom)>>,
+    ) -> Term {
+        let mut names: Vec<&String> = records.keys().collect();
+        names.sort();
+        self.sequence(
+            names
+                .into_iter()
+                .map(|name| (name.to_string(), &records[name]))
+                .collect(),
+            Vec::new(),
+            env,
+            done,
+        )
+    }
+
+    fn sequence(
+        &mut self,
+        mut nodes: Vec<(String, &'n ASTNode<'n>)>,
+        mut atoms: Vec<(String, Atom)>,
+        env: &Env,
+        done: Resume<'n, Vec<(String, Atom)>>,
+    ) -> Term {
+        if nodes.is_empty() {
+            return done(self, atoms);
+        }
+        let (name, node) = nodes.remove(0);
+        let scope = env.clone();
+        self.convert(
+            node,
+            env,
+            Next::Static(Box::new(move |c, atom| {
+                atoms.push((name, atom));
+                c.sequence(nodes, atoms, &scope, done)
+            })),
+        )
+    }
+
+    /// Converts a case expression, or a let which is a case with a single arm, into tests on the
+    /// components of the matched value. The continuation is only bound when more than one arm can
+    /// be reached.
+    fn matching(
+        &mut self,
+        (scrutinee, scrutinee_type): (Atom, TypeAssignment),
+        patterns: Vec<&'n Pattern>,
+        arms: Vec<&'n ASTNode<'n>>,
+        env: &Env,
+        data_type: TypeAssignment,
+        next: Next<'n>,
+    ) -> Term {
+        let tree = matching::compile(patterns, Some(&scrutinee_type));
+        let known = vec![(Vec::new(), scrutinee, scrutinee_type)];
+        if leaves(&tree) == 1 {
+            self.decide(&tree, known, &arms, env, &mut Some(next))
+        } else {
+            self.reify(next, data_type, |c, cont| {
+                c.decide(&tree, known, &arms, env, &mut Some(Next::Dynamic(cont)))
+            })
+        }
+    }
+
+    /// Converts a decision tree. The atoms standing for components of the matched value are
+    /// known for the paths that were accessed on the way to the current node.
+    fn decide(
+        &mut self,
+        tree: &Decision,
+        mut known: Vec<(Path, Atom, TypeAssignment)>,
+        arms: &[&'n ASTNode<'n>],
+        env: &Env,
+        next: &mut Option<Next<'n>>,
+    ) -> Term {
+        let mut lets = Vec::new();
+        let term = match tree {
+            Decision::Fail => Term::Fail,
+            Decision::Leaf { arm, bindings } => {
+                let mut bindings = bindings.clone();
+                bindings.sort_by(|(first, _), (second, _)| first.cmp(second));
+                let mut scope = env.clone();
+                for (name, path) in &bindings {
+                    let atom = self.access(path, &mut known, &mut lets);
+                    scope = scope.bind(name, atom);
+                }
+                let next = match next {
+                    Some(Next::Dynamic(cont)) => Next::Dynamic(cont.clone()),
+                    _ => next
+                        .take()
+                        .expect("Bug in pattern matching: single arm is reached twice"),
+                };
+                self.convert(arms[*arm], &scope, next)
+            }
+            Decision::Switch {
+                path,
+                cases,
+                default,
+            } => {
+                let scrutinee = self.access(path, &mut known, &mut lets);
+                let cases = cases
+                    .iter()
+                    .map(|(constructor, tree)| {
+                        let case = self.decide(tree, known.clone(), arms, env, next);
+                        (constructor.clone(), case)
+                    })
+                    .collect();
+                let default = default
+                    .as_ref()
+                    .map(|tree| Box::new(self.decide(tree, known.clone(), arms, env, next)));
+                Term::Switch {
+                    scrutinee,
+                    cases,
+                    default,
+                }
+            }
+        };
+        lets.into_iter()
+            .rev()
+            .fold(term, |body, (name, data_type, value)| Term::Let {
+                name,
+                data_type,
+                value,
+                body: Box::new(body),
+            })
+    }
+
+    /// Returns the atom for the component at the path, adding the bindings that access the
+    /// components which are not known yet
+    fn access(
+        &mut self,
+        path: &[Access],
+        known: &mut Vec<(Path, Atom, TypeAssignment)>,
+        lets: &mut Vec<(String, TypeAssignment, Primitive)>,
+    ) -> Atom {
+        if let Some((_, atom, _)) = known.iter().find(|(known, _, _)| known[..] == path[..]) {
+            return atom.clone();
+        }
+        let (last, prefix) = path
+            .split_last()
+            .expect("Bug in pattern matching: matched value is not known");
+        let parent = self.access(prefix, known, lets);
+        let parent_type = known
+            .iter()
+            .find(|(known, _, _)| known[..] == prefix[..])
+            .map(|(_, _, data_type)| data_type.clone())
+            .unwrap();
+        let (value, data_type) = match (last, parent_type) {
+            (Access::Field(name), TypeAssignment::Record(fields, _)) => (
+                Primitive::Project(parent, name.to_string()),
+                fields[name].clone(),
+            ),
+            (Access::Tag(tag), TypeAssignment::Variant(tags, _)) => {
+                (Primitive::Untag(parent, tag.to_string()), tags[tag].clone())
+            }
+            (Access::Pred, _) => (Primitive::Pred(parent), TypeAssignment::Single(Type::Nat)),
+            _ => panic!("Bug in typechecker: pattern does not match the type of the value"),
+        };
+        let name = self.fresh("t");
+        lets.push((name.clone(), data_type.clone(), value));
+        known.push((path.to_vec(), Atom::Var(name.clone()), data_type));
+        Atom::Var(name)
+    }
+}
+
+/// Counts the arms a decision tree selects, an arm selected at different leaves counts once per
+/// leaf
+fn leaves(tree: &Decision) -> usize {
+    match tree {
+        Decision::Fail => 0,
+        Decision::Leaf { .. } => 1,
+        Decision::Switch { cases, default, .. } => {
+            cases.iter().map(|(_, tree)| leaves(tree)).sum::<usize>()
+                + default.as_ref().map_or(0, |tree| leaves(tree))
+        }
+    }
+}
+
+impl Display for Atom {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Atom::Var(name) => write!(f, "{}", name),
+            Atom::Value(ast::Value::True) => write!(f, "true"),
+            Atom::Value(ast::Value::False) => write!(f, "false"),
+            Atom::Value(ast::Value::Zero) => write!(f, "0"),
+        }
+    }
+}
+
+impl Display for Cont {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Cont::Var(name) => write!(f, "{}", name),
+            Cont::Halt => write!(f, "halt"),
+        }
+    }
+}
+
+/// Writes fields separated by commas, like `a=x, b=0`
+fn write_fields(f: &mut Formatter, fields: &[(String, Atom)]) -> fmt::Result {
+    for (index, (name, atom)) in fields.iter().enumerate() {
+        if index > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}={}", name, atom)?;
+    }
+    Ok(())
+}
+
+impl Display for Term {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        self.write_term(f, 0)
+    }
+}
+
+impl Term {
+    /// Writes the term with every line indented by the given number of spaces. Bindings are
+    /// written one per line, the terms they scope over follow on the next lines.
+    fn write_term(&self, f: &mut Formatter, indent: usize) -> fmt::Result {
+        let pad = " ".repeat(indent);
+        match self {
+            Term::Let {
+                name,
+                data_type,
+                value,
+                body,
+            } => {
+                write!(f, "{}let {}: {} = ", pad, name, data_type)?;
+                match value {
+                    Primitive::Function(function) => {
+                        function.write_function(f, indent)?;
+                        write!(f, "\n{}in\n", pad)?;
+                    }
+                    Primitive::Succ(atom) => writeln!(f, "succ {} in", atom)?,
+                    Primitive::Pred(atom) => writeln!(f, "pred {} in", atom)?,
+                    Primitive::IsZero(atom) => writeln!(f, "iszero {} in", atom)?,
+                    Primitive::Record(fields) => {
+                        write!(f, "{{")?;
+                        write_fields(f, fields)?;
+                        writeln!(f, "}} in")?;
+                    }
+                    Primitive::Project(atom, attrib) => writeln!(f, "{}.{} in", atom, attrib)?,
+                    Primitive::Update(atom, fields) => {
+                        write!(f, "{{{} with ", atom)?;
+                        write_fields(f, fields)?;
+                        writeln!(f, "}} in")?;
+                    }
+                    Primitive::Extend(atom, fields) => {
+                        write!(f, "{{")?;
+                        write_fields(f, fields)?;
+                        writeln!(f, " | {}}} in", atom)?;
+                    }
+                    Primitive::Restrict(atom, attrib) => writeln!(f, "{}\\{} in", atom, attrib)?,
+                    Primitive::Tag(tag, atom) => writeln!(f, "<{}={}> in", tag, atom)?,
+                    Primitive::Untag(atom, tag) => writeln!(f, "untag {} <{}> in", atom, tag)?,
+                }
+                body.write_term(f, indent)
+            }
+            Term::LetCont {
+                name,
+                param,
+                data_type,
+                body,
+                rest,
+            } => {
+                writeln!(f, "{}letcont {} ({}: {}) =", pad, name, param, data_type)?;
+                body.write_term(f, indent + 2)?;
+                write!(f, "\n{}in\n", pad)?;
+                rest.write_term(f, indent)
+            }
+            Term::LetRec { functions, body } => {
+                writeln!(f, "{}letrec", pad)?;
+                for (name, function) in functions {
+                    write!(f, "{}  {} = ", pad, name)?;
+                    function.write_function(f, indent + 2)?;
+                    writeln!(f)?;
+                }
+                writeln!(f, "{}in", pad)?;
+                body.write_term(f, indent)
+            }
+            Term::Apply {
+                function,
+                arg,
+                cont,
+            } => write!(f, "{}{} {} {}", pad, function, arg, cont),
+            Term::Fix { function, cont } => write!(f, "{}fix {} {}", pad, function, cont),
+            Term::Continue { cont, arg } => write!(f, "{}{} {}", pad, cont, arg),
+            Term::If {
+                clause,
+                then_arm,
+                else_arm,
+            } => {
+                writeln!(f, "{}if {} then", pad, clause)?;
+                then_arm.write_term(f, indent + 2)?;
+                writeln!(f, "\n{}else", pad)?;
+                else_arm.write_term(f, indent + 2)
+            }
+            Term::Switch {
+                scrutinee,
+                cases,
+                default,
+            } => {
+                write!(f, "{}case {} of", pad, scrutinee)?;
+                for (constructor, case) in cases {
+                    let constructor = match constructor {
+                        Constructor::True => "true".to_string(),
+                        Constructor::False => "false".to_string(),
+                        Constructor::Zero => "0".to_string(),
+                        Constructor::Succ => "succ".to_string(),
+                        Constructor::Tag(tag) => format!("<{}>", tag),
+                    };
+                    writeln!(f, "\n{}| {} ->", pad, constructor)?;
+                    case.write_term(f, indent + 2)?;
+                }
+                if let Some(default) = default {
+                    writeln!(f, "\n{}| _ ->", pad)?;
+                    default.write_term(f, indent + 2)?;
+                }
+                Ok(())
+            }
+            Term::Fail => write!(f, "{}fail", pad),
+        }
+    }
+}
+
+impl Function {
+    /// Writes the function starting on the current line, its body is indented below it
+    fn write_function(&self, f: &mut Formatter, indent: usize) -> fmt::Result {
+        writeln!(
+            f,
+            "fun ({}: {}) ({}: cont {}) =",
+            self.param, self.param_type, self.cont, self.result_type
+        )?;
+        self.body.write_term(f, indent + 2)
+    }
+}
+
+/// Values computed by a term in continuation-passing style
+#[derive(Clone)]
+pub enum Value<'t> {
+    Nat(u64),
+    Bool(bool),
+    Func(Rc<Closure<'t>>),
+    /// Record with its fields sorted by name
+    Record(Rc<Vec<(String, Value<'t>)>>),
+    Variant(String, Rc<Value<'t>>),
+}
+
+/// A function together with the variables it captured
+pub struct Closure<'t> {
+    function: &'t Function,
+    env: Scope<'t>,
+}
+
+/// What a variable of a running term is bound to
+#[derive(Clone)]
+enum Slot<'t> {
+    Value(Value<'t>),
+    /// Continuation bound by a `letcont`, or `None` for the one ending the program
+    Cont(Option<Rc<Continuation<'t>>>),
+    /// Fixed point of the function, which is computed again whenever the variable is read
+    Fix(Value<'t>),
+    /// Member of a group of recursive functions bound in the scope
+    Rec(&'t [(String, Function)], Scope<'t>, usize),
+}
+
+struct Continuation<'t> {
+    param: &'t str,
+    body: &'t Term,
+    env: Scope<'t>,
+}
+
+/// Variables of a running term
+#[derive(Clone, Default)]
+struct Scope<'t>(Option<Rc<(&'t str, Slot<'t>, Scope<'t>)>>);
+
+impl<'t> Scope<'t> {
+    fn bind(&self, name: &'t str, slot: Slot<'t>) -> Scope<'t> {
+        Scope(Some(Rc::new((name, slot, self.clone()))))
+    }
+
+    fn lookup(&self, name: &str) -> &Slot<'t> {
+        let mut env = self;
+        while let Some(binding) = &env.0 {
+            if binding.0 == name {
+                return &binding.1;
+            }
+            env = &binding.2;
+        }
+        panic!(
+            "Term in continuation-passing style refers to unknown variable {}",
+            name
+        )
+    }
+}
+
+/// Runs a term in continuation-passing style and returns the value passed to `halt`. Terms never
+/// return, so the term runs in a loop and deep recursion only grows the continuations on the
+/// heap.
+///
+/// # Panics
+/// Throws a panic when the term is not well typed or fails to match a value
+pub fn run(term: &Term) -> Value<'_> {
+    execute(term, Scope::default())
+}
+
+fn execute<'t>(mut term: &'t Term, mut env: Scope<'t>) -> Value<'t> {
+    loop {
+        match term {
+            Term::Let {
+                name, value, body, ..
+            } => {
+                let value = primitive(value, &env);
+                env = env.bind(name, Slot::Value(value));
+                term = body;
+            }
+            Term::LetCont {
+                name,
+                param,
+                body,
+                rest,
+                ..
+            } => {
+                let continuation = Continuation {
+                    param,
+                    body,
+                    env: env.clone(),
+                };
+                env = env.bind(name, Slot::Cont(Some(Rc::new(continuation))));
+                term = rest;
+            }
+            Term::LetRec { functions, body } => {
+                let group = env.clone();
+                for (index, (name, _)) in functions.iter().enumerate() {
+                    env = env.bind(name, Slot::Rec(functions, group.clone(), index));
+                }
+                term = body;
+            }
+            Term::Apply {
+                function,
+                arg,
+                cont,
+            } => {
+                let arg = Slot::Value(value(arg, &env));
+                let cont = continuation(cont, &env);
+                let (body, scope) = enter(value(function, &env), arg, cont);
+                term = body;
+                env = scope;
+            }
+            Term::Fix { function, cont } => {
+                let function = value(function, &env);
+                let cont = continuation(cont, &env);
+                let (body, scope) = enter(function.clone(), Slot::Fix(function), cont);
+                term = body;
+                env = scope;
+            }
+            Term::Continue { cont, arg } => {
+                let arg = value(arg, &env);
+                match continuation(cont, &env) {
+                    Some(cont) => {
+                        env = cont.env.bind(cont.param, Slot::Value(arg));
+                        term = cont.body;
+                    }
+                    None => return arg,
+                }
+            }
+            Term::If {
+                clause,
+                then_arm,
+                else_arm,
+            } => {
+                term = match value(clause, &env) {
+                    Value::Bool(true) => then_arm,
+                    Value::Bool(false) => else_arm,
+                    _ => panic!("Clause of a conditional is not a boolean"),
+                }
+            }
+            Term::Switch {
+                scrutinee,
+                cases,
+                default,
+            } => {
+                let scrutinee = value(scrutinee, &env);
+                term = cases
+                    .iter()
+                    .find(|(constructor, _)| has_constructor(&scrutinee, constructor))
+                    .map(|(_, case)| case)
+                    .or(default.as_deref())
+                    .expect("No case matches the value");
+            }
+            Term::Fail => panic!("No arm of a case expression matches the value"),
+        }
+    }
+}
+
+/// Starts running the body of a function, returning it with the scope it runs in
+fn enter<'t>(
+    function: Value<'t>,
+    arg: Slot<'t>,
+    cont: Option<Rc<Continuation<'t>>>,
+) -> (&'t Term, Scope<'t>) {
+    match function {
+        Value::Func(closure) => {
+            let function = closure.function;
+            let env = closure
+                .env
+                .bind(&function.param, arg)
+                .bind(&function.cont, Slot::Cont(cont));
+            (&function.body, env)
+        }
+        _ => panic!("Only functions can be applied"),
+    }
+}
+
+fn value<'t>(atom: &Atom, env: &Scope<'t>) -> Value<'t> {
+    match atom {
+        Atom::Value(ast::Value::True) => Value::Bool(true),
+        Atom::Value(ast::Value::False) => Value::Bool(false),
+        Atom::Value(ast::Value::Zero) => Value::Nat(0),
+        Atom::Var(name) => match env.lookup(name) {
+            Slot::Value(value) => value.clone(),
+            Slot::Fix(function) => {
+                let arg = Slot::Fix(function.clone());
+                let (body, scope) = enter(function.clone(), arg, None);
+                execute(body, scope)
+            }
+            Slot::Rec(functions, group, index) => {
+                let mut scope = group.clone();
+                for (member, (name, _)) in functions.iter().enumerate() {
+                    scope = scope.bind(name, Slot::Rec(functions, group.clone(), member));
+                }
+                Value::Func(Rc::new(Closure {
+                    function: &functions[*index].1,
+                    env: scope,
+                }))
+            }
+            Slot::Cont(_) => panic!("Continuation {} is used as a value", name),
+        },
+    }
+}
+
+fn continuation<'t>(cont: &Cont, env: &Scope<'t>) -> Option<Rc<Continuation<'t>>> {
+    match cont {
+        Cont::Halt => None,
+        Cont::Var(name) => match env.lookup(name) {
+            Slot::Cont(cont) => cont.clone(),
+            _ => panic!("Value {} is used as a continuation", name),
+        },
+    }
+}
+
+fn primitive<'t>(value: &'t Primitive, env: &Scope<'t>) -> Value<'t> {
+    let nat = |atom| match self::value(atom, env) {
+        Value::Nat(n) => n,
+        _ => panic!("Arithmetic on a value that is not a number"),
+    };
+    let record = |atom| match self::value(atom, env) {
+        Value::Record(fields) => (*fields).clone(),
+        _ => panic!("Record operation on a value that is not a record"),
+    };
+    let set = |fields: &mut Vec<(String, Value<'t>)>, updates: &[(String, Atom)]| {
+        for (name, atom) in updates {
+            let value = self::value(atom, env);
+            match fields.iter().position(|(field, _)| field == name) {
+                Some(index) => fields[index].1 = value,
+                None => fields.push((name.to_string(), value)),
+            }
+        }
+        fields.sort_by(|(first, _), (second, _)| first.cmp(second));
+    };
+    match value {
+        Primitive::Succ(atom) => Value::Nat(nat(atom) + 1),
+        Primitive::Pred(atom) => Value::Nat(nat(atom).saturating_sub(1)),
+        Primitive::IsZero(atom) => Value::Bool(nat(atom) == 0),
+        Primitive::Function(function) => Value::Func(Rc::new(Closure {
+            function,
+            env: env.clone(),
+        })),
+        Primitive::Record(fields) => {
+            let mut record = Vec::new();
+            set(&mut record, fields);
+            Value::Record(Rc::new(record))
+        }
+        Primitive::Update(atom, fields) | Primitive::Extend(atom, fields) => {
+            let mut record = record(atom);
+            set(&mut record, fields);
+            Value::Record(Rc::new(record))
+        }
+        Primitive::Project(atom, attrib) => record(atom)
+            .into_iter()
+            .find(|(name, _)| name == attrib)
+            .map(|(_, value)| value)
+            .expect("Projection on a record without the attribute"),
+        Primitive::Restrict(atom, attrib) => {
+            let mut record = record(atom);
+            record.retain(|(name, _)| name != attrib);
+            Value::Record(Rc::new(record))
+        }
+        Primitive::Tag(tag, atom) => {
+            Value::Variant(tag.to_string(), Rc::new(self::value(atom, env)))
+        }
+        Primitive::Untag(atom, tag) => match self::value(atom, env) {
+            Value::Variant(ref found, ref value) if found == tag => (**value).clone(),
+            _ => panic!("Value is not a variant with tag {}", tag),
+        },
+    }
+}
+
+fn has_constructor(value: &Value<'_>, constructor: &Constructor) -> bool {
+    match (constructor, value) {
+        (Constructor::True, Value::Bool(x)) => *x,
+        (Constructor::False, Value::Bool(x)) => !*x,
+        (Constructor::Zero, Value::Nat(n)) => *n == 0,
+        (Constructor::Succ, Value::Nat(n)) => *n > 0,
+        (Constructor::Tag(tag), Value::Variant(found, _)) => tag == found,
+        _ => false,
+    }
+}
+
+impl<'t> Display for Value<'t> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Value::Nat(n) => write!(f, "{}", n),
+            Value::Bool(x) => write!(f, "{}", x),
+            Value::Func(_) => write!(f, "<function>"),
+            Value::Record(fields) => {
+                write!(f, "{{")?;
+                for (index, (name, value)) in fields.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}={}", name, value)?;
+                }
+                write!(f, "}}")
+            }
+            Value::Variant(tag, value) => write!(f, "<{}={}>", tag, value),
+        }
+    }
+}
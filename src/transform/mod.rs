@@ -0,0 +1,4 @@
+/// Conversion of programs to continuation-passing style
+pub mod cps;
+/// Specialization of programs to the values known before they run
+pub mod specialize;
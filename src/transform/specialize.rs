@@ -0,0 +1,1103 @@
+use ast::*;
+use nameless::no_span;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::str::FromStr;
+
+/// How the arguments for a parameter of a recursive function are treated when a call is
+/// specialized
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BindingTime {
+    /// Known arguments are kept, the function is specialized to them
+    Static,
+    /// Arguments are always passed when the residual program runs, even when they are known
+    Dynamic,
+}
+
+/// Binding times of the parameters of the recursive functions with the given name, in the order
+/// the parameters are taken. Parameters without a binding time are static.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Annotation {
+    pub function: String,
+    pub params: Vec<BindingTime>,
+}
+
+impl FromStr for Annotation {
+    type Err = String;
+
+    /// Reads an annotation like `power=DS`, with `S` for static and `D` for dynamic parameters
+    fn from_str(arg: &str) -> Result<Annotation, String> {
+        let error = || {
+            format!(
+                "Expected a binding time annotation like name=SD, found {}",
+                arg
+            )
+        };
+        let (function, times) = arg.split_once('=').ok_or_else(error)?;
+        let params = times
+            .chars()
+            .map(|time| match time {
+                'S' => Ok(BindingTime::Static),
+                'D' => Ok(BindingTime::Dynamic),
+                _ => Err(error()),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if function.is_empty() {
+            return Err(error());
+        }
+        Ok(Annotation {
+            function: function.to_string(),
+            params,
+        })
+    }
+}
+
+/// Binding times and the limits that keep specialization from running forever
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpecializeConfig {
+    pub annotations: Vec<Annotation>,
+    /// Maximum number of calls to recursive functions that are unfolded within each other, calls
+    /// beyond it are specialized even when all their arguments are known
+    pub max_unfolding: usize,
+    /// Maximum number of versions of a recursive function, further calls use the version in
+    /// which all parameters are dynamic
+    pub max_specializations: usize,
+}
+
+impl Default for SpecializeConfig {
+    fn default() -> SpecializeConfig {
+        SpecializeConfig {
+            annotations: Vec::new(),
+            max_unfolding: 64,
+            max_specializations: 32,
+        }
+    }
+}
+
+/// Specializes a program to the parts of it that are known before it runs, which are all parts
+/// that do not depend on the arguments of a function that is its result. What is known is
+/// computed, what is not is kept as residual code that computes the same result.
+///
+/// Calls of functions bound by `letrec` or by `fix |@f. @x. ...|` are specialization points.
+/// When all arguments are known the call is unfolded, otherwise it becomes a call of a version of
+/// the function specialized to the known arguments of its static parameters. These versions are
+/// memoized, so recursion on unknown values ends in calls of the version being built. Unfolding
+/// stops at `max_unfolding` nested calls and a function gets at most `max_specializations`
+/// versions, so specialization terminates even when the program does not.
+///
+/// Residual variables have fresh names made of the names in the program and underscores, so
+/// the residual program can be printed as source with `ASTNode::source`.
+///
+/// # Arguments
+/// * `node` - The typechecked program to specialize
+/// * `config` - The binding times of recursive functions and the limits on specialization
+///
+/// # Panics
+/// Throws a panic when the program is not well typed
+pub fn specialize<'a>(node: &ASTNode<'_>, config: &SpecializeConfig) -> ASTNode<'a> {
+    let mut specializer = Specializer {
+        config,
+        names: HashSet::new(),
+        depths: HashMap::new(),
+        boundaries: Vec::new(),
+        specs: Vec::new(),
+        memo: HashMap::new(),
+        counts: HashMap::new(),
+        ids: 0,
+        unfolding: 0,
+    };
+    specializer.scope(|s| s.eval(node, &Env::default()))
+}
+
+/// Values of a program while it is specialized. Parts of values that are not known are code of
+/// the residual program, which is either a variable or only used once.
+#[derive(Clone)]
+enum Sv<'n> {
+    Nat(u64),
+    Bool(bool),
+    /// Record with its fields sorted by name
+    Record(Vec<(String, Sv<'n>)>),
+    Tag(String, Box<Sv<'n>>, TypeAssignment),
+    Closure(Rc<Closure<'n>>),
+    /// Member of a group of recursive functions applied to fewer arguments than it takes
+    Rec(Rc<Group<'n>>, usize, Vec<Sv<'n>>),
+    /// Fixed point of the function, only bound to variables and unfolded when they are read
+    Fix(Rc<Closure<'n>>),
+    Code(ASTNode<'static>),
+}
+
+struct Closure<'n> {
+    ident: &'n str,
+    data_type: &'n Option<TypeAssignment>,
+    body: &'n ASTNode<'n>,
+    env: Env<'n>,
+}
+
+/// Recursive functions bound together, each one in scope of all of them
+struct Group<'n> {
+    id: usize,
+    members: Vec<Member<'n>>,
+    env: Env<'n>,
+}
+
+/// Recursive function with the parameters of the abstractions it starts with
+struct Member<'n> {
+    name: &'n str,
+    params: Vec<(&'n str, &'n Option<TypeAssignment>)>,
+    body: &'n ASTNode<'n>,
+}
+
+impl<'n> Member<'n> {
+    fn new(name: &'n str, value: &'n ASTNode<'n>) -> Member<'n> {
+        let mut params = Vec::new();
+        let mut body = value;
+        while let ASTNode::AbstractionNode {
+            ident,
+            data_type,
+            body: inner,
+            ..
+        } = body
+        {
+            params.push((ident.as_str(), data_type));
+            body = inner;
+        }
+        if params.is_empty() {
+            panic!("Bug in typechecker: recursive binding is not a function");
+        }
+        Member { name, params, body }
+    }
+}
+
+/// Variables in scope of the program
+#[derive(Clone, Default)]
+struct Env<'n>(Option<Rc<(&'n str, Sv<'n>, Env<'n>)>>);
+
+impl<'n> Env<'n> {
+    fn bind(&self, name: &'n str, value: Sv<'n>) -> Env<'n> {
+        Env(Some(Rc::new((name, value, self.clone()))))
+    }
+
+    fn lookup(&self, name: &str) -> Sv<'n> {
+        let mut env = self;
+        while let Some(binding) = &env.0 {
+            if binding.0 == name {
+                return binding.1.clone();
+            }
+            env = &binding.2;
+        }
+        panic!("Bug in typechecker: came across unknown variable")
+    }
+
+    /// Binds the members of a group of recursive functions
+    fn group(&self, group: &Rc<Group<'n>>) -> Env<'n> {
+        let mut env = self.clone();
+        for (index, member) in group.members.iter().enumerate() {
+            env = env.bind(member.name, Sv::Rec(group.clone(), index, Vec::new()));
+        }
+        env
+    }
+}
+
+/// Known argument of a specialized function, specializations are memoized by them
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Nat(u64),
+    Bool(bool),
+    Record(Vec<(String, Key)>),
+    /// Variant with its tag, value and type
+    Tag(String, Box<Key>, String),
+}
+
+/// Returns the key for a value that is known completely and does not contain functions
+fn key(value: &Sv<'_>) -> Option<Key> {
+    match value {
+        Sv::Nat(n) => Some(Key::Nat(*n)),
+        Sv::Bool(x) => Some(Key::Bool(*x)),
+        Sv::Record(fields) => fields
+            .iter()
+            .map(|(name, value)| key(value).map(|key| (name.to_string(), key)))
+            .collect::<Option<_>>()
+            .map(Key::Record),
+        Sv::Tag(tag, value, data_type) => {
+            key(value).map(|key| Key::Tag(tag.to_string(), Box::new(key), data_type.to_string()))
+        }
+        _ => None,
+    }
+}
+
+/// Returns whether the value has parts that are not known. Functions are known even when they
+/// refer to unknown values.
+fn has_code(value: &Sv<'_>) -> bool {
+    match value {
+        Sv::Code(_) => true,
+        Sv::Record(fields) => fields.iter().any(|(_, value)| has_code(value)),
+        Sv::Tag(_, value, _) => has_code(value),
+        Sv::Rec(_, _, args) => args.iter().any(has_code),
+        _ => false,
+    }
+}
+
+/// Outcome of matching a value against a pattern
+enum Match<'n> {
+    Yes(Vec<(&'n str, Sv<'n>)>),
+    No,
+    Unknown,
+}
+
+/// Scope of residual variables, the bindings made in it are placed around its code
+struct Boundary {
+    id: usize,
+    entries: Vec<Entry>,
+}
+
+enum Entry {
+    Let(String, ASTNode<'static>),
+    /// Specialized functions, by their index in `Specializer::specs`
+    Rec(Vec<usize>),
+}
+
+/// Version of a recursive function, its code is filled in once its body has been specialized
+struct Spec {
+    name: String,
+    boundary: usize,
+    code: Option<ASTNode<'static>>,
+}
+
+struct Specializer<'c> {
+    config: &'c SpecializeConfig,
+    /// Names of all residual variables, which are all different
+    names: HashSet<String>,
+    /// Position on the stack of boundaries of the boundary every residual variable is bound in
+    depths: HashMap<String, usize>,
+    boundaries: Vec<Boundary>,
+    specs: Vec<Spec>,
+    /// Specializations by group, member and the keys of their static parameters
+    memo: HashMap<(usize, usize, Vec<Option<Key>>), usize>,
+    /// Number of specializations of every member of a group
+    counts: HashMap<(usize, usize), usize>,
+    /// Source of the identities of groups and boundaries
+    ids: usize,
+    /// Number of calls of recursive functions currently being unfolded
+    unfolding: usize,
+}
+
+impl<'c> Specializer<'c> {
+    fn id(&mut self) -> usize {
+        self.ids += 1;
+        self.ids
+    }
+
+    /// Returns a name no residual variable has yet, which is the hint or the hint with the
+    /// smallest number as suffix that makes it new
+    fn fresh(&mut self, hint: &str) -> String {
+        let name = if self.names.contains(hint) {
+            let stem = hint.trim_end_matches(|c: char| c.is_ascii_digit());
+            (1..)
+                .map(|number| format!("{}{}", stem, number))
+                .find(|name| !self.names.contains(name))
+                .expect("Bug in specializer: ran out of numbers")
+        } else {
+            hint.to_string()
+        };
+        self.names.insert(name.to_string());
+        name
+    }
+
+    /// Returns a new residual variable bound in the innermost boundary
+    fn variable(&mut self, hint: &str) -> String {
+        let name = self.fresh(hint);
+        self.depths
+            .insert(name.to_string(), self.boundaries.len() - 1);
+        name
+    }
+
+    /// Binds code to a new residual variable, unless it is a variable or a literal already
+    fn bind<'n>(&mut self, hint: &str, value: Sv<'n>) -> Sv<'n> {
+        match value {
+            Sv::Code(ASTNode::IdentifierNode { .. }) | Sv::Code(ASTNode::ValueNode { .. }) => value,
+            Sv::Code(code) => {
+                let name = self.variable(hint);
+                let boundary = self
+                    .boundaries
+                    .last_mut()
+                    .expect("Bug in specializer: bound a variable outside of any boundary");
+                boundary.entries.push(Entry::Let(name.to_string(), code));
+                Sv::Code(identifier(name))
+            }
+            value => value,
+        }
+    }
+
+    /// Returns the code for a value computed in a new boundary, preceded by the bindings made
+    /// while computing it
+    fn scope<'n>(&mut self, body: impl FnOnce(&mut Self) -> Sv<'n>) -> ASTNode<'static> {
+        let id = self.id();
+        self.boundaries.push(Boundary {
+            id,
+            entries: Vec::new(),
+        });
+        let value = body(self);
+        let code = self.lift(value);
+        let boundary = self
+            .boundaries
+            .pop()
+            .expect("Bug in specializer: left a boundary that was not entered");
+        boundary
+            .entries
+            .into_iter()
+            .rev()
+            .fold(code, |body, entry| match entry {
+                Entry::Let(name, value) => ASTNode::LetNode {
+                    meta: no_span(),
+                    pattern: Pattern::Variable(name),
+                    value: Box::new(value),
+                    body: Box::new(body),
+                },
+                Entry::Rec(specs) => ASTNode::LetRecNode {
+                    meta: no_span(),
+                    bindings: specs
+                        .into_iter()
+                        .map(|index| {
+                            let spec = &mut self.specs[index];
+                            let code = spec
+                                .code
+                                .take()
+                                .expect("Bug in specializer: bound a function before its body");
+                            (spec.name.to_string(), None, code)
+                        })
+                        .collect(),
+                    body: Box::new(body),
+                },
+            })
+    }
+
+    fn eval<'n>(&mut self, node: &'n ASTNode<'n>, env: &Env<'n>) -> Sv<'n> {
+        match node {
+            ASTNode::ValueNode { value, .. } => match value {
+                Value::True => Sv::Bool(true),
+                Value::False => Sv::Bool(false),
+                Value::Zero => Sv::Nat(0),
+            },
+            ASTNode::IdentifierNode { name, .. } => match env.lookup(name) {
+                Sv::Fix(function) => self.fix(function),
+                value => value,
+            },
+            ASTNode::AbstractionNode {
+                ident,
+                data_type,
+                body,
+                ..
+            } => Sv::Closure(Rc::new(Closure {
+                ident,
+                data_type,
+                body,
+                env: env.clone(),
+            })),
+            ASTNode::ApplicationNode { left, right, .. } => {
+                let function = self.eval(left, env);
+                let arg = self.eval(right, env);
+                self.apply(function, arg)
+            }
+            ASTNode::ConditionNode {
+                clause,
+                then_arm,
+                else_arm,
+                ..
+            } => {
+                let clause = self.eval(clause, env);
+                self.decide(clause, then_arm, else_arm, env)
+            }
+            ASTNode::ArithmeticNode { op, expr, .. } => match (op, self.eval(expr, env)) {
+                (Operator::Succ, Sv::Nat(n)) => Sv::Nat(n + 1),
+                (Operator::Pred, Sv::Nat(n)) => Sv::Nat(n.saturating_sub(1)),
+                (op, Sv::Code(expr)) => Sv::Code(ASTNode::ArithmeticNode {
+                    meta: no_span(),
+                    op: op.clone(),
+                    expr: Box::new(expr),
+                }),
+                _ => panic!("Bug in typechecker: arithmetic on a value that is not a number"),
+            },
+            ASTNode::IsZeroNode { expr, .. } => match self.eval(expr, env) {
+                Sv::Nat(n) => Sv::Bool(n == 0),
+                Sv::Code(expr) => Sv::Code(ASTNode::IsZeroNode {
+                    meta: no_span(),
+                    expr: Box::new(expr),
+                }),
+                _ => panic!("Bug in typechecker: zero check on a value that is not a number"),
+            },
+            _ => self.structure(node, env),
+        }
+    }
+
+    /// Evaluates records, variants, cases and bindings, kept apart from `eval` so that the
+    /// frames of deeply nested unfoldings stay small
+    fn structure<'n>(&mut self, node: &'n ASTNode<'n>, env: &Env<'n>) -> Sv<'n> {
+        match node {
+            ASTNode::RecordNode { records, .. } => Sv::Record(self.fields(records, env)),
+            ASTNode::ProjectionNode { target, attrib, .. } => {
+                let target = self.eval(target, env);
+                project(target, attrib)
+            }
+            ASTNode::UpdateNode {
+                target, records, ..
+            }
+            | ASTNode::ExtensionNode {
+                target, records, ..
+            } => {
+                let target = self.eval(target, env);
+                let fields = self.fields(records, env);
+                self.extend(node, target, fields)
+            }
+            ASTNode::RestrictionNode { target, attrib, .. } => match self.eval(target, env) {
+                Sv::Record(mut record) => {
+                    record.retain(|(name, _)| name != attrib);
+                    Sv::Record(record)
+                }
+                Sv::Code(target) => Sv::Code(ASTNode::RestrictionNode {
+                    meta: no_span(),
+                    target: Box::new(target),
+                    attrib: attrib.to_string(),
+                }),
+                _ => panic!("Bug in typechecker: record operation on a value that is not a record"),
+            },
+            ASTNode::TaggingNode {
+                ident,
+                value,
+                data_type,
+                ..
+            } => {
+                let value = self.eval(value, env);
+                let value = self.bind(ident, value);
+                Sv::Tag(ident.to_string(), Box::new(value), data_type.clone())
+            }
+            ASTNode::MatchingNode {
+                to_match, cases, ..
+            } => {
+                let value = self.eval(to_match, env);
+                let cases = cases
+                    .iter()
+                    .map(|(pattern, arm)| (pattern, &**arm))
+                    .collect();
+                self.select(value, cases, env)
+            }
+            ASTNode::LetNode {
+                pattern: Pattern::Variable(name),
+                value,
+                body,
+                ..
+            } => {
+                let value = self.eval(value, env);
+                let value = self.bind(name, value);
+                self.eval(body, &env.bind(name, value))
+            }
+            ASTNode::LetNode {
+                pattern,
+                value,
+                body,
+                ..
+            } => {
+                let value = self.eval(value, env);
+                self.select(value, vec![(pattern, body)], env)
+            }
+            ASTNode::LetRecNode { bindings, body, .. } => {
+                let group = Rc::new(Group {
+                    id: self.id(),
+                    members: bindings
+                        .iter()
+                        .map(|(name, _, value)| Member::new(name, value))
+                        .collect(),
+                    env: env.clone(),
+                });
+                self.eval(body, &env.group(&group))
+            }
+            ASTNode::FixNode { point, .. } => {
+                // The fixed point of a function building a function is a recursive function
+                if let ASTNode::AbstractionNode { ident, body, .. } = &**point {
+                    if let ASTNode::AbstractionNode { .. } = **body {
+                        let group = Rc::new(Group {
+                            id: self.id(),
+                            members: vec![Member::new(ident, body)],
+                            env: env.clone(),
+                        });
+                        return Sv::Rec(group, 0, Vec::new());
+                    }
+                }
+                match self.eval(point, env) {
+                    Sv::Closure(function) => self.fix(function),
+                    Sv::Code(point) => Sv::Code(ASTNode::FixNode {
+                        meta: no_span(),
+                        point: Box::new(point),
+                    }),
+                    _ => {
+                        panic!("Bug in typechecker: fixed point of a value that is not a function")
+                    }
+                }
+            }
+            _ => panic!("Bug in specializer: expected a record, variant, case or binding"),
+        }
+    }
+
+    /// Takes the arm of a conditional with a known clause, or keeps both arms as code
+    fn decide<'n>(
+        &mut self,
+        clause: Sv<'n>,
+        then_arm: &'n ASTNode<'n>,
+        else_arm: &'n ASTNode<'n>,
+        env: &Env<'n>,
+    ) -> Sv<'n> {
+        match clause {
+            Sv::Bool(true) => self.eval(then_arm, env),
+            Sv::Bool(false) => self.eval(else_arm, env),
+            Sv::Code(clause) => Sv::Code(ASTNode::ConditionNode {
+                meta: no_span(),
+                clause: Box::new(clause),
+                then_arm: Box::new(self.scope(|s| s.eval(then_arm, env))),
+                else_arm: Box::new(self.scope(|s| s.eval(else_arm, env))),
+            }),
+            _ => panic!("Bug in typechecker: clause of a conditional is not a boolean"),
+        }
+    }
+
+    /// Updates or extends a known record with the given fields, or keeps the operation as code
+    fn extend<'n>(
+        &mut self,
+        node: &ASTNode<'_>,
+        target: Sv<'n>,
+        fields: Vec<(String, Sv<'n>)>,
+    ) -> Sv<'n> {
+        match target {
+            Sv::Record(mut record) => {
+                for (name, value) in fields {
+                    match record.iter().position(|(field, _)| *field == name) {
+                        Some(index) => record[index].1 = value,
+                        None => record.push((name, value)),
+                    }
+                }
+                record.sort_by(|(first, _), (second, _)| first.cmp(second));
+                Sv::Record(record)
+            }
+            Sv::Code(target) => {
+                let target = Box::new(target);
+                let records = fields
+                    .into_iter()
+                    .map(|(name, value)| (name, self.lift(value)))
+                    .collect();
+                Sv::Code(match node {
+                    ASTNode::UpdateNode { .. } => ASTNode::UpdateNode {
+                        meta: no_span(),
+                        target,
+                        records,
+                    },
+                    _ => ASTNode::ExtensionNode {
+                        meta: no_span(),
+                        target,
+                        records,
+                    },
+                })
+            }
+            _ => panic!("Bug in typechecker: record operation on a value that is not a record"),
+        }
+    }
+
+    /// Evaluates the fields of a record in the order of their names
+    fn fields<'n>(
+        &mut self,
+        records: &'n HashMap<String, ASTNode<'n>>,
+        env: &Env<'n>,
+    ) -> Vec<(String, Sv<'n>)> {
+        let mut names: Vec<&String> = records.keys().collect();
+        names.sort();
+        names
+            .into_iter()
+            .map(|name| {
+                let value = self.eval(&records[name], env);
+                (name.to_string(), self.bind(name, value))
+            })
+            .collect()
+    }
+
+    /// Unfolds the fixed point of a function once, or keeps it as code when too many unfoldings
+    /// are nested already
+    fn fix<'n>(&mut self, function: Rc<Closure<'n>>) -> Sv<'n> {
+        if self.unfolding >= self.config.max_unfolding {
+            let point = self.lift(Sv::Closure(function));
+            return Sv::Code(ASTNode::FixNode {
+                meta: no_span(),
+                point: Box::new(point),
+            });
+        }
+        self.unfolding += 1;
+        let env = function.env.bind(function.ident, Sv::Fix(function.clone()));
+        let value = self.eval(function.body, &env);
+        self.unfolding -= 1;
+        value
+    }
+
+    fn apply<'n>(&mut self, function: Sv<'n>, arg: Sv<'n>) -> Sv<'n> {
+        match function {
+            Sv::Closure(function) => {
+                let arg = self.bind(function.ident, arg);
+                self.eval(function.body, &function.env.bind(function.ident, arg))
+            }
+            Sv::Rec(group, index, mut args) => {
+                let params = &group.members[index].params;
+                if args.len() + 1 < params.len() {
+                    // The arguments of a partial application may be used more than once
+                    let arg = self.bind(params[args.len()].0, arg);
+                    args.push(arg);
+                    Sv::Rec(group, index, args)
+                } else {
+                    args.push(arg);
+                    self.call(group, index, args)
+                }
+            }
+            Sv::Code(function) => {
+                let arg = self.lift(arg);
+                Sv::Code(application(function, arg))
+            }
+            _ => panic!("Bug in typechecker: applied a value that is not a function"),
+        }
+    }
+
+    /// Calls a recursive function with all its arguments, by unfolding it when they are all
+    /// known and otherwise by calling a specialized version
+    fn call<'n>(&mut self, group: Rc<Group<'n>>, index: usize, mut args: Vec<Sv<'n>>) -> Sv<'n> {
+        let member = &group.members[index];
+        let times = self
+            .config
+            .annotations
+            .iter()
+            .find(|annotation| annotation.function == member.name)
+            .map_or(&[][..], |annotation| &annotation.params[..]);
+        for (arg, time) in args.iter_mut().zip(times) {
+            if *time == BindingTime::Dynamic && !has_code(arg) {
+                let code = self.lift(arg.clone());
+                *arg = Sv::Code(code);
+            }
+        }
+        let known = !args.iter().any(has_code);
+        if known && self.unfolding < self.config.max_unfolding {
+            let mut env = group.env.group(&group);
+            for ((param, _), arg) in member.params.iter().zip(args) {
+                let arg = self.bind(param, arg);
+                env = env.bind(param, arg);
+            }
+            self.unfolding += 1;
+            let value = self.eval(member.body, &env);
+            self.unfolding -= 1;
+            return value;
+        }
+        let mut keys: Vec<Option<Key>> = args.iter().map(key).collect();
+        if known {
+            // Unfolding went too deep, some parameter has to be dynamic to end the recursion
+            *keys
+                .last_mut()
+                .expect("Bug in specializer: unfolded a call without arguments") = None;
+        }
+        let code = self.residual_call(&group, index, keys, &args);
+        Sv::Code(code)
+    }
+
+    /// Returns the code calling the version of a recursive function for the keys of its static
+    /// arguments with its dynamic arguments, specializing the function when needed
+    fn residual_call<'n>(
+        &mut self,
+        group: &Rc<Group<'n>>,
+        index: usize,
+        mut keys: Vec<Option<Key>>,
+        args: &[Sv<'n>],
+    ) -> ASTNode<'static> {
+        let count = self.counts.get(&(group.id, index)).copied().unwrap_or(0);
+        if count >= self.config.max_specializations {
+            keys = vec![None; keys.len()];
+        }
+        let memoized = self
+            .memo
+            .get(&(group.id, index, keys.clone()))
+            .copied()
+            .filter(|spec| {
+                let boundary = self.specs[*spec].boundary;
+                self.boundaries.iter().any(|open| open.id == boundary)
+            });
+        let spec = match memoized {
+            Some(spec) => spec,
+            None => self.spec(group, index, &keys, args),
+        };
+        let mut code = identifier(self.specs[spec].name.to_string());
+        for (key, arg) in keys.iter().zip(args) {
+            if key.is_none() {
+                let arg = self.lift(arg.clone());
+                code = application(code, arg);
+            }
+        }
+        code
+    }
+
+    /// Specializes a member of a group to the known arguments of its static parameters. The
+    /// version is bound in the outermost boundary in which the variables the group refers to are
+    /// bound, and to be able to refer to itself it is memoized before its body is specialized.
+    fn spec<'n>(
+        &mut self,
+        group: &Rc<Group<'n>>,
+        index: usize,
+        keys: &[Option<Key>],
+        args: &[Sv<'n>],
+    ) -> usize {
+        let mut depth = 0;
+        let mut visited = HashSet::new();
+        self.env_depth(&group.env, &mut depth, &mut visited);
+        let member = &group.members[index];
+        let name = self.fresh(member.name);
+        self.depths.insert(name.to_string(), depth);
+        let spec = self.specs.len();
+        self.specs.push(Spec {
+            name,
+            boundary: self.boundaries[depth].id,
+            code: None,
+        });
+        let entries = &mut self.boundaries[depth].entries;
+        match entries.last_mut() {
+            Some(Entry::Rec(specs)) => specs.push(spec),
+            _ => entries.push(Entry::Rec(vec![spec])),
+        }
+        self.memo.insert((group.id, index, keys.to_vec()), spec);
+        *self.counts.entry((group.id, index)).or_insert(0) += 1;
+
+        // The body only sees the boundaries up to the one the version is bound in
+        let inner = self.boundaries.split_off(depth + 1);
+        let mut params = Vec::new();
+        let body = self.scope(|s| {
+            let mut env = group.env.group(group);
+            for (position, ((param, data_type), key)) in member.params.iter().zip(keys).enumerate()
+            {
+                let value = match key {
+                    Some(_) => args[position].clone(),
+                    None => {
+                        let name = s.variable(param);
+                        params.push((name.to_string(), (*data_type).clone()));
+                        Sv::Code(identifier(name))
+                    }
+                };
+                env = env.bind(param, value);
+            }
+            s.eval(member.body, &env)
+        });
+        self.boundaries.extend(inner);
+        let code = params
+            .into_iter()
+            .rev()
+            .fold(body, |body, (ident, data_type)| ASTNode::AbstractionNode {
+                meta: no_span(),
+                ident,
+                data_type,
+                body: Box::new(body),
+            });
+        self.specs[spec].code = Some(code);
+        spec
+    }
+
+    /// Finds the innermost boundary binding a residual variable that a value in the environment
+    /// refers to
+    fn env_depth(&self, env: &Env<'_>, depth: &mut usize, visited: &mut HashSet<usize>) {
+        let mut env = env;
+        while let Some(binding) = &env.0 {
+            if !visited.insert(&**binding as *const _ as usize) {
+                return;
+            }
+            self.value_depth(&binding.1, depth, visited);
+            env = &binding.2;
+        }
+    }
+
+    fn value_depth(&self, value: &Sv<'_>, depth: &mut usize, visited: &mut HashSet<usize>) {
+        match value {
+            Sv::Nat(_) | Sv::Bool(_) => {}
+            Sv::Record(fields) => {
+                for (_, value) in fields {
+                    self.value_depth(value, depth, visited);
+                }
+            }
+            Sv::Tag(_, value, _) => self.value_depth(value, depth, visited),
+            Sv::Closure(function) | Sv::Fix(function) => {
+                self.env_depth(&function.env, depth, visited)
+            }
+            Sv::Rec(group, _, args) => {
+                self.env_depth(&group.env, depth, visited);
+                for arg in args {
+                    self.value_depth(arg, depth, visited);
+                }
+            }
+            Sv::Code(code) => {
+                let mut names = Vec::new();
+                identifiers(code, &mut names);
+                for name in names {
+                    if let Some(bound) = self.depths.get(&name) {
+                        *depth = (*depth).max(*bound).min(self.boundaries.len() - 1);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Selects the arm of the first case whose pattern matches the value. When it is not known
+    /// which case matches, the remaining cases become a residual case expression.
+    fn select<'n>(
+        &mut self,
+        value: Sv<'n>,
+        cases: Vec<(&'n Pattern, &'n ASTNode<'n>)>,
+        env: &Env<'n>,
+    ) -> Sv<'n> {
+        let value = self.bind("value", value);
+        for (index, (pattern, arm)) in cases.iter().enumerate() {
+            match matches(pattern, &value) {
+                Match::Yes(values) => {
+                    let mut env = env.clone();
+                    for (name, value) in values {
+                        let value = self.bind(name, value);
+                        env = env.bind(name, value);
+                    }
+                    return self.eval(arm, &env);
+                }
+                Match::No => continue,
+                Match::Unknown => return self.residual_case(value, &cases[index..], env),
+            }
+        }
+        // The case fails at run time, which the residual program has to do as well
+        self.residual_case(value, &cases, env)
+    }
+
+    fn residual_case<'n>(
+        &mut self,
+        value: Sv<'n>,
+        cases: &[(&'n Pattern, &'n ASTNode<'n>)],
+        env: &Env<'n>,
+    ) -> Sv<'n> {
+        let to_match = self.lift(value);
+        let cases = cases
+            .iter()
+            .map(|(pattern, arm)| {
+                let mut renamed = Pattern::Wildcard;
+                let arm = self.scope(|s| {
+                    let mut scope = env.clone();
+                    renamed = s.rename(pattern, &mut scope);
+                    s.eval(arm, &scope)
+                });
+                (renamed, Box::new(arm))
+            })
+            .collect();
+        Sv::Code(ASTNode::MatchingNode {
+            meta: no_span(),
+            to_match: Box::new(to_match),
+            cases,
+        })
+    }
+
+    /// Renames the variables of a pattern to new residual variables and binds them
+    fn rename<'n>(&mut self, pattern: &'n Pattern, env: &mut Env<'n>) -> Pattern {
+        match pattern {
+            Pattern::Wildcard | Pattern::Value(_) => pattern.clone(),
+            Pattern::Variable(name) => {
+                let renamed = self.variable(name);
+                *env = env.bind(name, Sv::Code(identifier(renamed.to_string())));
+                Pattern::Variable(renamed)
+            }
+            Pattern::Succ(inner) => Pattern::Succ(Box::new(self.rename(inner, env))),
+            Pattern::Tag(tag, inner) => {
+                Pattern::Tag(tag.to_string(), Box::new(self.rename(inner, env)))
+            }
+            Pattern::Record(fields) => {
+                let mut names: Vec<&String> = fields.keys().collect();
+                names.sort();
+                Pattern::Record(
+                    names
+                        .into_iter()
+                        .map(|name| (name.to_string(), self.rename(&fields[name], env)))
+                        .collect(),
+                )
+            }
+        }
+    }
+
+    /// Returns the code computing a value
+    fn lift(&mut self, value: Sv<'_>) -> ASTNode<'static> {
+        match value {
+            Sv::Nat(n) => (0..n).fold(
+                ASTNode::ValueNode {
+                    meta: no_span(),
+                    value: Value::Zero,
+                },
+                |expr, _| ASTNode::ArithmeticNode {
+                    meta: no_span(),
+                    op: Operator::Succ,
+                    expr: Box::new(expr),
+                },
+            ),
+            Sv::Bool(x) => ASTNode::ValueNode {
+                meta: no_span(),
+                value: if x { Value::True } else { Value::False },
+            },
+            Sv::Record(fields) => ASTNode::RecordNode {
+                meta: no_span(),
+                records: fields
+                    .into_iter()
+                    .map(|(name, value)| (name, self.lift(value)))
+                    .collect(),
+            },
+            Sv::Tag(ident, value, data_type) => ASTNode::TaggingNode {
+                meta: no_span(),
+                ident,
+                value: Box::new(self.lift(*value)),
+                data_type,
+            },
+            Sv::Closure(function) => {
+                let mut ident = String::new();
+                let body = self.scope(|s| {
+                    ident = s.variable(function.ident);
+                    let arg = Sv::Code(identifier(ident.to_string()));
+                    s.eval(function.body, &function.env.bind(function.ident, arg))
+                });
+                ASTNode::AbstractionNode {
+                    meta: no_span(),
+                    ident,
+                    data_type: function.data_type.clone(),
+                    body: Box::new(body),
+                }
+            }
+            Sv::Rec(group, index, args) => {
+                // The version for the arguments so far, the other parameters are dynamic
+                let arity = group.members[index].params.len();
+                let mut keys: Vec<Option<Key>> = args.iter().map(key).collect();
+                keys.resize(arity, None);
+                self.residual_call(&group, index, keys, &args)
+            }
+            Sv::Fix(function) => {
+                let point = self.lift(Sv::Closure(function));
+                ASTNode::FixNode {
+                    meta: no_span(),
+                    point: Box::new(point),
+                }
+            }
+            Sv::Code(code) => code,
+        }
+    }
+}
+
+/// Matches a value against a pattern. Fields of records that are not known are matched by
+/// projecting them, which is safe because the record is bound to a variable first.
+fn matches<'n>(pattern: &'n Pattern, value: &Sv<'n>) -> Match<'n> {
+    match (pattern, value) {
+        (Pattern::Wildcard, _) => Match::Yes(Vec::new()),
+        (Pattern::Variable(name), _) => Match::Yes(vec![(name, value.clone())]),
+        (Pattern::Record(fields), _) => {
+            let mut names: Vec<&String> = fields.keys().collect();
+            names.sort();
+            let mut values = Vec::new();
+            for name in names {
+                match matches(&fields[name], &project(value.clone(), name)) {
+                    Match::Yes(found) => values.extend(found),
+                    other => return other,
+                }
+            }
+            Match::Yes(values)
+        }
+        (_, Sv::Code(_)) => Match::Unknown,
+        (Pattern::Value(Value::True), Sv::Bool(true))
+        | (Pattern::Value(Value::False), Sv::Bool(false))
+        | (Pattern::Value(Value::Zero), Sv::Nat(0)) => Match::Yes(Vec::new()),
+        (Pattern::Succ(inner), Sv::Nat(n)) if *n > 0 => matches(inner, &Sv::Nat(n - 1)),
+        (Pattern::Tag(tag, inner), Sv::Tag(ident, value, _)) if tag == ident => {
+            matches(inner, value)
+        }
+        _ => Match::No,
+    }
+}
+
+fn project<'n>(target: Sv<'n>, attrib: &str) -> Sv<'n> {
+    match target {
+        Sv::Record(fields) => fields
+            .into_iter()
+            .find(|(name, _)| name == attrib)
+            .map(|(_, value)| value)
+            .unwrap_or_else(|| panic!("Record does not have a field {}", attrib)),
+        Sv::Code(target) => Sv::Code(ASTNode::ProjectionNode {
+            meta: no_span(),
+            target: Box::new(target),
+            attrib: attrib.to_string(),
+        }),
+        _ => panic!("Bug in typechecker: projection on a value that is not a record"),
+    }
+}
+
+fn identifier(name: String) -> ASTNode<'static> {
+    ASTNode::IdentifierNode {
+        meta: no_span(),
+        name,
+    }
+}
+
+fn application(left: ASTNode<'static>, right: ASTNode<'static>) -> ASTNode<'static> {
+    ASTNode::ApplicationNode {
+        meta: no_span(),
+        left: Box::new(left),
+        right: Box::new(right),
+    }
+}
+
+/// Collects the names of all identifiers in residual code
+fn identifiers(code: &ASTNode<'_>, names: &mut Vec<String>) {
+    match code {
+        ASTNode::IdentifierNode { name, .. } => names.push(name.to_string()),
+        ASTNode::ValueNode { .. } => {}
+        ASTNode::AbstractionNode { body, .. } => identifiers(body, names),
+        ASTNode::ApplicationNode { left, right, .. } => {
+            identifiers(left, names);
+            identifiers(right, names);
+        }
+        ASTNode::ConditionNode {
+            clause,
+            then_arm,
+            else_arm,
+            ..
+        } => {
+            identifiers(clause, names);
+            identifiers(then_arm, names);
+            identifiers(else_arm, names);
+        }
+        ASTNode::ArithmeticNode { expr, .. } | ASTNode::IsZeroNode { expr, .. } => {
+            identifiers(expr, names)
+        }
+        ASTNode::ProjectionNode { target, .. } | ASTNode::RestrictionNode { target, .. } => {
+            identifiers(target, names)
+        }
+        ASTNode::RecordNode { records, .. } => {
+            records.values().for_each(|value| identifiers(value, names))
+        }
+        ASTNode::UpdateNode {
+            target, records, ..
+        }
+        | ASTNode::ExtensionNode {
+            target, records, ..
+        } => {
+            identifiers(target, names);
+            records.values().for_each(|value| identifiers(value, names));
+        }
+        ASTNode::MatchingNode {
+            to_match, cases, ..
+        } => {
+            identifiers(to_match, names);
+            cases.iter().for_each(|(_, arm)| identifiers(arm, names));
+        }
+        ASTNode::TaggingNode { value, .. } => identifiers(value, names),
+        ASTNode::FixNode { point, .. } => identifiers(point, names),
+        ASTNode::LetNode { value, body, .. } => {
+            identifiers(value, names);
+            identifiers(body, names);
+        }
+        ASTNode::LetRecNode { bindings, body, .. } => {
+            bindings
+                .iter()
+                .for_each(|(_, _, value)| identifiers(value, names));
+            identifiers(body, names);
+        }
+    }
+}
@@ -0,0 +1,324 @@
+use bytecode::*;
+use eval::{EvalConfig, EvalError};
+use std::fmt::*;
+use std::rc::Rc;
+use std::result;
+
+/// Values the virtual machine computes with
+#[derive(Clone, Debug)]
+pub enum Value {
+    Nat(usize),
+    Bool(bool),
+    Closure(Closure),
+    /// Fixpoint of a function, it is unfolded when it is loaded from a variable
+    Fix(Closure),
+    /// Member of a recursive group, it is unfolded when it is loaded from a variable
+    Rec(Rc<Group>, usize),
+    /// Fields sorted by their index in the field table of the program
+    Record(Rc<Vec<(usize, Value)>>),
+    Variant(usize, Rc<Value>),
+}
+
+/// Function value, the code of its body together with the variables it captured
+#[derive(Clone, Debug)]
+pub struct Closure {
+    pub code: usize,
+    pub captured: Rc<Vec<Value>>,
+}
+
+/// Recursive bindings introduced by a single letrec, sharing their captured variables
+#[derive(Debug)]
+pub struct Group {
+    pub codes: Vec<usize>,
+    pub captured: Rc<Vec<Value>>,
+}
+
+/// Activation of a compiled function
+struct Frame {
+    code: usize,
+    pc: usize,
+    slots: Vec<Value>,
+    captured: Rc<Vec<Value>>,
+}
+
+struct Machine<'p> {
+    program: &'p Program,
+    config: EvalConfig,
+    steps: usize,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+}
+
+/// Runs a compiled program on the virtual machine, giving up with an error when it exceeds one of
+/// the limits of the configuration. Arguments are passed by value.
+///
+/// # Errors
+/// Returns `EvalError::OutOfFuel` when more instructions are executed than the maximum number of
+/// steps, and `EvalError::TooDeep` when more calls are active than the maximum depth
+///
+/// # Panics
+/// Throws a panic when the bytecode is not well typed, which would indicate a problem in the
+/// typechecking logic.
+pub fn run(program: &Program, config: EvalConfig) -> result::Result<Value, EvalError> {
+    let mut machine = Machine {
+        program,
+        config,
+        steps: 0,
+        stack: Vec::new(),
+        frames: Vec::new(),
+    };
+    machine.enter(program.entry, Vec::new(), Rc::new(Vec::new()))?;
+    machine.execute()
+}
+
+impl<'p> Machine<'p> {
+    fn execute(&mut self) -> result::Result<Value, EvalError> {
+        loop {
+            if self.config.max_steps.is_some_and(|max| self.steps >= max) {
+                return Err(EvalError::OutOfFuel { steps: self.steps });
+            }
+            self.steps += 1;
+
+            let program = self.program;
+            let frame = self
+                .frames
+                .last_mut()
+                .expect("Machine has no running function");
+            let instr = &program.codes[frame.code].instrs[frame.pc];
+            frame.pc += 1;
+            match instr {
+                Instr::Nat(x) => self.stack.push(Value::Nat(*x)),
+                Instr::Bool(x) => self.stack.push(Value::Bool(*x)),
+                Instr::Local(slot) => {
+                    let value = frame.slots[*slot].clone();
+                    self.load(value)?;
+                }
+                Instr::Captured(index) => {
+                    let value = frame.captured[*index].clone();
+                    self.load(value)?;
+                }
+                Instr::Store(slot) => frame.slots[*slot] = self.stack.pop().expect("Empty stack"),
+                Instr::Closure(code, captures) => {
+                    let captured = captures.iter().map(|capture| capture_of(frame, capture));
+                    self.stack.push(Value::Closure(Closure {
+                        code: *code,
+                        captured: Rc::new(captured.collect()),
+                    }));
+                }
+                Instr::Group(codes, captures) => {
+                    let captured = captures.iter().map(|capture| capture_of(frame, capture));
+                    let group = Rc::new(Group {
+                        codes: codes.clone(),
+                        captured: Rc::new(captured.collect()),
+                    });
+                    for index in 0..codes.len() {
+                        self.stack.push(Value::Rec(group.clone(), index));
+                    }
+                }
+                Instr::Fix => match self.pop() {
+                    Value::Closure(function) => {
+                        let point = Value::Fix(function.clone());
+                        self.enter(function.code, vec![point], function.captured)?;
+                    }
+                    _ => panic!("Bug in typechecker: fixpoint of a value that is not a function"),
+                },
+                Instr::Call | Instr::TailCall => {
+                    let tail = *instr == Instr::TailCall;
+                    let argument = self.pop();
+                    match self.pop() {
+                        Value::Closure(function) => {
+                            if tail {
+                                self.frames.pop();
+                            }
+                            self.enter(function.code, vec![argument], function.captured)?;
+                        }
+                        _ => panic!("Bug in typechecker: called a value that is not a function"),
+                    }
+                }
+                Instr::Return => {
+                    self.frames.pop();
+                    if self.frames.is_empty() {
+                        return Ok(self.pop());
+                    }
+                }
+                Instr::Succ => match self.pop() {
+                    Value::Nat(x) => self.stack.push(Value::Nat(x + 1)),
+                    _ => panic!("Bug in typechecker: succ of a value that is not a Nat"),
+                },
+                Instr::Pred => match self.pop() {
+                    Value::Nat(x) => self.stack.push(Value::Nat(x.saturating_sub(1))),
+                    _ => panic!("Bug in typechecker: pred of a value that is not a Nat"),
+                },
+                Instr::IsZero => match self.pop() {
+                    Value::Nat(x) => self.stack.push(Value::Bool(x == 0)),
+                    _ => panic!("Bug in typechecker: iszero of a value that is not a Nat"),
+                },
+                Instr::Jump(target) => frame.pc = *target,
+                Instr::JumpIfFalse(target) => match self.stack.pop() {
+                    Some(Value::Bool(true)) => {}
+                    Some(Value::Bool(false)) => frame.pc = *target,
+                    _ => panic!("Bug in typechecker: condition is not a Bool"),
+                },
+                Instr::JumpUnless(test, target) => {
+                    if !passes(test, &self.stack.pop().expect("Empty stack")) {
+                        frame.pc = *target;
+                    }
+                }
+                Instr::Record(fields) => {
+                    let values = self.stack.split_off(self.stack.len() - fields.len());
+                    let mut record: Vec<(usize, Value)> =
+                        fields.iter().cloned().zip(values).collect();
+                    record.sort_by_key(|(field, _)| *field);
+                    self.stack.push(Value::Record(Rc::new(record)));
+                }
+                Instr::Update(fields) => {
+                    let values = self.stack.split_off(self.stack.len() - fields.len());
+                    let mut record = self.record();
+                    for (field, value) in fields.iter().zip(values) {
+                        match record.binary_search_by_key(field, |(known, _)| *known) {
+                            Ok(position) => record[position].1 = value,
+                            Err(position) => record.insert(position, (*field, value)),
+                        }
+                    }
+                    self.stack.push(Value::Record(Rc::new(record)));
+                }
+                Instr::Replace(offsets) => {
+                    let values = self.stack.split_off(self.stack.len() - offsets.len());
+                    let mut record = self.record();
+                    for (offset, value) in offsets.iter().zip(values) {
+                        record[*offset].1 = value;
+                    }
+                    self.stack.push(Value::Record(Rc::new(record)));
+                }
+                Instr::Field(offset) => {
+                    let record = self.record();
+                    self.stack.push(record[*offset].1.clone());
+                }
+                Instr::Project(field) => {
+                    let record = self.record();
+                    let position = record
+                        .binary_search_by_key(field, |(known, _)| *known)
+                        .expect("Bug in typechecker: projected field was not found in record");
+                    self.stack.push(record[position].1.clone());
+                }
+                Instr::Restrict(field) => {
+                    let mut record = self.record();
+                    record.retain(|(known, _)| known != field);
+                    self.stack.push(Value::Record(Rc::new(record)));
+                }
+                Instr::Tag(tag) => {
+                    let value = self.pop();
+                    self.stack.push(Value::Variant(*tag, Rc::new(value)));
+                }
+                Instr::Untag => match self.pop() {
+                    Value::Variant(_, value) => self.stack.push((*value).clone()),
+                    _ => panic!("Bug in typechecker: matched value is not a variant"),
+                },
+                Instr::Fail => panic!("Bug in typechecker: no arm of case matches the argument"),
+            }
+        }
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("Empty stack")
+    }
+
+    /// Pops a record and returns its fields
+    fn record(&mut self) -> Vec<(usize, Value)> {
+        match self.pop() {
+            Value::Record(fields) => {
+                Rc::try_unwrap(fields).unwrap_or_else(|fields| (*fields).clone())
+            }
+            _ => panic!("Bug in typechecker: target is not a record"),
+        }
+    }
+
+    /// Pushes the value of a variable, recursive bindings are unfolded by calling their code
+    fn load(&mut self, value: Value) -> result::Result<(), EvalError> {
+        match value {
+            Value::Fix(function) => {
+                let point = Value::Fix(function.clone());
+                self.enter(function.code, vec![point], function.captured)
+            }
+            Value::Rec(group, index) => {
+                let members = (0..group.codes.len())
+                    .map(|member| Value::Rec(group.clone(), member))
+                    .collect();
+                self.enter(group.codes[index], members, group.captured.clone())
+            }
+            value => {
+                self.stack.push(value);
+                Ok(())
+            }
+        }
+    }
+
+    /// Starts running a code with the given arguments in its first slots
+    fn enter(
+        &mut self,
+        code: usize,
+        mut slots: Vec<Value>,
+        captured: Rc<Vec<Value>>,
+    ) -> result::Result<(), EvalError> {
+        if let Some(max) = self
+            .config
+            .max_depth
+            .filter(|max| self.frames.len() >= *max)
+        {
+            return Err(EvalError::TooDeep { depth: max });
+        }
+        slots.resize(self.program.codes[code].slots, Value::Nat(0));
+        self.frames.push(Frame {
+            code,
+            pc: 0,
+            slots,
+            captured,
+        });
+        Ok(())
+    }
+}
+
+fn capture_of(frame: &Frame, capture: &Capture) -> Value {
+    match capture {
+        Capture::Local(slot) => frame.slots[*slot].clone(),
+        Capture::Captured(index) => frame.captured[*index].clone(),
+    }
+}
+
+fn passes(test: &Test, value: &Value) -> bool {
+    match (test, value) {
+        (Test::True, Value::Bool(x)) => *x,
+        (Test::False, Value::Bool(x)) => !*x,
+        (Test::Zero, Value::Nat(x)) => *x == 0,
+        (Test::Succ, Value::Nat(x)) => *x != 0,
+        (Test::Tag(tag), Value::Variant(ident, _)) => tag == ident,
+        _ => false,
+    }
+}
+
+/// Helper to print a value of the machine with the names of its fields and tags
+pub struct Show<'v, 'p>(pub &'v Value, pub &'p Program);
+
+impl<'v, 'p> Display for Show<'v, 'p> {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        let Show(value, program) = *self;
+        match value {
+            Value::Nat(x) => write!(f, "{}", x),
+            Value::Bool(x) => write!(f, "{}", x),
+            Value::Closure(_) | Value::Fix(_) | Value::Rec(_, _) => write!(f, "<function>"),
+            Value::Record(fields) => {
+                let mut list: Vec<String> = fields
+                    .iter()
+                    .map(|(field, value)| {
+                        format!("{}={}", program.fields[*field], Show(value, program))
+                    })
+                    .collect();
+                list.sort();
+                write!(f, "{{{}}}", list.join(", "))
+            }
+            Value::Variant(tag, value) => {
+                write!(f, "<{}={}>", program.tags[*tag], Show(value, program))
+            }
+        }
+    }
+}
@@ -1,21 +1,95 @@
 extern crate lambda_rs;
 extern crate pest;
 
-use lambda_rs::{ast::build_ast, eval::OutputValue, parser::parse_file, read_file};
+use lambda_rs::ast::{build_ast, ASTNode, Operator, Type, TypeAssignment, Value};
+use lambda_rs::eval::{EvalConfig, EvalError, OutputValue, Strategy};
+use lambda_rs::ir::{anf, opt};
+use lambda_rs::machine::{Control, State};
+use lambda_rs::nameless::{self, Term};
+use lambda_rs::transform::cps;
+use lambda_rs::transform::specialize::{specialize, Annotation, BindingTime, SpecializeConfig};
+use lambda_rs::{bytecode, closure, codegen, vm};
+use lambda_rs::{nbe, parser::parse_file, read_file, small_step};
+use pest::Error;
 use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::process::{self, Command};
+use std::thread;
 
-fn run_file<'a>(filename: &'a str, expected: OutputValue) {
-    let contents = read_file(&filename).unwrap_or_else(|_e| panic!("Cant read file"));
+fn run_file(filename: &str, expected: OutputValue) {
+    let contents = read_file(filename).unwrap_or_else(|_e| panic!("Cant read file"));
     let pairs = parse_file(&contents).unwrap_or_else(|_e| {
         panic!("Problem when parsing file");
     });
     let ast_tree = build_ast(pairs);
-    let _tree_type = ast_tree
+    let tree_type = ast_tree
         .check::<i32>()
-        .unwrap_or_else(|e| panic!(format!("Typechecking for {} failed with {}", filename, e)));
+        .unwrap_or_else(|e| panic!("Typechecking for {} failed with {}", filename, e));
+    let inferred = ast_tree
+        .infer::<i32>()
+        .unwrap_or_else(|e| panic!("Type inference for {} failed with {}", filename, e));
+    assert_eq!(inferred.data_type, tree_type);
     assert_eq!(ast_tree.eval(), expected);
 }
 
+fn infer_file(filename: &str, expected_type: &str, expected: Option<OutputValue>) {
+    let contents = read_file(filename).unwrap_or_else(|_e| panic!("Cant read file"));
+    let pairs = parse_file(&contents).unwrap_or_else(|_e| {
+        panic!("Problem when parsing file");
+    });
+    let ast_tree = build_ast(pairs);
+    let inferred = ast_tree
+        .infer::<i32>()
+        .unwrap_or_else(|e| panic!("Type inference for {} failed with {}", filename, e));
+    assert_eq!(format!("{}", inferred), expected_type);
+    if let Some(expected) = expected {
+        assert_eq!(ast_tree.eval(), expected);
+    }
+}
+
+fn check_fails(filename: &str, message: &str) {
+    let contents = read_file(filename).unwrap_or_else(|_e| panic!("Cant read file"));
+    let pairs = parse_file(&contents).unwrap_or_else(|_e| {
+        panic!("Problem when parsing file");
+    });
+    let ast_tree = build_ast(pairs);
+    let result = ast_tree.check::<i32>();
+    if let Err(e) = &result {
+        // The error points at the source, which has to be printable for the command line
+        assert!(e.to_string().contains(message), "{}: {}", filename, e);
+    }
+    match result {
+        Ok(_) => panic!("Typechecking for {} should have failed", filename),
+        Err(Error::CustomErrorSpan { message: msg, .. }) => assert!(
+            msg.contains(message),
+            "Typechecking for {} failed with {}",
+            filename,
+            msg
+        ),
+        Err(e) => panic!("Typechecking for {} failed with {:?}", filename, e),
+    }
+}
+
+fn infer_fails(filename: &str, message: &str) {
+    let contents = read_file(filename).unwrap_or_else(|_e| panic!("Cant read file"));
+    let ast_tree = build_ast(parse_file(&contents).unwrap());
+    let result = ast_tree.infer::<i32>();
+    if let Err(e) = &result {
+        // The command line prints the error together with the source it points at
+        assert!(e.to_string().contains(message), "{}: {}", filename, e);
+    }
+    match result {
+        Err(Error::CustomErrorSpan { message: msg, .. }) => assert!(
+            msg.contains(message),
+            "Type inference for {} failed with {}",
+            filename,
+            msg
+        ),
+        result => panic!("Type inference should have failed, got {:?}", result),
+    }
+}
+
 #[test]
 fn evaluate_examples() {
     run_file("examples/correct0.lambda", OutputValue::Nat(2));
@@ -37,4 +111,1293 @@ fn evaluate_examples() {
     run_file("examples/variant2.lambda", OutputValue::Nat(3));
     run_file("examples/iseven1.lambda", OutputValue::Bool(true));
     run_file("examples/iseven2.lambda", OutputValue::Bool(false));
+    run_file("examples/nested_pattern.lambda", OutputValue::Bool(true));
+    run_file("examples/nat_pattern.lambda", OutputValue::Nat(3));
+    run_file("examples/let_record.lambda", OutputValue::Nat(2));
+    run_file("examples/pattern_binder.lambda", OutputValue::Nat(2));
+
+    let mut testmap = HashMap::new();
+    testmap.insert("status".to_string(), OutputValue::Bool(false));
+    testmap.insert("result".to_string(), OutputValue::Nat(2));
+
+    run_file(
+        "examples/record_update.lambda",
+        OutputValue::Record(testmap),
+    );
+    run_file("examples/record_extension.lambda", OutputValue::Nat(2));
+    run_file("examples/even_odd.lambda", OutputValue::Bool(false));
+    run_file("examples/fix_plus.lambda", OutputValue::Nat(5));
+    run_file("examples/scope_exit.lambda", OutputValue::Nat(1));
+    run_file("examples/paren_projection.lambda", OutputValue::Nat(1));
+}
+
+#[test]
+fn evaluate_function_examples() {
+    let applications = [
+        ("examples/power.lambda", 2, 8),
+        ("examples/interpreter.lambda", 2, 5),
+        ("examples/interpreter.lambda", 0, 1),
+    ];
+    for (filename, argument, expected) in applications.iter() {
+        let source = format!(
+            "let f = {} in f {}0",
+            read_file(filename).unwrap(),
+            "succ ".repeat(*argument)
+        );
+        let ast_tree = build_ast(parse_file(&source).unwrap());
+        assert_eq!(ast_tree.eval(), OutputValue::Nat(*expected), "{}", filename);
+    }
+}
+
+#[test]
+fn reject_spaced_projections() {
+    for source in &[
+        "let r = {a=0} in r . a",
+        "let r = {a=0} in (r) .a",
+        "let r = {a=0, b=0} in (r) \\ b",
+    ] {
+        assert!(
+            parse_file(source).is_err(),
+            "Source {} should not parse",
+            source
+        );
+    }
+}
+
+#[test]
+fn reject_duplicate_pattern_fields() {
+    for source in &[
+        "let {a=x, a=y} = {a=0} in x",
+        "case {a=0, b=0} of {b=_, a=0, b=y} => y | _ => 0",
+        "(@ {a=x, b={c=y, c=z}}. x) {a=0, b={c=0}}",
+    ] {
+        match parse_file(source) {
+            Err(Error::CustomErrorSpan { message, .. }) => assert!(
+                message.contains("is matched more than once in a record pattern"),
+                "{}: {}",
+                source,
+                message
+            ),
+            _ => panic!("Source {} should not parse", source),
+        }
+    }
+}
+
+#[test]
+fn infer_examples() {
+    infer_file(
+        "examples/infer_projection.lambda",
+        "forall r. ({x:Nat | r} -> Nat)",
+        None,
+    );
+    infer_file(
+        "examples/row_polymorphism.lambda",
+        "Nat",
+        Some(OutputValue::Nat(1)),
+    );
+    infer_file(
+        "examples/open_variant.lambda",
+        "Nat",
+        Some(OutputValue::Nat(2)),
+    );
+    infer_file(
+        "examples/letrec_infer.lambda",
+        "Bool",
+        Some(OutputValue::Bool(true)),
+    );
+}
+
+#[test]
+fn reject_incorrect_examples() {
+    check_fails(
+        "examples/incorrect1.lambda",
+        "zero check should be of type Nat",
+    );
+    check_fails("examples/incorrect2.lambda", "should be of type Bool");
+    check_fails(
+        "examples/incorrect3.lambda",
+        "values matching <some=0> are not handled",
+    );
+    check_fails(
+        "examples/incorrect4.lambda",
+        "Arm 3 of the case expression can never be reached",
+    );
+    check_fails(
+        "examples/incorrect5.lambda",
+        "Field value of pattern is not part of the record type",
+    );
+    check_fails(
+        "examples/incorrect6.lambda",
+        "Updated attribute should keep the type it has in the record",
+    );
+    check_fails(
+        "examples/incorrect8.lambda",
+        "Recursive binding of loop should be an abstraction",
+    );
+    check_fails("examples/incorrect9.lambda", "Identifier is not defined");
+    check_fails(
+        "examples/incorrect10.lambda",
+        "Incorrect type of right argument in an application",
+    );
+}
+
+#[test]
+fn reject_incorrect_inferred_examples() {
+    infer_fails(
+        "examples/incorrect3.lambda",
+        "values matching <some=0> are not handled",
+    );
+    infer_fails(
+        "examples/incorrect4.lambda",
+        "Arm 3 of the case expression can never be reached",
+    );
+    infer_fails(
+        "examples/incorrect7.lambda",
+        "Attribute marked is already part of the record type",
+    );
+    infer_fails("examples/incorrect9.lambda", "Identifier is not defined");
+    infer_fails(
+        "examples/incorrect10.lambda",
+        "type Nat does not match type (Nat -> Nat)",
+    );
+}
+
+#[test]
+fn infer_fresh_variables_in_a_fixed_order() {
+    // The fields of the pattern get their variables in alphabetical order, so the error names
+    // the same variables every time
+    for _ in 0..20 {
+        infer_fails(
+            "examples/incorrect11.lambda",
+            "Type t2 can't be equal to (t2 -> t7) as it occurs in it",
+        );
+    }
+}
+
+#[test]
+fn compare_function_values() {
+    let files = [
+        "examples/function_a.lambda",
+        "examples/function_b.lambda",
+        "examples/function_c.lambda",
+        "examples/function_d.lambda",
+    ];
+    let sources: Vec<String> = files.iter().map(|f| read_file(f).unwrap()).collect();
+    let trees: Vec<_> = sources
+        .iter()
+        .map(|s| build_ast(parse_file(s).unwrap()))
+        .collect();
+    let values: Vec<OutputValue> = trees.iter().map(|t| t.eval()).collect();
+
+    assert_eq!(values[0], values[1]);
+    assert_ne!(values[0], values[2]);
+    assert_ne!(values[2], values[3]);
+    assert_eq!(values[2], values[2].clone());
+}
+
+#[test]
+fn nameless_round_trip() {
+    let files = [
+        "examples/correct2.lambda",
+        "examples/high-order.lambda",
+        "examples/variant2.lambda",
+        "examples/iseven1.lambda",
+        "examples/nested_pattern.lambda",
+        "examples/pattern_binder.lambda",
+        "examples/record_update.lambda",
+        "examples/even_odd.lambda",
+        "examples/scope_exit.lambda",
+    ];
+    for filename in files.iter() {
+        let contents = read_file(filename).unwrap();
+        let ast_tree = build_ast(parse_file(&contents).unwrap());
+        let term = nameless::to_nameless(&ast_tree);
+        let restored = nameless::from_nameless(&term);
+        assert!(
+            nameless::alpha_eq(&term, &nameless::to_nameless(&restored)),
+            "Converting {} back changed its meaning",
+            filename
+        );
+        assert_eq!(restored.eval(), ast_tree.eval());
+    }
+}
+
+/// Returns whether a term resulting from the small-step reducer is the value computed by `eval`
+fn same_value(term: &Term, value: &OutputValue) -> bool {
+    match (term, value) {
+        (Term::Value(Value::Zero), OutputValue::Nat(0)) => true,
+        (Term::Arith(Operator::Succ, inner), OutputValue::Nat(n)) if *n > 0 => {
+            same_value(inner, &OutputValue::Nat(n - 1))
+        }
+        (Term::Value(Value::True), OutputValue::Bool(true)) => true,
+        (Term::Value(Value::False), OutputValue::Bool(false)) => true,
+        (Term::Record(records), OutputValue::Record(values)) => {
+            records.len() == values.len()
+                && records
+                    .iter()
+                    .all(|(name, t)| values.get(name).is_some_and(|v| same_value(t, v)))
+        }
+        (Term::Tag(tag, inner, _), OutputValue::Variant(ident, v, _)) => {
+            tag == ident && same_value(inner, v)
+        }
+        (Term::Abs(_, _, _), OutputValue::Func(_, _)) => {
+            nameless::alpha_eq(&nbe::normalize(term), &nbe::normalize(&value.quote()))
+        }
+        _ => false,
+    }
+}
+
+#[test]
+fn small_step_agrees_with_eval() {
+    let files = [
+        "examples/correct0.lambda",
+        "examples/correct1.lambda",
+        "examples/correct2.lambda",
+        "examples/correct3.lambda",
+        "examples/correct4.lambda",
+        "examples/correct5.lambda",
+        "examples/arrowtype.lambda",
+        "examples/high-order.lambda",
+        "examples/record.lambda",
+        "examples/record_proj.lambda",
+        "examples/variant1.lambda",
+        "examples/variant2.lambda",
+        "examples/iseven1.lambda",
+        "examples/iseven2.lambda",
+        "examples/nested_pattern.lambda",
+        "examples/nat_pattern.lambda",
+        "examples/let_record.lambda",
+        "examples/pattern_binder.lambda",
+        "examples/record_update.lambda",
+        "examples/record_extension.lambda",
+        "examples/row_polymorphism.lambda",
+        "examples/open_variant.lambda",
+        "examples/even_odd.lambda",
+        "examples/letrec_infer.lambda",
+        "examples/fix_plus.lambda",
+        "examples/scope_exit.lambda",
+        "examples/function_b.lambda",
+    ];
+    for filename in files.iter() {
+        let contents = read_file(filename).unwrap();
+        let ast_tree = build_ast(parse_file(&contents).unwrap());
+        let result = small_step::normalize(&nameless::to_nameless(&ast_tree));
+        assert!(
+            same_value(&result, &ast_tree.eval()),
+            "Small-step reduction of {} resulted in {:?}",
+            filename,
+            result
+        );
+    }
+}
+
+#[test]
+fn small_step_reductions() {
+    let contents = read_file("examples/redex.lambda").unwrap();
+    let ast_tree = build_ast(parse_file(&contents).unwrap());
+    let mut term = nameless::to_nameless(&ast_tree);
+    let mut steps = 0;
+    while let Some(next) = small_step::step(&term) {
+        term = next;
+        steps += 1;
+    }
+    assert_eq!(steps, 3);
+    assert!(small_step::is_value(&term));
+}
+
+#[test]
+fn evaluate_with_strategies() {
+    let files = [
+        "examples/correct2.lambda",
+        "examples/high-order.lambda",
+        "examples/variant2.lambda",
+        "examples/iseven1.lambda",
+        "examples/even_odd.lambda",
+        "examples/fix_plus.lambda",
+        "examples/pattern_binder.lambda",
+    ];
+    for filename in files.iter() {
+        let contents = read_file(filename).unwrap();
+        let ast_tree = build_ast(parse_file(&contents).unwrap());
+        let expected = ast_tree.eval();
+        assert_eq!(ast_tree.eval_using(Strategy::CallByName), expected);
+        assert_eq!(ast_tree.eval_using(Strategy::CallByNeed), expected);
+    }
+
+    let diverging = [
+        "examples/diverging_argument.lambda",
+        "examples/diverging_let.lambda",
+    ];
+    for filename in diverging.iter() {
+        let contents = read_file(filename).unwrap();
+        let ast_tree = build_ast(parse_file(&contents).unwrap());
+        assert_eq!(
+            ast_tree.eval_using(Strategy::CallByName),
+            OutputValue::Nat(0),
+            "{}",
+            filename
+        );
+        assert_eq!(
+            ast_tree.eval_using(Strategy::CallByNeed),
+            OutputValue::Nat(0),
+            "{}",
+            filename
+        );
+    }
+}
+
+#[test]
+fn print_source_round_trip() {
+    let files = [
+        "examples/correct0.lambda",
+        "examples/correct1.lambda",
+        "examples/correct2.lambda",
+        "examples/correct4.lambda",
+        "examples/correct5.lambda",
+        "examples/arrowtype.lambda",
+        "examples/high-order.lambda",
+        "examples/record_proj.lambda",
+        "examples/variant1.lambda",
+        "examples/variant2.lambda",
+        "examples/iseven1.lambda",
+        "examples/iseven2.lambda",
+        "examples/nested_pattern.lambda",
+        "examples/nat_pattern.lambda",
+        "examples/let_record.lambda",
+        "examples/pattern_binder.lambda",
+        "examples/record_update.lambda",
+        "examples/paren_projection.lambda",
+        "examples/record_target.lambda",
+        "examples/record_extension.lambda",
+        "examples/infer_projection.lambda",
+        "examples/row_polymorphism.lambda",
+        "examples/open_variant.lambda",
+        "examples/even_odd.lambda",
+        "examples/letrec_infer.lambda",
+        "examples/fix_plus.lambda",
+        "examples/scope_exit.lambda",
+        "examples/function_b.lambda",
+    ];
+    for filename in files.iter() {
+        let contents = read_file(filename).unwrap();
+        let ast_tree = build_ast(parse_file(&contents).unwrap());
+        let source = ast_tree.source().to_string();
+        let reparsed = build_ast(parse_file(&source).unwrap_or_else(|e| {
+            panic!(
+                "Source {} printed for {} does not parse: {:?}",
+                source, filename, e
+            )
+        }));
+        assert!(
+            nameless::alpha_eq(
+                &nameless::to_nameless(&ast_tree),
+                &nameless::to_nameless(&reparsed)
+            ),
+            "Source {} printed for {} parses to a different tree",
+            source,
+            filename
+        );
+    }
+}
+
+#[test]
+fn normalize_functions() {
+    let files = [
+        ("examples/twice.lambda", "examples/twice_normal.lambda"),
+        (
+            "examples/partial_plus.lambda",
+            "examples/partial_plus_normal.lambda",
+        ),
+        ("examples/function_b.lambda", "examples/function_a.lambda"),
+    ];
+    for (filename, normal_file) in files.iter() {
+        let contents = read_file(filename).unwrap();
+        let ast_tree = build_ast(parse_file(&contents).unwrap());
+        let expected = read_file(normal_file).unwrap();
+        let expected_tree = build_ast(parse_file(&expected).unwrap());
+        let normal = nbe::normalize(&nameless::to_nameless(&ast_tree));
+        assert!(
+            nameless::alpha_eq(&normal, &nameless::to_nameless(&expected_tree)),
+            "Normal form of {} is {:?}",
+            filename,
+            normal
+        );
+    }
+}
+
+#[test]
+fn print_normal_forms_round_trip() {
+    let files = [
+        "examples/correct3.lambda",
+        "examples/high-order.lambda",
+        "examples/record.lambda",
+        "examples/twice.lambda",
+        "examples/partial_plus.lambda",
+        "examples/open_recursion.lambda",
+        "examples/even_odd.lambda",
+        "examples/fix_plus.lambda",
+        "examples/variant2.lambda",
+        "examples/function_c.lambda",
+        "examples/function_d.lambda",
+        "examples/capture.lambda",
+        "examples/power.lambda",
+        "examples/interpreter.lambda",
+    ];
+    for filename in files.iter() {
+        let contents = read_file(filename).unwrap();
+        let ast_tree = build_ast(parse_file(&contents).unwrap());
+        let normal = nbe::normalize(&nameless::to_nameless(&ast_tree));
+        let source = nameless::from_nameless(&normal).source().to_string();
+        let reparsed = build_ast(parse_file(&source).unwrap_or_else(|e| {
+            panic!(
+                "Source {} printed for {} does not parse: {:?}",
+                source, filename, e
+            )
+        }));
+        assert!(
+            nameless::alpha_eq(&normal, &nameless::to_nameless(&reparsed)),
+            "Source {} printed for {} parses to a different term",
+            source,
+            filename
+        );
+        assert!(
+            reparsed.infer::<i32>().is_ok(),
+            "Source {} does not typecheck",
+            source
+        );
+    }
+}
+
+#[test]
+fn print_normal_forms_with_fresh_names() {
+    let files = [
+        ("examples/twice.lambda", "(@ x: Nat. succ succ x)"),
+        ("examples/capture.lambda", "(@ y: Nat. (@ y1: Nat. succ y))"),
+    ];
+    for (filename, expected) in files.iter() {
+        let contents = read_file(filename).unwrap();
+        let ast_tree = build_ast(parse_file(&contents).unwrap());
+        let normal = nbe::normalize(&nameless::to_nameless(&ast_tree));
+        let source = nameless::from_nameless(&normal).source().to_string();
+        assert_eq!(&source, expected, "{}", filename);
+    }
+}
+
+#[test]
+fn normalize_recursive_functions() {
+    let contents = read_file("examples/power.lambda").unwrap();
+    let term = nameless::to_nameless(&build_ast(parse_file(&contents).unwrap()));
+    let source = nameless::from_nameless(&nbe::normalize(&term))
+        .source()
+        .to_string();
+    assert_eq!(
+        source,
+        "letrec plus: (Nat -> (Nat -> Nat)) = (@ m: Nat. (@ n: Nat. if iszero m then n else \
+         succ ((plus (pred m)) n))) in letrec times: (Nat -> (Nat -> Nat)) = (@ m: Nat. (@ n: Nat. \
+         if iszero m then 0 else (plus n) ((times (pred m)) n))) in \
+         (@ x: Nat. (times x) ((times x) ((times x) (succ 0))))"
+    );
+
+    // Calls beyond the limit are kept
+    let config = EvalConfig {
+        max_steps: Some(1),
+        max_depth: None,
+    };
+    let source = nameless::from_nameless(&nbe::normalize_with(&term, config))
+        .source()
+        .to_string();
+    assert!(
+        source.ends_with("(@ x: Nat. (times x) ((power (succ succ 0)) x))"),
+        "{}",
+        source
+    );
+}
+
+#[test]
+fn evaluate_with_limits() {
+    let contents = read_file("examples/loop_forever.lambda").unwrap();
+    let ast_tree = build_ast(parse_file(&contents).unwrap());
+    let steps = EvalConfig {
+        max_steps: Some(1000),
+        max_depth: None,
+    };
+    assert_eq!(
+        ast_tree.eval_with(Strategy::CallByValue, steps),
+        Err(EvalError::OutOfFuel { steps: 1000 })
+    );
+    let contents = read_file("examples/grow_forever.lambda").unwrap();
+    let ast_tree = build_ast(parse_file(&contents).unwrap());
+    let depth = EvalConfig {
+        max_steps: None,
+        max_depth: Some(100),
+    };
+    for strategy in [
+        Strategy::CallByValue,
+        Strategy::CallByName,
+        Strategy::CallByNeed,
+    ]
+    .iter()
+    {
+        assert_eq!(
+            ast_tree.eval_with(*strategy, depth),
+            Err(EvalError::TooDeep { depth: 100 })
+        );
+    }
+
+    let contents = read_file("examples/iseven1.lambda").unwrap();
+    let ast_tree = build_ast(parse_file(&contents).unwrap());
+    assert_eq!(
+        ast_tree.eval_with(Strategy::CallByValue, steps),
+        Ok(OutputValue::Bool(true))
+    );
+}
+
+#[test]
+fn evaluate_deep_recursion() {
+    let deep = [
+        ("examples/deep_count.lambda", OutputValue::Nat(131072)),
+        ("examples/deep_even.lambda", OutputValue::Bool(false)),
+    ];
+    for (filename, expected) in deep.iter() {
+        let contents = read_file(filename).unwrap();
+        let ast_tree = build_ast(parse_file(&contents).unwrap());
+        assert_eq!(&ast_tree.eval(), expected);
+        assert_eq!(&ast_tree.eval_using(Strategy::CallByNeed), expected);
+    }
+}
+
+#[test]
+fn machine_transitions() {
+    let contents = read_file("examples/redex.lambda").unwrap();
+    let ast_tree = build_ast(parse_file(&contents).unwrap());
+    let mut state = State::new(&ast_tree);
+    assert_eq!(
+        state.to_string(),
+        "control: (@ a: Nat. iszero a) (pred 0)\nenv:     empty\nkont:    halt"
+    );
+    for _ in 0..3 {
+        state = state.step(Strategy::CallByValue);
+    }
+    assert_eq!(
+        state.to_json(),
+        r#"{"control": {"term": "pred 0"}, "env": [], "kont": ["(@ a. iszero a) []"]}"#
+    );
+    for _ in 0..4 {
+        state = state.step(Strategy::CallByValue);
+    }
+    assert!(matches!(state.control, Control::Term(_)));
+    assert_eq!(
+        state.to_json(),
+        r#"{"control": {"term": "iszero a"}, "env": [{"name": "a", "binding": "0"}], "kont": []}"#
+    );
+    let mut transitions = 7;
+    while !state.is_final() {
+        state = state.step(Strategy::CallByValue);
+        transitions += 1;
+    }
+    assert_eq!(transitions, 10);
+    assert_eq!(state.result(), Some(OutputValue::Bool(true)));
+}
+
+/// The correct examples every backend runs, checked against the evaluator
+const PROGRAMS: [&str; 39] = [
+    "examples/correct0.lambda",
+    "examples/correct1.lambda",
+    "examples/correct2.lambda",
+    "examples/correct3.lambda",
+    "examples/correct4.lambda",
+    "examples/correct5.lambda",
+    "examples/arrowtype.lambda",
+    "examples/high-order.lambda",
+    "examples/record.lambda",
+    "examples/record_proj.lambda",
+    "examples/variant1.lambda",
+    "examples/variant2.lambda",
+    "examples/iseven1.lambda",
+    "examples/iseven2.lambda",
+    "examples/nested_pattern.lambda",
+    "examples/nat_pattern.lambda",
+    "examples/let_record.lambda",
+    "examples/pattern_binder.lambda",
+    "examples/record_update.lambda",
+    "examples/record_extension.lambda",
+    "examples/infer_projection.lambda",
+    "examples/row_polymorphism.lambda",
+    "examples/open_variant.lambda",
+    "examples/even_odd.lambda",
+    "examples/letrec_infer.lambda",
+    "examples/fix_plus.lambda",
+    "examples/scope_exit.lambda",
+    "examples/function_b.lambda",
+    "examples/function_c.lambda",
+    "examples/twice.lambda",
+    "examples/partial_plus.lambda",
+    "examples/open_recursion.lambda",
+    "examples/fix_general.lambda",
+    "examples/redex.lambda",
+    "examples/paren_projection.lambda",
+    "examples/power.lambda",
+    "examples/interpreter.lambda",
+    "examples/nested_function.lambda",
+    "examples/letrec_function.lambda",
+];
+
+/// The examples that recurse too deep for backends that evaluate on the Rust stack, with what they
+/// print. Evaluating them takes a while, so their output is not taken from the evaluator.
+const DEEP_PROGRAMS: [(&str, &str); 3] = [
+    ("examples/deep_count.lambda", "131072"),
+    ("examples/deep_even.lambda", "false"),
+    ("examples/tail_loop.lambda", "true"),
+];
+
+/// Returns the source of the normal form of a program
+fn normal_form(ast_tree: &ASTNode<'_>) -> String {
+    let normal = nbe::normalize(&nameless::to_nameless(ast_tree));
+    nameless::from_nameless(&normal).source().to_string()
+}
+
+/// Returns a program applying the function computed by the source to numbers and booleans until
+/// it computes something else, or `None` when the source does not compute a function taking them
+fn applied(source: &str, ast_tree: &ASTNode<'_>) -> Option<String> {
+    let mut data_type = ast_tree.infer::<i32>().unwrap().data_type;
+    let mut application = "f".to_string();
+    while let TypeAssignment::Arrow(from, to) = data_type {
+        let argument = match *from {
+            TypeAssignment::Single(Type::Nat) => "succ succ 0",
+            TypeAssignment::Single(Type::Bool) => "true",
+            _ => return None,
+        };
+        application = format!("({}) ({})", application, argument);
+        data_type = *to;
+    }
+    if application == "f" {
+        return None;
+    }
+    Some(format!("let f = {} in {}", source.trim_end(), application))
+}
+
+/// Runs every example with the backend and compares what it prints to what the interpreter prints
+/// when evaluating the example. Every backend prints functions as `<function>`, so examples that
+/// compute a function are also compared once they are applied to arguments.
+///
+/// # Arguments
+/// * `files` - The examples to run
+/// * `backend` - Runs the program built from the named file and returns what it prints
+fn agrees_with_eval<F>(files: &[&str], backend: F)
+where
+    F: Fn(&str, &ASTNode<'_>) -> String,
+{
+    for filename in files {
+        let contents = read_file(filename).unwrap();
+        let ast_tree = build_ast(parse_file(&contents).unwrap());
+        let expected = ast_tree.eval().to_string();
+        assert_eq!(backend(filename, &ast_tree), expected, "{}", filename);
+
+        if let Some(source) = applied(&contents, &ast_tree) {
+            let applied_tree = build_ast(parse_file(&source).unwrap());
+            let expected = applied_tree.eval().to_string();
+            let name = filename.replace(".lambda", "_applied.lambda");
+            assert_eq!(backend(&name, &applied_tree), expected, "{}", name);
+        }
+    }
+}
+
+/// Runs every example with the backend and compares what it prints to the expected output
+///
+/// # Arguments
+/// * `programs` - The examples to run, with their expected output
+/// * `backend` - Runs the program built from the named file and returns what it prints
+fn prints<F>(programs: &[(&str, &str)], backend: F)
+where
+    F: Fn(&str, &ASTNode<'_>) -> String,
+{
+    for (filename, expected) in programs {
+        let contents = read_file(filename).unwrap();
+        let ast_tree = build_ast(parse_file(&contents).unwrap());
+        assert_eq!(backend(filename, &ast_tree), *expected, "{}", filename);
+    }
+}
+
+/// Compares what the backend makes of every example to its golden file
+///
+/// # Arguments
+/// * `names` - The examples, without their directory and extension
+/// * `golden` - The path of the golden files, with `{}` in place of the name of the example
+/// * `backend` - Returns the output for the program built from the named example
+fn matches_golden_files<F>(names: &[&str], golden: &str, backend: F)
+where
+    F: Fn(&str, &ASTNode<'_>) -> String,
+{
+    for name in names {
+        let contents = read_file(&format!("examples/{}.lambda", name)).unwrap();
+        let ast_tree = build_ast(parse_file(&contents).unwrap());
+        let expected = read_file(&golden.replace("{}", name)).unwrap();
+        assert_eq!(backend(name, &ast_tree), expected, "{}", name);
+    }
+}
+
+/// Runs the program on the virtual machine and returns what the interpreter prints for it
+fn run_vm(ast_tree: &ASTNode<'_>) -> String {
+    let program = bytecode::compile(ast_tree);
+    let value = vm::run(&program, EvalConfig::default()).unwrap();
+    vm::Show(&value, &program).to_string()
+}
+
+#[test]
+fn vm_agrees_with_eval() {
+    agrees_with_eval(&PROGRAMS, |_, ast_tree| run_vm(ast_tree));
+    prints(&DEEP_PROGRAMS, |_, ast_tree| run_vm(ast_tree));
+
+    let contents = read_file("examples/loop_forever.lambda").unwrap();
+    let program = bytecode::compile(&build_ast(parse_file(&contents).unwrap()));
+    let config = EvalConfig {
+        max_steps: Some(1000),
+        max_depth: Some(10),
+    };
+    assert_eq!(
+        vm::run(&program, config).unwrap_err(),
+        EvalError::OutOfFuel { steps: 1000 }
+    );
+}
+
+/// Compiles the program to closures and returns what the interpreter prints for its value
+fn run_closures(filename: &str, ast_tree: &ASTNode<'_>) -> String {
+    let program = closure::compile(ast_tree);
+    let result = program.run();
+
+    // Compiled programs keep no state between runs
+    assert_eq!(
+        program.run().to_string(),
+        result.to_string(),
+        "{}",
+        filename
+    );
+    result.to_string()
+}
+
+#[test]
+fn vm_reads_closed_records_at_fixed_offsets() {
+    let contents = read_file("examples/row_polymorphism.lambda").unwrap();
+    let program = bytecode::compile(&build_ast(parse_file(&contents).unwrap()));
+    let instrs: Vec<&bytecode::Instr> =
+        program.codes.iter().flat_map(|code| &code.instrs).collect();
+
+    // getx takes records with more fields than its type lists, q.z is read at its offset
+    let x = program
+        .fields
+        .iter()
+        .position(|field| field == "x")
+        .unwrap();
+    let z = program
+        .fields
+        .iter()
+        .position(|field| field == "z")
+        .unwrap();
+    assert!(instrs.contains(&&bytecode::Instr::Project(x)));
+    assert!(instrs.contains(&&bytecode::Instr::Field(usize::from(z > x))));
+    assert!(!instrs.contains(&&bytecode::Instr::Project(z)));
+}
+
+#[test]
+fn closures_agree_with_eval() {
+    agrees_with_eval(&PROGRAMS, run_closures);
+
+    // Calls in tail position are made by a loop in the caller
+    prints(&DEEP_PROGRAMS[2..], run_closures);
+}
+
+#[test]
+fn tail_calls_run_in_constant_space() {
+    let contents = read_file("examples/tail_loop.lambda").unwrap();
+    let ast_tree = build_ast(parse_file(&contents).unwrap());
+    let shallow = |depth| EvalConfig {
+        max_steps: None,
+        max_depth: Some(depth),
+    };
+    assert_eq!(
+        ast_tree.eval_with(Strategy::CallByValue, shallow(20)),
+        Ok(OutputValue::Bool(true))
+    );
+    assert_eq!(
+        ast_tree.eval_with(Strategy::CallByNeed, shallow(30)),
+        Ok(OutputValue::Bool(true))
+    );
+    let program = bytecode::compile(&ast_tree);
+    assert!(matches!(
+        vm::run(&program, shallow(5)),
+        Ok(vm::Value::Bool(true))
+    ));
+}
+
+/// Compiles the program to C, builds it with the system compiler at its default optimization level
+/// and the flags the generated file asks for, and returns what it printed
+fn run_c(filename: &str, ast_tree: &ASTNode<'_>) -> String {
+    let name = filename
+        .trim_start_matches("examples/")
+        .replace(".lambda", "");
+    let dir = env::temp_dir().join(format!("lambda-rs-c-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let source = dir.join(format!("{}.c", name));
+    let binary = dir.join(&name);
+    fs::write(&source, codegen::c::compile(ast_tree)).unwrap();
+
+    let build = Command::new("cc")
+        .args([
+            "-std=c99",
+            "-pthread",
+            "-Wall",
+            "-Wextra",
+            "-pedantic",
+            "-o",
+        ])
+        .arg(&binary)
+        .arg(&source)
+        .output()
+        .expect("Could not run cc");
+    assert!(
+        build.status.success() && build.stderr.is_empty(),
+        "C compiled from {} does not build cleanly:\n{}",
+        filename,
+        String::from_utf8_lossy(&build.stderr)
+    );
+    let run = Command::new(&binary).output().unwrap();
+    assert!(run.status.success(), "C compiled from {} failed", filename);
+    let output = String::from_utf8(run.stdout).unwrap();
+    output.trim_end().to_string()
+}
+
+#[test]
+fn compiled_c_agrees_with_eval() {
+    if Command::new("cc").arg("--version").output().is_err() {
+        eprintln!("cc is not available, the compiled C is not run");
+        return;
+    }
+    agrees_with_eval(&PROGRAMS, run_c);
+    prints(&DEEP_PROGRAMS, run_c);
+}
+
+#[test]
+fn compiled_js_matches_golden_files() {
+    let files = [
+        "tail_loop",
+        "fix_plus",
+        "variant2",
+        "record_update",
+        "record_extension",
+        "open_recursion",
+        "nested_pattern",
+    ];
+    matches_golden_files(&files, "tests/golden/js/{}.js", |_, ast_tree| {
+        codegen::js::compile(ast_tree)
+    });
+}
+
+#[test]
+fn compiled_wat_matches_golden_files() {
+    let files = ["fix_plus", "variant2", "record_update", "even_odd"];
+    // Every module contains the whole runtime, which is left out of the golden files so they only
+    // change with the code generated for the program
+    let runtime = include_str!("../src/codegen/runtime.wat");
+    matches_golden_files(&files, "tests/golden/wat/{}.wat", |name, ast_tree| {
+        let module = codegen::wat::compile(ast_tree);
+        assert!(module.contains(runtime), "{}", name);
+        module.replacen(runtime, "  ;; runtime.wat\n", 1)
+    });
+}
+
+#[test]
+fn compiled_wat_is_valid() {
+    let deep = DEEP_PROGRAMS.iter().map(|(filename, _)| filename);
+    for filename in PROGRAMS.iter().chain(deep) {
+        let contents = read_file(filename).unwrap();
+        let ast_tree = build_ast(parse_file(&contents).unwrap());
+        let module = codegen::wat::compile(&ast_tree);
+        assert_eq!(codegen::wat::validate(&module), Ok(()), "{}", filename);
+    }
+
+    let contents = read_file("examples/fix_plus.lambda").unwrap();
+    let module = codegen::wat::compile(&build_ast(parse_file(&contents).unwrap()));
+    let broken = [
+        module.replacen("call $apply", "call $missing", 1),
+        module.replacen("call $succ", "call $succ\n    drop", 1),
+        module.replacen("global.set $heap", "global.set $text_true", 1),
+        module.replacen("br $grow", "br $nowhere", 1),
+        module.replacen("    end)", "    )", 1),
+        module.replacen(
+            "(elem (i32.const 0) $f0",
+            "(elem (i32.const 0) $f0 $f0 $f0 $f0 $f0",
+            1,
+        ),
+        module.replacen("local.get $arg", "local.get $argument", 1),
+    ];
+    for (index, module) in broken.iter().enumerate() {
+        assert!(codegen::wat::validate(module).is_err(), "{}", index);
+    }
+}
+
+#[test]
+fn compiled_js_agrees_with_eval() {
+    if Command::new("node").arg("--version").output().is_err() {
+        eprintln!(
+            "node is not available, the compiled JavaScript is only compared to golden files"
+        );
+        return;
+    }
+    let dir = env::temp_dir().join(format!("lambda-rs-js-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let run_js = |filename: &str, ast_tree: &ASTNode<'_>| {
+        let script = dir.join(
+            filename
+                .trim_start_matches("examples/")
+                .replace(".lambda", ".js"),
+        );
+        fs::write(&script, codegen::js::compile(ast_tree)).unwrap();
+        let run = Command::new("node").arg(&script).output().unwrap();
+        assert!(
+            run.status.success(),
+            "JavaScript compiled from {} failed:\n{}",
+            filename,
+            String::from_utf8_lossy(&run.stderr)
+        );
+        let output = String::from_utf8(run.stdout).unwrap();
+        output.trim_end().to_string()
+    };
+    agrees_with_eval(&PROGRAMS, run_js);
+    prints(&DEEP_PROGRAMS, run_js);
+}
+
+#[test]
+fn cps_matches_golden_files() {
+    let files = ["correct3", "variant2", "let_record", "even_odd", "fix_plus"];
+    matches_golden_files(&files, "tests/golden/cps/{}.cps", |_, ast_tree| {
+        format!("{}\n", cps::convert(ast_tree))
+    });
+}
+
+/// Runs the program in continuation-passing style and returns what the interpreter prints for it
+fn run_cps(ast_tree: &ASTNode<'_>) -> String {
+    cps::run(&cps::convert(ast_tree)).to_string()
+}
+
+#[test]
+fn cps_agrees_with_eval() {
+    agrees_with_eval(&PROGRAMS, |_, ast_tree| run_cps(ast_tree));
+
+    // Continuations live on the heap, so deep recursion needs no stack
+    prints(&DEEP_PROGRAMS, |_, ast_tree| run_cps(ast_tree));
+}
+
+#[test]
+fn anf_matches_golden_files() {
+    let files = ["correct3", "variant2", "let_record", "even_odd", "fix_plus"];
+    matches_golden_files(&files, "tests/golden/anf/{}.anf", |_, ast_tree| {
+        format!("{}\n", anf::convert(ast_tree))
+    });
+}
+
+/// Runs the program in A-normal form and returns what the interpreter prints for it
+fn run_anf(term: &anf::Term) -> String {
+    anf::run(term).to_string()
+}
+
+#[test]
+fn anf_agrees_with_eval() {
+    agrees_with_eval(&PROGRAMS, |_, ast_tree| run_anf(&anf::convert(ast_tree)));
+
+    // Calls in tail position reuse the loop of the interpreter
+    prints(&DEEP_PROGRAMS[2..], |_, ast_tree| {
+        run_anf(&anf::convert(ast_tree))
+    });
+}
+
+#[test]
+fn optimized_anf_matches_golden_files() {
+    let files = [
+        "correct1",
+        "correct4",
+        "redex",
+        "variant2",
+        "record_update",
+        "twice",
+    ];
+    matches_golden_files(&files, "tests/golden/opt/{}.anf", |_, ast_tree| {
+        format!("{}\n", opt::optimize(anf::convert(ast_tree)))
+    });
+}
+
+#[test]
+fn optimizing_anf_keeps_results() {
+    let optimized = |filename: &str, ast_tree: &ASTNode<'_>| {
+        let optimized = opt::optimize(anf::convert(ast_tree));
+
+        // Optimizing stops once nothing changes, so a second run changes nothing either
+        assert_eq!(opt::optimize(optimized.clone()), optimized, "{}", filename);
+        run_anf(&optimized)
+    };
+    agrees_with_eval(&PROGRAMS, optimized);
+    prints(&DEEP_PROGRAMS[2..], optimized);
+}
+
+fn specialized_source(filename: &str, config: &SpecializeConfig) -> String {
+    let contents = read_file(filename).unwrap();
+    let ast_tree = build_ast(parse_file(&contents).unwrap());
+    specialize(&ast_tree, config).source().to_string()
+}
+
+#[test]
+fn specialize_matches_golden_files() {
+    let files = ["partial_plus", "open_recursion", "power", "interpreter"];
+    matches_golden_files(
+        &files,
+        "tests/golden/specialize/{}.lambda",
+        |name, ast_tree| {
+            let mut config = SpecializeConfig::default();
+            if name == "interpreter" {
+                config.annotations.push("run=SDD".parse().unwrap());
+            }
+            format!("{}\n", specialize(ast_tree, &config).source())
+        },
+    );
+}
+
+#[test]
+fn specializing_keeps_results() {
+    let config = SpecializeConfig::default();
+    agrees_with_eval(&PROGRAMS, |filename, ast_tree| {
+        let source = specialize(ast_tree, &config).source().to_string();
+        let residual = build_ast(parse_file(&source).unwrap());
+        residual
+            .infer::<i32>()
+            .unwrap_or_else(|e| panic!("Residual of {} failed with {}", filename, e));
+        cps::run(&cps::convert(&residual)).to_string()
+    });
+
+    // Unfolding stops at the limit and leaves nested calls and long numbers that are too deep to
+    // read back quickly, so these residual programs run on the virtual machine. Compiling them
+    // infers the types of their records, which recurses deeper than the stack of a test thread.
+    thread::Builder::new()
+        .stack_size(1 << 30)
+        .spawn(move || {
+            prints(&DEEP_PROGRAMS, |_, ast_tree| {
+                let residual = specialize(ast_tree, &config);
+                let program = bytecode::compile(&residual);
+                let result = vm::run(&program, EvalConfig::default()).unwrap();
+                vm::Show(&result, &program).to_string()
+            })
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+
+    // Residual functions are compared by what they return for the same argument
+    let functions = [
+        ("examples/twice.lambda", ""),
+        ("examples/partial_plus.lambda", ""),
+        ("examples/open_recursion.lambda", ""),
+        ("examples/power.lambda", ""),
+        ("examples/interpreter.lambda", ""),
+        ("examples/interpreter.lambda", "run=SDD"),
+    ];
+    for (filename, binding_time) in functions.iter() {
+        let mut config = SpecializeConfig::default();
+        if !binding_time.is_empty() {
+            config.annotations.push(binding_time.parse().unwrap());
+        }
+        let applied = |source: &str| {
+            let applied = format!("let f = {} in f succ succ succ 0", source);
+            closure::compile(&build_ast(parse_file(&applied).unwrap()))
+                .run()
+                .to_string()
+        };
+        let expected = applied(&read_file(filename).unwrap());
+        let residual = specialized_source(filename, &config);
+        assert_eq!(applied(&residual), expected, "{}", filename);
+    }
+}
+
+#[test]
+fn binding_time_annotations_parse() {
+    let annotation: Annotation = "run=SDD".parse().unwrap();
+    assert_eq!(annotation.function, "run");
+    assert_eq!(
+        annotation.params,
+        vec![
+            BindingTime::Static,
+            BindingTime::Dynamic,
+            BindingTime::Dynamic
+        ]
+    );
+    assert!("run".parse::<Annotation>().is_err());
+    assert!("run=SX".parse::<Annotation>().is_err());
+    assert!("=SD".parse::<Annotation>().is_err());
+}
+
+/// Runs the interpreter binary with the given arguments, returning its exit status and output
+fn cli(args: &[&str]) -> (bool, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_lambda-rs"))
+        .args(args)
+        .output()
+        .expect("Could not run lambda-rs");
+    (
+        output.status.success(),
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+    )
+}
+
+#[test]
+fn reject_flags_that_do_not_apply() {
+    let cases: [(&[&str], &str); 7] = [
+        (
+            &["compile", "--strategy", "name", "examples/correct0.lambda"],
+            "The --strategy flag does not apply to compile",
+        ),
+        (
+            &["compile", "--backend", "vm", "examples/correct0.lambda"],
+            "The --backend flag does not apply to compile",
+        ),
+        (
+            &["specialize", "--max-steps", "5", "examples/correct0.lambda"],
+            "The --max-steps flag does not apply to specialize",
+        ),
+        (
+            &["--target", "js", "examples/correct0.lambda"],
+            "Targets only apply to compile",
+        ),
+        (
+            &["-O", "--dump-cps", "examples/correct0.lambda"],
+            "Only the anf backend and --dump-anf optimize programs",
+        ),
+        (
+            &[
+                "--machine-trace",
+                "--backend",
+                "vm",
+                "examples/correct0.lambda",
+            ],
+            "Only the eval backend traces the abstract machine",
+        ),
+        (
+            &["--machine-trace", "--dump-anf", "examples/correct0.lambda"],
+            "Only the eval backend traces the abstract machine",
+        ),
+    ];
+    for &(args, message) in &cases {
+        let (success, output) = cli(args);
+        assert!(!success, "{:?} should fail", args);
+        assert_eq!(output.trim(), message, "{:?}", args);
+    }
+
+    let (success, output) = cli(&[]);
+    assert!(!success);
+    assert!(output.starts_with("Usage: lambda-rs"), "{}", output);
+}
+
+#[test]
+fn report_the_configured_depth() {
+    for limit in &["1", "7", "100"] {
+        for backend in &["eval", "vm"] {
+            let (success, output) = cli(&[
+                "--backend",
+                backend,
+                "--max-depth",
+                limit,
+                "examples/deep_count.lambda",
+            ]);
+            assert!(!success, "{} {}", backend, limit);
+            assert_eq!(
+                output.trim(),
+                format!("Evaluation exceeded the maximum depth of {}", limit),
+                "{}",
+                backend
+            );
+        }
+    }
+}
+
+#[test]
+fn machine_trace_stops_at_the_limits_of_eval() {
+    for limit in &["--max-steps", "--max-depth"] {
+        let (_, evaluated) = cli(&[limit, "3", "examples/fix_plus.lambda"]);
+        let (success, traced) = cli(&["--machine-trace", limit, "3", "examples/fix_plus.lambda"]);
+        assert!(!success, "{}", limit);
+        assert_eq!(traced.lines().last(), evaluated.lines().last(), "{}", limit);
+    }
+}
+
+#[test]
+fn print_function_examples() {
+    // The evaluator prints the normal form of the function it evaluated to
+    let functions = [
+        ("examples/capture.lambda", "(@ y: Nat. (@ y1: Nat. succ y))"),
+        ("examples/letrec_function.lambda", "(@ n: Nat. succ n)"),
+        (
+            "examples/open_recursion.lambda",
+            "letrec count: (Nat -> Nat) = (@ n: Nat. if iszero n then 0 else succ (count (pred n))) \
+             in (@ k: Nat. {a=count k, b=succ succ 0})",
+        ),
+    ];
+    for (filename, expected) in functions.iter() {
+        for strategy in &["value", "name", "need"] {
+            let (success, output) = cli(&["--strategy", strategy, filename]);
+            assert!(success, "{} passing by {}", filename, strategy);
+            assert_eq!(
+                output.trim_end(),
+                *expected,
+                "{} passing by {}",
+                filename,
+                strategy
+            );
+        }
+    }
+
+    // Normalizing the function the evaluator returns gives what normalizing the program gives
+    for filename in &["examples/power.lambda", "examples/interpreter.lambda"] {
+        let contents = read_file(filename).unwrap();
+        let expected = normal_form(&build_ast(parse_file(&contents).unwrap()));
+        let (success, output) = cli(&[filename]);
+        assert!(success, "{}", filename);
+        assert_eq!(output.trim_end(), expected, "{}", filename);
+        for backend in &["vm", "closure", "anf"] {
+            let (success, output) = cli(&["--backend", backend, filename]);
+            assert!(success, "{} with {}", filename, backend);
+            assert_eq!(
+                output.trim_end(),
+                "<function>",
+                "{} with {}",
+                filename,
+                backend
+            );
+        }
+    }
+
+    // A function is only printed once the program evaluated to it
+    let filename = "examples/diverging_function.lambda";
+    let (success, output) = cli(&["--strategy", "name", filename]);
+    assert!(success);
+    assert_eq!(output.trim_end(), "(@ y: Nat. y)");
+    for backend in &["eval", "vm"] {
+        let (success, output) = cli(&["--backend", backend, "--max-steps", "100", filename]);
+        assert!(!success, "{}", backend);
+        assert_eq!(
+            output.trim_end(),
+            "Evaluation ran out of fuel after 100 steps",
+            "{}",
+            backend
+        );
+    }
+}
+
+#[test]
+fn print_functions_nested_in_values() {
+    // The other backends are compared to the evaluator, so they all print nested functions alike
+    for backend in &["eval", "vm", "closure", "anf"] {
+        let (success, output) = cli(&["--backend", backend, "examples/nested_function.lambda"]);
+        assert!(success, "{}", backend);
+        assert_eq!(
+            output.trim_end(),
+            "<some={f=<function>, n=1}>",
+            "{}",
+            backend
+        );
+    }
 }
@@ -0,0 +1,65 @@
+// Generated by lambda-rs
+"use strict";
+
+class Variant {
+    constructor(tag, value) {
+        this.tag = tag;
+        this.value = value;
+    }
+}
+
+class TailCall {
+    constructor(callee, argument) {
+        this.callee = callee;
+        this.argument = argument;
+    }
+}
+
+function call(callee, argument) {
+    let result = callee(argument);
+    while (result instanceof TailCall) {
+        result = result.callee(result.argument);
+    }
+    return result;
+}
+
+function show(value) {
+    if (typeof value === "function") {
+        return "<function>";
+    } else if (value instanceof Variant) {
+        return `<${value.tag}=${show(value.value)}>`;
+    } else if (typeof value === "object") {
+        const fields = Object.keys(value).sort().map((field) => `${field}=${show(value[field])}`);
+        return `{${fields.join(", ")}}`;
+    }
+    return String(value);
+}
+
+function main() {
+    return call((a) => {
+        if (a.tag === "some") {
+            if (a.value.x === 0) {
+                const b = a.value.y;
+                return b;
+            } else if (a.value.x !== 0) {
+                if (a.value.y) {
+                    const n = (a.value.x - 1);
+                    return n === 0;
+                } else {
+                    return false;
+                }
+            } else {
+                throw new Error("No arm of case matches the argument");
+            }
+        } else {
+            return false;
+        }
+    }, new Variant("some", { x: 1, y: true }));
+}
+
+const threads = typeof require === "function" ? require("worker_threads") : null;
+if (threads && threads.isMainThread) {
+    new threads.Worker(__filename, { resourceLimits: { stackSizeMb: 1024 } });
+} else {
+    console.log(show(main()));
+}
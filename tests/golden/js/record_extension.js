@@ -0,0 +1,54 @@
+// Generated by lambda-rs
+"use strict";
+
+class Variant {
+    constructor(tag, value) {
+        this.tag = tag;
+        this.value = value;
+    }
+}
+
+function without(record, field) {
+    const { [field]: _, ...rest } = record;
+    return rest;
+}
+
+class TailCall {
+    constructor(callee, argument) {
+        this.callee = callee;
+        this.argument = argument;
+    }
+}
+
+function call(callee, argument) {
+    let result = callee(argument);
+    while (result instanceof TailCall) {
+        result = result.callee(result.argument);
+    }
+    return result;
+}
+
+function show(value) {
+    if (typeof value === "function") {
+        return "<function>";
+    } else if (value instanceof Variant) {
+        return `<${value.tag}=${show(value.value)}>`;
+    } else if (typeof value === "object") {
+        const fields = Object.keys(value).sort().map((field) => `${field}=${show(value[field])}`);
+        return `{${fields.join(", ")}}`;
+    }
+    return String(value);
+}
+
+function main() {
+    const r = { x: 1, y: 0 };
+    const s = { ...r, flag: true };
+    return call((p) => p.flag ? p.x + 1 : p.x, without(s, "y"));
+}
+
+const threads = typeof require === "function" ? require("worker_threads") : null;
+if (threads && threads.isMainThread) {
+    new threads.Worker(__filename, { resourceLimits: { stackSizeMb: 1024 } });
+} else {
+    console.log(show(main()));
+}
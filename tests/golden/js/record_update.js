@@ -0,0 +1,47 @@
+// Generated by lambda-rs
+"use strict";
+
+class Variant {
+    constructor(tag, value) {
+        this.tag = tag;
+        this.value = value;
+    }
+}
+
+class TailCall {
+    constructor(callee, argument) {
+        this.callee = callee;
+        this.argument = argument;
+    }
+}
+
+function call(callee, argument) {
+    let result = callee(argument);
+    while (result instanceof TailCall) {
+        result = result.callee(result.argument);
+    }
+    return result;
+}
+
+function show(value) {
+    if (typeof value === "function") {
+        return "<function>";
+    } else if (value instanceof Variant) {
+        return `<${value.tag}=${show(value.value)}>`;
+    } else if (typeof value === "object") {
+        const fields = Object.keys(value).sort().map((field) => `${field}=${show(value[field])}`);
+        return `{${fields.join(", ")}}`;
+    }
+    return String(value);
+}
+
+function main() {
+    return call((a) => ({ ...a, result: a.result + 1, status: false }), { result: 1, status: true });
+}
+
+const threads = typeof require === "function" ? require("worker_threads") : null;
+if (threads && threads.isMainThread) {
+    new threads.Worker(__filename, { resourceLimits: { stackSizeMb: 1024 } });
+} else {
+    console.log(show(main()));
+}
@@ -0,0 +1,85 @@
+// Generated by lambda-rs
+"use strict";
+
+class Variant {
+    constructor(tag, value) {
+        this.tag = tag;
+        this.value = value;
+    }
+}
+
+class TailCall {
+    constructor(callee, argument) {
+        this.callee = callee;
+        this.argument = argument;
+    }
+}
+
+function call(callee, argument) {
+    let result = callee(argument);
+    while (result instanceof TailCall) {
+        result = result.callee(result.argument);
+    }
+    return result;
+}
+
+function show(value) {
+    if (typeof value === "function") {
+        return "<function>";
+    } else if (value instanceof Variant) {
+        return `<${value.tag}=${show(value.value)}>`;
+    } else if (typeof value === "object") {
+        const fields = Object.keys(value).sort().map((field) => `${field}=${show(value[field])}`);
+        return `{${fields.join(", ")}}`;
+    }
+    return String(value);
+}
+
+function main() {
+    function add(p) {
+        while (true) {
+            if (p.m === 0) {
+                return p.n;
+            } else {
+                p = { m: Math.max(p.m - 1, 0), n: p.n + 1 };
+                continue;
+            }
+        }
+    }
+
+    function grow(p2) {
+        while (true) {
+            if (p2.k === 0) {
+                return p2.acc;
+            } else {
+                p2 = { acc: call(add, { m: p2.acc, n: p2.acc }), k: Math.max(p2.k - 1, 0) };
+                continue;
+            }
+        }
+    }
+
+    function even(n) {
+        if (n === 0) {
+            return true;
+        } else {
+            return new TailCall(odd, Math.max(n - 1, 0));
+        }
+    }
+
+    function odd(n2) {
+        if (n2 === 0) {
+            return false;
+        } else {
+            return new TailCall(even, Math.max(n2 - 1, 0));
+        }
+    }
+
+    return call(even, call(grow, { acc: 1, k: 17 }));
+}
+
+const threads = typeof require === "function" ? require("worker_threads") : null;
+if (threads && threads.isMainThread) {
+    new threads.Worker(__filename, { resourceLimits: { stackSizeMb: 1024 } });
+} else {
+    console.log(show(main()));
+}
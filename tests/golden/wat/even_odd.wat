@@ -0,0 +1,127 @@
+;; Generated by lambda-rs
+(module
+  (type $code (func (param i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (global $heap (mut i32) (i32.const 56))
+  (global $text_true i32 (i32.const 16))
+  (global $text_false i32 (i32.const 24))
+  (global $text_function i32 (i32.const 36))
+  (global $field_names i32 (i32.const 52))
+  (global $field_ranks i32 (i32.const 52))
+  (global $tag_names i32 (i32.const 52))
+  (table 5 funcref)
+  (elem (i32.const 0) $f0 $f1 $f2 $f3 $f4)
+  (data (i32.const 16) "\04\00\00\00true") ;; "true"
+  (data (i32.const 24) "\05\00\00\00false") ;; "false"
+  (data (i32.const 36) "\0a\00\00\00<function>") ;; "<function>"
+  (data (i32.const 52) "") ;; field names
+  (data (i32.const 52) "") ;; ranks of the fields in alphabetical order
+  (data (i32.const 52) "") ;; tag names
+
+  ;; runtime.wat
+
+  (func $f0 (type $code) (param $env i32) (param $arg i32) (result i32)
+    local.get $arg
+    call $unfold
+    call $iszero
+    i32.load offset=4
+    if (result i32)
+      i32.const 1
+      call $bool
+    else
+      local.get $env
+      i32.load
+      call $unfold
+      local.get $arg
+      call $unfold
+      call $pred
+      call $apply
+    end)
+
+  (func $f1 (type $code) (param $env i32) (param $arg i32) (result i32)
+    (local $t1 i32)
+    i32.const 4
+    call $alloc
+    local.set $t1
+    local.get $t1
+    local.get $env
+    i32.load offset=4
+    i32.store
+    i32.const 0
+    local.get $t1
+    call $closure)
+
+  (func $f2 (type $code) (param $env i32) (param $arg i32) (result i32)
+    local.get $arg
+    call $unfold
+    call $iszero
+    i32.load offset=4
+    if (result i32)
+      i32.const 0
+      call $bool
+    else
+      local.get $env
+      i32.load
+      call $unfold
+      local.get $arg
+      call $unfold
+      call $pred
+      call $apply
+    end)
+
+  (func $f3 (type $code) (param $env i32) (param $arg i32) (result i32)
+    (local $t1 i32)
+    i32.const 4
+    call $alloc
+    local.set $t1
+    local.get $t1
+    local.get $env
+    i32.load
+    i32.store
+    i32.const 2
+    local.get $t1
+    call $closure)
+
+  (func $f4 (type $code) (param $env i32) (param $arg i32) (result i32)
+    (local $t1 i32)
+    i32.const 8
+    call $alloc
+    local.set $t1
+    local.get $t1
+    i32.const 1
+    local.get $t1
+    call $rec
+    i32.store
+    local.get $t1
+    i32.const 3
+    local.get $t1
+    call $rec
+    i32.store offset=4
+    local.get $t1
+    i32.load
+    call $unfold
+    i32.const 0
+    call $nat
+    call $succ
+    call $succ
+    call $succ
+    call $apply)
+
+  (func (export "main") (result i32)
+    (local $output i32)
+    i32.const 0
+    i32.const 0
+    call $f4
+    i32.const 4
+    call $alloc
+    local.set $output
+    call $show
+    local.get $output
+    global.get $heap
+    local.get $output
+    i32.sub
+    i32.const 4
+    i32.sub
+    i32.store
+    local.get $output)
+)
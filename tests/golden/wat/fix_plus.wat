@@ -0,0 +1,137 @@
+;; Generated by lambda-rs
+(module
+  (type $code (func (param i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (global $heap (mut i32) (i32.const 88))
+  (global $text_true i32 (i32.const 16))
+  (global $text_false i32 (i32.const 24))
+  (global $text_function i32 (i32.const 36))
+  (global $field_names i32 (i32.const 68))
+  (global $field_ranks i32 (i32.const 76))
+  (global $tag_names i32 (i32.const 84))
+  (table 3 funcref)
+  (elem (i32.const 0) $f0 $f1 $f2)
+  (data (i32.const 16) "\04\00\00\00true") ;; "true"
+  (data (i32.const 24) "\05\00\00\00false") ;; "false"
+  (data (i32.const 36) "\0a\00\00\00<function>") ;; "<function>"
+  (data (i32.const 52) "\01\00\00\00m") ;; "m"
+  (data (i32.const 60) "\01\00\00\00n") ;; "n"
+  (data (i32.const 68) "4\00\00\00<\00\00\00") ;; field names
+  (data (i32.const 76) "\00\00\00\00\01\00\00\00") ;; ranks of the fields in alphabetical order
+  (data (i32.const 84) "") ;; tag names
+
+  ;; runtime.wat
+
+  (func $f0 (type $code) (param $env i32) (param $arg i32) (result i32)
+    (local $t1 i32)
+    (local $t2 i32)
+    local.get $arg
+    call $unfold
+    local.set $t1
+    local.get $t1
+    i32.const 0
+    call $record_get
+    call $unfold
+    call $iszero
+    i32.load offset=4
+    if (result i32)
+      local.get $t1
+      i32.const 1
+      call $record_get
+      call $unfold
+    else
+      local.get $env
+      i32.load
+      call $unfold
+      i32.const 2
+      call $record
+      local.set $t2
+      local.get $t2
+      i32.const 0
+      i32.store offset=8
+      local.get $t2
+      local.get $t1
+      i32.const 0
+      call $record_get
+      call $unfold
+      call $pred
+      i32.store offset=12
+      local.get $t2
+      i32.const 1
+      i32.store offset=16
+      local.get $t2
+      local.get $t1
+      i32.const 1
+      call $record_get
+      call $unfold
+      call $succ
+      i32.store offset=20
+      local.get $t2
+      call $apply
+    end)
+
+  (func $f1 (type $code) (param $env i32) (param $arg i32) (result i32)
+    (local $t1 i32)
+    i32.const 4
+    call $alloc
+    local.set $t1
+    local.get $t1
+    local.get $arg
+    i32.store
+    i32.const 0
+    local.get $t1
+    call $closure)
+
+  (func $f2 (type $code) (param $env i32) (param $arg i32) (result i32)
+    (local $t1 i32)
+    (local $t2 i32)
+    i32.const 1
+    i32.const 0
+    call $closure
+    call $fix
+    local.set $t1
+    local.get $t1
+    call $unfold
+    i32.const 2
+    call $record
+    local.set $t2
+    local.get $t2
+    i32.const 0
+    i32.store offset=8
+    local.get $t2
+    i32.const 0
+    call $nat
+    call $succ
+    call $succ
+    i32.store offset=12
+    local.get $t2
+    i32.const 1
+    i32.store offset=16
+    local.get $t2
+    i32.const 0
+    call $nat
+    call $succ
+    call $succ
+    call $succ
+    i32.store offset=20
+    local.get $t2
+    call $apply)
+
+  (func (export "main") (result i32)
+    (local $output i32)
+    i32.const 0
+    i32.const 0
+    call $f2
+    i32.const 4
+    call $alloc
+    local.set $output
+    call $show
+    local.get $output
+    global.get $heap
+    local.get $output
+    i32.sub
+    i32.const 4
+    i32.sub
+    i32.store
+    local.get $output)
+)
@@ -0,0 +1,83 @@
+;; Generated by lambda-rs
+(module
+  (type $code (func (param i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (global $heap (mut i32) (i32.const 96))
+  (global $text_true i32 (i32.const 16))
+  (global $text_false i32 (i32.const 24))
+  (global $text_function i32 (i32.const 36))
+  (global $field_names i32 (i32.const 76))
+  (global $field_ranks i32 (i32.const 84))
+  (global $tag_names i32 (i32.const 92))
+  (table 2 funcref)
+  (elem (i32.const 0) $f0 $f1)
+  (data (i32.const 16) "\04\00\00\00true") ;; "true"
+  (data (i32.const 24) "\05\00\00\00false") ;; "false"
+  (data (i32.const 36) "\0a\00\00\00<function>") ;; "<function>"
+  (data (i32.const 52) "\06\00\00\00result") ;; "result"
+  (data (i32.const 64) "\06\00\00\00status") ;; "status"
+  (data (i32.const 76) "4\00\00\00@\00\00\00") ;; field names
+  (data (i32.const 84) "\00\00\00\00\01\00\00\00") ;; ranks of the fields in alphabetical order
+  (data (i32.const 92) "") ;; tag names
+
+  ;; runtime.wat
+
+  (func $f0 (type $code) (param $env i32) (param $arg i32) (result i32)
+    local.get $arg
+    call $unfold
+    i32.const 0
+    local.get $arg
+    call $unfold
+    i32.const 0
+    call $record_get
+    call $succ
+    call $record_set
+    i32.const 1
+    i32.const 0
+    call $bool
+    call $record_set)
+
+  (func $f1 (type $code) (param $env i32) (param $arg i32) (result i32)
+    (local $t1 i32)
+    i32.const 0
+    i32.const 0
+    call $closure
+    i32.const 2
+    call $record
+    local.set $t1
+    local.get $t1
+    i32.const 0
+    i32.store offset=8
+    local.get $t1
+    i32.const 0
+    call $nat
+    call $succ
+    i32.store offset=12
+    local.get $t1
+    i32.const 1
+    i32.store offset=16
+    local.get $t1
+    i32.const 1
+    call $bool
+    i32.store offset=20
+    local.get $t1
+    call $apply)
+
+  (func (export "main") (result i32)
+    (local $output i32)
+    i32.const 0
+    i32.const 0
+    call $f1
+    i32.const 4
+    call $alloc
+    local.set $output
+    call $show
+    local.get $output
+    global.get $heap
+    local.get $output
+    i32.sub
+    i32.const 4
+    i32.sub
+    i32.store
+    local.get $output)
+)
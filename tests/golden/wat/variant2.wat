@@ -0,0 +1,94 @@
+;; Generated by lambda-rs
+(module
+  (type $code (func (param i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (global $heap (mut i32) (i32.const 88))
+  (global $text_true i32 (i32.const 16))
+  (global $text_false i32 (i32.const 24))
+  (global $text_function i32 (i32.const 36))
+  (global $field_names i32 (i32.const 52))
+  (global $field_ranks i32 (i32.const 52))
+  (global $tag_names i32 (i32.const 76))
+  (table 2 funcref)
+  (elem (i32.const 0) $f0 $f1)
+  (data (i32.const 16) "\04\00\00\00true") ;; "true"
+  (data (i32.const 24) "\05\00\00\00false") ;; "false"
+  (data (i32.const 36) "\0a\00\00\00<function>") ;; "<function>"
+  (data (i32.const 52) "") ;; field names
+  (data (i32.const 52) "") ;; ranks of the fields in alphabetical order
+  (data (i32.const 52) "\05\00\00\00b_val") ;; "b_val"
+  (data (i32.const 64) "\05\00\00\00n_val") ;; "n_val"
+  (data (i32.const 76) "4\00\00\00@\00\00\00") ;; tag names
+
+  ;; runtime.wat
+
+  (func $f0 (type $code) (param $env i32) (param $arg i32) (result i32)
+    (local $t1 i32)
+    (local $t2 i32)
+    local.get $arg
+    call $unfold
+    local.set $t1
+    local.get $t1
+    local.set $t2
+    local.get $t2
+    i32.load offset=4
+    i32.const 0
+    i32.eq
+    if (result i32)
+      local.get $t1
+      i32.load offset=8
+      call $unfold
+      i32.load offset=4
+      if (result i32)
+        i32.const 0
+        call $nat
+      else
+        i32.const 0
+        call $nat
+        call $succ
+      end
+    else
+      local.get $t2
+      i32.load offset=4
+      i32.const 1
+      i32.eq
+      if (result i32)
+        local.get $t1
+        i32.load offset=8
+        call $unfold
+        call $succ
+      else
+        unreachable
+      end
+    end)
+
+  (func $f1 (type $code) (param $env i32) (param $arg i32) (result i32)
+    i32.const 0
+    i32.const 0
+    call $closure
+    i32.const 1
+    i32.const 0
+    call $nat
+    call $succ
+    call $succ
+    call $variant
+    call $apply)
+
+  (func (export "main") (result i32)
+    (local $output i32)
+    i32.const 0
+    i32.const 0
+    call $f1
+    i32.const 4
+    call $alloc
+    local.set $output
+    call $show
+    local.get $output
+    global.get $heap
+    local.get $output
+    i32.sub
+    i32.const 4
+    i32.sub
+    i32.store
+    local.get $output)
+)